@@ -0,0 +1,195 @@
+use std::str::pattern::Pattern;
+
+use crate::{cursor::Cursor, piece_table::PieceTable};
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum BufferMode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BufferCommand {
+    InsertCursorAbove,
+    InsertCursorBelow,
+    ReplaceChar(u8),
+    Surround(u8),
+    CutSelection,
+    CutSingleSelection,
+    CutMotion(u8, CutMotion, bool),
+    InsertChar(u8),
+    InsertUnicode(char),
+    ExpandAbbreviation,
+    InsertRegister(u8),
+    InsertNewLine,
+    IndentLine,
+    UnindentLine,
+    ToggleComment,
+    DeleteCharBack,
+    DeleteWordBack,
+    DeleteWordFront,
+    DeleteToLineStart,
+    Undo,
+    Redo,
+    StartCompletion,
+    Complete,
+    CopySelection,
+    CopyLine,
+    PasteSelection,
+    PasteSelectionReindented,
+    PasteCursorSelection,
+    DuplicateSelection,
+    GotoDefinition,
+    GotoImplementation,
+    FindReferences,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CutMotion {
+    Inside,
+    ForwardUntil,
+    ForwardTo,
+    BackwardUntil,
+    BackwardTo,
+}
+
+// J and gK add a cursor below the last cursor or above the first one
+pub fn insert_cursor_above(piece_table: &PieceTable, cursors: &mut Vec<Cursor>) {
+    if let Some(first_cursor) = cursors
+        .iter()
+        .min_by(|c1, c2| c1.position.cmp(&c2.position))
+    {
+        let mut cursor = *first_cursor;
+        cursor.cached_col = 0;
+        cursor.move_up(piece_table, 1);
+        cursors.push(cursor);
+    }
+}
+
+pub fn insert_cursor_below(piece_table: &PieceTable, cursors: &mut Vec<Cursor>) {
+    if let Some(last_cursor) = cursors
+        .iter()
+        .max_by(|c1, c2| c1.position.cmp(&c2.position))
+    {
+        let mut cursor = *last_cursor;
+        cursor.cached_col = 0;
+        cursor.move_down(piece_table, 1);
+        cursors.push(cursor);
+    }
+}
+
+pub fn is_prefix_of_command(str: &str, mode: BufferMode) -> bool {
+    match mode {
+        BufferMode::Normal => {
+            NORMAL_MODE_COMMANDS.iter().any(|cmd| str.is_prefix_of(cmd))
+                || (str.starts_with('f') && str.len() <= 2)
+                || (str.starts_with('F') && str.len() <= 2)
+                || (str.starts_with('r') && str.len() <= 2)
+                || (str.starts_with('m') && str.len() <= 2)
+                || (str.starts_with('\'') && str.len() <= 2)
+                || (str.starts_with('`') && str.len() <= 2)
+                || (str.starts_with('t') && str.len() <= 2)
+                || (str.starts_with('T') && str.len() <= 2)
+                || (str.starts_with("ci") && str.len() <= 3)
+                || (str.starts_with("di") && str.len() <= 3)
+                || (str.starts_with("gci") && str.len() <= 4)
+                || (str.starts_with("ct") && str.len() <= 3)
+                || (str.starts_with("dt") && str.len() <= 3)
+                || (str.starts_with("cT") && str.len() <= 3)
+                || (str.starts_with("dT") && str.len() <= 3)
+                || is_prefix_of_operator_command(str)
+                || counted_goto(str).is_some()
+        }
+        BufferMode::Visual => {
+            VISUAL_MODE_COMMANDS.iter().any(|cmd| str.is_prefix_of(cmd))
+                || (str.starts_with('f') && str.len() <= 2)
+                || (str.starts_with('F') && str.len() <= 2)
+                || (str.starts_with('t') && str.len() <= 2)
+                || (str.starts_with('T') && str.len() <= 2)
+                || (str.starts_with('i') && str.len() <= 2)
+                || (str.starts_with('S') && str.len() <= 2)
+        }
+        BufferMode::VisualLine => {
+            VISUAL_MODE_COMMANDS.iter().any(|cmd| str.is_prefix_of(cmd))
+                || (str.starts_with('f') && str.len() <= 2)
+                || (str.starts_with('F') && str.len() <= 2)
+                || (str.starts_with('t') && str.len() <= 2)
+                || (str.starts_with('T') && str.len() <= 2)
+                || (str.starts_with('S') && str.len() <= 2)
+        }
+        _ => false,
+    }
+}
+
+const NORMAL_MODE_COMMANDS: [&str; 49] = [
+    "j", "k", "h", "l", "w", "b", "^", "$", "gg", "G", "{", "}", "x", "dd", "D", "J", "K", "gK",
+    "v", "V", "u", ">", "<", "p", "P", "]p", "yy", "zz", "n", "N", "/", "gd", "gi", "gr", "gf",
+    "gF", "gx", "]d", "[d", "]e", "[e", "gcc", "gcj", "gck", "gcG", "gcgg", ".", "'\"", "`\"",
+];
+const VISUAL_MODE_COMMANDS: [&str; 23] = [
+    "j", "k", "h", "l", "w", "b", "^", "$", "gg", "G", "{", "}", "x", "d", ">", "<", "y", "p", "P",
+    "zz", "n", "N", "/",
+];
+
+// Motions that can follow an operator, besides f/F/t/T/i and a character
+const OPERATOR_MOTIONS: [&str; 13] = [
+    "h", "l", "j", "k", "w", "b", "0", "$", "^", "G", "gg", "{", "}",
+];
+
+// Splits e.g. "2d3w" into the operator, the total count and the motion
+pub fn split_operator_command(input: &str) -> Option<(u8, usize, &str)> {
+    let (count, rest) = split_count(input);
+    let operator = *rest.as_bytes().first()?;
+    if !matches!(operator, b'd' | b'c' | b'y') {
+        return None;
+    }
+    let (motion_count, motion) = split_count(&rest[1..]);
+    Some((operator, count * motion_count, motion))
+}
+
+// [count]G goes to line count
+pub fn counted_goto(input: &str) -> Option<usize> {
+    let count = input.strip_suffix('G')?;
+    if count.starts_with('0') {
+        return None;
+    }
+    count.parse().ok()
+}
+
+pub fn split_count(input: &str) -> (usize, &str) {
+    // A leading 0 is the motion to the start of the line rather than a count
+    let num_digits = if input.starts_with('0') {
+        0
+    } else {
+        input.bytes().take_while(u8::is_ascii_digit).count()
+    };
+    (
+        input[..num_digits].parse().unwrap_or(1),
+        &input[num_digits..],
+    )
+}
+
+pub fn is_operator_command(input: &str) -> bool {
+    split_operator_command(input).is_some_and(|(operator, _, motion)| {
+        OPERATOR_MOTIONS.contains(&motion)
+            || motion.as_bytes() == [operator]
+            || (motion.len() == 2
+                && matches!(motion.as_bytes()[0], b'f' | b'F' | b't' | b'T' | b'i'))
+    })
+}
+
+pub fn is_prefix_of_operator_command(input: &str) -> bool {
+    let (_, rest) = split_count(input);
+    if rest.is_empty() {
+        return !input.is_empty();
+    }
+    split_operator_command(input).is_some_and(|(operator, _, motion)| {
+        motion.is_empty()
+            || OPERATOR_MOTIONS.iter().any(|m| motion.is_prefix_of(m))
+            || motion.as_bytes() == [operator]
+            || (motion.len() <= 2
+                && matches!(motion.as_bytes()[0], b'f' | b'F' | b't' | b'T' | b'i'))
+    })
+}
@@ -1,18 +1,13 @@
 use std::{
-    cell::RefCell,
     cmp::{max, min},
     ops::Range,
-    rc::Rc,
 };
 
 use crate::{
-    language_server::LanguageServer,
-    language_server_types::{CompletionItem, CompletionList},
     piece_table::PieceTable,
     text_utils::{self, CharType},
 };
 
-const MAX_CURSOR_CLIPBOARD_SIZE: usize = 256;
 #[derive(Copy, Clone, Debug)]
 pub struct Cursor {
     pub position: usize,
@@ -20,8 +15,6 @@ pub struct Cursor {
     pub cached_col: usize,
     pub completion_request: Option<CompletionRequest>,
     pub signature_help_request: Option<SignatureHelpRequest>,
-    pub clipboard: [u8; MAX_CURSOR_CLIPBOARD_SIZE],
-    pub clipboard_size: usize,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -33,6 +26,7 @@ pub struct CompletionRequest {
     pub initial_position: usize,
     pub selection_index: usize,
     pub selection_view_offset: usize,
+    pub documentation_offset: usize,
     pub manually_triggered: bool,
 }
 
@@ -79,84 +73,10 @@ pub fn cursors_insert_rebalance(cursors: &mut [Cursor], position: usize, count:
     }
 }
 
-pub fn get_filtered_completions(
-    piece_table: &PieceTable,
-    completion_list: &CompletionList,
-    request: &CompletionRequest,
-    cursor_position: usize,
-) -> Vec<CompletionItem> {
-    let mut merged_items: Vec<CompletionItem> = vec![];
-    for item in &completion_list.items {
-        if !merged_items.iter().any(|x| {
-            x.insert_text.as_ref().unwrap_or(&x.label)
-                == item.insert_text.as_ref().unwrap_or(&item.label)
-        }) {
-            merged_items.push(item.clone());
-        } else {
-            let existing_item = merged_items
-                .iter_mut()
-                .find(|x| {
-                    x.insert_text.as_ref().unwrap_or(&x.label)
-                        == item.insert_text.as_ref().unwrap_or(&item.label)
-                })
-                .unwrap();
-
-            if let Some(existing_details) = &mut existing_item.detail {
-                if let Some(details) = &item.detail {
-                    existing_item.label.push(b'\n' as char);
-                    existing_item.label.push_str(&item.label);
-                    existing_details.push(b'\n' as char);
-                    existing_details.push_str(details)
-                }
-            }
-        }
-    }
-
-    // Filter from start of word if manually triggered or
-    let request_position = if request.manually_triggered {
-        cursor_position.saturating_sub(
-            piece_table
-                .iter_chars_at_rev(cursor_position.saturating_sub(1))
-                .position(|c| text_utils::char_type(c) != CharType::Word)
-                .unwrap_or(0),
-        )
-    // Filter from start of request if triggered by a trigger character
-    } else {
-        request.initial_position
-    };
-
-    let match_string: Vec<u8> = piece_table
-        .iter_chars_at(request_position)
-        .take(cursor_position - request_position)
-        .collect();
-    let trimmed_match_string = match_string.trim_ascii();
-
-    let mut filtered_completions: Vec<CompletionItem> = merged_items
-        .iter()
-        .filter(|item| {
-            item.insert_text
-                .as_ref()
-                .unwrap_or(&item.label)
-                .starts_with(unsafe { std::str::from_utf8_unchecked(trimmed_match_string) })
-        })
-        .cloned()
-        .collect();
-
-    // If the match string doesn't match anything, show all entries
-    if filtered_completions.is_empty() {
-        filtered_completions = completion_list.items.to_vec();
-    }
-
-    if match_string.first() == Some(&b' ') {
-        for item in &mut filtered_completions {
-            item.label.insert(0, ' ');
-            if let Some(insert_text) = &mut item.insert_text {
-                insert_text.insert(0, ' ');
-            }
-        }
+impl Default for Cursor {
+    fn default() -> Self {
+        Self::new(0)
     }
-
-    filtered_completions
 }
 
 impl Cursor {
@@ -167,8 +87,6 @@ impl Cursor {
             cached_col: 0,
             completion_request: None,
             signature_help_request: None,
-            clipboard: [b'\0'; MAX_CURSOR_CLIPBOARD_SIZE],
-            clipboard_size: 0,
         }
     }
 
@@ -176,18 +94,6 @@ impl Cursor {
         min(self.position, self.anchor)..max(self.position, self.anchor)
     }
 
-    pub fn default() -> Self {
-        Self {
-            position: 0,
-            anchor: 0,
-            cached_col: 0,
-            completion_request: None,
-            signature_help_request: None,
-            clipboard: [b'\0'; MAX_CURSOR_CLIPBOARD_SIZE],
-            clipboard_size: 0,
-        }
-    }
-
     pub fn stick_col(&mut self, piece_table: &PieceTable) {
         self.cached_col = max(self.cached_col, piece_table.col_index(self.position));
     }
@@ -283,6 +189,55 @@ impl Cursor {
         self.position = 0;
     }
 
+    pub fn move_to_end_of_word(&mut self, piece_table: &PieceTable) {
+        if let Some(c) = piece_table.char_at(self.position) {
+            let char_type = text_utils::char_type(c);
+            let count = piece_table
+                .iter_chars_at(self.position + 1)
+                .take_while(|c| *c != b'\n' && text_utils::char_type(*c) == char_type)
+                .count();
+            self.position += count;
+        }
+    }
+
+    // Paragraphs are separated by empty lines, like vim's { and } motions
+    pub fn move_to_next_paragraph(&mut self, piece_table: &PieceTable) {
+        let is_empty = |index| {
+            piece_table
+                .line_at_index(index)
+                .map(|line| line.length == 0)
+        };
+        let mut index = piece_table.line_index(self.position);
+        while is_empty(index) == Some(true) {
+            index += 1;
+        }
+        while is_empty(index) == Some(false) {
+            index += 1;
+        }
+        match piece_table.line_at_index(index) {
+            Some(line) => self.position = line.start,
+            None => self.move_to_end_of_file(piece_table),
+        }
+    }
+
+    pub fn move_to_previous_paragraph(&mut self, piece_table: &PieceTable) {
+        let is_empty = |index| {
+            piece_table
+                .line_at_index(index)
+                .map(|line| line.length == 0)
+        };
+        let mut index = piece_table.line_index(self.position);
+        while index > 0 && is_empty(index) == Some(true) {
+            index -= 1;
+        }
+        while index > 0 && is_empty(index) == Some(false) {
+            index -= 1;
+        }
+        if let Some(line) = piece_table.line_at_index(index) {
+            self.position = line.start;
+        }
+    }
+
     pub fn move_to_start_of_line(&mut self, piece_table: &PieceTable) {
         if let Some(line) = piece_table.line_at_char(self.position) {
             self.position = line.start;
@@ -395,15 +350,15 @@ impl Cursor {
             if let Some(c) = piece_table.char_at(self.position) {
                 let char_type = text_utils::char_type(c);
 
-                if let (Some(backward_match), Some(forward_match)) = (
-                    (self.chars_until_pred_rev(piece_table, |c| {
-                        text_utils::char_type(c) != char_type
-                    })),
-                    (self.chars_until_pred(piece_table, |c| text_utils::char_type(c) != char_type)),
-                ) {
-                    self.anchor = max(line.start, self.position - backward_match);
-                    self.position = min(line.end, self.position + forward_match);
-                }
+                // Words at the very start or end of the file run up to it
+                let backward_match = self
+                    .chars_until_pred_rev(piece_table, |c| text_utils::char_type(c) != char_type)
+                    .unwrap_or(self.position);
+                let forward_match = self
+                    .chars_until_pred(piece_table, |c| text_utils::char_type(c) != char_type)
+                    .unwrap_or(line.end.saturating_sub(self.position));
+                self.anchor = max(line.start, self.position - backward_match);
+                self.position = min(line.end, self.position + forward_match);
             }
         }
     }
@@ -492,6 +447,7 @@ impl Cursor {
     }
 
     pub fn seek(&mut self, piece_table: &PieceTable, text: &[u8], self_inclusive: bool) {
+        let ignore_case = text_utils::ignores_case(text);
         let inclusive_offset = if self_inclusive { 0 } else { 1 };
         let mut match_text = vec![];
         let mut offset = 0;
@@ -499,11 +455,10 @@ impl Cursor {
             match_text.push(c);
             offset += 1;
 
-            if match_text
-                .iter()
-                .enumerate()
-                .all(|(i, x)| text.get(i) == Some(x))
-            {
+            if match_text.iter().enumerate().all(|(i, x)| {
+                text.get(i)
+                    .is_some_and(|p| text_utils::search_char_eq(*p, *x, ignore_case))
+            }) {
                 if match_text.len() == text.len() {
                     self.position += inclusive_offset + offset - text.len();
                     self.anchor = self.position;
@@ -522,11 +477,10 @@ impl Cursor {
         {
             match_text.push(c);
 
-            if match_text
-                .iter()
-                .enumerate()
-                .all(|(i, x)| text.get(i) == Some(x))
-            {
+            if match_text.iter().enumerate().all(|(i, x)| {
+                text.get(i)
+                    .is_some_and(|p| text_utils::search_char_eq(*p, *x, ignore_case))
+            }) {
                 if match_text.len() == text.len() {
                     self.position = 1 + i - text.len();
                     self.anchor = self.position;
@@ -539,6 +493,7 @@ impl Cursor {
     }
 
     pub fn seek_back(&mut self, piece_table: &PieceTable, text: &[u8], self_inclusive: bool) {
+        let ignore_case = text_utils::ignores_case(text);
         let inclusive_offset = if self_inclusive { 0 } else { 1 };
         let mut match_text = vec![];
         let mut offset = 0;
@@ -546,12 +501,10 @@ impl Cursor {
             match_text.insert(0, c);
             offset += 1;
 
-            if match_text
-                .iter()
-                .rev()
-                .enumerate()
-                .all(|(i, x)| text.get(text.len().saturating_sub(i + 1)) == Some(x))
-            {
+            if match_text.iter().rev().enumerate().all(|(i, x)| {
+                text.get(text.len().saturating_sub(i + 1))
+                    .is_some_and(|p| text_utils::search_char_eq(*p, *x, ignore_case))
+            }) {
                 if match_text.len() == text.len() {
                     self.position -= offset;
                     self.anchor = self.position;
@@ -571,12 +524,10 @@ impl Cursor {
         {
             match_text.insert(0, c);
 
-            if match_text
-                .iter()
-                .rev()
-                .enumerate()
-                .all(|(i, x)| text.get(text.len().saturating_sub(i + 1)) == Some(x))
-            {
+            if match_text.iter().rev().enumerate().all(|(i, x)| {
+                text.get(text.len().saturating_sub(i + 1))
+                    .is_some_and(|p| text_utils::search_char_eq(*p, *x, ignore_case))
+            }) {
                 if match_text.len() == text.len() {
                     self.position = num_chars.saturating_sub(i + 1);
                     self.anchor = self.position;
@@ -588,17 +539,6 @@ impl Cursor {
         }
     }
 
-    pub fn save_selection_to_clipboard(&mut self, piece_table: &PieceTable) {
-        let start = min(self.position, self.anchor);
-        let end = max(self.position, self.anchor);
-        let size = min(end - start + 1, MAX_CURSOR_CLIPBOARD_SIZE);
-
-        for (i, c) in piece_table.iter_chars_at(start).enumerate().take(size) {
-            self.clipboard[i] = c;
-        }
-        self.clipboard_size = size;
-    }
-
     pub fn get_selection(&mut self, piece_table: &PieceTable) -> Vec<u8> {
         let start = min(self.position, self.anchor);
         let end = max(self.position, self.anchor);
@@ -606,42 +546,6 @@ impl Cursor {
         piece_table.iter_chars_at(start).take(size).collect()
     }
 
-    pub fn reset_completion(&mut self, language_server: &mut Option<Rc<RefCell<LanguageServer>>>) {
-        if let Some(server) = &language_server {
-            if let Some(request) = self.completion_request {
-                server.borrow_mut().saved_completions.remove(&request.id);
-            }
-        }
-        self.completion_request = None;
-    }
-
-    pub fn reset_completion_view(
-        &mut self,
-        language_server: &mut Option<Rc<RefCell<LanguageServer>>>,
-    ) {
-        if let Some(server) = &language_server {
-            if let Some(ref mut request) = self.completion_request {
-                request.selection_index = 0;
-                request.selection_view_offset = 0;
-            }
-        }
-    }
-
-    pub fn reset_signature_help(
-        &mut self,
-        language_server: &mut Option<Rc<RefCell<LanguageServer>>>,
-    ) {
-        if let Some(server) = &language_server {
-            if let Some(request) = self.signature_help_request {
-                server
-                    .borrow_mut()
-                    .saved_signature_helps
-                    .remove(&request.id);
-            }
-        }
-        self.signature_help_request = None;
-    }
-
     pub fn reset_anchor(&mut self) {
         self.anchor = self.position;
     }
@@ -0,0 +1,13 @@
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![feature(drain_filter)]
+#![feature(pattern)]
+
+// Editing logic that doesn't depend on windowing, rendering or language servers,
+// so that it can be tested headlessly and reused by other frontends
+pub mod command;
+pub mod cursor;
+pub mod log;
+pub mod motion;
+pub mod piece_table;
+pub mod text_utils;
@@ -0,0 +1,101 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const MAX_LOG_MESSAGES: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct LogMessage {
+    pub level: LogLevel,
+    pub message: String,
+    pub time: Instant,
+    // Warnings, errors and notifications are also shown as toasts for a while
+    pub toast: bool,
+}
+
+// Global so that background threads (language servers, syntect) can report failures too
+static MESSAGES: Mutex<Vec<LogMessage>> = Mutex::new(Vec::new());
+
+pub fn log(level: LogLevel, message: impl Into<String>) {
+    push(level, message.into(), level >= LogLevel::Warning);
+}
+
+fn push(level: LogLevel, message: String, toast: bool) {
+    if let Ok(mut messages) = MESSAGES.lock() {
+        if messages.len() >= MAX_LOG_MESSAGES {
+            messages.remove(0);
+        }
+        messages.push(LogMessage {
+            level,
+            message,
+            time: Instant::now(),
+            toast,
+        });
+    }
+}
+
+// Information the user asked for or should notice, like a finished save
+pub fn notify(message: impl Into<String>) {
+    push(LogLevel::Info, message.into(), true);
+}
+
+pub fn info(message: impl Into<String>) {
+    log(LogLevel::Info, message);
+}
+
+pub fn warning(message: impl Into<String>) {
+    log(LogLevel::Warning, message);
+}
+
+pub fn error(message: impl Into<String>) {
+    log(LogLevel::Error, message);
+}
+
+pub fn messages(min_level: LogLevel) -> Vec<LogMessage> {
+    MESSAGES
+        .lock()
+        .map(|messages| {
+            messages
+                .iter()
+                .filter(|message| message.level >= min_level)
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// The most recent toasts that were logged within the duration, oldest first
+pub fn toasts(duration: Duration, max_toasts: usize) -> Vec<LogMessage> {
+    MESSAGES
+        .lock()
+        .map(|messages| {
+            let mut toasts: Vec<LogMessage> = messages
+                .iter()
+                .rev()
+                .take_while(|message| message.time.elapsed() < duration)
+                .filter(|message| message.toast)
+                .take(max_toasts)
+                .cloned()
+                .collect();
+            toasts.reverse();
+            toasts
+        })
+        .unwrap_or_default()
+}
+
+pub fn level_from_str(level: &str) -> Option<LogLevel> {
+    match level {
+        "" | "info" => Some(LogLevel::Info),
+        "warning" | "warn" => Some(LogLevel::Warning),
+        "error" => Some(LogLevel::Error),
+        _ => None,
+    }
+}
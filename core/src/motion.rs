@@ -0,0 +1,165 @@
+use std::cmp::max;
+
+use bstr::ByteSlice;
+use CursorMotion::*;
+
+use crate::{
+    command::BufferMode::{self, *},
+    cursor::Cursor,
+    piece_table::PieceTable,
+};
+
+#[derive(Clone, Copy)]
+pub enum CursorMotion<'a> {
+    Forward(usize),
+    Backward(usize),
+    BackwardOnceWrapping,
+    Up(usize),
+    Down(usize),
+    ForwardByWord,
+    BackwardByWord,
+    ToStartOfLine,
+    ToEndOfLine,
+    ToStartOfFile,
+    ToEndOfFile,
+    ToFirstNonBlankChar,
+    ToEndOfWord,
+    ToNextParagraph,
+    ToPreviousParagraph,
+    ForwardToChar(u8),
+    BackwardToChar(u8),
+    ForwardUntilChar(u8),
+    BackwardUntilChar(u8),
+    ExtendSelection,
+    ExtendSelectionInside(u8),
+    GotoLine(usize),
+    SeekUntil(&'a [u8]),
+    SeekBackUntil(&'a [u8]),
+    SeekToSelf(&'a [u8]),
+    SeekBackToSelf(&'a [u8]),
+}
+
+pub fn apply_motion(
+    piece_table: &PieceTable,
+    cursors: &mut [Cursor],
+    mode: BufferMode,
+    motion: CursorMotion,
+) {
+    for cursor in cursors.iter_mut() {
+        match motion {
+            Forward(count) => cursor.move_forward(piece_table, count),
+            Backward(count) => cursor.move_backward(piece_table, count),
+            BackwardOnceWrapping => cursor.move_backward_once_wrapping(piece_table),
+            Up(count) => cursor.move_up(piece_table, count),
+            Down(count) => cursor.move_down(piece_table, count),
+            ForwardByWord => cursor.move_forward_by_word(piece_table),
+            BackwardByWord => cursor.move_backward_by_word(piece_table),
+            ToStartOfLine => cursor.move_to_start_of_line(piece_table),
+            ToEndOfLine => cursor.move_to_end_of_line(piece_table),
+            ToStartOfFile => cursor.move_to_start_of_file(),
+            ToEndOfFile => cursor.move_to_end_of_file(piece_table),
+            ToFirstNonBlankChar => cursor.move_to_first_non_blank_char(piece_table),
+            ToEndOfWord => cursor.move_to_end_of_word(piece_table),
+            ToNextParagraph => cursor.move_to_next_paragraph(piece_table),
+            ToPreviousParagraph => cursor.move_to_previous_paragraph(piece_table),
+            ForwardToChar(c) => cursor.move_to_char(piece_table, c),
+            BackwardToChar(c) => cursor.move_back_to_char(piece_table, c),
+            ForwardUntilChar(c) => cursor.move_until_char(piece_table, c),
+            BackwardUntilChar(c) => cursor.move_back_until_char(piece_table, c),
+            ExtendSelection => cursor.extend_selection(piece_table),
+            ExtendSelectionInside(c) => cursor.extend_selection_inside(piece_table, c),
+            GotoLine(n) => cursor.goto_line(piece_table, n),
+            SeekUntil(text) => cursor.seek(piece_table, text.as_bytes(), false),
+            SeekBackUntil(text) => cursor.seek_back(piece_table, text.as_bytes(), false),
+            SeekToSelf(text) => cursor.seek(piece_table, text.as_bytes(), true),
+            SeekBackToSelf(text) => cursor.seek_back(piece_table, text.as_bytes(), true),
+        }
+
+        // Normal mode does not allow cursors to be on newlines
+        if mode == Normal && cursor.at_line_end(piece_table) {
+            cursor.move_backward(piece_table, 1);
+        }
+
+        // Cache the column position of the cursor when moving up or down
+        match motion {
+            Up(_) | Down(_) => cursor.stick_col(piece_table),
+            _ => cursor.unstick_col(piece_table),
+        }
+    }
+
+    if mode == Insert || mode == Normal {
+        for cursor in cursors.iter_mut() {
+            cursor.reset_anchor();
+        }
+    }
+}
+
+// Moves the selections of an operator command like d3w over its motion, count times. cw on a word
+// changes until the end of the word rather than the start of the next one
+pub fn apply_operator_motion(
+    piece_table: &PieceTable,
+    cursors: &mut [Cursor],
+    mode: BufferMode,
+    operator: u8,
+    count: usize,
+    motion: &str,
+    change_word: bool,
+) {
+    let mut apply = |cursor_motion| apply_motion(piece_table, cursors, mode, cursor_motion);
+    let c = motion.as_bytes().get(1).copied().unwrap_or(0);
+    match motion {
+        _ if change_word => {
+            for _ in 1..count {
+                apply(ForwardByWord);
+            }
+            apply(ToEndOfWord);
+        }
+        _ if motion.as_bytes() == [operator] => {
+            for _ in 1..count {
+                apply(Down(1));
+            }
+        }
+        "h" => apply(Backward(count)),
+        "l" => apply(Forward(count)),
+        "0" => apply(ToStartOfLine),
+        "$" => apply(ToEndOfLine),
+        "^" => apply(ToFirstNonBlankChar),
+        "G" => apply(ToEndOfFile),
+        "gg" => apply(ToStartOfFile),
+        _ if motion.starts_with('i') => apply(ExtendSelectionInside(c)),
+        _ => {
+            for _ in 0..count {
+                match motion.as_bytes()[0] {
+                    b'j' => apply(Down(1)),
+                    b'k' => apply(Up(1)),
+                    b'w' => apply(ForwardByWord),
+                    b'b' => apply(BackwardByWord),
+                    b'}' => apply(ToNextParagraph),
+                    b'{' => apply(ToPreviousParagraph),
+                    b'f' => apply(ForwardToChar(c)),
+                    b'F' => apply(BackwardToChar(c)),
+                    b't' => apply(ForwardUntilChar(c)),
+                    b'T' => apply(BackwardUntilChar(c)),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+// Exclusive motions like w and $ leave out the character the selections end on
+pub fn exclude_selection_ends(piece_table: &PieceTable, cursors: &mut [Cursor]) {
+    for cursor in cursors.iter_mut() {
+        if cursor.position > cursor.anchor {
+            // Ending at the start of a later line stops at the end of the previous one
+            if piece_table.col_index(cursor.position) == 0
+                && piece_table.line_index(cursor.position) > piece_table.line_index(cursor.anchor)
+            {
+                cursor.position -= 1;
+            }
+            cursor.position = max(cursor.position.saturating_sub(1), cursor.anchor);
+        } else if cursor.position < cursor.anchor {
+            cursor.anchor -= 1;
+        }
+    }
+}
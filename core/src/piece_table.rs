@@ -1,17 +1,30 @@
 use std::{
-    cmp::min,
-    fs::File,
-    io::{BufReader, Read, Write},
+    cmp::{max, min},
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, Read, Write},
+    ops::Range,
 };
 
 use bstr::{ByteSlice, ByteVec};
 
+use crate::{log, text_utils};
+
+// Fragmented piece lists are compacted once they grow beyond this many pieces, see compact
+const COMPACTION_THRESHOLD: usize = 1024;
+// Runs of pieces shorter than this are copied into a single new piece when compacting
+const SMALL_PIECE_LENGTH: usize = 256;
+
 pub struct PieceTable {
     pub pieces: Vec<Piece>,
     pub indent_width: usize,
     pub dirty: bool,
     original: Vec<u8>,
     add: Vec<u8>,
+    // How many lines there are of each length, kept up to date by every edit so that the longest
+    // line is known without going through the text
+    line_lengths: BTreeMap<usize, usize>,
+    compaction_threshold: usize,
 }
 
 #[derive(Debug)]
@@ -22,6 +35,17 @@ pub struct Line {
     pub length: usize,
 }
 
+// What saving would change in the file on disk
+#[derive(Debug, Default)]
+pub struct DiskChanges {
+    pub lines_modified: usize,
+    pub lines_added: usize,
+    pub lines_removed: usize,
+    pub whitespace_lines: usize,
+    pub line_ending_conversions: usize,
+    pub tab_conversions: usize,
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 enum PieceFile {
     Original,
@@ -36,11 +60,34 @@ pub struct Piece {
     linebreaks: Vec<usize>,
 }
 
+// A change of the text, applying it returns the change that reverts it. Deleted text is kept as the
+// pieces that held it, so the size of a change doesn't depend on the size of the file
+#[derive(Debug, Clone)]
+pub enum Delta {
+    Insert { position: usize, pieces: Vec<Piece> },
+    Delete { start: usize, end: usize },
+}
+
 impl PieceTable {
     pub fn from_file(path: &str) -> Self {
         let t = std::time::Instant::now();
+        let file = File::open(path);
+        if let Err(error) = &file {
+            log::error(format!("Failed to open {}: {}", path, error));
+        }
+        Self::from_byte_stream(
+            file.into_iter()
+                .flat_map(|file| BufReader::new(file).bytes()),
+        )
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self::from_byte_stream(bytes.iter().map(|byte| Ok(*byte)))
+    }
+
+    fn from_byte_stream(bytes: impl Iterator<Item = io::Result<u8>>) -> Self {
         let mut original = vec![];
-        let mut bytes = BufReader::new(File::open(path).unwrap()).bytes().peekable();
+        let mut bytes = bytes.peekable();
         let mut linebreaks = vec![];
         let mut index = 0;
 
@@ -124,6 +171,12 @@ impl PieceTable {
         };
 
         let file_length = original.len();
+        let mut line_lengths = BTreeMap::new();
+        let mut line_start = 0;
+        for line_end in linebreaks.iter().copied().chain([file_length]) {
+            *line_lengths.entry(line_end - line_start).or_default() += 1;
+            line_start = line_end + 1;
+        }
         Self {
             original,
             add: vec![],
@@ -135,11 +188,19 @@ impl PieceTable {
                 linebreaks,
             }],
             indent_width,
+            line_lengths,
+            compaction_threshold: COMPACTION_THRESHOLD,
         }
     }
 
-    pub fn save_to(&mut self, path: &str) {
-        let mut file = File::create(path).unwrap();
+    pub fn save_to(&mut self, path: &str) -> bool {
+        let mut file = match File::create(path) {
+            Ok(file) => file,
+            Err(error) => {
+                log::error(format!("Failed to save {}: {}", path, error));
+                return false;
+            }
+        };
 
         for piece in self.pieces.iter() {
             let buffer = if piece.file == PieceFile::Original {
@@ -147,11 +208,59 @@ impl PieceTable {
             } else {
                 &self.add
             };
-            file.write_all(&buffer[piece.start..piece.start + piece.length])
-                .unwrap();
+            if let Err(error) = file.write_all(&buffer[piece.start..piece.start + piece.length]) {
+                log::error(format!("Failed to save {}: {}", path, error));
+                return false;
+            }
         }
 
+        log::info(format!("Saved {}", path));
         self.dirty = false;
+        true
+    }
+
+    // The disk contents are normalized like a loaded file, line endings and tabs are converted when
+    // the file is read, so every '\r' and '\t' on disk is counted as a conversion instead
+    pub fn disk_changes(&self, path: &str) -> Option<DiskChanges> {
+        let on_disk = fs::read(path).ok()?;
+        let line_ending_conversions = on_disk.iter().filter(|&c| *c == b'\r').count();
+        let tab_conversions = on_disk.iter().filter(|&c| *c == b'\t').count();
+        let old_text: Vec<u8> = Self::from_bytes(&on_disk).iter_chars().collect();
+        let new_text: Vec<u8> = self.iter_chars().collect();
+
+        let old_lines: Vec<&[u8]> = old_text.split(|c| *c == b'\n').collect();
+        let new_lines: Vec<&[u8]> = new_text.split(|c| *c == b'\n').collect();
+        let prefix = old_lines
+            .iter()
+            .zip(&new_lines)
+            .take_while(|(old, new)| old == new)
+            .count();
+        let suffix = old_lines[prefix..]
+            .iter()
+            .rev()
+            .zip(new_lines[prefix..].iter().rev())
+            .take_while(|(old, new)| old == new)
+            .count();
+        let old_lines = &old_lines[prefix..old_lines.len() - suffix];
+        let new_lines = &new_lines[prefix..new_lines.len() - suffix];
+
+        let mut changes = DiskChanges {
+            lines_added: new_lines.len().saturating_sub(old_lines.len()),
+            lines_removed: old_lines.len().saturating_sub(new_lines.len()),
+            line_ending_conversions,
+            tab_conversions,
+            ..Default::default()
+        };
+        for (old, new) in old_lines.iter().zip(new_lines) {
+            if old == new {
+                continue;
+            } else if old.trim_end() == new.trim_end() {
+                changes.whitespace_lines += 1;
+            } else {
+                changes.lines_modified += 1;
+            }
+        }
+        Some(changes)
     }
 
     pub fn iter_lines<F>(&self, start: usize, end: usize, mut f: F)
@@ -268,16 +377,25 @@ impl PieceTable {
     }
 
     pub fn insert(&mut self, position: usize, bytes: &[u8]) {
+        self.insert_indexed(position, bytes, linebreaks(bytes));
+    }
+
+    // Inserts text whose linebreaks were already found, e.g. on another thread
+    pub fn insert_indexed(&mut self, position: usize, bytes: &[u8], linebreaks: Vec<usize>) {
+        let old_line_lengths = self.line_lengths_around(position, position);
+        self.insert_piece(position, bytes, linebreaks);
+        self.replace_line_lengths(
+            old_line_lengths,
+            self.line_lengths_around(position, position + bytes.len()),
+        );
+    }
+
+    fn insert_piece(&mut self, position: usize, bytes: &[u8], linebreaks: Vec<usize>) {
         let piece = Piece {
             file: PieceFile::Add,
             start: self.add.len(),
             length: bytes.len(),
-            linebreaks: bytes
-                .iter()
-                .enumerate()
-                .filter(|(i, &c)| c == b'\n')
-                .map(|(i, c)| i)
-                .collect(),
+            linebreaks,
         };
         self.add.push_str(bytes);
 
@@ -329,6 +447,12 @@ impl PieceTable {
     }
 
     pub fn delete(&mut self, start: usize, end: usize) {
+        let old_line_lengths = self.line_lengths_around(start, end);
+        self.delete_pieces(start, end);
+        self.replace_line_lengths(old_line_lengths, self.line_lengths_around(start, start));
+    }
+
+    fn delete_pieces(&mut self, start: usize, end: usize) {
         let mut current_position = 0;
         for i in 0..self.pieces.len() {
             let next_position = current_position + self.pieces[i].length;
@@ -387,6 +511,204 @@ impl PieceTable {
         self.dirty = true;
     }
 
+    pub fn apply(&mut self, delta: Delta) -> Delta {
+        match delta {
+            Delta::Insert { position, pieces } => {
+                let length = pieces.iter().map(|piece| piece.length).sum::<usize>();
+                let old_line_lengths = self.line_lengths_around(position, position);
+                let i = self.split_at(position);
+                self.pieces.splice(i..i, pieces);
+                self.replace_line_lengths(
+                    old_line_lengths,
+                    self.line_lengths_around(position, position + length),
+                );
+                self.dirty = true;
+                Delta::Delete {
+                    start: position,
+                    end: position + length,
+                }
+            }
+            Delta::Delete { start, end } => {
+                let pieces = self.pieces_between(start, end);
+                self.delete(start, end);
+                Delta::Insert {
+                    position: start,
+                    pieces,
+                }
+            }
+        }
+    }
+
+    // The pieces holding start..end, the pieces at either end cut to the range
+    pub fn pieces_between(&self, start: usize, end: usize) -> Vec<Piece> {
+        let mut pieces = vec![];
+        let mut offset = 0;
+        for piece in &self.pieces {
+            if offset >= end {
+                break;
+            }
+            let first = start.saturating_sub(offset);
+            let last = min(end - offset, piece.length);
+            if first < last {
+                pieces.push(Piece {
+                    file: piece.file,
+                    start: piece.start + first,
+                    length: last - first,
+                    linebreaks: piece
+                        .linebreaks
+                        .iter()
+                        .filter(|i| (first..last).contains(i))
+                        .map(|i| i - first)
+                        .collect(),
+                });
+            }
+            offset += piece.length;
+        }
+        pieces
+    }
+
+    // Splits the piece containing the position so that a piece starts there, returns its index
+    fn split_at(&mut self, position: usize) -> usize {
+        let mut offset = 0;
+        for i in 0..self.pieces.len() {
+            let length = self.pieces[i].length;
+            if position == offset {
+                return i;
+            }
+            if position < offset + length {
+                let split = position - offset;
+                let piece = &mut self.pieces[i];
+                let last_piece = Piece {
+                    file: piece.file,
+                    start: piece.start + split,
+                    length: length - split,
+                    linebreaks: piece
+                        .linebreaks
+                        .iter()
+                        .filter(|i| **i >= split)
+                        .map(|i| i - split)
+                        .collect(),
+                };
+                piece.length = split;
+                piece.linebreaks.retain(|i| *i < split);
+                self.pieces.insert(i + 1, last_piece);
+                return i + 1;
+            }
+            offset += length;
+        }
+        self.pieces.len()
+    }
+
+    // In bytes, without the linebreak
+    pub fn longest_line(&self) -> usize {
+        self.line_lengths
+            .last_key_value()
+            .map_or(0, |(length, _)| *length)
+    }
+
+    // The lengths of every line from the one containing start to the one containing end
+    fn line_lengths_around(&self, start: usize, end: usize) -> Vec<usize> {
+        let mut lengths = vec![];
+        let mut line_start = 0;
+        let mut offset = 0;
+        for piece in &self.pieces {
+            if offset + piece.length <= start {
+                if let Some(linebreak) = piece.linebreaks.last() {
+                    line_start = offset + linebreak + 1;
+                }
+                offset += piece.length;
+                continue;
+            }
+            for linebreak in piece.linebreaks.iter().map(|i| offset + i) {
+                if linebreak < start {
+                    line_start = linebreak + 1;
+                    continue;
+                }
+                lengths.push(linebreak - line_start);
+                if linebreak >= end {
+                    return lengths;
+                }
+                line_start = linebreak + 1;
+            }
+            offset += piece.length;
+        }
+        lengths.push(offset - line_start);
+        lengths
+    }
+
+    fn replace_line_lengths(&mut self, old: Vec<usize>, new: Vec<usize>) {
+        for length in old {
+            if let Some(count) = self.line_lengths.get_mut(&length) {
+                *count -= 1;
+                if *count == 0 {
+                    self.line_lengths.remove(&length);
+                }
+            }
+        }
+        for length in new {
+            *self.line_lengths.entry(length).or_default() += 1;
+        }
+    }
+
+    pub fn needs_compaction(&self) -> bool {
+        self.pieces.len() > self.compaction_threshold
+    }
+
+    // Merges pieces that are adjacent in the same buffer and copies runs of small pieces into
+    // a single new piece. The original and add buffers are only ever appended to, so the pieces
+    // stored in the undo and redo stacks stay valid.
+    pub fn compact(&mut self) {
+        let fragmented_pieces = std::mem::take(&mut self.pieces);
+        let mut small_pieces = vec![];
+        for piece in fragmented_pieces {
+            if piece.length < SMALL_PIECE_LENGTH {
+                push_merged(&mut small_pieces, piece);
+                continue;
+            }
+
+            self.push_small_pieces(&mut small_pieces);
+            push_merged(&mut self.pieces, piece);
+        }
+        self.push_small_pieces(&mut small_pieces);
+
+        // Pieces that can't be merged or copied stay, the next pass waits until there are twice as
+        // many so that the add buffer doesn't grow with passes that change nothing
+        self.compaction_threshold = max(COMPACTION_THRESHOLD, self.pieces.len() * 2);
+    }
+
+    // A small piece on its own is kept, copying it wouldn't lower the number of pieces
+    fn push_small_pieces(&mut self, pieces: &mut Vec<Piece>) {
+        if pieces.len() < 2 {
+            for piece in pieces.drain(..) {
+                push_merged(&mut self.pieces, piece);
+            }
+            return;
+        }
+
+        let mut bytes = vec![];
+        for piece in pieces.drain(..) {
+            let buffer = if piece.file == PieceFile::Original {
+                &self.original
+            } else {
+                &self.add
+            };
+            bytes.extend_from_slice(&buffer[piece.start..piece.start + piece.length]);
+        }
+        let piece = Piece {
+            file: PieceFile::Add,
+            start: self.add.len(),
+            length: bytes.len(),
+            linebreaks: bytes
+                .iter()
+                .enumerate()
+                .filter(|(_, &c)| c == b'\n')
+                .map(|(i, _)| i)
+                .collect(),
+        };
+        self.add.append(&mut bytes);
+        push_merged(&mut self.pieces, piece);
+    }
+
     pub fn line_at_index(&self, index: usize) -> Option<Line> {
         let mut start = 0;
         let mut offset = 0;
@@ -474,6 +796,16 @@ impl PieceTable {
         false
     }
 
+    pub fn line_is_blank(&self, index: usize) -> bool {
+        if let Some(line) = self.line_at_index(index) {
+            return self
+                .iter_chars_at(line.start)
+                .take(line.length)
+                .all(|c| c.is_ascii_whitespace());
+        }
+        true
+    }
+
     pub fn char_index_from_line_col(&self, line: usize, col: usize) -> Option<usize> {
         if let Some(line) = self.line_at_index(line) {
             return Some(line.start + min(col, line.length));
@@ -481,6 +813,63 @@ impl PieceTable {
         None
     }
 
+    pub fn utf16_col(&self, line: usize, col: usize) -> usize {
+        if let Some(line) = self.line_at_index(line) {
+            let bytes: Vec<u8> = self
+                .iter_chars_at(line.start)
+                .take(min(col, line.length))
+                .collect();
+            return bytes.chars().map(char::len_utf16).sum();
+        }
+        col
+    }
+
+    pub fn col_from_utf16(&self, line: usize, utf16_col: usize) -> usize {
+        if let Some(line) = self.line_at_index(line) {
+            let bytes: Vec<u8> = self.iter_chars_at(line.start).take(line.length).collect();
+            let mut units = 0;
+            for (start, _, c) in bytes.char_indices() {
+                if units >= utf16_col {
+                    return start;
+                }
+                units += c.len_utf16();
+            }
+            return line.length;
+        }
+        utf16_col
+    }
+
+    // The cells the bytes start_col..end_col of a line are drawn in, see text_utils::display_text
+    pub fn display_cols(&self, line: usize, start_col: usize, end_col: usize) -> Range<usize> {
+        let bytes: Vec<u8> = self.line_at_index(line).map_or(vec![], |line| {
+            self.iter_chars_at(line.start).take(line.length).collect()
+        });
+        text_utils::display_range(&text_utils::display_text(&bytes).1, start_col, end_col)
+    }
+
+    pub fn display_col(&self, line: usize, col: usize) -> usize {
+        self.display_cols(line, col, col).start
+    }
+
+    // The first byte of the character drawn in the cell, the inverse of display_col
+    pub fn col_from_display(&self, line: usize, display_col: usize) -> usize {
+        let bytes: Vec<u8> = self.line_at_index(line).map_or(vec![], |line| {
+            self.iter_chars_at(line.start).take(line.length).collect()
+        });
+        let positions = text_utils::display_text(&bytes).1;
+        match positions
+            .iter()
+            .rposition(|position| *position <= display_col)
+        {
+            Some(col) if col == bytes.len() => col + display_col - positions[col],
+            Some(col) => positions
+                .iter()
+                .position(|p| *p == positions[col])
+                .unwrap_or(col),
+            None => 0,
+        }
+    }
+
     pub fn col_index(&self, position: usize) -> usize {
         self.iter_chars_at_rev(position.saturating_sub(1))
             .position(|c| c == b'\n')
@@ -572,3 +961,25 @@ impl<'a> Iterator for PieceTableCharReverseIterator<'a> {
             })
     }
 }
+
+fn push_merged(pieces: &mut Vec<Piece>, piece: Piece) {
+    if let Some(last) = pieces.last_mut() {
+        if last.file == piece.file && last.start + last.length == piece.start {
+            let offset = last.length;
+            last.linebreaks
+                .extend(piece.linebreaks.iter().map(|linebreak| linebreak + offset));
+            last.length += piece.length;
+            return;
+        }
+    }
+    pieces.push(piece);
+}
+
+pub fn linebreaks(bytes: &[u8]) -> Vec<usize> {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, &c)| c == b'\n')
+        .map(|(i, _)| i)
+        .collect()
+}
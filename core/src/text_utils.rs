@@ -0,0 +1,447 @@
+use std::{
+    cmp::{max, min},
+    ops::Range,
+};
+
+use bstr::ByteSlice;
+
+const UNMATCHED_LETTER_PENALTY: isize = -1;
+const LEADING_LETTER_PENALTY: isize = -5;
+const MAX_LEADING_LETTER_PENALTY: isize = -15;
+const ADJACENCY_BONUS: isize = 15;
+const FIRST_LETTER_BONUS: isize = 15;
+const UPPERCASE_START_BONUS: isize = 30;
+const SEPARATOR_BONUS: isize = 30;
+
+fn match_score(chars_since_match: usize, c: u8, prev_c: Option<u8>) -> isize {
+    match (prev_c, c) {
+        (Some(prev_c), c) => {
+            ADJACENCY_BONUS * (chars_since_match == 0) as isize
+                + UPPERCASE_START_BONUS
+                    * (c.is_ascii_uppercase() && prev_c.is_ascii_lowercase()) as isize
+                + SEPARATOR_BONUS
+                    * (c.is_ascii_alphanumeric() && !prev_c.is_ascii_alphanumeric()) as isize
+        }
+        (None, c) => FIRST_LETTER_BONUS * (chars_since_match == 0) as isize,
+    }
+}
+
+fn match_recursively(pattern: &[u8], text: &[u8], prev_c: Option<u8>, score: isize) -> isize {
+    if pattern.is_empty() {
+        return score;
+    }
+
+    let mut sub_string = text;
+    let mut best_score = isize::MIN;
+
+    let mut chars_since_match = 0;
+    while let Some(i) = sub_string
+        .iter()
+        .position(|&c| c.to_ascii_lowercase() == pattern[0].to_ascii_lowercase())
+    {
+        chars_since_match += i;
+        let sub_score = match_recursively(
+            &pattern[1..],
+            &sub_string[i + 1..],
+            Some(sub_string[i]),
+            match_score(
+                chars_since_match,
+                sub_string[i],
+                if chars_since_match == 0 || prev_c.is_none() {
+                    prev_c
+                } else {
+                    text.get(chars_since_match - 1).copied()
+                },
+            ),
+        );
+        best_score = max(best_score, sub_score);
+        sub_string = &sub_string[i + 1..];
+    }
+
+    if best_score == isize::MIN {
+        isize::MIN
+    } else {
+        score + best_score
+    }
+}
+
+// Based on https://github.com/tajmone/fuzzy-search/blob/master/fts_fuzzy_match/0.2.0/c/fts_fuzzy_match.c
+pub fn fuzzy_match(pattern: &[u8], text: &[u8]) -> isize {
+    let unmatched_penalty = -1;
+    let mut score = 100;
+
+    if pattern.is_empty() {
+        return score;
+    }
+    if text.len() < pattern.len() {
+        return isize::MIN;
+    }
+
+    score += UNMATCHED_LETTER_PENALTY * (text.len() - pattern.len()) as isize;
+
+    for i in 0..pattern.len() {
+        match (text.get(i), pattern.get(i)) {
+            (Some(c1), Some(c2)) if c1 == c2 => (),
+            _ => score += LEADING_LETTER_PENALTY,
+        }
+    }
+
+    match_recursively(pattern, text, None, score)
+}
+
+// Searches in a buffer are smart-case, a pattern without uppercase letters ignores case
+pub fn ignores_case(pattern: &[u8]) -> bool {
+    !pattern.iter().any(u8::is_ascii_uppercase)
+}
+
+pub fn search_char_eq(pattern_char: u8, c: u8, ignore_case: bool) -> bool {
+    pattern_char == c || (ignore_case && pattern_char == c.to_ascii_lowercase())
+}
+
+pub fn search_highlights(text: &[u8], match_text: &str, ignore_case: bool) -> Vec<(usize, usize)> {
+    if match_text.is_empty() {
+        return vec![];
+    }
+
+    // Lowercasing ASCII keeps every byte in place, so the offsets apply to the original text
+    let lowercase_text;
+    let text = if ignore_case {
+        lowercase_text = text.to_ascii_lowercase();
+        &lowercase_text
+    } else {
+        text
+    };
+
+    let mut matches = vec![];
+
+    let mut search_index = 0;
+    let mut current_text = text;
+    while let Some(i) = current_text.as_bstr().find(match_text) {
+        search_index += i;
+
+        // Match here
+        matches.push((search_index, match_text.len()));
+        search_index += match_text.len();
+
+        current_text = &text[search_index..];
+    }
+
+    matches
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CharType {
+    Word,
+    Punctuation,
+    Whitespace,
+}
+
+pub fn char_type(c: u8) -> CharType {
+    match c {
+        c if c.is_ascii_alphanumeric() || c == b'_' => CharType::Word,
+        c if c.is_ascii_whitespace() => CharType::Whitespace,
+        _ => CharType::Punctuation,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdentifierCase {
+    Snake,
+    Camel,
+    Pascal,
+    ScreamingSnake,
+}
+
+// Words are separated by underscores and by uppercase letters that follow a lowercase letter or
+// digit, or that start a capitalized word after an acronym ("parseHTTPRequest" is parse, HTTP,
+// Request). Leading and trailing underscores are kept
+pub fn convert_case(identifier: &[u8], case: IdentifierCase) -> Vec<u8> {
+    let prefix_length = identifier.iter().take_while(|c| **c == b'_').count();
+    if prefix_length == identifier.len() {
+        return identifier.to_vec();
+    }
+    let suffix_length = identifier.iter().rev().take_while(|c| **c == b'_').count();
+    let core = &identifier[prefix_length..identifier.len() - suffix_length];
+
+    let mut words = vec![];
+    let mut start = 0;
+    for i in 1..core.len() {
+        let (previous, c) = (core[i - 1], core[i]);
+        let starts_word = c.is_ascii_uppercase()
+            && (previous.is_ascii_lowercase()
+                || previous.is_ascii_digit()
+                || previous.is_ascii_uppercase()
+                    && core.get(i + 1).is_some_and(u8::is_ascii_lowercase));
+        if c == b'_' || (starts_word && previous != b'_') {
+            words.push(&core[start..i]);
+            start = if c == b'_' { i + 1 } else { i };
+        }
+    }
+    words.push(&core[start..]);
+    words.retain(|word| !word.is_empty());
+
+    let mut converted = identifier[..prefix_length].to_vec();
+    for (i, word) in words.iter().enumerate() {
+        let mut word = match case {
+            IdentifierCase::ScreamingSnake => word.to_ascii_uppercase(),
+            _ => word.to_ascii_lowercase(),
+        };
+        match case {
+            IdentifierCase::Snake | IdentifierCase::ScreamingSnake if i > 0 => converted.push(b'_'),
+            IdentifierCase::Camel if i > 0 => word[0] = word[0].to_ascii_uppercase(),
+            IdentifierCase::Pascal => word[0] = word[0].to_ascii_uppercase(),
+            _ => (),
+        }
+        converted.extend(word);
+    }
+    converted.extend_from_slice(&identifier[identifier.len() - suffix_length..]);
+    converted
+}
+
+// Valid UTF-8 is drawn as it is. Control characters are shown in caret notation like ^M, and
+// bytes that aren't valid UTF-8 and C1 control characters as <0x9b>, so that neither breaks up
+// the layout. Alongside the text comes the position each byte is drawn at, followed by the length
+// of the text, both counted in UTF-16 units like the text layouts of the platforms
+pub fn display_text(text: &[u8]) -> (String, Vec<usize>) {
+    let mut display = String::with_capacity(text.len());
+    let mut positions = Vec::with_capacity(text.len() + 1);
+    let mut length = 0;
+    let mut push = |display: &mut String, s: &str, num_bytes: usize| {
+        positions.resize(positions.len() + num_bytes, length);
+        display.push_str(s);
+        length += s.encode_utf16().count();
+    };
+
+    let mut i = 0;
+    while i < text.len() {
+        let (valid_up_to, num_invalid) = match std::str::from_utf8(&text[i..]) {
+            Ok(valid) => (valid.len(), 0),
+            Err(e) => (
+                e.valid_up_to(),
+                e.error_len().unwrap_or(text.len() - i - e.valid_up_to()),
+            ),
+        };
+        let valid = std::str::from_utf8(&text[i..i + valid_up_to]).unwrap();
+        for c in valid.chars() {
+            match c {
+                '\n' => push(&mut display, "\n", 1),
+                '\u{0}'..='\u{1F}' | '\u{7F}' => {
+                    push(&mut display, &format!("^{}", (c as u8 ^ 0x40) as char), 1)
+                }
+                '\u{80}'..='\u{9F}' => {
+                    push(&mut display, &format!("<0x{:02x}>", c as u32), c.len_utf8())
+                }
+                c => push(&mut display, c.encode_utf8(&mut [0; 4]), c.len_utf8()),
+            }
+        }
+        for c in &text[i + valid_up_to..i + valid_up_to + num_invalid] {
+            push(&mut display, &format!("<0x{:02x}>", c), 1);
+        }
+        i += valid_up_to + num_invalid;
+    }
+
+    positions.push(length);
+    (display, positions)
+}
+
+// The part of the display text covering the bytes start..end, see display_text. A range ending
+// inside a character covers all of it, and bytes past the end, like a line break, take one each
+pub fn display_range(positions: &[usize], start: usize, end: usize) -> Range<usize> {
+    let num_bytes = positions.len() - 1;
+    let position = |i: usize| positions[min(i, num_bytes)] + i.saturating_sub(num_bytes);
+    if end <= start {
+        return position(start)..position(start);
+    }
+    let last = position(end - 1);
+    let end = (end..)
+        .map(position)
+        .find(|p| *p > last)
+        .unwrap_or(last + 1);
+    position(start)..end
+}
+
+// Prefixed with offsets and followed by the printable ASCII characters, like xxd
+pub fn hex_dump(bytes: &[u8]) -> Vec<u8> {
+    let mut dump = vec![];
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (j, c) in chunk.iter().enumerate() {
+            if j % 2 == 0 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x}", c));
+        }
+        // Short lines are padded to the width of eight groups of two bytes
+        let mut line = format!("{:08x}:{:<40}  ", i * 16, hex);
+        line.extend(chunk.iter().map(|c| match c {
+            0x20..=0x7E => *c as char,
+            _ => '.',
+        }));
+        line.push('\n');
+        dump.extend_from_slice(line.as_bytes());
+    }
+    dump
+}
+
+// Heuristic used to warn before showing a binary file as text
+pub fn looks_binary(text: &[u8]) -> bool {
+    let num_control_chars = text
+        .iter()
+        .filter(|c| c.is_ascii_control() && !c.is_ascii_whitespace())
+        .count();
+    text.contains(&0) || num_control_chars * 10 > text.len()
+}
+
+pub fn is_path_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"/\\._-~:+@".contains(&c)
+}
+
+pub fn is_url_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&'*+,;=%".contains(&c)
+}
+
+// Splits the "path:line" and "path:line:col" suffixes printed by compilers and test runners
+pub fn split_location_suffix(text: &str) -> (&str, Option<(usize, usize)>) {
+    if let Some((rest, last)) = text.rsplit_once(':') {
+        if let Ok(last) = last.parse::<usize>() {
+            if let Some((path, line)) = rest.rsplit_once(':') {
+                if let Ok(line) = line.parse::<usize>() {
+                    return (path, Some((line, last)));
+                }
+            }
+            return (rest, Some((last, 1)));
+        }
+    }
+    (text, None)
+}
+
+pub fn is_closing_bracket(c: u8) -> bool {
+    c == b')' || c == b'}' || c == b']' || c == b'>'
+}
+
+pub fn matching_bracket(c: u8) -> u8 {
+    match c {
+        b'(' => b')',
+        b'{' => b'}',
+        b'[' => b']',
+        b'<' => b'>',
+        b')' => b'(',
+        b'}' => b'{',
+        b']' => b'[',
+        b'>' => b'<',
+        _ => panic!(),
+    }
+}
+
+// A subset of the RFC 1345 digraphs used by Vim
+const DIGRAPHS: &[(&[u8; 2], char)] = &[
+    (b"a:", 'ä'),
+    (b"e:", 'ë'),
+    (b"i:", 'ï'),
+    (b"o:", 'ö'),
+    (b"u:", 'ü'),
+    (b"y:", 'ÿ'),
+    (b"A:", 'Ä'),
+    (b"E:", 'Ë'),
+    (b"I:", 'Ï'),
+    (b"O:", 'Ö'),
+    (b"U:", 'Ü'),
+    (b"a'", 'á'),
+    (b"e'", 'é'),
+    (b"i'", 'í'),
+    (b"o'", 'ó'),
+    (b"u'", 'ú'),
+    (b"A'", 'Á'),
+    (b"E'", 'É'),
+    (b"I'", 'Í'),
+    (b"O'", 'Ó'),
+    (b"U'", 'Ú'),
+    (b"a!", 'à'),
+    (b"e!", 'è'),
+    (b"i!", 'ì'),
+    (b"o!", 'ò'),
+    (b"u!", 'ù'),
+    (b"A!", 'À'),
+    (b"E!", 'È'),
+    (b"I!", 'Ì'),
+    (b"O!", 'Ò'),
+    (b"U!", 'Ù'),
+    (b"a>", 'â'),
+    (b"e>", 'ê'),
+    (b"i>", 'î'),
+    (b"o>", 'ô'),
+    (b"u>", 'û'),
+    (b"A>", 'Â'),
+    (b"E>", 'Ê'),
+    (b"I>", 'Î'),
+    (b"O>", 'Ô'),
+    (b"U>", 'Û'),
+    (b"n?", 'ñ'),
+    (b"N?", 'Ñ'),
+    (b"c,", 'ç'),
+    (b"C,", 'Ç'),
+    (b"aa", 'å'),
+    (b"AA", 'Å'),
+    (b"ae", 'æ'),
+    (b"AE", 'Æ'),
+    (b"o/", 'ø'),
+    (b"O/", 'Ø'),
+    (b"ss", 'ß'),
+    (b"Eu", '€'),
+    (b"Pd", '£'),
+    (b"Ye", '¥'),
+    (b"Ct", '¢'),
+    (b"SE", '§'),
+    (b"Co", '©'),
+    (b"Rg", '®'),
+    (b"TM", '™'),
+    (b"DG", '°'),
+    (b"+-", '±'),
+    (b"*X", '×'),
+    (b"-:", '÷'),
+    (b"!=", '≠'),
+    (b"=<", '≤'),
+    (b">=", '≥'),
+    (b"?2", '≈'),
+    (b"00", '∞'),
+    (b"RT", '√'),
+    (b"->", '→'),
+    (b"<-", '←'),
+    (b"-!", '↑'),
+    (b"-v", '↓'),
+    (b"<<", '«'),
+    (b">>", '»'),
+    (b"!I", '¡'),
+    (b"?I", '¿'),
+    (b",.", '…'),
+    (b"-N", '–'),
+    (b"-M", '—'),
+    (b"a*", 'α'),
+    (b"b*", 'β'),
+    (b"g*", 'γ'),
+    (b"d*", 'δ'),
+    (b"e*", 'ε'),
+    (b"l*", 'λ'),
+    (b"m*", 'μ'),
+    (b"p*", 'π'),
+    (b"s*", 'σ'),
+    (b"t*", 'τ'),
+    (b"f*", 'φ'),
+    (b"w*", 'ω'),
+    (b"D*", 'Δ'),
+    (b"S*", 'Σ'),
+    (b"W*", 'Ω'),
+];
+
+pub fn digraph(first: u8, second: u8) -> Option<char> {
+    // Like Vim, the two characters may be typed in either order
+    DIGRAPHS
+        .iter()
+        .find(|(chars, _)| **chars == [first, second])
+        .or_else(|| {
+            DIGRAPHS
+                .iter()
+                .find(|(chars, _)| **chars == [second, first])
+        })
+        .map(|(_, c)| *c)
+}
@@ -1,9 +1,12 @@
 use std::{
     cell::{RefCell, RefMut},
     cmp::{max, min},
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
+    fs,
+    path::Path,
     rc::Rc,
-    str::pattern::Pattern,
+    sync::mpsc::{channel, Receiver},
+    time::{Duration, Instant, SystemTime},
 };
 
 use bstr::ByteSlice;
@@ -18,39 +21,158 @@ use CursorMotion::*;
 use VirtualKeyCode::{Back, Delete, Escape, Left, Return, Right, Slash, Space, Tab, J, K, R};
 
 use crate::{
+    command::{
+        counted_goto, insert_cursor_above, insert_cursor_below, is_operator_command,
+        is_prefix_of_command, split_operator_command, BufferCommand, BufferMode, CutMotion,
+    },
+    completion::{
+        self, completion_documentation, get_filtered_completions, reset_completion,
+        reset_completion_view, reset_signature_help, MAX_SHOWN_DOCUMENTATION_LINES,
+    },
+    config::Settings,
     cursor::{
-        cursors_delete_rebalance, cursors_insert_rebalance, cursors_overlapping,
-        get_filtered_completions, CompletionRequest, Cursor, SignatureHelpRequest,
+        cursors_delete_rebalance, cursors_insert_rebalance, cursors_overlapping, CompletionRequest,
+        Cursor, SignatureHelpRequest,
     },
-    editor::EditorCommand,
+    decoration::{decorations_delete_rebalance, decorations_insert_rebalance, Decoration},
+    editor::{ContextMenuItem, EditorCommand, PathFormat},
+    encryption, export, file_history,
+    git::{self, FileStatus},
+    keymap::{self, KeymapAction, KeymapMatch},
     language_server::LanguageServer,
     language_server_types::{
-        CompletionParams, DefinitionParams, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
-        HoverParams, ImplementationParams, Position, Range, SignatureHelpContext,
-        SignatureHelpParams, TextDocumentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
-        VersionedTextDocumentIdentifier,
+        CodeActionContext, CodeActionParams, CompletionParams, DefinitionParams,
+        DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+        DocumentFormattingParams, DocumentRangeFormattingParams, FormattingOptions, HoverParams,
+        ImplementationParams, Location, Position, Range, ReferenceContext, ReferenceParams,
+        RenameParams, SignatureHelpContext, SignatureHelpParams, TextDocumentChangeEvent,
+        TextDocumentIdentifier, TextDocumentItem, TextEdit, VersionedTextDocumentIdentifier,
     },
-    language_support::{language_from_path, Language},
-    piece_table::{Piece, PieceTable},
+    language_support::{
+        filetype_from_content, language_from_filetype, language_from_path, normalize_filetype,
+        Language, NewlineIndent,
+    },
+    lint::{lint_lines, LintKind},
+    log,
+    motion::{self, CursorMotion},
+    piece_table::{self, Delta, PieceTable},
     platform_resources::PlatformResources,
-    renderer::RenderLayout,
+    renderer::{RenderLayout, TextEffect},
     syntect::{IndexedLine, Syntect, SYNTECT_CACHE_FREQUENCY},
-    text_utils::{self},
+    text_utils::{self, char_type, CharType, IdentifierCase},
     theme::Theme,
-    view::View,
+    thread_pool::{self, Priority},
+    view::{self, DragOrigin, JumpCenter, View},
+    workspace_index::WorkspaceIndex,
 };
 
-#[derive(Copy, Clone, PartialEq)]
-pub enum BufferMode {
-    Normal,
-    Insert,
-    Visual,
-    VisualLine,
+// A pending source.* code action (e.g. source.organizeImports), optionally saving once it's applied
+#[derive(Clone, Copy, Debug)]
+pub struct CodeActionRequest {
+    pub id: i32,
+    pub kind: &'static str,
+    pub save: bool,
+}
+
+// Ctrl+N / Ctrl+P cycle through the words starting with the word before the cursor, like vim
+// the original word comes back after the last candidate
+struct WordCompletion {
+    prefix: Vec<u8>,
+    candidates: Vec<Vec<u8>>,
+    index: usize,
+    position: usize,
+}
+
+impl WordCompletion {
+    fn current(&self) -> &[u8] {
+        self.candidates.get(self.index).unwrap_or(&self.prefix)
+    }
+}
+
+// :s/PATTERN/REPLACEMENT/FLAGS on the current line or :%s on the whole file. The pattern is
+// literal and smart-case, the g flag replaces every match of a line instead of the first
+struct Substitution {
+    whole_file: bool,
+    pattern: Vec<u8>,
+    replacement: Option<Vec<u8>>,
+    global: bool,
+}
+
+// What :s would change on the visible lines, shown while the command is typed. The view it is typed
+// in keeps it until the command, the text, the cursor line or the visible lines change
+pub struct SubstitutionPreview {
+    pub matches: Vec<(usize, usize)>,
+    pub replaced: bool,
+    pub lines: Vec<(usize, String)>,
+    key: (String, i32, usize, usize, usize),
+}
+
+// The occurrences of a symbol in the buffer are edited together while the new name is typed. The
+// typed text is only a preview, Enter reverts it and lets the language server do the rename in every
+// file while Escape just reverts it
+pub struct RenameSession {
+    pub regions: Vec<(usize, usize)>,
+    primary_region: usize,
+    original_name: Vec<u8>,
+    undo_depth: usize,
+}
+
+impl RenameSession {
+    // Text typed at either end of a region belongs to it
+    fn insert_rebalance(&mut self, position: usize, count: usize) {
+        for (start, end) in &mut self.regions {
+            if *start > position {
+                *start += count;
+            }
+            if *end >= position {
+                *end += count;
+            }
+        }
+    }
+
+    fn delete_rebalance(&mut self, position: usize, end: usize) {
+        let rebalance = |p: usize| {
+            if p >= end {
+                p - (end - position)
+            } else {
+                min(p, position)
+            }
+        };
+        for region in &mut self.regions {
+            *region = (rebalance(region.0), rebalance(region.1));
+        }
+    }
+}
+
+// A complete change that "." repeats: the normal mode command that made it, with its count and
+// motion, or the keymap actions its keys are bound to, and what was typed if the command entered
+// insert mode
+#[derive(Clone)]
+struct ChangeRecord {
+    command: String,
+    actions: Option<Vec<KeymapAction>>,
+    insertion: Vec<BufferCommand>,
+}
+
+// Clipboard text inserted over several frames, see start_large_paste
+struct PendingPaste {
+    chunks: Receiver<(Vec<u8>, Vec<usize>)>,
+    position: usize,
+    inserted: usize,
+    size: usize,
+    cursor_position: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CursorRebalance {
+    Insert(usize, usize),
+    Delete(usize, usize),
 }
 
+// The changes made since the state was pushed, undoing applies them from last to first
 #[derive(Clone, Debug)]
 pub struct BufferState {
-    pieces: Vec<Piece>,
+    deltas: Vec<Delta>,
     cursors: Vec<Cursor>,
 }
 
@@ -58,34 +180,124 @@ pub struct Buffer {
     pub path: String,
     pub uri: String,
     pub language: Option<&'static Language>,
+    pub filetype: Option<String>,
     pub piece_table: PieceTable,
-    pub cursors: Vec<Cursor>,
+    // The cursors and the mode belong to the views, an edit made through one view is replayed on
+    // the cursors of the other one when that is synced
+    cursor_rebalances: Vec<CursorRebalance>,
     pub undo_stack: Vec<BufferState>,
     pub redo_stack: Vec<BufferState>,
-    pub mode: BufferMode,
     pub language_server: Option<Rc<RefCell<LanguageServer>>>,
+    utf16_positions: bool,
     pub syntect: Option<Syntect>,
     pub input: String,
-    last_executed_command: Option<String>,
-    insertion_command_stack: Vec<BufferCommand>,
-    insertion_stack_dirty: bool,
+    pub format_request: Option<i32>,
+    pub code_action_request: Option<CodeActionRequest>,
+    hover_request: Option<i32>,
+    references_request: Option<i32>,
+    rename_request: Option<i32>,
+    pub rename_session: Option<RenameSession>,
+    pub save_after_format: bool,
+    pub format_on_save: bool,
+    pub organize_imports_on_save: bool,
+    // What a save changes on disk is logged instead of a plain notification
+    pub save_summary: bool,
+    pub rulers: Vec<usize>,
+    pub word_highlight: bool,
+    pub lint: bool,
+    abbreviations: Vec<(String, String)>,
+    // Where the cursor was when the file was last closed, '" jumps back to it
+    pub last_close_position: Option<(usize, usize)>,
+    // Set with m and a letter, they move with the text around them like cursors
+    marks: Vec<(u8, usize)>,
+    pub highlighted_word: Option<Vec<u8>>,
+    word_under_cursor: Option<(Vec<u8>, Instant)>,
+    last_change: Option<ChangeRecord>,
+    // The change being typed in insert mode, it becomes the last change when leaving insert mode
+    pending_change: Option<ChangeRecord>,
+    repeating_change: bool,
     highlight_queue: VecDeque<usize>,
+    highlight_viewport: Option<(usize, usize)>,
+    highlights_evicted: bool,
     search_string: String,
     search_anchor: usize,
-    version: i32,
-    platform_resources: PlatformResources,
+    pub version: i32,
+    // Headless buffers (scripted tests) have no window and keep the clipboard to themselves
+    platform_resources: Option<PlatformResources>,
+    headless_clipboard: Vec<u8>,
+    pending_paste: Option<PendingPaste>,
+    word_completion: Option<WordCompletion>,
+    keymap_input: String,
+    replaying_keymap: bool,
+    pub decorations: Vec<Decoration>,
+    // Text replaced by pasting over a selection, which leaves the clipboard alone
+    deleted_register: Vec<u8>,
+    // What each cursor copied, by the index of the cursor. Undo leaves them alone, so they aren't
+    // part of the undo states
+    cursor_clipboards: Vec<Vec<u8>>,
+    // Files with a passphrase are encrypted with it on save
+    pub passphrase: Option<String>,
+    // The modification time of the file when it was last read or written by the editor, newer
+    // times mean that another program changed it
+    disk_modified: Option<SystemTime>,
+    // Queried again whenever the file is written or reloaded
+    pub git_status: FileStatus,
 }
 
 impl Buffer {
     pub fn new(
-        window: &Window,
+        window: Option<&Window>,
+        path: &str,
+        theme: &Theme,
+        language_server: Option<Rc<RefCell<LanguageServer>>>,
+    ) -> Self {
+        Self::with_piece_table(
+            window,
+            path,
+            theme,
+            language_server,
+            PieceTable::from_file(path),
+        )
+    }
+
+    // For text that doesn't come straight from the file, like decrypted files
+    pub fn with_piece_table(
+        window: Option<&Window>,
         path: &str,
         theme: &Theme,
         language_server: Option<Rc<RefCell<LanguageServer>>>,
+        piece_table: PieceTable,
     ) -> Self {
         let uri = Url::from_file_path(path).unwrap().to_string();
         let language = language_from_path(path);
-        let piece_table = PieceTable::from_file(path);
+        let head: Vec<u8> = piece_table.iter_chars().take(8192).collect();
+        if text_utils::looks_binary(&head) {
+            log::warning(format!(
+                "{} appears to be a binary file, :hex shows it as a hex dump",
+                path
+            ));
+        }
+
+        // Fall back to sniffing the content when the extension is missing or unknown
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_string());
+        let filetype = if language.is_some() {
+            extension
+        } else {
+            let text: Vec<u8> = piece_table.iter_chars().take(4096).collect();
+            filetype_from_content(&text)
+                .map(|filetype| filetype.to_string())
+                .or(extension)
+        };
+        let language = language.or_else(|| filetype.as_deref().and_then(language_from_filetype));
+        let syntect = filetype
+            .as_deref()
+            .and_then(|filetype| Syntect::new(filetype, theme));
+
+        let git_status = FileStatus::default();
+        git::query_status(path, &git_status);
 
         let mut highlight_queue = VecDeque::new();
         let mut i = 0;
@@ -98,35 +310,401 @@ impl Buffer {
             path: path.to_string(),
             uri,
             language,
+            filetype,
             piece_table,
-            cursors: vec![Cursor::default()],
+            cursor_rebalances: vec![],
             undo_stack: vec![],
             redo_stack: vec![],
-            mode: BufferMode::Normal,
             language_server,
-            syntect: Syntect::new(path, theme),
+            utf16_positions: false,
+            syntect,
             input: String::default(),
-            last_executed_command: None,
-            insertion_command_stack: vec![],
-            insertion_stack_dirty: false,
+            format_request: None,
+            code_action_request: None,
+            hover_request: None,
+            references_request: None,
+            rename_request: None,
+            rename_session: None,
+            save_after_format: false,
+            format_on_save: false,
+            organize_imports_on_save: false,
+            save_summary: false,
+            rulers: vec![],
+            word_highlight: false,
+            lint: true,
+            abbreviations: vec![],
+            last_close_position: None,
+            marks: vec![],
+            highlighted_word: None,
+            word_under_cursor: None,
+            last_change: None,
+            pending_change: None,
+            repeating_change: false,
             highlight_queue,
+            highlight_viewport: None,
+            highlights_evicted: false,
             search_string: String::new(),
             search_anchor: 0,
             version: 1,
-            platform_resources: PlatformResources::new(window),
+            platform_resources: window.map(PlatformResources::new),
+            headless_clipboard: vec![],
+            pending_paste: None,
+            word_completion: None,
+            keymap_input: String::new(),
+            replaying_keymap: false,
+            decorations: vec![],
+            deleted_register: vec![],
+            cursor_clipboards: vec![],
+            passphrase: None,
+            disk_modified: fs::metadata(path).and_then(|m| m.modified()).ok(),
+            git_status,
         }
     }
 
     pub fn syntect_reload(&mut self, theme: &Theme) {
-        self.syntect = Syntect::new(&self.path, theme);
+        self.syntect = self
+            .filetype
+            .as_deref()
+            .and_then(|filetype| Syntect::new(filetype, theme));
+        self.queue_all_highlights();
+    }
+
+    fn queue_all_highlights(&mut self) {
         let mut i = 0;
         while i < self.piece_table.num_lines() {
             self.highlight_queue.push_back(i);
             i += SYNTECT_CACHE_FREQUENCY;
         }
+        self.highlight_viewport = None;
+    }
+
+    pub fn syntect_memory_usage(&self) -> usize {
+        self.syntect.as_ref().map_or(0, Syntect::memory_usage)
+    }
+
+    // Frees the highlights of a buffer that isn't shown, they are recomputed from the start of the
+    // file once it is shown again (see prioritize_highlights)
+    pub fn evict_highlights(&mut self) {
+        if let Some(syntect) = &mut self.syntect {
+            syntect.evict();
+            self.highlight_queue.clear();
+            self.highlights_evicted = true;
+        }
+    }
+
+    pub fn set_filetype(
+        &mut self,
+        filetype: &str,
+        theme: &Theme,
+        language_server: Option<Rc<RefCell<LanguageServer>>>,
+    ) {
+        if let Some(server) = &self.language_server {
+            server.borrow_mut().send_notification(
+                "textDocument/didClose",
+                DidCloseTextDocumentParams {
+                    text_document: TextDocumentIdentifier {
+                        uri: self.uri.clone(),
+                    },
+                },
+            );
+        }
+
+        self.language = language_from_filetype(filetype);
+        self.filetype = Some(filetype.to_string());
+        self.language_server = language_server;
+        self.syntect_reload(theme);
+
+        if let Some(server) = self.language_server.clone() {
+            self.send_did_open(&mut server.borrow_mut());
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        if let Some(indent_width) = settings.indent_width.filter(|width| *width > 0) {
+            self.piece_table.indent_width = indent_width;
+        }
+        self.format_on_save = settings.format_on_save.unwrap_or(false);
+        self.organize_imports_on_save = settings.organize_imports_on_save.unwrap_or(false);
+        self.save_summary = settings.save_summary.unwrap_or(false);
+        self.rulers = settings.rulers.clone().unwrap_or_default();
+        self.word_highlight = settings.word_highlight.unwrap_or(false);
+        self.lint = settings.lint.unwrap_or(true);
+        self.abbreviations = settings.abbreviations.clone().unwrap_or_default();
+    }
+
+    // Other occurrences of the word under the cursor are highlighted once the cursor has rested on
+    // it for WORD_HIGHLIGHT_DELAY. Returns true if the highlighted word changed.
+    pub fn update_word_highlight(&mut self, view: &mut View) -> bool {
+        let word = if self.word_highlight && view.mode == Normal && self.input.is_empty() {
+            self.word_under_cursor(view)
+        } else {
+            None
+        };
+        if self.word_under_cursor.as_ref().map(|(word, _)| word) != word.as_ref() {
+            self.word_under_cursor = word.map(|word| (word, Instant::now()));
+        }
+
+        let highlighted_word = self
+            .word_under_cursor
+            .as_ref()
+            .filter(|(_, since)| since.elapsed() >= WORD_HIGHLIGHT_DELAY)
+            .map(|(word, _)| word.clone());
+        if highlighted_word != self.highlighted_word {
+            self.highlighted_word = highlighted_word;
+            return true;
+        }
+        false
+    }
+
+    fn word_under_cursor(&self, view: &View) -> Option<Vec<u8>> {
+        let position = view.cursors.last()?.position;
+        let (line, col) = (
+            self.piece_table.line_index(position),
+            self.piece_table.col_index(position),
+        );
+        let (start, length) = self.word_at(line, col)?;
+        let start = self.piece_table.char_index_from_line_col(line, start)?;
+        Some(self.piece_table.iter_chars_at(start).take(length).collect())
+    }
+
+    // Organizing imports and formatting run first when enabled, the save then happens once the
+    // language server has answered. Returns whether the file was saved right away, see save_pending
+    pub fn save_with_hooks(&mut self, organize_imports: bool) -> Option<bool> {
+        if organize_imports
+            && self.organize_imports_on_save
+            && self.lsp_code_action("source.organizeImports", true)
+        {
+            return None;
+        }
+        if self.format_on_save && self.lsp_format_document() {
+            self.save_after_format = true;
+            return None;
+        }
+        Some(self.save())
+    }
+
+    pub fn save_pending(&self) -> bool {
+        self.save_after_format || self.code_action_request.is_some_and(|request| request.save)
+    }
+
+    // For a language server that doesn't answer, its late answer is applied but not saved
+    pub fn save_without_hooks(&mut self) -> bool {
+        self.save_after_format = false;
+        if let Some(request) = &mut self.code_action_request {
+            request.save = false;
+        }
+        self.save()
+    }
+
+    pub fn save(&mut self) -> bool {
+        if let Some(passphrase) = self.passphrase.clone() {
+            return self.save_encrypted(&passphrase);
+        }
+        let save_summary = self.save_summary;
+        if save_summary {
+            self.log_disk_changes();
+        }
+        let saved = self.piece_table.save_to(&self.path);
+        if saved {
+            self.disk_modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+            git::query_status(&self.path, &self.git_status);
+            file_history::record(&self.path, self.piece_table.iter_chars().collect());
+        }
+        if saved && !save_summary {
+            log::notify(format!("Saved {}", self.path));
+        }
+        saved
+    }
+
+    // Replaces the text with another version of it as one change that can be undone. Only the part
+    // in between the bytes both versions start and end with is replaced, so cursors and marks in
+    // front of it stay where they are
+    pub fn restore_text(&mut self, view: &mut View, text: &[u8]) {
+        let current: Vec<u8> = self.piece_table.iter_chars().collect();
+        let is_continuation = |c: &u8| (0x80..0xC0).contains(c);
+        let mut prefix = current
+            .iter()
+            .zip(text)
+            .take_while(|(current, text)| current == text)
+            .count();
+        while current.get(prefix).is_some_and(is_continuation) {
+            prefix -= 1;
+        }
+        let mut suffix = current[prefix..]
+            .iter()
+            .rev()
+            .zip(text[prefix..].iter().rev())
+            .take_while(|(current, text)| current == text)
+            .count();
+        while suffix > 0 && is_continuation(&current[current.len() - suffix]) {
+            suffix -= 1;
+        }
+        if current.len() == text.len() && prefix + suffix == current.len() {
+            return;
+        }
+
+        self.switch_to_normal_mode(view);
+        self.push_undo_state(view);
+        let mut content_changes = vec![];
+        if current.len() - suffix > prefix {
+            content_changes.push(self.delete_chars(view, prefix, current.len() - suffix));
+        }
+        if text.len() - suffix > prefix {
+            content_changes.push(self.insert_chars(
+                view,
+                prefix,
+                &text[prefix..text.len() - suffix],
+            ));
+        }
+
+        let last_position = self.piece_table.num_chars().saturating_sub(1);
+        for cursor in &mut view.cursors {
+            cursor.position = min(cursor.position, last_position);
+            cursor.anchor = min(cursor.anchor, last_position);
+        }
+        self.lsp_change(content_changes);
+        self.update_syntect(view, self.piece_table.line_index(prefix));
+    }
+
+    // Returns whether the text was replaced by the file on disk. Unsaved changes are only thrown
+    // away if the user agrees to it, otherwise the next save overwrites the file. Encrypted files
+    // are left alone, reading them needs the passphrase
+    pub fn reload_if_changed(&mut self, view: &mut View, modified: SystemTime) -> bool {
+        if self.passphrase.is_some() || self.disk_modified.is_some_and(|known| modified <= known) {
+            return false;
+        }
+        self.disk_modified = Some(modified);
+
+        if self.piece_table.dirty
+            && !self
+                .platform_resources
+                .as_ref()
+                .is_some_and(|platform_resources| platform_resources.confirm_reload(&self.path))
+        {
+            log::warning(format!(
+                "{} changed on disk, saving will overwrite the changes",
+                self.path
+            ));
+            return false;
+        }
+
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                log::error(format!("Failed to reload {}: {}", self.path, error));
+                return false;
+            }
+        };
+        // Read like a file that is opened, with its line endings and tabs converted
+        let text: Vec<u8> = PieceTable::from_bytes(&bytes).iter_chars().collect();
+        self.restore_text(view, &text);
+        self.piece_table.dirty = false;
+        git::query_status(&self.path, &self.git_status);
+        log::notify(format!("Reloaded {}, it was changed on disk", self.path));
+        true
+    }
+
+    // The summary of changes is skipped, it would have to compare against the ciphertext
+    fn save_encrypted(&mut self, passphrase: &str) -> bool {
+        let text: Vec<u8> = self.piece_table.iter_chars().collect();
+        let Some(data) = encryption::encrypt(&text, passphrase) else {
+            log::error(format!("Failed to encrypt {}", self.path));
+            return false;
+        };
+        // Written next to the file first, a failed write would otherwise leave a file that can't be
+        // decrypted anymore
+        let temporary_path = format!("{}.nimble-tmp", self.path);
+        if let Err(error) =
+            fs::write(&temporary_path, data).and_then(|_| fs::rename(&temporary_path, &self.path))
+        {
+            let _ = fs::remove_file(&temporary_path);
+            log::error(format!("Failed to save {}: {}", self.path, error));
+            return false;
+        }
+
+        log::notify(format!("Saved {} (encrypted)", self.path));
+        self.piece_table.dirty = false;
+        true
+    }
+
+    fn log_disk_changes(&self) {
+        let Some(changes) = self.piece_table.disk_changes(&self.path) else {
+            return;
+        };
+
+        let summary: Vec<String> = [
+            (changes.lines_modified, "modified"),
+            (changes.lines_added, "added"),
+            (changes.lines_removed, "removed"),
+            (
+                changes.whitespace_lines,
+                "changed only in trailing whitespace",
+            ),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, kind)| {
+            format!(
+                "{} line{} {}",
+                count,
+                if *count == 1 { "" } else { "s" },
+                kind
+            )
+        })
+        .chain((changes.line_ending_conversions > 0).then(|| {
+            format!(
+                "{} line ending{} converted to LF",
+                changes.line_ending_conversions,
+                if changes.line_ending_conversions == 1 {
+                    ""
+                } else {
+                    "s"
+                }
+            )
+        }))
+        .chain((changes.tab_conversions > 0).then(|| {
+            format!(
+                "{} tab{} converted to spaces",
+                changes.tab_conversions,
+                if changes.tab_conversions == 1 {
+                    ""
+                } else {
+                    "s"
+                }
+            )
+        }))
+        .collect();
+
+        if summary.is_empty() {
+            log::notify(format!("Saving {}: no changes on disk", self.path));
+        } else {
+            log::notify(format!("Saving {}: {}", self.path, summary.join(", ")));
+        }
     }
 
-    pub fn send_did_open(&self, server: &mut RefMut<LanguageServer>) {
+    // The document is reopened under its new uri after the file was moved on disk
+    pub fn set_path(&mut self, path: &str) {
+        if let Some(server) = &self.language_server {
+            server.borrow_mut().send_notification(
+                "textDocument/didClose",
+                DidCloseTextDocumentParams {
+                    text_document: TextDocumentIdentifier {
+                        uri: self.uri.clone(),
+                    },
+                },
+            );
+        }
+
+        self.path = path.to_string();
+        self.uri = Url::from_file_path(path).unwrap().to_string();
+
+        if let Some(server) = self.language_server.clone() {
+            self.send_did_open(&mut server.borrow_mut());
+        }
+    }
+
+    pub fn send_did_open(&mut self, server: &mut RefMut<LanguageServer>) {
+        self.utf16_positions = server.utf16_positions;
         let text = self.piece_table.iter_chars().collect();
         let open_params = DidOpenTextDocumentParams {
             text_document: TextDocumentItem {
@@ -140,27 +718,39 @@ impl Buffer {
         server.send_notification("textDocument/didOpen", Some(open_params));
     }
 
-    pub fn set_cursor(&mut self, line: usize, col: usize) {
+    pub fn set_cursor(&mut self, view: &mut View, line: usize, col: usize) {
         if let Some(mouse_line) = self.piece_table.line_at_index(line) {
             if let Some(position) = self
                 .piece_table
                 .char_index_from_line_col(line, min(col, mouse_line.length.saturating_sub(1)))
             {
-                self.cursors.truncate(1);
-                self.switch_to_normal_mode();
-                self.cursors[0].position = position;
-                self.cursors[0].anchor = position;
+                view.cursors.truncate(1);
+                self.switch_to_normal_mode(view);
+                view.cursors[0].position = position;
+                view.cursors[0].anchor = position;
+                view.drag_origin = None;
             }
         } else {
-            self.cursors.truncate(1);
-            self.switch_to_normal_mode();
+            view.cursors.truncate(1);
+            self.switch_to_normal_mode(view);
             let last_position = self.piece_table.num_chars().saturating_sub(2);
-            self.cursors[0].position = last_position;
-            self.cursors[0].anchor = last_position;
+            view.cursors[0].position = last_position;
+            view.cursors[0].anchor = last_position;
+            view.drag_origin = None;
         }
     }
 
-    pub fn set_drag(&mut self, line: usize, col: usize) {
+    pub fn set_drag(&mut self, view: &mut View, line: usize, col: usize) {
+        match view.drag_origin {
+            Some(DragOrigin::Words(start, end)) => {
+                return self.drag_words(view, line, col, start, end)
+            }
+            Some(DragOrigin::Lines(first, last)) => {
+                return self.drag_lines(view, line, first, last)
+            }
+            None => (),
+        }
+
         if let Some(mouse_line) = self.piece_table.line_at_index(line) {
             if let Some(position) = self
                 .piece_table
@@ -168,40 +758,415 @@ impl Buffer {
             {
                 // Only start visual selection if the cursor moved cell
                 // Disallowing selecting '\n' on the same line by dragging
-                if self.cursors[0].position == position
-                    || (self.piece_table.line_index(self.cursors[0].position) == mouse_line.index
-                        && self.cursors[0].position == mouse_line.end.saturating_sub(1)
-                        && col >= self.piece_table.col_index(self.cursors[0].position))
+                if view.cursors[0].position == position
+                    || (self.piece_table.line_index(view.cursors[0].position) == mouse_line.index
+                        && view.cursors[0].position == mouse_line.end.saturating_sub(1)
+                        && col >= self.piece_table.col_index(view.cursors[0].position))
                 {
                     return;
                 }
 
-                if self.cursors[0].position != position {
-                    self.switch_to_visual_mode();
-                    self.cursors[0].position = position;
+                if view.cursors[0].position != position {
+                    self.switch_to_visual_mode(view);
+                    view.cursors[0].position = position;
                 }
             }
         }
     }
 
-    pub fn handle_mouse_double_click(&mut self, line: usize, col: usize) -> bool {
+    pub fn handle_mouse_double_click(&mut self, view: &mut View, line: usize, col: usize) -> bool {
         if let Some(cursor_line) = self.piece_table.line_at_index(line) {
             if let Some(position) = self
                 .piece_table
                 .char_index_from_line_col(line, min(col, cursor_line.length.saturating_sub(1)))
             {
-                if self.cursors[0].position == position {
-                    self.switch_to_visual_mode();
-                    self.motion(ExtendSelectionInside(b'w'));
+                if view.cursors[0].position == position {
+                    self.switch_to_visual_mode(view);
+                    self.motion(view, ExtendSelectionInside(b'w'));
+                    let cursor = view.cursors[0];
+                    view.drag_origin = Some(DragOrigin::Words(
+                        min(cursor.anchor, cursor.position),
+                        max(cursor.anchor, cursor.position),
+                    ));
                     return true;
                 }
             }
         }
-        self.set_cursor(line, col);
+        self.set_cursor(view, line, col);
         false
     }
 
-    pub fn handle_mouse_hover(&mut self, line: usize, col: usize) {
+    pub fn handle_mouse_triple_click(&mut self, view: &mut View, line: usize) {
+        self.select_lines(view, line, line);
+        view.drag_origin = Some(DragOrigin::Lines(line, line));
+    }
+
+    pub fn handle_mouse_quadruple_click(&mut self, view: &mut View, line: usize) {
+        let (first_line, last_line) = match self.piece_table.line_is_blank(line) {
+            true => (line, line),
+            false => self.paragraph_at(line),
+        };
+        self.select_lines(view, first_line, last_line);
+        view.drag_origin = Some(DragOrigin::Lines(first_line, last_line));
+    }
+
+    // The selection always covers the double-clicked word and grows by whole words towards the mouse
+    fn drag_words(&mut self, view: &mut View, line: usize, col: usize, start: usize, end: usize) {
+        let Some(position) = self.piece_table.line_at_index(line).and_then(|mouse_line| {
+            self.piece_table
+                .char_index_from_line_col(line, min(col, mouse_line.length.saturating_sub(1)))
+        }) else {
+            return;
+        };
+
+        let mut word = view.cursors[0];
+        word.position = position;
+        word.anchor = position;
+        word.extend_selection_inside(&self.piece_table, b'w');
+        let (word_start, word_end) = (
+            min(word.anchor, word.position),
+            max(word.anchor, word.position),
+        );
+
+        view.cursors.truncate(1);
+        if view.mode != Visual {
+            self.switch_to_normal_mode(view);
+            self.switch_to_visual_mode(view);
+        }
+        if position < start {
+            view.cursors[0].anchor = end;
+            view.cursors[0].position = word_start;
+        } else {
+            view.cursors[0].anchor = start;
+            view.cursors[0].position = max(end, word_end);
+        }
+    }
+
+    fn drag_lines(&mut self, view: &mut View, line: usize, first: usize, last: usize) {
+        let line = min(line, self.piece_table.num_lines().saturating_sub(1));
+        let (anchor_line, mouse_line) = match line < first {
+            true => (last, line),
+            false => (first, max(line, last)),
+        };
+        if let (Some(anchor_line), Some(mouse_line)) = (
+            self.piece_table.line_at_index(anchor_line),
+            self.piece_table.line_at_index(mouse_line),
+        ) {
+            view.cursors.truncate(1);
+            if view.mode != VisualLine {
+                self.switch_to_normal_mode(view);
+                self.switch_to_visual_line_mode(view);
+            }
+            view.cursors[0].anchor = anchor_line.start;
+            view.cursors[0].position = mouse_line.start;
+        }
+    }
+
+    // Lines around the given line up to the nearest blank lines
+    fn paragraph_at(&self, line: usize) -> (usize, usize) {
+        let mut first_line = line;
+        while first_line > 0 && !self.piece_table.line_is_blank(first_line - 1) {
+            first_line -= 1;
+        }
+        let mut last_line = line;
+        while last_line + 1 < self.piece_table.num_lines()
+            && !self.piece_table.line_is_blank(last_line + 1)
+        {
+            last_line += 1;
+        }
+        (first_line, last_line)
+    }
+
+    // Comment operator (gc), toggles comments on the lines each cursor's motion or text object covers
+    fn comment_motion(&mut self, view: &mut View, motion: &str) {
+        let last_line = self.piece_table.num_lines().saturating_sub(1);
+        let mut first_lines = vec![];
+        for i in 0..view.cursors.len() {
+            let line = self.piece_table.line_index(view.cursors[i].position);
+            let (first, last) = match motion {
+                "c" => (line, line),
+                "j" => (line, min(line + 1, last_line)),
+                "k" => (line.saturating_sub(1), line),
+                "G" => (line, last_line),
+                "gg" => (0, line),
+                "ip" => self.paragraph_at(line),
+                motion if let Some(c) = motion.strip_prefix('i') => {
+                    let mut cursor = view.cursors[i];
+                    cursor.extend_selection_inside(&self.piece_table, c.as_bytes()[0]);
+                    let (start, end) = (cursor.anchor, cursor.position);
+                    let mut first = self.piece_table.line_index(start);
+                    let mut last = self.piece_table.line_index(end);
+                    // Brackets on lines of their own stay uncommented
+                    if first < last && self.piece_table.char_at(start) == Some(b'\n') {
+                        first += 1;
+                    }
+                    if first < last
+                        && self.piece_table.char_at(end) != Some(b'\n')
+                        && self
+                            .piece_table
+                            .iter_chars_at_rev(end)
+                            .take_while(|c| *c != b'\n')
+                            .all(|c| c == b' ')
+                    {
+                        last -= 1;
+                    }
+                    (first, last)
+                }
+                _ => return,
+            };
+
+            if let (Some(first_line), Some(last_line)) = (
+                self.piece_table.line_at_index(first),
+                self.piece_table.line_at_index(last),
+            ) {
+                view.cursors[i].anchor = first_line.start;
+                view.cursors[i].position = last_line.start;
+            }
+            first_lines.push(first);
+        }
+
+        self.push_undo_state(view);
+        self.command(view, ToggleComment);
+
+        // Like vim, leave the cursors at the start of what was commented
+        for (i, first) in first_lines.into_iter().enumerate() {
+            if let Some(line) = self.piece_table.line_at_index(first) {
+                view.cursors[i].position = line.start;
+                view.cursors[i].anchor = line.start;
+            }
+        }
+        self.motion(view, ToFirstNonBlankChar);
+    }
+
+    pub fn extend_selection_to(&mut self, view: &mut View, line: usize, col: usize) {
+        if let Some(mouse_line) = self.piece_table.line_at_index(line) {
+            if let Some(position) = self
+                .piece_table
+                .char_index_from_line_col(line, min(col, mouse_line.length))
+            {
+                view.cursors.truncate(1);
+                if view.mode != Visual && view.mode != VisualLine {
+                    self.switch_to_normal_mode(view);
+                    self.switch_to_visual_mode(view);
+                }
+                view.cursors[0].position = position;
+                view.drag_origin = None;
+            }
+        }
+    }
+
+    fn select_lines(&mut self, view: &mut View, first_line: usize, last_line: usize) {
+        if let (Some(first_line), Some(last_line)) = (
+            self.piece_table.line_at_index(first_line),
+            self.piece_table.line_at_index(last_line),
+        ) {
+            view.cursors.truncate(1);
+            self.switch_to_normal_mode(view);
+            self.switch_to_visual_line_mode(view);
+            view.cursors[0].anchor = first_line.start;
+            view.cursors[0].position = last_line.start;
+        }
+    }
+
+    // Zero-based line and byte column of the primary cursor
+    pub fn cursor_line_col(&self, view: &View) -> (usize, usize) {
+        view.cursors.last().map_or((0, 0), |cursor| {
+            (
+                self.piece_table.line_index(cursor.position),
+                self.piece_table.col_index(cursor.position),
+            )
+        })
+    }
+
+    pub fn selection_contains(&self, view: &View, line: usize, col: usize) -> bool {
+        if view.mode != Visual && view.mode != VisualLine {
+            return false;
+        }
+        self.piece_table
+            .char_index_from_line_col(line, col)
+            .is_some_and(|position| {
+                view.cursors.iter().any(|cursor| {
+                    (min(cursor.position, cursor.anchor)..=max(cursor.position, cursor.anchor))
+                        .contains(&position)
+                })
+            })
+    }
+
+    // Ranges of the visual selections, whole lines in visual line mode
+    pub fn visual_selections(&self, view: &View) -> Vec<(usize, usize)> {
+        if view.mode != Visual && view.mode != VisualLine {
+            return vec![];
+        }
+        view.cursors
+            .iter()
+            .map(|cursor| {
+                let mut cursor = *cursor;
+                if view.mode == VisualLine {
+                    cursor.extend_selection(&self.piece_table);
+                }
+                (
+                    min(cursor.position, cursor.anchor),
+                    min(
+                        max(cursor.position, cursor.anchor) + 1,
+                        self.piece_table.num_chars(),
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    // Joined like the selections of multiple cursors are when copied
+    pub fn text_in_ranges(&self, ranges: &[(usize, usize)]) -> Vec<u8> {
+        let mut text = vec![];
+        for &(start, end) in ranges {
+            text.extend(self.piece_table.iter_chars_at(start).take(end - start));
+            if ranges.len() > 1 {
+                text.push(b'\n');
+            }
+        }
+        text
+    }
+
+    // Inserts in front of the clicked character and leaves the cursor after the inserted text
+    pub fn paste_at(&mut self, view: &mut View, line: usize, col: usize, text: &[u8]) {
+        let Some(position) = self.piece_table.line_at_index(line).and_then(|mouse_line| {
+            self.piece_table
+                .char_index_from_line_col(line, min(col, mouse_line.length))
+        }) else {
+            return;
+        };
+        if text.is_empty() {
+            return;
+        }
+
+        view.cursors.truncate(1);
+        self.switch_to_normal_mode(view);
+        self.push_undo_state(view);
+        let cursor_position = position + text.len();
+        if text.len() > LARGE_PASTE_SIZE {
+            self.start_large_paste(position, text.to_vec(), cursor_position);
+            return;
+        }
+
+        let changes = self.insert_chars(view, position, text);
+        self.lsp_change(vec![changes]);
+        self.syntect_change(view);
+        view.cursors[0].position = cursor_position;
+        if view.cursors[0].at_line_end(&self.piece_table) {
+            view.cursors[0].move_backward(&self.piece_table, 1);
+        }
+        view.cursors[0].reset_anchor();
+        view.cursors[0].unstick_col(&self.piece_table);
+    }
+
+    pub fn run_context_menu_item(&mut self, view: &mut View, item: ContextMenuItem) {
+        // Without a selection, cut and copy operate on the word under the cursor
+        if view.mode == Normal && (item == ContextMenuItem::Cut || item == ContextMenuItem::Copy) {
+            self.switch_to_visual_mode(view);
+            self.motion(view, ExtendSelectionInside(b'w'));
+        }
+        if view.mode == VisualLine && item != ContextMenuItem::FormatSelection {
+            self.motion(view, ExtendSelection);
+        }
+
+        match item {
+            ContextMenuItem::Cut => {
+                self.push_undo_state(view);
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
+                self.switch_to_normal_mode(view);
+            }
+            ContextMenuItem::Copy => {
+                self.command(view, CopySelection);
+                for cursor in &mut view.cursors {
+                    cursor.position = min(cursor.anchor, cursor.position);
+                }
+                self.switch_to_normal_mode(view);
+            }
+            ContextMenuItem::Paste => {
+                self.push_undo_state(view);
+                if view.mode == Visual || view.mode == VisualLine {
+                    self.command(view, CutSelection);
+                    self.motion(view, BackwardOnceWrapping);
+                }
+                self.command(view, PasteSelection);
+                self.switch_to_normal_mode(view);
+            }
+            ContextMenuItem::GotoDefinition => self.command(view, GotoDefinition),
+            ContextMenuItem::FindReferences => {
+                if let Some(last_cursor) = view.cursors.last() {
+                    self.lsp_find_references(last_cursor.position);
+                }
+            }
+            ContextMenuItem::Rename => {
+                self.switch_to_normal_mode(view);
+                self.start_rename_session(view);
+            }
+            ContextMenuItem::FormatSelection => {
+                let (start, end) = if view.mode == Visual || view.mode == VisualLine {
+                    let cursor = view.cursors[0];
+                    (
+                        min(cursor.position, cursor.anchor),
+                        max(cursor.position, cursor.anchor),
+                    )
+                } else {
+                    (view.cursors[0].position, view.cursors[0].position)
+                };
+                let (start_line, end_line) = (
+                    self.piece_table.line_index(start),
+                    self.piece_table.line_index(end),
+                );
+                self.lsp_format(start_line, end_line);
+                self.switch_to_normal_mode(view);
+            }
+        }
+
+        self.merge_cursors(view);
+    }
+
+    pub fn apply_text_edits(&mut self, view: &mut View, edits: &[TextEdit]) {
+        if edits.is_empty() {
+            return;
+        }
+        self.push_undo_state(view);
+
+        // Apply the edits back to front so that earlier edits don't shift the positions of later ones
+        let mut edits: Vec<&TextEdit> = edits.iter().collect();
+        edits.sort_by(|edit1, edit2| {
+            (edit2.range.start.line, edit2.range.start.character)
+                .cmp(&(edit1.range.start.line, edit1.range.start.character))
+        });
+
+        let mut content_changes = vec![];
+        for edit in edits {
+            let num_chars = self.piece_table.num_chars();
+            let start = self
+                .char_index_from_lsp(&edit.range.start)
+                .unwrap_or(num_chars);
+            let end = self
+                .char_index_from_lsp(&edit.range.end)
+                .unwrap_or(num_chars);
+
+            if end > start {
+                content_changes.push(self.delete_chars(view, start, end));
+            }
+            let text: Vec<u8> = edit.new_text.bytes().filter(|c| *c != b'\r').collect();
+            if !text.is_empty() {
+                content_changes.push(self.insert_chars(view, start, &text));
+            }
+        }
+
+        let last_position = self.piece_table.num_chars().saturating_sub(1);
+        for cursor in &mut view.cursors {
+            cursor.position = min(cursor.position, last_position);
+            cursor.anchor = min(cursor.anchor, last_position);
+        }
+
+        self.lsp_change(content_changes);
+        self.syntect_change(view);
+    }
+
+    pub fn request_hover(&mut self, line: usize, col: usize) {
         if let Some(cursor_line) = self.piece_table.line_at_index(line) {
             if col >= cursor_line.length {
                 return;
@@ -210,13 +1175,312 @@ impl Buffer {
         }
     }
 
-    pub fn insert_cursor(&mut self, line: usize, col: usize) {
+    // Text around the primary cursor for which the predicate holds
+    fn token_at_cursor(&self, view: &View, predicate: impl Fn(u8) -> bool) -> Option<String> {
+        let position = view.cursors.last()?.position;
+        if !predicate(self.piece_table.char_at(position)?) {
+            return None;
+        }
+
+        let start = position + 1
+            - self
+                .piece_table
+                .iter_chars_at_rev(position)
+                .take_while(|c| predicate(*c))
+                .count();
+        let end = position
+            + self
+                .piece_table
+                .iter_chars_at(position)
+                .take_while(|c| predicate(*c))
+                .count();
+        let token: Vec<u8> = self
+            .piece_table
+            .iter_chars_at(start)
+            .take(end - start)
+            .collect();
+        Some(String::from_utf8_lossy(&token).to_string())
+    }
+
+    fn file_at_cursor(&mut self, view: &mut View, with_location: bool) -> Option<EditorCommand> {
+        self.input.clear();
+        let token = self.token_at_cursor(view, text_utils::is_path_char)?;
+        // Punctuation ending a sentence is rarely part of the path
+        let (path, location) =
+            text_utils::split_location_suffix(token.trim_end_matches(['.', ',', ':']));
+        Some(EditorCommand::GotoFile(
+            path.to_string(),
+            location.filter(|_| with_location),
+        ))
+    }
+
+    // Errors and warnings the language server reported for the file
+    pub fn diagnostic_counts(&self) -> (usize, usize) {
+        let Some(server) = &self.language_server else {
+            return (0, 0);
+        };
+        let server = server.borrow();
+        let Some(diagnostics) = server.saved_diagnostics.get(&self.uri.to_lowercase()) else {
+            return (0, 0);
+        };
+        let count = |severity| {
+            diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.severity.unwrap_or(1) == severity)
+                .count()
+        };
+        (count(1), count(2))
+    }
+
+    // Errors and warnings, the diagnostics that are underlined, are visited in the order they
+    // appear in the buffer. Either end wraps around to the other
+    fn goto_diagnostic(
+        &mut self,
+        view: &mut View,
+        forward: bool,
+        errors_only: bool,
+    ) -> Option<EditorCommand> {
+        self.input.clear();
+        let mut positions: Vec<(usize, usize)> = self
+            .language_server
+            .as_ref()
+            .and_then(|server| {
+                Some(
+                    server
+                        .borrow()
+                        .saved_diagnostics
+                        .get(&self.uri.to_lowercase())?
+                        .iter()
+                        .filter(|diagnostic| match diagnostic.severity.unwrap_or(1) {
+                            1 => true,
+                            2 => !errors_only,
+                            _ => false,
+                        })
+                        .map(|diagnostic| {
+                            (
+                                diagnostic.range.start.line as usize,
+                                self.col_from_lsp(&diagnostic.range.start),
+                            )
+                        })
+                        .collect(),
+                )
+            })
+            .unwrap_or_default();
+        positions.sort_unstable();
+        positions.dedup();
+
+        let position = view.cursors.last().unwrap().position;
+        let cursor = (
+            self.piece_table.line_index(position),
+            self.piece_table.col_index(position),
+        );
+        let target = match forward {
+            true => positions
+                .iter()
+                .find(|&&position| position > cursor)
+                .or(positions.first()),
+            false => positions
+                .iter()
+                .rev()
+                .find(|&&position| position < cursor)
+                .or(positions.last()),
+        };
+        let Some(&(line, col)) = target else {
+            log::notify(match errors_only {
+                true => "No errors",
+                false => "No diagnostics",
+            });
+            return None;
+        };
+
+        self.set_cursor(view, line, col);
+        Some(EditorCommand::ShowDiagnostic)
+    }
+
+    // Only web links are opened, other schemes could launch arbitrary programs
+    fn url_at_cursor(&mut self, view: &mut View) -> Option<EditorCommand> {
+        self.input.clear();
+        let token = self.token_at_cursor(view, text_utils::is_url_char)?;
+        let Some(start) = token.find("https://").or_else(|| token.find("http://")) else {
+            log::warning("No URL under the cursor");
+            return None;
+        };
+        // Punctuation ending a sentence or closing a parenthesis is rarely part of the link
+        let url = token[start..].trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', ')']);
+        Some(EditorCommand::OpenUrl(url.to_string()))
+    }
+
+    // Returns the start column and length of the identifier at the given cell
+    pub fn word_at(&self, line: usize, col: usize) -> Option<(usize, usize)> {
+        let line = self.piece_table.line_at_index(line)?;
+        let text: Vec<u8> = self
+            .piece_table
+            .iter_chars_at(line.start)
+            .take(line.length)
+            .collect();
+        if char_type(*text.get(col)?) != CharType::Word {
+            return None;
+        }
+
+        let start = text[..col]
+            .iter()
+            .rposition(|c| char_type(*c) != CharType::Word)
+            .map_or(0, |i| i + 1);
+        let end = text[col..]
+            .iter()
+            .position(|c| char_type(*c) != CharType::Word)
+            .map_or(text.len(), |i| col + i);
+        Some((start, end - start))
+    }
+
+    // '" goes to the first non-blank character of the line, `" to the exact position
+    fn goto_last_close_position(&mut self, view: &mut View, exact: bool) -> Option<EditorCommand> {
+        let Some((line, col)) = self.last_close_position else {
+            log::notify("No position from before the file was last closed");
+            self.input.clear();
+            return None;
+        };
+        if exact {
+            self.set_cursor(view, line, col);
+        } else {
+            self.set_cursor(view, line, 0);
+            self.motion(view, ToFirstNonBlankChar);
+        }
+        Some(EditorCommand::RevealJump)
+    }
+
+    fn set_mark(&mut self, view: &mut View, mark: u8) {
+        if !mark.is_ascii_alphabetic() {
+            return;
+        }
+        let position = view.cursors.last().unwrap().position;
+        match self.marks.iter_mut().find(|(name, _)| *name == mark) {
+            Some((_, mark_position)) => *mark_position = position,
+            None => self.marks.push((mark, position)),
+        }
+    }
+
+    fn goto_mark(&mut self, view: &mut View, mark: u8, exact: bool) -> Option<EditorCommand> {
+        let Some(&(_, position)) = self.marks.iter().find(|(name, _)| *name == mark) else {
+            log::notify(format!("Mark {} is not set", mark as char));
+            self.input.clear();
+            return None;
+        };
+        // Deleting the text up to the end of the file moves the marks in it past the last character
+        let position = min(position, self.piece_table.num_chars().saturating_sub(1));
+        let line = self.piece_table.line_index(position);
+        if exact {
+            self.set_cursor(view, line, self.piece_table.col_index(position));
+        } else {
+            self.set_cursor(view, line, 0);
+            self.motion(view, ToFirstNonBlankChar);
+        }
+        Some(EditorCommand::RevealJump)
+    }
+
+    pub fn handle_mouse_goto_definition(&mut self, view: &mut View, line: usize, col: usize) {
+        self.set_cursor(view, line, col);
+        self.lsp_goto_definition(view.cursors[0].position);
+    }
+
+    // Ctrl+V followed by u/U/x and hex digits inserts a codepoint, Ctrl+K followed by two characters a digraph
+    fn handle_unicode_input(&mut self, view: &mut View, c: char) {
+        let max_digits = match self.input.as_bytes().get(2) {
+            Some(b'x') => 2,
+            Some(b'u') => 4,
+            Some(b'U') => 8,
+            _ => 0,
+        };
+
+        if self.input.starts_with("^K") {
+            if self.input.len() == 2 {
+                self.input.push(c);
+                return;
+            }
+            let first = self.input.as_bytes()[2];
+            self.input.clear();
+            match u8::try_from(c)
+                .ok()
+                .and_then(|c| text_utils::digraph(first, c))
+            {
+                Some(c) => self.command(view, InsertUnicode(c)),
+                None => log::warning(format!("Unknown digraph {}{}", first as char, c)),
+            }
+        } else if max_digits == 0 {
+            if matches!(c, 'x' | 'u' | 'U') {
+                self.input.push(c);
+            } else {
+                // Any other character is inserted literally
+                self.input.clear();
+                self.command(view, InsertUnicode(c));
+            }
+        } else if c.is_ascii_hexdigit() {
+            self.input.push(c);
+            if self.input.len() - 3 == max_digits {
+                self.insert_codepoint(view);
+            }
+        } else {
+            self.insert_codepoint(view);
+            self.handle_char(view, c);
+        }
+    }
+
+    fn insert_codepoint(&mut self, view: &mut View) {
+        let codepoint = u32::from_str_radix(&self.input[3..], 16)
+            .ok()
+            .and_then(char::from_u32);
+        match codepoint {
+            Some(c) => self.command(view, InsertUnicode(c)),
+            None => log::warning(format!("Invalid codepoint {}", &self.input[3..])),
+        }
+        self.input.clear();
+    }
+
+    // Contents that a paste would produce, labeled by where they come from
+    pub fn registers(&self, view: &View) -> Vec<(String, Vec<u8>)> {
+        let mut registers = vec![
+            ("clipboard".to_string(), self.get_clipboard()),
+            ("deleted".to_string(), self.deleted_register.clone()),
+        ];
+        for (i, clipboard) in self
+            .cursor_clipboards
+            .iter()
+            .take(view.cursors.len())
+            .enumerate()
+        {
+            registers.push((format!("cursor {}", i + 1), clipboard.clone()));
+        }
+        registers
+    }
+
+    fn set_cursor_clipboard(&mut self, i: usize, text: Vec<u8>) {
+        if self.cursor_clipboards.len() <= i {
+            self.cursor_clipboards.resize(i + 1, vec![]);
+        }
+        self.cursor_clipboards[i] = text;
+    }
+
+    // Like vim, pasting over a selection keeps the yanked text so that it can be pasted again
+    fn paste_over_selection(&mut self, view: &mut View, paste: BufferCommand) {
+        let clipboard = self.get_clipboard();
+        let cursor_clipboards = self.cursor_clipboards.clone();
+
+        self.command(view, CutSelection);
+        self.deleted_register = self.get_clipboard();
+        self.set_clipboard(&clipboard);
+        self.cursor_clipboards = cursor_clipboards;
+
+        self.motion(view, BackwardOnceWrapping);
+        self.command(view, paste);
+    }
+
+    pub fn insert_cursor(&mut self, view: &mut View, line: usize, col: usize) {
         if let Some(cursor_line) = self.piece_table.line_at_index(line) {
             if let Some(position) = self
                 .piece_table
                 .char_index_from_line_col(line, min(col, cursor_line.length))
             {
-                self.cursors.push(Cursor::new(position));
+                view.cursors.push(Cursor::new(position));
             }
         }
     }
@@ -225,41 +1489,133 @@ impl Buffer {
         &mut self,
         key_code: VirtualKeyCode,
         modifiers: Option<ModifiersState>,
-        view: &View,
+        view: &mut View,
         layout: &RenderLayout,
     ) -> Option<EditorCommand> {
-        match (self.mode, key_code) {
-            (_, VirtualKeyCode::Down) => self.motion(Down(1)),
-            (_, VirtualKeyCode::Up) => self.motion(Up(1)),
+        if self.pending_paste.is_some() {
+            return None;
+        }
+
+        // Holding shift while navigating starts a visual selection from the current position
+        if modifiers
+            .is_some_and(|m| m.contains(ModifiersState::SHIFT) && !m.contains(ModifiersState::ALT))
+            && (view.mode == Normal || view.mode == Insert)
+            && matches!(
+                key_code,
+                VirtualKeyCode::Up
+                    | VirtualKeyCode::Down
+                    | Left
+                    | Right
+                    | VirtualKeyCode::Home
+                    | VirtualKeyCode::End
+                    | VirtualKeyCode::PageUp
+                    | VirtualKeyCode::PageDown
+            )
+        {
+            self.switch_to_normal_mode(view);
+            self.switch_to_visual_mode(view);
+        }
+
+        match (view.mode, key_code) {
+            (Normal | Visual | VisualLine, VirtualKeyCode::Down)
+                if modifiers.is_some_and(|m| {
+                    m.contains(ModifiersState::SHIFT) && m.contains(ModifiersState::ALT)
+                }) =>
+            {
+                self.push_undo_state(view);
+                self.command(view, DuplicateSelection);
+            }
+            (_, VirtualKeyCode::Down) => self.motion(view, Down(1)),
+            (_, VirtualKeyCode::Up) => self.motion(view, Up(1)),
             (_, Right) if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
-                self.motion(ForwardByWord)
+                self.motion(view, ForwardByWord)
             }
             (_, Left) if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
-                self.motion(BackwardByWord)
+                self.motion(view, BackwardByWord)
             }
-            (_, Right) => self.motion(Forward(1)),
-            (_, Left) => self.motion(Backward(1)),
-
-            (Normal, Escape) if self.input.as_bytes().first() == Some(&b'/') => {
-                self.input.clear();
-                self.cursors[0].position = self.search_anchor;
-                self.cursors[0].anchor = self.search_anchor;
-                return Some(EditorCommand::CenterIfNotVisible);
+            (_, Right) => self.motion(view, Forward(1)),
+            (_, Left) => self.motion(view, Backward(1)),
+            (_, VirtualKeyCode::Home)
+                if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) =>
+            {
+                self.motion(view, ToStartOfFile);
             }
-            (Normal, Escape) => {
-                self.cursors.truncate(1);
-                self.input.clear();
+            (_, VirtualKeyCode::End)
+                if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) =>
+            {
+                self.motion(view, ToEndOfFile);
             }
-            (Insert, Escape) => {
-                self.motion(Backward(1));
-                self.switch_to_normal_mode();
+            (_, VirtualKeyCode::Home) => self.motion(view, ToStartOfLine),
+            (_, VirtualKeyCode::End) => self.motion(view, ToEndOfLine),
+            (Insert, VirtualKeyCode::PageUp | VirtualKeyCode::PageDown)
+                if view
+                    .cursors
+                    .iter()
+                    .any(|cursor| cursor.completion_request.is_some()) =>
+            {
+                self.move_completion_selection(
+                    key_code == VirtualKeyCode::PageDown,
+                    true,
+                    view,
+                    layout,
+                );
+            }
+            (_, VirtualKeyCode::PageUp) => self.motion(view, Up(layout.num_rows)),
+            (_, VirtualKeyCode::PageDown) => self.motion(view, Down(layout.num_rows)),
+            (Normal, VirtualKeyCode::Insert) => {
+                self.push_undo_state(view);
+                self.switch_to_insert_mode(view);
+            }
+
+            (_, Escape) => return self.handle_escape(view),
+            (Insert, VirtualKeyCode::V)
+                if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) =>
+            {
+                self.input = "^V".to_string();
+            }
+            (Insert, K)
+                if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL))
+                    && view
+                        .cursors
+                        .iter()
+                        .all(|cursor| cursor.completion_request.is_none()) =>
+            {
+                self.input = "^K".to_string();
+            }
+            (Insert, R) if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
+                self.input = "^R".to_string();
+            }
+            (Insert, VirtualKeyCode::N)
+                if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) =>
+            {
+                return Some(EditorCommand::CompleteWord(true));
+            }
+            (Insert, VirtualKeyCode::P)
+                if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) =>
+            {
+                return Some(EditorCommand::CompleteWord(false));
+            }
+            (Insert, VirtualKeyCode::W)
+                if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) =>
+            {
+                self.command(view, DeleteWordBack);
+            }
+            (Insert, VirtualKeyCode::U)
+                if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) =>
+            {
+                self.command(view, DeleteToLineStart);
+            }
+            (Insert, Back) if self.input.starts_with('^') => {
+                self.input.pop();
+                if self.input.len() < 2 {
+                    self.input.clear();
+                }
             }
-            (_, Escape) => self.switch_to_normal_mode(),
 
             (Insert, Back) if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
-                self.command(DeleteWordBack);
+                self.command(view, DeleteWordBack);
             }
-            (Insert, Back) => self.command(DeleteCharBack),
+            (Insert, Back) => self.command(view, DeleteCharBack),
             (_, Back) => {
                 if self
                     .input
@@ -269,116 +1625,81 @@ impl Buffer {
                 {
                     self.input.pop();
                 } else {
-                    self.motion(Backward(1));
+                    self.motion(view, Backward(1));
                 }
             }
 
-            (Insert, Return) => self.command(InsertNewLine),
-            (_, Return) => {
-                if self
-                    .input
-                    .as_bytes()
-                    .first()
-                    .is_some_and(|c| *c == b':' || *c == b'/')
-                {
-                    let editor_command = self.handle_input_command();
-                    self.input.clear();
-                    return editor_command;
-                } else {
-                    self.motion(Down(1));
-                }
-            }
+            (_, Return) => return self.handle_return(view),
 
             (Normal, Delete) => {
-                self.command(CopySelection);
-                self.command(CutSelection);
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
             }
             (Visual, Delete) => {
-                self.command(CopySelection);
-                self.command(CutSelection);
-                self.switch_to_normal_mode();
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
+                self.switch_to_normal_mode(view);
             }
             (VisualLine, Delete) => {
-                self.motion(ExtendSelection);
-                self.command(CopySelection);
-                self.command(CutSelection);
-                self.switch_to_normal_mode();
+                self.motion(view, ExtendSelection);
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
+                self.switch_to_normal_mode(view);
             }
             (Insert, Delete) if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
-                self.command(DeleteWordFront);
+                self.command(view, DeleteWordFront);
             }
-            (Insert, Delete) => self.command(CutSingleSelection),
+            (Insert, Delete) => self.command(view, CutSingleSelection),
 
             (Normal, R) if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
-                self.command(Redo);
+                self.command(view, Redo);
             }
 
             (Insert, J) if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
-                for cursor in &mut self.cursors {
-                    if let Some(ref mut request) = cursor.completion_request {
-                        if let Some(server) = &self.language_server {
-                            if let Some(completion_list) =
-                                server.borrow().saved_completions.get(&request.id)
-                            {
-                                let filtered_completions = get_filtered_completions(
-                                    &self.piece_table,
-                                    completion_list,
-                                    request,
-                                    cursor.position,
-                                );
-
-                                if let Some(completion_view) = view.get_completion_view(
-                                    &self.piece_table,
-                                    &filtered_completions,
-                                    request.position,
-                                    layout,
-                                ) {
-                                    request.selection_index = min(
-                                        request.selection_index + 1,
-                                        filtered_completions.len().saturating_sub(1),
-                                    );
-
-                                    if request.selection_index
-                                        >= request.selection_view_offset + completion_view.height
-                                    {
-                                        request.selection_view_offset += 1;
-                                    }
-                                }
-                            }
-                        }
-                    }
+                self.move_completion_selection(true, false, view, layout);
+            }
+            (
+                Insert,
+                VirtualKeyCode::F
+                | VirtualKeyCode::B
+                | VirtualKeyCode::D
+                | VirtualKeyCode::RBracket,
+            ) if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL))
+                && view
+                    .cursors
+                    .last()
+                    .is_some_and(|cursor| cursor.completion_request.is_some()) =>
+            {
+                match key_code {
+                    VirtualKeyCode::F => self.scroll_completion_documentation(view, true),
+                    VirtualKeyCode::B => self.scroll_completion_documentation(view, false),
+                    VirtualKeyCode::D => self.toggle_deprecated_completions(view),
+                    _ => self.goto_completion_definition(view),
                 }
             }
             (Insert, K) if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
-                for cursor in &mut self.cursors {
-                    if let Some(ref mut request) = cursor.completion_request {
-                        request.selection_index = request.selection_index.saturating_sub(1);
-                        if request.selection_index < request.selection_view_offset {
-                            request.selection_view_offset -= 1;
-                        }
-                    }
-                }
+                self.move_completion_selection(false, false, view, layout);
             }
 
             (Normal | Visual | VisualLine, Slash)
                 if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) =>
             {
-                self.push_undo_state();
-                self.command(ToggleComment);
+                self.push_undo_state(view);
+                self.command(view, ToggleComment);
             }
 
             (Insert, Tab)
-                if self
+                if view
                     .cursors
                     .last()
                     .is_some_and(|cursor| cursor.completion_request.is_some()) =>
             {
-                self.push_undo_state();
-                self.command(Complete);
+                self.push_undo_state(view);
+                self.command(view, Complete);
             }
             (Insert, Tab) => {
                 for _ in 0..self.piece_table.indent_width {
-                    self.command(InsertChar(b' '));
+                    self.command(view, InsertChar(b' '));
                 }
             }
             (_, Tab) if modifiers.is_some_and(|m| m.contains(ModifiersState::SHIFT)) => {
@@ -387,26 +1708,271 @@ impl Buffer {
             (_, Tab) => return Some(EditorCommand::NextTab),
 
             (Insert, Space) if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
-                self.command(StartCompletion);
+                self.command(view, StartCompletion);
             }
 
             _ => return None,
         }
 
-        self.merge_cursors();
+        self.merge_cursors(view);
+        None
+    }
+
+    fn handle_escape(&mut self, view: &mut View) -> Option<EditorCommand> {
+        self.keymap_input.clear();
+        match view.mode {
+            Normal if self.input.as_bytes().first() == Some(&b'/') => {
+                self.input.clear();
+                view.cursors[0].position = self.search_anchor;
+                view.cursors[0].anchor = self.search_anchor;
+                return Some(EditorCommand::RevealJump);
+            }
+            Normal => {
+                view.cursors.truncate(1);
+                self.input.clear();
+            }
+            Insert if self.input.starts_with('^') => self.input.clear(),
+            Insert if self.rename_session.is_some() => self.end_rename_session(view, false),
+            Insert => {
+                self.motion(view, Backward(1));
+                self.switch_to_normal_mode(view);
+            }
+            _ => self.switch_to_normal_mode(view),
+        }
+
+        self.merge_cursors(view);
         None
     }
 
-    pub fn handle_char(&mut self, c: char) -> Option<EditorCommand> {
-        if self.mode == Insert {
+    fn handle_return(&mut self, view: &mut View) -> Option<EditorCommand> {
+        match view.mode {
+            Insert if self.input.starts_with("^V") => self.insert_codepoint(view),
+            Insert if self.rename_session.is_some() => self.end_rename_session(view, true),
+            Insert => {
+                self.command(view, ExpandAbbreviation);
+                self.command(view, InsertNewLine);
+            }
+            _ if self
+                .input
+                .as_bytes()
+                .first()
+                .is_some_and(|c| *c == b':' || *c == b'/') =>
+            {
+                let editor_command = self.handle_input_command(view);
+                self.input.clear();
+                return editor_command;
+            }
+            _ => self.motion(view, Down(1)),
+        }
+
+        self.merge_cursors(view);
+        None
+    }
+
+    // The actions keys are bound to in keymap.toml run like the commands of the default keys do,
+    // a binding that changes the text is undone in one step
+    fn run_keymap_actions(&mut self, view: &mut View, actions: &[KeymapAction]) {
+        let undoable = view.mode != Insert
+            && !actions
+                .iter()
+                .any(|action| matches!(action, KeymapAction::Command(Undo | Redo)));
+        if undoable {
+            self.push_undo_state(view);
+        }
+
+        for action in actions {
+            match *action {
+                KeymapAction::Motion(motion) => self.motion(view, motion),
+                KeymapAction::Command(command) => self.command(view, command),
+                KeymapAction::Mode(Normal) => self.switch_to_normal_mode(view),
+                KeymapAction::Mode(Insert) => self.switch_to_insert_mode(view),
+                KeymapAction::Mode(Visual) => self.switch_to_visual_mode(view),
+                KeymapAction::Mode(VisualLine) => self.switch_to_visual_line_mode(view),
+            }
+        }
+
+        if undoable
+            && view.mode != Insert
+            && self
+                .undo_stack
+                .last()
+                .is_some_and(|state| state.deltas.is_empty())
+        {
+            self.pop_undo_state();
+        }
+        if view.mode == Normal {
+            for cursor in &mut view.cursors {
+                cursor.reset_anchor();
+            }
+        }
+        self.merge_cursors(view);
+    }
+
+    // Keys that turned out not to be bound mean what they do by default. Only the last editor
+    // command is returned
+    fn replay_keys(&mut self, view: &mut View, keys: &str) -> Option<EditorCommand> {
+        self.replaying_keymap = true;
+        let mut editor_command = None;
+        for c in keys.chars() {
+            editor_command = self.handle_char(view, c).or(editor_command);
+        }
+        self.replaying_keymap = false;
+        editor_command
+    }
+
+    // Whatever changed the text from normal mode is what "." repeats
+    fn record_change(
+        &mut self,
+        view: &View,
+        mode: BufferMode,
+        undo_depth: usize,
+        change: ChangeRecord,
+    ) {
+        if mode == Normal && self.undo_stack.len() > undo_depth && !self.repeating_change {
+            match view.mode {
+                Insert => self.pending_change = Some(change),
+                _ => self.last_change = Some(change),
+            }
+        }
+    }
+
+    // Mappings are only looked up at the start of a command, so counts and operators keep working
+    // with the default keys
+    fn apply_keymap(&mut self, view: &mut View, c: char) -> Option<Option<EditorCommand>> {
+        if self.replaying_keymap || !(self.input.is_empty() || self.input == self.keymap_input) {
+            return None;
+        }
+
+        let keys = format!("{}{}", self.keymap_input, c);
+        match keymap::lookup(view.mode, &keys) {
+            KeymapMatch::Complete(actions) => {
+                self.keymap_input.clear();
+                self.input.clear();
+                let (mode, undo_depth) = (view.mode, self.undo_stack.len());
+                self.run_keymap_actions(view, &actions);
+                let change = ChangeRecord {
+                    command: keys,
+                    actions: Some(actions),
+                    insertion: vec![],
+                };
+                self.record_change(view, mode, undo_depth, change);
+                Some(None)
+            }
+            KeymapMatch::Prefix => {
+                self.keymap_input = keys.clone();
+                self.input = keys;
+                Some(None)
+            }
+            // The keys typed so far weren't a mapping after all and mean what they do by default
+            KeymapMatch::None if !self.keymap_input.is_empty() => {
+                self.keymap_input.clear();
+                self.input.clear();
+                Some(self.replay_keys(view, &keys))
+            }
+            KeymapMatch::None => None,
+        }
+    }
+
+    // Insert mode bindings like jk = ["Backward(1)", "Normal"] are inserted like any other text
+    // while being typed and removed again once all of their keys have been typed
+    fn apply_insert_keymap(&mut self, view: &mut View, c: char) -> Option<EditorCommand> {
+        if self.replaying_keymap {
+            return None;
+        }
+
+        let keys = format!("{}{}", self.keymap_input, c);
+        let typed = view
+            .cursors
+            .last()
+            .is_some_and(|cursor| self.text_before_is(cursor.position, keys.as_bytes()));
+        match keymap::lookup(Insert, &keys) {
+            KeymapMatch::Complete(actions) if typed => {
+                self.keymap_input.clear();
+                for _ in 0..keys.len() {
+                    self.command(view, DeleteCharBack);
+                }
+                self.run_keymap_actions(view, &actions);
+                None
+            }
+            KeymapMatch::Prefix if typed => {
+                self.keymap_input = keys;
+                None
+            }
+            _ if !self.keymap_input.is_empty() => {
+                self.keymap_input.clear();
+                self.apply_insert_keymap(view, c)
+            }
+            _ => None,
+        }
+    }
+
+    // The longest abbreviation ending at the position, it has to start the line or follow a
+    // character that can't be part of a word
+    fn abbreviation_before(&self, position: usize) -> Option<(String, String)> {
+        self.abbreviations
+            .iter()
+            .filter(|(abbreviation, _)| {
+                let start = position.saturating_sub(abbreviation.len());
+                self.text_before_is(position, abbreviation.as_bytes())
+                    && (start == 0
+                        || self
+                            .piece_table
+                            .char_at(start - 1)
+                            .is_some_and(|c| char_type(c) != CharType::Word))
+            })
+            .max_by_key(|(abbreviation, _)| abbreviation.len())
+            .cloned()
+    }
+
+    fn text_before_is(&self, position: usize, text: &[u8]) -> bool {
+        position >= text.len()
+            && self
+                .piece_table
+                .iter_chars_at(position - text.len())
+                .take(text.len())
+                .eq(text.iter().copied())
+    }
+
+    pub fn handle_char(&mut self, view: &mut View, c: char) -> Option<EditorCommand> {
+        if self.pending_paste.is_some() {
+            return None;
+        }
+        // The oldest undo states are dropped before a command starts, commands and rename sessions
+        // compare the depth of the undo stack while they run
+        if self.undo_stack.len() > MAX_UNDO_STATES
+            && !self.replaying_keymap
+            && self.rename_session.is_none()
+        {
+            self.undo_stack
+                .drain(..self.undo_stack.len() - MAX_UNDO_STATES);
+        }
+        if view.mode == Insert && (self.input.starts_with("^V") || self.input.starts_with("^K")) {
+            self.handle_unicode_input(view, c);
+            return None;
+        }
+
+        if view.mode == Insert && self.input.starts_with("^R") {
+            self.input.clear();
+            if matches!(c, '"' | '+' | '*' | '-') {
+                self.command(view, InsertRegister(c as u8));
+            } else {
+                log::warning(format!("Unknown register {}", c));
+            }
+            return None;
+        }
+
+        if view.mode == Insert {
             if c as u8 >= 0x20 && c as u8 <= 0x7E {
-                self.command(InsertChar(c as u8));
+                if char_type(c as u8) != CharType::Word {
+                    self.command(view, ExpandAbbreviation);
+                }
+                self.command(view, InsertChar(c as u8));
             }
-            for cursor in &mut self.cursors {
+            for cursor in &mut view.cursors {
                 cursor.reset_anchor();
             }
-            self.merge_cursors();
-            return None;
+            self.merge_cursors(view);
+            return self.apply_insert_keymap(view, c);
         }
 
         if self.input.as_bytes().first() == Some(&b':') {
@@ -421,312 +1987,386 @@ impl Buffer {
                 self.input.push(c);
             }
             let partial_search = self.input[1..].to_string();
-            self.motion(SeekToSelf(partial_search.as_bytes()));
-            return Some(EditorCommand::CenterIfNotVisible);
+            self.motion(view, SeekToSelf(partial_search.as_bytes()));
+            return Some(EditorCommand::RevealJump);
+        }
+
+        if let Some(editor_command) = self.apply_keymap(view, c) {
+            return editor_command;
         }
 
         self.input.push(c);
 
-        if !is_prefix_of_command(&self.input, self.mode) {
+        if !is_prefix_of_command(&self.input, view.mode) {
             self.input.clear();
             self.input.push(c);
         }
 
-        match (self.mode, self.input.as_str()) {
-            (_, "j") => self.motion(Down(1)),
-            (_, "k") => self.motion(Up(1)),
-            (_, "h") => self.motion(Backward(1)),
-            (_, "l") => self.motion(Forward(1)),
-            (_, "w") => self.motion(ForwardByWord),
-            (_, "b") => self.motion(BackwardByWord),
-            (_, "0") => self.motion(ToStartOfLine),
-            (_, "$") => self.motion(ToEndOfLine),
-            (_, "^") => self.motion(ToFirstNonBlankChar),
-            (_, "gg") => self.motion(ToStartOfFile),
+        let (mode, input, undo_depth) = (view.mode, self.input.clone(), self.undo_stack.len());
+        let editor_command = self.run_command(view);
+        let change = ChangeRecord {
+            command: input,
+            actions: None,
+            insertion: vec![],
+        };
+        self.record_change(view, mode, undo_depth, change);
+        editor_command
+    }
+
+    fn run_command(&mut self, view: &mut View) -> Option<EditorCommand> {
+        match (view.mode, self.input.as_str()) {
+            (_, "j") => self.motion(view, Down(1)),
+            (_, "k") => self.motion(view, Up(1)),
+            (_, "h") => self.motion(view, Backward(1)),
+            (_, "l") => self.motion(view, Forward(1)),
+            (_, "w") => self.motion(view, ForwardByWord),
+            (_, "b") => self.motion(view, BackwardByWord),
+            (_, "0") => self.motion(view, ToStartOfLine),
+            (_, "$") => self.motion(view, ToEndOfLine),
+            (_, "^") => self.motion(view, ToFirstNonBlankChar),
+            (_, "gg") => self.motion(view, ToStartOfFile),
+            (_, "}") => self.motion(view, ToNextParagraph),
+            (_, "{") => self.motion(view, ToPreviousParagraph),
             (_, "zz") => return Some(EditorCommand::CenterView),
+            (Normal, "'\"") => return self.goto_last_close_position(view, false),
+            (Normal, "`\"") => return self.goto_last_close_position(view, true),
+            (Normal, s) if s.starts_with('m') && s.len() == 2 => {
+                self.set_mark(view, s.as_bytes()[1])
+            }
+            (Normal, s) if s.starts_with('\'') && s.len() == 2 => {
+                return self.goto_mark(view, s.as_bytes()[1], false);
+            }
+            (Normal, s) if s.starts_with('`') && s.len() == 2 => {
+                return self.goto_mark(view, s.as_bytes()[1], true);
+            }
             (_, "/") => {
-                self.cursors.truncate(1);
+                view.cursors.truncate(1);
                 self.search_string.clear();
-                self.search_anchor = self.cursors.first().unwrap().position;
+                self.search_anchor = view.cursors.first().unwrap().position;
                 return None;
             }
             (_, "n") => {
-                self.motion(SeekUntil(self.search_string.clone().as_bytes()));
-                return Some(EditorCommand::CenterIfNotVisible);
+                self.motion(view, SeekUntil(self.search_string.clone().as_bytes()));
+                return Some(EditorCommand::RevealJump);
             }
             (_, "N") => {
-                self.motion(SeekBackUntil(self.search_string.clone().as_bytes()));
-                return Some(EditorCommand::CenterIfNotVisible);
+                self.motion(view, SeekBackUntil(self.search_string.clone().as_bytes()));
+                return Some(EditorCommand::RevealJump);
             }
-            (_, "G") => self.motion(ToEndOfFile),
+            (_, "G") => self.motion(view, ToEndOfFile),
             (_, s) if s.starts_with('f') && s.len() == 2 => {
-                self.motion(ForwardToChar(s.chars().nth(1).unwrap() as u8));
+                self.motion(view, ForwardToChar(s.chars().nth(1).unwrap() as u8));
             }
             (_, s) if s.starts_with('F') && s.len() == 2 => {
-                self.motion(BackwardToChar(s.chars().nth(1).unwrap() as u8));
+                self.motion(view, BackwardToChar(s.chars().nth(1).unwrap() as u8));
             }
             (_, s) if s.starts_with('t') && s.len() == 2 => {
-                self.motion(ForwardUntilChar(s.chars().nth(1).unwrap() as u8));
+                self.motion(view, ForwardUntilChar(s.chars().nth(1).unwrap() as u8));
             }
             (_, s) if s.starts_with('T') && s.len() == 2 => {
-                self.motion(BackwardUntilChar(s.chars().nth(1).unwrap() as u8));
+                self.motion(view, BackwardUntilChar(s.chars().nth(1).unwrap() as u8));
             }
 
             (Visual, "y") => {
-                self.command(CopySelection);
-                for cursor in &mut self.cursors {
+                self.command(view, CopySelection);
+                for cursor in &mut view.cursors {
                     cursor.position = min(cursor.anchor, cursor.position);
                 }
-                self.switch_to_normal_mode();
+                self.switch_to_normal_mode(view);
             }
             (VisualLine, "y") => {
-                self.motion(ExtendSelection);
-                self.command(CopySelection);
-                for cursor in &mut self.cursors {
+                self.motion(view, ExtendSelection);
+                self.command(view, CopySelection);
+                for cursor in &mut view.cursors {
                     cursor.position = min(cursor.anchor, cursor.position);
                 }
-                self.switch_to_normal_mode();
+                self.switch_to_normal_mode(view);
             }
 
+            (Visual | VisualLine, s) if s.starts_with('S') && s.len() == 2 => {
+                let c = s.as_bytes()[1];
+                if matches!(c, b'(' | b')' | b'{' | b'}' | b'[' | b']' | b'<' | b'>')
+                    || matches!(c, b'"' | b'\'' | b'`')
+                {
+                    self.push_undo_state(view);
+                    if view.mode == VisualLine {
+                        self.motion(view, ExtendSelection);
+                    }
+                    self.command(view, Surround(c));
+                    self.switch_to_normal_mode(view);
+                }
+            }
             (Visual, "p") => {
-                self.push_undo_state();
-                self.command(CutSelection);
-                self.motion(BackwardOnceWrapping);
-                self.command(PasteSelection);
-                self.switch_to_normal_mode();
+                self.push_undo_state(view);
+                self.paste_over_selection(view, PasteSelection);
+                self.switch_to_normal_mode(view);
             }
             (Visual, "P") => {
-                self.push_undo_state();
-                self.command(CutSelection);
-                self.motion(BackwardOnceWrapping);
-                self.command(PasteCursorSelection);
-                self.switch_to_normal_mode();
+                self.push_undo_state(view);
+                self.paste_over_selection(view, PasteCursorSelection);
+                self.switch_to_normal_mode(view);
             }
 
             (VisualLine, "p") => {
-                self.push_undo_state();
-                self.motion(ExtendSelection);
-                self.command(CutSelection);
-                self.motion(BackwardOnceWrapping);
-                self.command(PasteSelection);
-                self.switch_to_normal_mode();
+                self.push_undo_state(view);
+                self.motion(view, ExtendSelection);
+                self.paste_over_selection(view, PasteSelection);
+                self.switch_to_normal_mode(view);
             }
             (VisualLine, "P") => {
-                self.push_undo_state();
-                self.motion(ExtendSelection);
-                self.command(CutSelection);
-                self.motion(BackwardOnceWrapping);
-                self.command(PasteCursorSelection);
-                self.switch_to_normal_mode();
+                self.push_undo_state(view);
+                self.motion(view, ExtendSelection);
+                self.paste_over_selection(view, PasteCursorSelection);
+                self.switch_to_normal_mode(view);
             }
 
             (Normal, "yy") => {
-                self.switch_to_visual_mode();
-                self.command(CopyLine);
-                self.switch_to_normal_mode();
+                self.switch_to_visual_mode(view);
+                self.command(view, CopyLine);
+                self.switch_to_normal_mode(view);
             }
             (Normal, "p") => {
-                self.push_undo_state();
-                self.command(PasteSelection);
-                self.last_executed_command = Some(self.input.clone());
+                self.push_undo_state(view);
+                self.command(view, PasteSelection);
             }
             (Normal, "P") => {
-                self.push_undo_state();
-                self.command(PasteCursorSelection);
-                self.last_executed_command = Some(self.input.clone());
+                self.push_undo_state(view);
+                self.command(view, PasteCursorSelection);
+            }
+            (Normal, "]p") => {
+                self.push_undo_state(view);
+                self.command(view, PasteSelectionReindented);
             }
 
             (Normal | Visual | VisualLine, ">") => {
-                self.push_undo_state();
-                self.command(IndentLine);
+                self.push_undo_state(view);
+                self.command(view, IndentLine);
             }
 
             (Normal | Visual | VisualLine, "<") => {
-                self.push_undo_state();
-                self.command(UnindentLine);
+                self.push_undo_state(view);
+                self.command(view, UnindentLine);
+            }
+
+            (Normal, "gcc" | "gcj" | "gck" | "gcG" | "gcgg" | "gcip") => {
+                let motion = self.input[2..].to_string();
+                self.comment_motion(view, &motion);
+            }
+            (Normal, s) if s.starts_with("gci") && s.len() == 4 => {
+                let motion = s[2..].to_string();
+                self.comment_motion(view, &motion);
             }
 
             (Normal, s) if s.starts_with("ci") && s.len() == 3 => {
-                self.last_executed_command = Some(self.input.clone());
                 let c = s.chars().nth(2).unwrap() as u8;
-                self.command(CutMotion(c, CutMotion::Inside, true));
+                self.command(view, CutMotion(c, CutMotion::Inside, true));
             }
             (Normal, s) if s.starts_with("di") && s.len() == 3 => {
-                self.last_executed_command = Some(self.input.clone());
                 let c = s.chars().nth(2).unwrap() as u8;
-                self.command(CutMotion(c, CutMotion::Inside, false));
+                self.command(view, CutMotion(c, CutMotion::Inside, false));
             }
 
             (Normal, s) if s.starts_with("ct") && s.len() == 3 => {
-                self.last_executed_command = Some(self.input.clone());
                 let c = s.chars().nth(2).unwrap() as u8;
-                self.command(CutMotion(c, CutMotion::ForwardUntil, true));
+                self.command(view, CutMotion(c, CutMotion::ForwardUntil, true));
             }
             (Normal, s) if s.starts_with("dt") && s.len() == 3 => {
-                self.last_executed_command = Some(self.input.clone());
                 let c = s.chars().nth(2).unwrap() as u8;
-                self.command(CutMotion(c, CutMotion::ForwardUntil, false));
+                self.command(view, CutMotion(c, CutMotion::ForwardUntil, false));
             }
             (Normal, s) if s.starts_with("cT") && s.len() == 3 => {
-                self.last_executed_command = Some(self.input.clone());
                 let c = s.chars().nth(2).unwrap() as u8;
-                self.command(CutMotion(c, CutMotion::BackwardUntil, true));
+                self.command(view, CutMotion(c, CutMotion::BackwardUntil, true));
             }
             (Normal, s) if s.starts_with("dT") && s.len() == 3 => {
-                self.last_executed_command = Some(self.input.clone());
                 let c = s.chars().nth(2).unwrap() as u8;
-                self.command(CutMotion(c, CutMotion::BackwardTo, false));
+                self.command(view, CutMotion(c, CutMotion::BackwardTo, false));
             }
 
             (Visual, s) if s.starts_with('i') && s.len() == 2 => {
-                self.motion(ExtendSelectionInside(s.chars().nth(1).unwrap() as u8))
+                self.motion(view, ExtendSelectionInside(s.chars().nth(1).unwrap() as u8))
             }
 
             (Normal, "x") => {
-                self.last_executed_command = Some(self.input.clone());
-                self.push_undo_state();
-                self.command(CopySelection);
-                self.command(CutSelection);
+                self.push_undo_state(view);
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
             }
             (Visual, "x") => {
-                self.push_undo_state();
-                self.command(CopySelection);
-                self.command(CutSelection);
+                self.push_undo_state(view);
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
             }
             (VisualLine, "x") => {
-                self.push_undo_state();
-                self.motion(ExtendSelection);
-                self.command(CopySelection);
-                self.command(CutSelection);
+                self.push_undo_state(view);
+                self.motion(view, ExtendSelection);
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
             }
 
             (Visual, "d") => {
-                self.push_undo_state();
-                self.command(CopySelection);
-                self.command(CutSelection);
-                self.switch_to_normal_mode();
+                self.push_undo_state(view);
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
+                self.switch_to_normal_mode(view);
             }
             (VisualLine, "d") => {
-                self.push_undo_state();
-                self.motion(ExtendSelection);
-                self.command(CopySelection);
-                self.command(CutSelection);
-                self.switch_to_normal_mode();
+                self.push_undo_state(view);
+                self.motion(view, ExtendSelection);
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
+                self.switch_to_normal_mode(view);
             }
 
             (Normal, "dd") => {
-                self.last_executed_command = Some(self.input.clone());
-                self.push_undo_state();
-                self.switch_to_visual_mode();
-                self.motion(ExtendSelection);
-                self.command(CopySelection);
-                self.command(CutSelection);
-                self.switch_to_normal_mode();
+                self.push_undo_state(view);
+                self.switch_to_visual_mode(view);
+                self.motion(view, ExtendSelection);
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
+                self.switch_to_normal_mode(view);
             }
             (Normal, "D") => {
-                self.last_executed_command = Some(self.input.clone());
-                self.push_undo_state();
-                self.switch_to_visual_mode();
-                self.motion(ToEndOfLine);
-                self.motion(Backward(1));
-                self.command(CopySelection);
-                self.command(CutSelection);
-                self.switch_to_normal_mode();
-            }
-            (Normal, "J") => self.command(InsertCursorBelow),
-            (Normal, "K") => self.command(InsertCursorAbove),
+                self.push_undo_state(view);
+                self.switch_to_visual_mode(view);
+                self.motion(view, ToEndOfLine);
+                self.motion(view, Backward(1));
+                self.command(view, CopySelection);
+                self.command(view, CutSelection);
+                self.switch_to_normal_mode(view);
+            }
+            (Normal, "J") => self.command(view, InsertCursorBelow),
+            (Normal, "gK") => self.command(view, InsertCursorAbove),
+            (Normal, "K") => return Some(EditorCommand::Hover),
             (Normal, s) if s.starts_with('r') && s.len() == 2 => {
                 let c = s.chars().nth(1).unwrap() as u8;
-                self.push_undo_state();
-                self.command(ReplaceChar(c));
+                self.push_undo_state(view);
+                self.command(view, ReplaceChar(c));
             }
             (Normal, "i") => {
-                self.push_undo_state();
-                self.switch_to_insert_mode();
+                self.push_undo_state(view);
+                self.switch_to_insert_mode(view);
             }
             (Normal, "I") => {
-                self.push_undo_state();
-                self.motion(ToFirstNonBlankChar);
-                self.switch_to_insert_mode();
+                self.push_undo_state(view);
+                self.motion(view, ToFirstNonBlankChar);
+                self.switch_to_insert_mode(view);
             }
             (Normal, "a") => {
-                self.push_undo_state();
-                self.switch_to_insert_mode();
-                self.motion(Forward(1));
+                self.push_undo_state(view);
+                self.switch_to_insert_mode(view);
+                self.motion(view, Forward(1));
             }
             (Normal, "A") => {
-                self.push_undo_state();
-                self.switch_to_insert_mode();
-                self.motion(ToEndOfLine);
-                self.motion(Forward(1));
+                self.push_undo_state(view);
+                self.switch_to_insert_mode(view);
+                self.motion(view, ToEndOfLine);
+                self.motion(view, Forward(1));
             }
             (Normal, "o") => {
-                self.push_undo_state();
-                self.switch_to_insert_mode();
-                self.motion(ToEndOfLine);
-                self.motion(Forward(1));
-                self.command(InsertNewLine);
+                self.push_undo_state(view);
+                self.switch_to_insert_mode(view);
+                self.motion(view, ToEndOfLine);
+                self.motion(view, Forward(1));
+                self.command(view, InsertNewLine);
             }
             (Normal, "O") => {
-                self.push_undo_state();
-                self.switch_to_insert_mode();
-                self.motion(ToStartOfLine);
-                self.command(InsertNewLine);
-                self.motion(Up(1));
+                self.push_undo_state(view);
+                self.switch_to_insert_mode(view);
+                self.motion(view, ToStartOfLine);
+                self.command(view, InsertNewLine);
+                self.motion(view, Up(1));
             }
             (Normal, "u") => {
-                self.command(Undo);
-            }
-            (Normal, ".") => {
-                if let Some(command) = &self.last_executed_command {
-                    if let Some(last_char) = command.as_bytes().last() {
-                        self.input = command[..command.len().saturating_sub(1)].to_string();
-                        let change_command = self.input.starts_with('c');
-                        self.handle_char(*last_char as char);
-
-                        if change_command {
-                            self.switch_to_insert_mode();
-                            let insertion_commands: Vec<BufferCommand> =
-                                self.insertion_command_stack.to_vec();
-                            let tmp = self.insertion_command_stack.clone();
-                            for insertion_command in &insertion_commands {
-                                self.command(*insertion_command);
-                            }
-                            self.insertion_command_stack = tmp;
-                            self.motion(Backward(1));
-                            self.switch_to_normal_mode();
-                        }
-                    }
-                }
+                self.command(view, Undo);
+            }
+            (Normal, ".") => self.repeat_change(view),
+            (Normal, s) if let Some(line) = counted_goto(s) => {
+                self.motion(view, GotoLine(line));
+                self.motion(view, ToFirstNonBlankChar);
+                self.input.clear();
+                return Some(EditorCommand::RevealJump);
             }
             (Normal, "gd") => {
-                self.command(GotoDefinition);
+                self.command(view, GotoDefinition);
             }
             (Normal, "gi") => {
-                self.command(GotoImplementation);
+                self.command(view, GotoImplementation);
+            }
+            (Normal, "gr") => {
+                self.command(view, FindReferences);
+            }
+            (Normal, "gf") => return self.file_at_cursor(view, false),
+            (Normal, "gF") => return self.file_at_cursor(view, true),
+            (Normal, "gx") => return self.url_at_cursor(view),
+            (Normal, "]d") => return self.goto_diagnostic(view, true, false),
+            (Normal, "[d") => return self.goto_diagnostic(view, false, false),
+            (Normal, "]e") => return self.goto_diagnostic(view, true, true),
+            (Normal, "[e") => return self.goto_diagnostic(view, false, true),
+            (Visual, "v") => self.switch_to_normal_mode(view),
+            (_, "v") => self.switch_to_visual_mode(view),
+            (VisualLine, "V") => self.switch_to_normal_mode(view),
+            (_, "V") => self.switch_to_visual_line_mode(view),
+
+            (Normal, s) if is_operator_command(s) => {
+                let input = self.input.clone();
+                self.operator_command(view, &input);
             }
-            (Visual, "v") => self.switch_to_normal_mode(),
-            (_, "v") => self.switch_to_visual_mode(),
-            (VisualLine, "V") => self.switch_to_normal_mode(),
-            (_, "V") => self.switch_to_visual_line_mode(),
 
             _ => return None,
         }
 
-        if self.mode == Normal {
-            for cursor in &mut self.cursors {
+        if view.mode == Normal {
+            for cursor in &mut view.cursors {
                 cursor.reset_anchor();
             }
         }
         self.input.clear();
-        self.merge_cursors();
+        self.merge_cursors(view);
         None
     }
 
-    pub fn update_highlights(&mut self) -> bool {
+    // Moves pending chunks in (and right around) the viewport to the front of the queue. They stay
+    // queued further back as well, so they are redone once the preceding chunks provide the parse state.
+    pub fn prioritize_highlights(&mut self, line_offset: usize, num_rows: usize) {
+        if self.highlights_evicted {
+            self.highlights_evicted = false;
+            self.queue_all_highlights();
+        }
+
+        let viewport = (
+            line_offset / SYNTECT_CACHE_FREQUENCY,
+            (line_offset + num_rows) / SYNTECT_CACHE_FREQUENCY,
+        );
+        if self.highlight_viewport == Some(viewport) {
+            return;
+        }
+        self.highlight_viewport = Some(viewport);
+
+        let mut chunks: Vec<usize> = (viewport.0..=viewport.1).collect();
+        chunks.push(viewport.1 + 1);
+        if viewport.0 > 0 {
+            chunks.push(viewport.0 - 1);
+        }
+
+        for chunk in chunks.iter().rev() {
+            let line = chunk * SYNTECT_CACHE_FREQUENCY;
+            if self.highlight_queue.contains(&line) {
+                self.highlight_queue.push_front(line);
+            }
+        }
+    }
+
+    // Chunks stay in our queue until the highlighter is nearly idle so that they can still be
+    // reordered when the view scrolls. Copying them out stops once the deadline has passed
+    pub fn update_highlights(&mut self, deadline: Instant) -> bool {
         if let Some(syntect) = &mut self.syntect {
-            if let Some(line) = self.highlight_queue.pop_front() {
-                syntect.queue.lock().unwrap().push_back(IndexedLine {
+            while Instant::now() < deadline && syntect.queue_length() < MAX_QUEUED_HIGHLIGHT_CHUNKS
+            {
+                let Some(line) = self.highlight_queue.pop_front() else {
+                    break;
+                };
+                syntect.enqueue(IndexedLine {
                     index: line,
                     text: self
                         .piece_table
@@ -746,8 +2386,8 @@ impl Buffer {
         false
     }
 
-    pub fn update_completions(&mut self, server: &mut RefMut<LanguageServer>) {
-        for cursor in &mut self.cursors {
+    pub fn update_completions(&mut self, view: &mut View, server: &mut RefMut<LanguageServer>) {
+        for cursor in &mut view.cursors {
             if let Some(request) = cursor.completion_request.as_mut() {
                 if request
                     .next_id
@@ -764,8 +2404,132 @@ impl Buffer {
         }
     }
 
-    pub fn update_signature_helps(&mut self, server: &mut RefMut<LanguageServer>) {
-        for cursor in &mut self.cursors {
+    // Moves the selection of every open completion popup by one item or one page
+    fn move_completion_selection(
+        &mut self,
+        forward: bool,
+        page: bool,
+        view: &mut View,
+        layout: &RenderLayout,
+    ) {
+        let Some(server) = &self.language_server else {
+            return;
+        };
+        let server = server.borrow();
+        for i in 0..view.cursors.len() {
+            let cursor = &view.cursors[i];
+            let Some(request) = &cursor.completion_request else {
+                continue;
+            };
+            let Some(completion_list) = server.saved_completions.get(&request.id) else {
+                continue;
+            };
+            let filtered_completions = get_filtered_completions(
+                &self.piece_table,
+                completion_list,
+                request,
+                cursor.position,
+            );
+            let Some(completion_view) = view.get_completion_view(
+                &self.piece_table,
+                &filtered_completions,
+                request.position,
+                layout,
+            ) else {
+                continue;
+            };
+            let num_completions = filtered_completions.len();
+
+            let step = if page {
+                max(completion_view.height, 1)
+            } else {
+                1
+            };
+            let Some(request) = &mut view.cursors[i].completion_request else {
+                continue;
+            };
+            request.selection_index = if forward {
+                min(
+                    request.selection_index + step,
+                    num_completions.saturating_sub(1),
+                )
+            } else {
+                request.selection_index.saturating_sub(step)
+            };
+            request.documentation_offset = 0;
+
+            if request.selection_index < request.selection_view_offset {
+                request.selection_view_offset = request.selection_index;
+            } else if request.selection_index
+                >= request.selection_view_offset + completion_view.height
+            {
+                request.selection_view_offset =
+                    (request.selection_index + 1).saturating_sub(completion_view.height);
+            }
+        }
+    }
+
+    // Scrolls the documentation of the selected completions by half of the documentation panel
+    fn scroll_completion_documentation(&mut self, view: &mut View, down: bool) {
+        let Some(server) = &self.language_server else {
+            return;
+        };
+        let server = server.borrow();
+        for cursor in &mut view.cursors {
+            let Some(ref mut request) = cursor.completion_request else {
+                continue;
+            };
+            let Some(completion_list) = server.saved_completions.get(&request.id) else {
+                continue;
+            };
+            let Some(documentation) = get_filtered_completions(
+                &self.piece_table,
+                completion_list,
+                request,
+                cursor.position,
+            )
+            .get(request.selection_index)
+            .and_then(completion_documentation) else {
+                continue;
+            };
+
+            let step = MAX_SHOWN_DOCUMENTATION_LINES / 2;
+            request.documentation_offset = if down {
+                min(
+                    request.documentation_offset + step,
+                    documentation
+                        .num_lines
+                        .saturating_sub(MAX_SHOWN_DOCUMENTATION_LINES),
+                )
+            } else {
+                request.documentation_offset.saturating_sub(step)
+            };
+        }
+    }
+
+    // Deprecated items are hidden or shown in every completion popup, the selection starts over
+    fn toggle_deprecated_completions(&mut self, view: &mut View) {
+        if completion::toggle_deprecated_completions() {
+            log::notify("Showing deprecated completions");
+        } else {
+            log::notify("Hiding deprecated completions");
+        }
+        for cursor in &mut view.cursors {
+            reset_completion_view(cursor, &mut self.language_server);
+        }
+    }
+
+    // Accepts the selected completion and goes to the definition of what was inserted
+    fn goto_completion_definition(&mut self, view: &mut View) {
+        self.push_undo_state(view);
+        self.command(view, Complete);
+        let position = view.cursors.last().unwrap().position.saturating_sub(1);
+        self.switch_to_normal_mode(view);
+        self.lsp_goto_definition(position);
+    }
+
+    pub fn update_signature_helps(&mut self, view: &mut View, server: &mut RefMut<LanguageServer>) {
+        for cursor in &mut view.cursors {
             if let Some(request) = cursor.signature_help_request.as_mut() {
                 if request
                     .next_id
@@ -782,331 +2546,1010 @@ impl Buffer {
 
                     server.saved_signature_helps.remove(&request.id);
 
-                    request.id = request.next_id.unwrap();
-                    request.next_id = None;
-                    request.next_position = None;
+                    request.id = request.next_id.unwrap();
+                    request.next_id = None;
+                    request.next_position = None;
+                }
+            }
+        }
+    }
+
+    // Catches the cursors of every view up with the edits made through the others
+    pub fn sync_views(&mut self, views: &mut [View]) {
+        for view in views.iter_mut() {
+            self.catch_up(view);
+            view.rebalanced = 0;
+        }
+        self.cursor_rebalances.clear();
+    }
+
+    fn catch_up(&self, view: &mut View) {
+        if view.rebalanced >= self.cursor_rebalances.len() {
+            return;
+        }
+        for rebalance in &self.cursor_rebalances[view.rebalanced..] {
+            match *rebalance {
+                CursorRebalance::Insert(position, count) => {
+                    cursors_insert_rebalance(&mut view.cursors, position, count)
+                }
+                CursorRebalance::Delete(start, end) => {
+                    cursors_delete_rebalance(&mut view.cursors, start, end)
+                }
+            }
+        }
+        view.rebalanced = self.cursor_rebalances.len();
+
+        // Undo and redo restore the cursors of the view they are made through, the cursors of
+        // the other view may end up past the end
+        let last_char = self.piece_table.num_chars().saturating_sub(1);
+        for cursor in &mut view.cursors {
+            cursor.position = min(cursor.position, last_char);
+            cursor.anchor = min(cursor.anchor, last_char);
+        }
+    }
+
+    pub fn ready_to_quit(&mut self) -> bool {
+        if !self.piece_table.dirty {
+            return true;
+        }
+
+        let Some(platform_resources) = &self.platform_resources else {
+            return true;
+        };
+        if let Some(user_wants_save) = platform_resources.confirm_quit(&self.path) {
+            if user_wants_save {
+                self.save();
+            }
+            return true;
+        }
+
+        false
+    }
+
+    pub fn update_syntect(&mut self, view: &mut View, line: usize) {
+        if let Some(syntect) = &mut self.syntect {
+            syntect.clear_queue();
+            self.highlight_queue.clear();
+            self.highlight_viewport = None;
+
+            let start = if let Some(last_cursor) = view.cursors.last() {
+                self.piece_table.line_index(last_cursor.position) / SYNTECT_CACHE_FREQUENCY
+            } else {
+                0
+            };
+
+            if start > 0 {
+                self.highlight_queue
+                    .push_back((start - 1) * SYNTECT_CACHE_FREQUENCY);
+                self.highlight_queue
+                    .push_back(start * SYNTECT_CACHE_FREQUENCY);
+                if start + 1 < self.piece_table.num_lines() {
+                    self.highlight_queue
+                        .push_back((start + 1) * SYNTECT_CACHE_FREQUENCY);
+                }
+            }
+
+            let mut i = (line / SYNTECT_CACHE_FREQUENCY) * SYNTECT_CACHE_FREQUENCY;
+            while i < self.piece_table.num_lines() {
+                self.highlight_queue.push_back(i);
+                i += SYNTECT_CACHE_FREQUENCY;
+            }
+        }
+    }
+
+    fn handle_input_command(&mut self, view: &mut View) -> Option<EditorCommand> {
+        let input = self.input.clone();
+        match input.as_str() {
+            input if input.as_bytes().first() == Some(&b'/') => {
+                self.motion(view, SeekToSelf(input[1..].as_bytes()));
+                self.search_string = input[1..].to_string();
+                return Some(EditorCommand::RevealJump);
+            }
+            input
+                if let Some(filetype) = input
+                    .strip_prefix(":set filetype=")
+                    .or_else(|| input.strip_prefix(":set ft=")) =>
+            {
+                return Some(EditorCommand::SetFiletype(
+                    normalize_filetype(filetype.trim()).to_string(),
+                ));
+            }
+            ":set announcemode" => return Some(EditorCommand::SetAnnounceMode(true)),
+            ":set noannouncemode" => return Some(EditorCommand::SetAnnounceMode(false)),
+            input
+                if let Some(num_items) = input
+                    .strip_prefix(":set pumheight=")
+                    .and_then(|num_items| num_items.trim().parse::<usize>().ok()) =>
+            {
+                view::set_max_shown_completion_items(num_items);
+            }
+            input if let Some(name) = input.strip_prefix(":set jumpcenter=") => {
+                match JumpCenter::parse(name.trim()) {
+                    Some(jump_center) => view::set_jump_center(jump_center),
+                    None => log::warning("Usage: :set jumpcenter=always|offscreen|scrolloff"),
+                }
+            }
+            input
+                if let Some(milliseconds) = input
+                    .strip_prefix(":set hoverdelay=")
+                    .and_then(|milliseconds| milliseconds.trim().parse::<u64>().ok()) =>
+            {
+                return Some(EditorCommand::SetHoverDelay(milliseconds));
+            }
+            ":set savesummary" => self.save_summary = true,
+            ":set nosavesummary" => self.save_summary = false,
+            ":set organizeimportsonsave" => self.organize_imports_on_save = true,
+            ":set noorganizeimportsonsave" => self.organize_imports_on_save = false,
+            input if let Some(substitution) = parse_substitution(input) => {
+                self.substitute(view, &substitution);
+            }
+            input if input.starts_with(":s/") || input.starts_with(":%s/") => log::warning(
+                "Usage: :s/pattern/replacement/[g] or :%s/pattern/replacement/[g], g is the only flag",
+            ),
+            ":set wordhighlight" => self.word_highlight = true,
+            ":set nowordhighlight" => self.word_highlight = false,
+            ":set lint" => self.lint = true,
+            ":set nolint" => self.lint = false,
+            ":set colorblind" => return Some(EditorCommand::SetColorBlind(true)),
+            ":set nocolorblind" => return Some(EditorCommand::SetColorBlind(false)),
+            ":set number" => return Some(EditorCommand::SetNumber(true)),
+            ":set nonumber" => return Some(EditorCommand::SetNumber(false)),
+            ":set relativenumber" => return Some(EditorCommand::SetRelativeNumber(true)),
+            ":set norelativenumber" => return Some(EditorCommand::SetRelativeNumber(false)),
+            ":set inlinediagnostics" => return Some(EditorCommand::SetInlineDiagnostics(true)),
+            ":set noinlinediagnostics" => return Some(EditorCommand::SetInlineDiagnostics(false)),
+            ":set primaryselection" => return Some(EditorCommand::SetPrimarySelection(true)),
+            ":set noprimaryselection" => return Some(EditorCommand::SetPrimarySelection(false)),
+            input
+                if let Some(line) = goto_line_target(
+                    &input[1..],
+                    self.piece_table
+                        .line_index(view.cursors.last().unwrap().position)
+                        + 1,
+                    self.piece_table.num_lines(),
+                ) =>
+            {
+                self.motion(view, GotoLine(line));
+                self.motion(view, ToFirstNonBlankChar);
+                return Some(EditorCommand::RevealJump);
+            }
+            ":w" => {
+                self.save_with_hooks(true);
+            }
+            ":wq" => {
+                return Some(EditorCommand::SaveAndQuit);
+            }
+            ":q" | ":bd" => {
+                return Some(EditorCommand::Quit);
+            }
+            ":q!" | ":bd!" => {
+                return Some(EditorCommand::QuitNoCheck);
+            }
+            ":qa" => {
+                return Some(EditorCommand::QuitAll);
+            }
+            ":qa!" => {
+                return Some(EditorCommand::QuitAllNoCheck);
+            }
+            ":only" => {
+                return Some(EditorCommand::QuitOthers);
+            }
+            ":only!" => {
+                return Some(EditorCommand::QuitOthersNoCheck);
+            }
+            ":closesaved" => {
+                return Some(EditorCommand::QuitSaved);
+            }
+            ":closehidden" => {
+                return Some(EditorCommand::QuitHidden);
+            }
+            ":closehidden!" => {
+                return Some(EditorCommand::QuitHiddenNoCheck);
+            }
+            ":wa" => {
+                return Some(EditorCommand::SaveAll);
+            }
+            ":xa" | ":wqa" => {
+                return Some(EditorCommand::SaveAllAndQuit);
+            }
+            ":split" => {
+                return Some(EditorCommand::ToggleSplitView);
+            }
+            ":vsplit" | ":vsp" => {
+                return Some(EditorCommand::VerticalSplit(None));
+            }
+            // The jump target of gd/gi/gf is opened in the other view
+            input
+                if let Some(target) = input
+                    .strip_prefix(":vsplit ")
+                    .or_else(|| input.strip_prefix(":vsp ")) =>
+            {
+                match target.trim() {
+                    "gd" => {
+                        self.command(view, GotoDefinition);
+                        return Some(EditorCommand::SplitNextJump);
+                    }
+                    "gi" => {
+                        self.command(view, GotoImplementation);
+                        return Some(EditorCommand::SplitNextJump);
+                    }
+                    "gf" => {
+                        if let Some(EditorCommand::GotoFile(path, _)) =
+                            self.file_at_cursor(view, false)
+                        {
+                            return Some(EditorCommand::VerticalSplit(Some(path)));
+                        }
+                    }
+                    "" => return Some(EditorCommand::VerticalSplit(None)),
+                    path => return Some(EditorCommand::VerticalSplit(Some(path.to_string()))),
+                }
+            }
+            ":delete" => {
+                if self.piece_table.dirty {
+                    log::warning(format!(
+                        "{} has unsaved changes, use :delete! to discard them",
+                        self.path
+                    ));
+                } else {
+                    return Some(EditorCommand::DeleteFile);
+                }
+            }
+            ":delete!" => {
+                return Some(EditorCommand::DeleteFile);
+            }
+            ":undelete" => {
+                return Some(EditorCommand::RestoreFile);
+            }
+            ":copypath" => return Some(EditorCommand::CopyPath(PathFormat::Absolute)),
+            ":copyrelpath" => return Some(EditorCommand::CopyPath(PathFormat::Relative)),
+            ":copyref" => return Some(EditorCommand::CopyPath(PathFormat::Reference)),
+            ":reveal" => return Some(EditorCommand::RevealFile),
+            ":hex" => return Some(EditorCommand::HexView),
+            ":trust" => return Some(EditorCommand::TrustWorkspace),
+            ":registers" | ":reg" => {
+                return Some(EditorCommand::ShowRegisters);
+            }
+            ":history" => return Some(EditorCommand::ShowHistory),
+            ":blame" => git::blame(
+                &self.path,
+                self.piece_table
+                    .line_index(view.cursors.last().unwrap().position),
+            ),
+            ":OrganizeImports" => {
+                self.lsp_code_action("source.organizeImports", false);
+            }
+            ":FixAll" => {
+                self.lsp_code_action("source.fixAll", false);
+            }
+            ":retab" => self.retab(view, false),
+            ":retab!" => self.retab(view, true),
+            ":trim" => self.trim_trailing_whitespace(view),
+            ":align" => self.align(view, ""),
+            input if let Some(pattern) = input.strip_prefix(":align ") => {
+                self.align(view, pattern.trim())
+            }
+            input if let Some(case) = input.strip_prefix(":case") => match case.trim() {
+                "snake" => self.convert_case(view, IdentifierCase::Snake),
+                "camel" => self.convert_case(view, IdentifierCase::Camel),
+                "pascal" => self.convert_case(view, IdentifierCase::Pascal),
+                "screaming" => self.convert_case(view, IdentifierCase::ScreamingSnake),
+                _ => log::warning("Usage: :case snake|camel|pascal|screaming"),
+            },
+            input if let Some(arguments) = input.strip_prefix(":seq") => {
+                let arguments: Option<Vec<i64>> = arguments
+                    .split_whitespace()
+                    .map(|argument| argument.parse().ok())
+                    .collect();
+                match arguments.as_deref() {
+                    Some([]) => self.insert_sequence(view, 1, 1),
+                    Some([start]) => self.insert_sequence(view, *start, 1),
+                    Some([start, step]) => self.insert_sequence(view, *start, *step),
+                    _ => log::warning("Usage: :seq [START] [STEP]"),
+                }
+            }
+            input
+                if let Some(level) = input
+                    .strip_prefix(":messages")
+                    .and_then(|level| log::level_from_str(level.trim())) =>
+            {
+                return Some(EditorCommand::ShowMessages(level));
+            }
+            ":grep" => return Some(EditorCommand::SearchWorkspace(String::default())),
+            input if let Some(query) = input.strip_prefix(":grep ") => {
+                return Some(EditorCommand::SearchWorkspace(query.to_string()));
+            }
+            ":symbols" => return Some(EditorCommand::SearchSymbols(String::default())),
+            input if let Some(query) = input.strip_prefix(":symbols ") => {
+                return Some(EditorCommand::SearchSymbols(query.to_string()));
+            }
+            input if let Some(path) = input.strip_prefix(":move ") => {
+                if !path.trim().is_empty() {
+                    return Some(EditorCommand::RenameFile(path.trim().to_string()));
+                }
+            }
+            ":rename" => self.start_rename_session(view),
+            input if let Some(new_name) = input.strip_prefix(":rename ") => {
+                if let Some(last_cursor) = view.cursors.last() {
+                    let new_name = new_name.trim().to_string();
+                    match new_name.is_empty() {
+                        true => self.start_rename_session(view),
+                        false => self.lsp_rename(last_cursor.position, &new_name),
+                    }
+                }
+            }
+            ":copyrich" => {
+                if let Some((text, html, rtf)) = self.export_selection(view) {
+                    match &self.platform_resources {
+                        Some(platform_resources) => platform_resources.set_clipboard_rich(
+                            &text,
+                            html.as_bytes(),
+                            rtf.as_bytes(),
+                        ),
+                        None => self.headless_clipboard = text,
+                    }
+                }
+                self.switch_to_normal_mode(view);
+            }
+            input if let Some(path) = input.strip_prefix(":export") => {
+                if self.passphrase.is_some() {
+                    log::warning("Encrypted files can't be exported as plain text");
+                    self.switch_to_normal_mode(view);
+                    return None;
+                }
+                let path = match path.trim() {
+                    "" => format!("{}.html", self.path),
+                    path => path.to_string(),
+                };
+                if let Some((_, html, _)) = self.export_selection(view) {
+                    match std::fs::write(&path, html) {
+                        Ok(()) => log::notify(format!("Exported {}", path)),
+                        Err(error) => log::error(format!("Failed to export {}: {}", path, error)),
+                    }
+                }
+                self.switch_to_normal_mode(view);
+            }
+            _ => (),
+        }
+        None
+    }
+
+    // Rewrites the indentation of the selected lines (or the whole buffer) as spaces or tabs
+    fn retab(&mut self, view: &mut View, use_tabs: bool) {
+        let mut lines: Vec<usize> = match view.mode {
+            Visual | VisualLine => view
+                .cursors
+                .iter()
+                .flat_map(|cursor| {
+                    let line = self.piece_table.line_index(cursor.position);
+                    let anchor_line = self.piece_table.line_index(cursor.anchor);
+                    min(line, anchor_line)..=max(line, anchor_line)
+                })
+                .collect(),
+            _ => (0..self.piece_table.num_lines()).collect(),
+        };
+        lines.sort_unstable();
+        lines.dedup();
+
+        let indent_width = max(self.piece_table.indent_width, 1);
+        let mut edits = vec![];
+        for i in lines {
+            let Some(line) = self.piece_table.line_at_index(i) else {
+                continue;
+            };
+            let indentation: Vec<u8> = self
+                .piece_table
+                .iter_chars_at(line.start)
+                .take_while(|c| *c == b' ' || *c == b'\t')
+                .collect();
+            let width = indentation.iter().fold(0, |width, c| {
+                if *c == b'\t' {
+                    width + indent_width - width % indent_width
+                } else {
+                    width + 1
+                }
+            });
+            let new_indentation = if use_tabs {
+                "\t".repeat(width / indent_width) + &" ".repeat(width % indent_width)
+            } else {
+                " ".repeat(width)
+            };
+
+            if new_indentation.as_bytes() != indentation {
+                edits.push(TextEdit {
+                    range: Range {
+                        start: Position {
+                            line: i as u32,
+                            character: 0,
+                        },
+                        end: Position {
+                            line: i as u32,
+                            character: indentation.len() as u32,
+                        },
+                    },
+                    new_text: new_indentation,
+                });
+            }
+        }
+
+        self.switch_to_normal_mode(view);
+        self.apply_text_edits(view, &edits);
+    }
+
+    // Removes the trailing whitespace of the selected lines (or the whole buffer)
+    fn trim_trailing_whitespace(&mut self, view: &mut View) {
+        let mut lines: Vec<usize> = match view.mode {
+            Visual | VisualLine => view
+                .cursors
+                .iter()
+                .flat_map(|cursor| {
+                    let line = self.piece_table.line_index(cursor.position);
+                    let anchor_line = self.piece_table.line_index(cursor.anchor);
+                    min(line, anchor_line)..=max(line, anchor_line)
+                })
+                .collect(),
+            _ => (0..self.piece_table.num_lines()).collect(),
+        };
+        lines.sort_unstable();
+        lines.dedup();
+
+        // The edits are byte ranges, unlike the character ranges of LSP text edits
+        let ranges: Vec<(usize, usize)> = lines
+            .into_iter()
+            .filter_map(|i| {
+                let line = self.piece_table.line_at_index(i)?;
+                let text: Vec<u8> = self
+                    .piece_table
+                    .iter_chars_at(line.start)
+                    .take(line.length)
+                    .collect();
+                lint_lines(&text, None)
+                    .into_iter()
+                    .find(|issue| issue.kind == LintKind::TrailingWhitespace)
+                    .map(|issue| (line.start + issue.start, line.start + issue.end))
+            })
+            .collect();
+
+        self.switch_to_normal_mode(view);
+        if ranges.is_empty() {
+            return;
+        }
+        self.push_undo_state(view);
+        let mut content_changes = vec![];
+        for (start, end) in ranges.into_iter().rev() {
+            content_changes.push(self.delete_chars(view, start, end));
+        }
+
+        let last_position = self.piece_table.num_chars().saturating_sub(1);
+        for cursor in &mut view.cursors {
+            cursor.position = min(cursor.position, last_position);
+            cursor.anchor = min(cursor.anchor, last_position);
+        }
+        self.lsp_change(content_changes);
+        self.syntect_change(view);
+    }
+
+    // Pads the selected lines (or the lines of the cursors) with spaces so that the first occurrence
+    // of the pattern on each of them starts in the same column. Lines without it are left alone
+    fn align(&mut self, view: &mut View, pattern: &str) {
+        if pattern.is_empty() {
+            log::warning("Usage: :align TEXT");
+            return;
+        }
+        let mut lines: Vec<usize> = match view.mode {
+            Visual | VisualLine => view
+                .cursors
+                .iter()
+                .flat_map(|cursor| {
+                    let line = self.piece_table.line_index(cursor.position);
+                    let anchor_line = self.piece_table.line_index(cursor.anchor);
+                    min(line, anchor_line)..=max(line, anchor_line)
+                })
+                .collect(),
+            _ => view
+                .cursors
+                .iter()
+                .map(|cursor| self.piece_table.line_index(cursor.position))
+                .collect(),
+        };
+        lines.sort_unstable();
+        lines.dedup();
+
+        // Columns are counted in characters rather than bytes so that the text lines up on screen
+        let matches: Vec<(usize, usize, usize)> = lines
+            .into_iter()
+            .filter_map(|i| {
+                let line = self.piece_table.line_at_index(i)?;
+                let text: Vec<u8> = self
+                    .piece_table
+                    .iter_chars_at(line.start)
+                    .take(line.length)
+                    .collect();
+                let col = text.find(pattern)?;
+                let width = text[..col].iter().filter(|c| (**c & 0xC0) != 0x80).count();
+                Some((i, col, width))
+            })
+            .collect();
+        let Some(target_width) = matches.iter().map(|(_, _, width)| *width).max() else {
+            log::warning(format!("No selected line contains \"{}\"", pattern));
+            return;
+        };
+
+        let edits: Vec<TextEdit> = matches
+            .into_iter()
+            .filter(|(_, _, width)| *width < target_width)
+            .map(|(line, col, width)| TextEdit {
+                range: Range {
+                    start: self.lsp_position(line, col),
+                    end: self.lsp_position(line, col),
+                },
+                new_text: " ".repeat(target_width - width),
+            })
+            .collect();
+        self.switch_to_normal_mode(view);
+        self.apply_text_edits(view, &edits);
+    }
+
+    // Converts every identifier in the visual selections, or the identifier under each cursor. The
+    // cursors end up at the start of the converted text
+    fn convert_case(&mut self, view: &mut View, case: IdentifierCase) {
+        let num_chars = self.piece_table.num_chars();
+        let mut ranges: Vec<(usize, usize)> = view
+            .cursors
+            .iter()
+            .filter_map(|cursor| {
+                let (start, end) = (
+                    min(cursor.position, cursor.anchor),
+                    max(cursor.position, cursor.anchor),
+                );
+                match view.mode {
+                    Visual => Some((start, min(end + 1, num_chars))),
+                    VisualLine => {
+                        let first_line = self
+                            .piece_table
+                            .line_at_index(self.piece_table.line_index(start))?;
+                        let last_line = self
+                            .piece_table
+                            .line_at_index(self.piece_table.line_index(end))?;
+                        Some((first_line.start, last_line.start + last_line.length))
+                    }
+                    _ => {
+                        let col = self.piece_table.col_index(cursor.position);
+                        let line_start = cursor.position - col;
+                        let (start, length) =
+                            self.word_at(self.piece_table.line_index(cursor.position), col)?;
+                        Some((line_start + start, line_start + start + length))
+                    }
+                }
+            })
+            .collect();
+        ranges.sort_unstable();
+        // Cursors in the same identifier convert it once
+        ranges.dedup_by(|(start, end), (_, previous_end)| {
+            let overlapping = *start < *previous_end;
+            if overlapping {
+                *previous_end = max(*previous_end, *end);
+            }
+            overlapping
+        });
+
+        let conversions: Vec<(usize, usize, Vec<u8>)> = ranges
+            .into_iter()
+            .filter_map(|(start, end)| {
+                let text: Vec<u8> = self
+                    .piece_table
+                    .iter_chars_at(start)
+                    .take(end - start)
+                    .collect();
+                let mut converted = Vec::with_capacity(text.len());
+                let mut i = 0;
+                while i < text.len() {
+                    let word = char_type(text[i]) == CharType::Word;
+                    let length = text[i..]
+                        .iter()
+                        .position(|c| (char_type(*c) == CharType::Word) != word)
+                        .unwrap_or(text.len() - i);
+                    match word {
+                        true => {
+                            converted.extend(text_utils::convert_case(&text[i..i + length], case))
+                        }
+                        false => converted.extend_from_slice(&text[i..i + length]),
+                    }
+                    i += length;
+                }
+                (converted != text).then_some((start, end, converted))
+            })
+            .collect();
+
+        self.switch_to_normal_mode(view);
+        if conversions.is_empty() {
+            return;
+        }
+        self.push_undo_state(view);
+
+        // Cursors in a converted identifier move to its start. Their new positions are worked out
+        // up front, the edits below move the cursors along with the text as well
+        let new_position = |position: usize| {
+            let mut shift = 0isize;
+            for (start, end, converted) in &conversions {
+                if position < *start {
+                    break;
+                }
+                if position < *end {
+                    return start.saturating_add_signed(shift);
                 }
+                shift += converted.len() as isize - (end - start) as isize;
             }
+            position.saturating_add_signed(shift)
+        };
+        let positions: Vec<usize> = view
+            .cursors
+            .iter()
+            .map(|cursor| new_position(cursor.position))
+            .collect();
+
+        // Back to front so that the positions of the earlier identifiers stay valid
+        let mut content_changes = vec![];
+        for (start, end, converted) in conversions.iter().rev() {
+            content_changes.push(self.delete_chars(view, *start, *end));
+            content_changes.push(self.insert_chars(view, *start, converted));
         }
-    }
 
-    pub fn ready_to_quit(&mut self) -> bool {
-        if !self.piece_table.dirty {
-            return true;
+        for (cursor, position) in view.cursors.iter_mut().zip(positions) {
+            cursor.position = position;
+            cursor.reset_anchor();
+            cursor.unstick_col(&self.piece_table);
         }
+        self.merge_cursors(view);
 
-        if let Some(user_wants_save) = self.platform_resources.confirm_quit(&self.path) {
-            if user_wants_save {
-                self.piece_table.save_to(&self.path);
+        self.lsp_change(content_changes);
+        self.syntect_change(view);
+    }
+
+    // Inserts START, START + STEP, ... at the cursors in the order they appear in the buffer, or at
+    // the start of the selections
+    fn insert_sequence(&mut self, view: &mut View, start: i64, step: i64) {
+        self.push_undo_state(view);
+        if matches!(view.mode, Visual | VisualLine) {
+            for cursor in &mut view.cursors {
+                cursor.position = min(cursor.position, cursor.anchor);
             }
-            return true;
         }
+        self.switch_to_normal_mode(view);
+
+        let mut order: Vec<usize> = (0..view.cursors.len()).collect();
+        order.sort_by_key(|i| view.cursors[*i].position);
+        let mut value = start;
+        for i in order {
+            let changes =
+                self.insert_chars(view, view.cursors[i].position, value.to_string().as_bytes());
+            self.lsp_change(vec![changes]);
+            self.syntect_change(view);
+            value = value.saturating_add(step);
+        }
+    }
 
-        false
+    // Exports the selection (or the whole buffer in normal mode) as plain text, HTML and RTF
+    fn export_selection(&self, view: &View) -> Option<(Vec<u8>, String, String)> {
+        let syntect = self.syntect.as_ref()?;
+
+        let mut cursor = view.cursors[0];
+        let (start, end) = match view.mode {
+            Visual => (
+                min(cursor.position, cursor.anchor),
+                max(cursor.position, cursor.anchor) + 1,
+            ),
+            VisualLine => {
+                cursor.extend_selection(&self.piece_table);
+                (
+                    min(cursor.position, cursor.anchor),
+                    max(cursor.position, cursor.anchor) + 1,
+                )
+            }
+            _ => (0, self.piece_table.num_chars()),
+        };
+
+        // Highlight from the start of the file so the selection is parsed in the right context
+        let text: Vec<u8> = self.piece_table.iter_chars().take(end).collect();
+        let start = min(start, text.len());
+        let effects: Vec<TextEffect> = syntect
+            .highlight_text(&text)
+            .into_iter()
+            .filter(|effect| effect.start + effect.length > start)
+            .map(|mut effect| {
+                let effect_start = max(effect.start, start);
+                effect.length -= effect_start - effect.start;
+                effect.start = effect_start - start;
+                effect
+            })
+            .collect();
+
+        let text = &text[start..];
+        let (foreground, background) = syntect.theme_colors();
+        Some((
+            text.to_vec(),
+            export::to_html(text, &effects, foreground, background),
+            export::to_rtf(text, &effects, foreground),
+        ))
     }
 
-    pub fn update_syntect(&mut self, line: usize) {
-        if let Some(syntect) = &mut self.syntect {
-            syntect.queue.lock().unwrap().clear();
-            self.highlight_queue.clear();
+    // d, c and y followed by an optional count and any motion or text object, e.g. dw, c3w, yG or
+    // d}. Doubling the operator (dd, cc, yy) works on whole lines.
+    fn operator_command(&mut self, view: &mut View, input: &str) {
+        let Some((operator, count, motion)) = split_operator_command(input) else {
+            return;
+        };
+        let linewise = matches!(motion, "j" | "k" | "G" | "gg") || motion.as_bytes() == [operator];
+
+        // cw on a word changes until the end of the word rather than the start of the next one
+        let change_word = operator == b'c'
+            && motion == "w"
+            && view.cursors.iter().all(|cursor| {
+                self.piece_table
+                    .char_at(cursor.position)
+                    .is_some_and(|c| !c.is_ascii_whitespace())
+            });
+        let exclusive = !change_word
+            && (matches!(motion, "h" | "w" | "b" | "0" | "^" | "$" | "{" | "}")
+                || motion.starts_with('F')
+                || motion.starts_with('T'));
+
+        if operator != b'y' {
+            self.push_undo_state(view);
+        }
+        let original_positions: Vec<usize> =
+            view.cursors.iter().map(|cursor| cursor.position).collect();
+        if linewise {
+            self.switch_to_visual_line_mode(view);
+        } else {
+            self.switch_to_visual_mode(view);
+        }
 
-            let start = if let Some(last_cursor) = self.cursors.last() {
-                self.piece_table.line_index(last_cursor.position) / SYNTECT_CACHE_FREQUENCY
-            } else {
-                0
-            };
+        motion::apply_operator_motion(
+            &self.piece_table,
+            &mut view.cursors,
+            view.mode,
+            operator,
+            count,
+            motion,
+            change_word,
+        );
 
-            if start > 0 {
-                self.highlight_queue
-                    .push_back((start - 1) * SYNTECT_CACHE_FREQUENCY);
-                self.highlight_queue
-                    .push_back(start * SYNTECT_CACHE_FREQUENCY);
-                if start + 1 < self.piece_table.num_lines() {
-                    self.highlight_queue
-                        .push_back((start + 1) * SYNTECT_CACHE_FREQUENCY);
-                }
-            }
+        let moved = view
+            .cursors
+            .iter()
+            .zip(&original_positions)
+            .any(|(cursor, position)| cursor.position != *position || cursor.anchor != *position);
+        if !moved && !linewise && !change_word {
+            if operator != b'y' {
+                self.pop_undo_state();
+            }
+            self.switch_to_normal_mode(view);
+            return;
+        }
 
-            let mut i = (line / SYNTECT_CACHE_FREQUENCY) * SYNTECT_CACHE_FREQUENCY;
-            while i < self.piece_table.num_lines() {
-                self.highlight_queue.push_back(i);
-                i += SYNTECT_CACHE_FREQUENCY;
-            }
+        if linewise {
+            self.motion(view, ExtendSelection);
+        } else if exclusive {
+            motion::exclude_selection_ends(&self.piece_table, &mut view.cursors);
         }
-    }
 
-    fn handle_input_command(&mut self) -> Option<EditorCommand> {
-        let input = self.input.clone();
-        match input.as_str() {
-            input if input.as_bytes().first() == Some(&b'/') => {
-                self.motion(SeekToSelf(input[1..].as_bytes()));
-                self.search_string = input[1..].to_string();
-                return Some(EditorCommand::CenterIfNotVisible);
-            }
-            input if let Ok(num) = input[1..].parse::<usize>() => {
-                self.motion(GotoLine(num));
-                self.motion(ToFirstNonBlankChar);
-                return Some(EditorCommand::CenterView);
-            }
-            ":w" => {
-                self.piece_table.save_to(&self.path);
-            }
-            ":wq" => {
-                self.piece_table.save_to(&self.path);
-                return Some(EditorCommand::Quit);
+        self.command(view, CopySelection);
+        match operator {
+            b'd' => {
+                self.command(view, CutSelection);
+                self.switch_to_normal_mode(view);
             }
-            ":q" | ":bd" => {
-                return Some(EditorCommand::Quit);
-            }
-            ":q!" | ":bd!" => {
-                return Some(EditorCommand::QuitNoCheck);
-            }
-            ":qa" => {
-                return Some(EditorCommand::QuitAll);
+            b'c' if linewise => {
+                // The lines are emptied but kept, along with the indentation of the first one
+                let mut content_changes = vec![];
+                for i in 0..view.cursors.len() {
+                    let cursor = view.cursors[i];
+                    let first = min(cursor.anchor, cursor.position);
+                    let last = max(cursor.anchor, cursor.position);
+                    let indent = self
+                        .piece_table
+                        .iter_chars_at(first)
+                        .take(last - first)
+                        .take_while(|c| *c == b' ' || *c == b'\t')
+                        .count();
+                    let start = first + indent;
+                    let end = if self.piece_table.char_at(last) == Some(b'\n') {
+                        last
+                    } else {
+                        last + 1
+                    };
+                    if start < end {
+                        content_changes.push(self.delete_chars(view, start, end));
+                    }
+                    view.cursors[i].position = start;
+                    view.cursors[i].anchor = start;
+                }
+                self.lsp_change(content_changes);
+                self.syntect_change(view);
+                self.switch_to_insert_mode(view);
             }
-            ":qa!" => {
-                return Some(EditorCommand::QuitAllNoCheck);
+            b'c' => {
+                self.command(view, CutSelection);
+                self.switch_to_insert_mode(view);
             }
-            ":split" => {
-                return Some(EditorCommand::ToggleSplitView);
+            _ => {
+                for (cursor, position) in view.cursors.iter_mut().zip(original_positions) {
+                    cursor.position = if linewise {
+                        position
+                    } else {
+                        min(cursor.anchor, cursor.position)
+                    };
+                }
+                self.switch_to_normal_mode(view);
             }
-            _ => ()
         }
-        None
     }
 
-    fn motion(&mut self, motion: CursorMotion) {
-        for cursor in &mut self.cursors {
-            match motion {
-                Forward(count) => cursor.move_forward(&self.piece_table, count),
-                Backward(count) => cursor.move_backward(&self.piece_table, count),
-                BackwardOnceWrapping => cursor.move_backward_once_wrapping(&self.piece_table),
-                Up(count) => cursor.move_up(&self.piece_table, count),
-                Down(count) => cursor.move_down(&self.piece_table, count),
-                ForwardByWord => cursor.move_forward_by_word(&self.piece_table),
-                BackwardByWord => cursor.move_backward_by_word(&self.piece_table),
-                ToStartOfLine => cursor.move_to_start_of_line(&self.piece_table),
-                ToEndOfLine => cursor.move_to_end_of_line(&self.piece_table),
-                ToStartOfFile => cursor.move_to_start_of_file(),
-                ToEndOfFile => cursor.move_to_end_of_file(&self.piece_table),
-                ToFirstNonBlankChar => cursor.move_to_first_non_blank_char(&self.piece_table),
-                ForwardToChar(c) => cursor.move_to_char(&self.piece_table, c),
-                BackwardToChar(c) => cursor.move_back_to_char(&self.piece_table, c),
-                ForwardUntilChar(c) => cursor.move_until_char(&self.piece_table, c),
-                BackwardUntilChar(c) => cursor.move_back_until_char(&self.piece_table, c),
-                ExtendSelection => cursor.extend_selection(&self.piece_table),
-                ExtendSelectionInside(c) => cursor.extend_selection_inside(&self.piece_table, c),
-                GotoLine(n) => cursor.goto_line(&self.piece_table, n),
-                SeekUntil(text) => cursor.seek(&self.piece_table, text.as_bytes(), false),
-                SeekBackUntil(text) => cursor.seek_back(&self.piece_table, text.as_bytes(), false),
-                SeekToSelf(text) => cursor.seek(&self.piece_table, text.as_bytes(), true),
-                SeekBackToSelf(text) => cursor.seek_back(&self.piece_table, text.as_bytes(), true),
-            }
-
-            // Normal mode does not allow cursors to be on newlines
-            if self.mode == Normal && cursor.at_line_end(&self.piece_table) {
-                cursor.move_backward(&self.piece_table, 1);
-            }
-
-            // Cache the column position of the cursor when moving up or down
-            match motion {
-                Up(_) | Down(_) => cursor.stick_col(&self.piece_table),
-                _ => cursor.unstick_col(&self.piece_table),
-            }
-        }
-
-        if self.mode == Insert || self.mode == Normal {
-            for cursor in &mut self.cursors {
-                cursor.reset_anchor();
-            }
-        }
+    fn motion(&mut self, view: &mut View, motion: CursorMotion) {
+        motion::apply_motion(&self.piece_table, &mut view.cursors, view.mode, motion);
     }
 
-    fn command(&mut self, command: BufferCommand) {
+    fn command(&mut self, view: &mut View, command: BufferCommand) {
         match command {
-            InsertCursorAbove => {
-                if let Some(first_cursor) = self
-                    .cursors
-                    .iter()
-                    .min_by(|c1, c2| c1.position.cmp(&c2.position))
-                {
-                    let mut cursor = *first_cursor;
-                    cursor.cached_col = 0;
-                    cursor.move_up(&self.piece_table, 1);
-                    self.cursors.push(cursor);
-                }
-            }
-            InsertCursorBelow => {
-                if let Some(first_cursor) = self
-                    .cursors
-                    .iter()
-                    .max_by(|c1, c2| c1.position.cmp(&c2.position))
-                {
-                    let mut cursor = *first_cursor;
-                    cursor.cached_col = 0;
-                    cursor.move_down(&self.piece_table, 1);
-                    self.cursors.push(cursor);
-                }
-            }
+            InsertCursorAbove => insert_cursor_above(&self.piece_table, &mut view.cursors),
+            InsertCursorBelow => insert_cursor_below(&self.piece_table, &mut view.cursors),
             ReplaceChar(c) => {
                 let mut content_changes = vec![];
 
                 let num_chars = self.piece_table.num_chars();
-                for i in 0..self.cursors.len() {
-                    content_changes.push(
-                        self.delete_chars(self.cursors[i].position, self.cursors[i].position + 1),
-                    );
-                    content_changes.push(self.insert_chars(self.cursors[i].position, &[c]));
+                for i in 0..view.cursors.len() {
+                    content_changes.push(self.delete_chars(
+                        view,
+                        view.cursors[i].position,
+                        view.cursors[i].position + 1,
+                    ));
+                    content_changes.push(self.insert_chars(view, view.cursors[i].position, &[c]));
                 }
 
                 self.lsp_change(content_changes);
-                self.syntect_change();
+                self.syntect_change(view);
             }
             CutSelection => {
                 let mut content_changes = vec![];
 
                 let num_chars = self.piece_table.num_chars();
-                for i in 0..self.cursors.len() {
-                    if self.cursors[i].position < self.cursors[i].anchor {
-                        let start = self.cursors[i].position;
-                        let end = min(self.cursors[i].anchor + 1, num_chars);
-                        content_changes.push(self.delete_chars(start, end));
+                for i in 0..view.cursors.len() {
+                    if view.cursors[i].position < view.cursors[i].anchor {
+                        let start = view.cursors[i].position;
+                        let end = min(view.cursors[i].anchor + 1, num_chars);
+                        content_changes.push(self.delete_chars(view, start, end));
                     } else {
-                        let start = self.cursors[i].anchor;
-                        let end = min(self.cursors[i].position + 1, num_chars);
-                        content_changes.push(self.delete_chars(start, end));
-                        self.cursors[i].position =
+                        let start = view.cursors[i].anchor;
+                        let end = min(view.cursors[i].position + 1, num_chars);
+                        content_changes.push(self.delete_chars(view, start, end));
+                        view.cursors[i].position =
                             min(start, self.piece_table.num_chars().saturating_sub(1));
                     }
                 }
 
                 self.lsp_change(content_changes);
-                self.syntect_change();
+                self.syntect_change(view);
             }
             CutMotion(c, motion, change_command) => {
-                self.push_undo_state();
-                self.switch_to_visual_mode();
+                self.push_undo_state(view);
+                self.switch_to_visual_mode(view);
 
                 let mut content_changes = vec![];
                 let mut selection: Vec<u8> = vec![];
 
                 let num_chars = self.piece_table.num_chars();
-                let num_cursors = self.cursors.len();
+                let num_cursors = view.cursors.len();
                 for i in 0..num_cursors {
-                    let old_anchor = self.cursors[i].anchor;
-                    let old_position = self.cursors[i].position;
+                    let old_anchor = view.cursors[i].anchor;
+                    let old_position = view.cursors[i].position;
 
                     match motion {
                         CutMotion::Inside => {
-                            self.cursors[i].extend_selection_inside(&self.piece_table, c)
+                            view.cursors[i].extend_selection_inside(&self.piece_table, c)
                         }
                         CutMotion::ForwardUntil => {
-                            self.cursors[i].move_until_char(&self.piece_table, c)
+                            view.cursors[i].move_until_char(&self.piece_table, c)
                         }
-                        CutMotion::ForwardTo => self.cursors[i].move_to_char(&self.piece_table, c),
+                        CutMotion::ForwardTo => view.cursors[i].move_to_char(&self.piece_table, c),
                         CutMotion::BackwardUntil => {
-                            self.cursors[i].move_back_until_char(&self.piece_table, c)
+                            view.cursors[i].move_back_until_char(&self.piece_table, c)
                         }
                         CutMotion::BackwardTo => {
-                            self.cursors[i].move_back_to_char(&self.piece_table, c)
+                            view.cursors[i].move_back_to_char(&self.piece_table, c)
                         }
                     }
 
-                    if self.cursors[i].position != old_position
-                        || self.cursors[i].anchor != old_anchor
+                    if view.cursors[i].position != old_position
+                        || view.cursors[i].anchor != old_anchor
                     {
-                        self.cursors[i].save_selection_to_clipboard(&self.piece_table);
-                        selection.extend(self.cursors[i].get_selection(&self.piece_table));
+                        let cursor_selection = view.cursors[i].get_selection(&self.piece_table);
+                        selection.extend(&cursor_selection);
+                        self.set_cursor_clipboard(i, cursor_selection);
 
                         // Insert new lines between the concatenated clipboard content in multi-cursor mode
                         if num_cursors > 1 {
                             selection.push(b'\n');
                         }
 
-                        if self.cursors[i].position < self.cursors[i].anchor {
-                            let start = self.cursors[i].position;
-                            let end = min(self.cursors[i].anchor + 1, num_chars);
-                            content_changes.push(self.delete_chars(start, end));
+                        if view.cursors[i].position < view.cursors[i].anchor {
+                            let start = view.cursors[i].position;
+                            let end = min(view.cursors[i].anchor + 1, num_chars);
+                            content_changes.push(self.delete_chars(view, start, end));
                         } else {
-                            let start = self.cursors[i].anchor;
-                            let end = min(self.cursors[i].position + 1, num_chars);
-                            content_changes.push(self.delete_chars(start, end));
-                            self.cursors[i].position =
+                            let start = view.cursors[i].anchor;
+                            let end = min(view.cursors[i].position + 1, num_chars);
+                            content_changes.push(self.delete_chars(view, start, end));
+                            view.cursors[i].position =
                                 min(start, self.piece_table.num_chars().saturating_sub(1));
                         }
                     }
                 }
 
                 if content_changes.is_empty() {
-                    self.undo_stack.pop();
+                    self.pop_undo_state();
                 }
 
                 if !content_changes.is_empty() && change_command {
-                    self.switch_to_insert_mode();
+                    self.switch_to_insert_mode(view);
                 } else {
-                    self.switch_to_normal_mode();
+                    self.switch_to_normal_mode(view);
                 }
 
                 if !selection.is_empty() {
-                    self.platform_resources.set_clipboard(&selection);
+                    self.set_clipboard(&selection);
                 }
 
                 self.lsp_change(content_changes);
-                self.syntect_change();
+                self.syntect_change(view);
             }
             CutSingleSelection => {
                 let mut content_changes = vec![];
 
                 let num_chars = self.piece_table.num_chars();
-                for i in 0..self.cursors.len() {
-                    debug_assert!(self.cursors[i].anchor == self.cursors[i].position);
-                    if self.cursors[i].position == num_chars.saturating_sub(1)
-                        && self.piece_table.char_at(self.cursors[i].position) == Some(b'\n')
+                for i in 0..view.cursors.len() {
+                    debug_assert!(view.cursors[i].anchor == view.cursors[i].position);
+                    if view.cursors[i].position == num_chars.saturating_sub(1)
+                        && self.piece_table.char_at(view.cursors[i].position) == Some(b'\n')
                     {
                         continue;
                     } else {
-                        content_changes.push(
-                            self.delete_chars(
-                                self.cursors[i].position,
-                                self.cursors[i].position + 1,
-                            ),
-                        );
-                        self.cursors[i].position = min(
-                            self.cursors[i].position,
+                        content_changes.push(self.delete_chars(
+                            view,
+                            view.cursors[i].position,
+                            view.cursors[i].position + 1,
+                        ));
+                        view.cursors[i].position = min(
+                            view.cursors[i].position,
                             self.piece_table.num_chars().saturating_sub(1),
                         );
                     }
                 }
 
                 self.lsp_change(content_changes);
-                self.syntect_change();
+                self.syntect_change(view);
             }
             InsertChar(c) => {
-                if self.insertion_stack_dirty {
-                    self.insertion_command_stack.clear();
-                    self.insertion_stack_dirty = false;
-                }
-                self.insertion_command_stack.push(InsertChar(c));
+                self.record_insertion(InsertChar(c));
 
-                for i in 0..self.cursors.len() {
-                    let start = self.cursors[i].position;
+                for i in 0..view.cursors.len() {
+                    let start = view.cursors[i].position;
 
                     // Special case for moving over end brackets
                     match c {
                         b')' | b'}' | b']' | b'>' if self.piece_table.char_at(start) == Some(c) => {
-                            self.motion(Forward(1));
+                            self.motion(view, Forward(1));
                             continue;
                         }
                         _ => (),
                     }
 
-                    let changes = self.insert_chars(start, &[c]);
+                    let changes = self.insert_chars(view, start, &[c]);
                     self.lsp_change(vec![changes]);
 
                     // Only show signature help for single cursor
-                    if self.cursors.len() == 1 {
+                    if view.cursors.len() == 1 {
                         lsp_signature_help(
-                            &mut self.cursors[i],
+                            &mut view.cursors[i],
                             Some(c),
                             &mut self.language_server,
                             &self.piece_table,
@@ -1116,48 +3559,139 @@ impl Buffer {
                     }
 
                     lsp_complete(
-                        &mut self.cursors[i],
+                        &mut view.cursors[i],
                         Some(c),
                         &mut self.language_server,
                         &self.piece_table,
                         &self.uri,
                         start + 1,
                     );
-                    self.cursors[i].position += 1;
+                    view.cursors[i].position += 1;
                 }
 
                 // Special case for inserting brackets
                 // Here we don't call InsertChar(c) because we don't want lsp_completion for the closing bracket
                 match c {
                     b'(' | b'{' | b'[' | b'<' => {
-                        for i in 0..self.cursors.len() {
-                            let start = self.cursors[i].position;
+                        for i in 0..view.cursors.len() {
+                            let start = view.cursors[i].position;
+                            if has_unmatched_closer(&self.piece_table, start, c) {
+                                continue;
+                            }
                             let changes =
-                                self.insert_chars(start, &[text_utils::matching_bracket(c)]);
+                                self.insert_chars(view, start, &[text_utils::matching_bracket(c)]);
                             self.lsp_change(vec![changes]);
                         }
                     }
                     _ => (),
                 }
 
-                self.syntect_change();
+                self.syntect_change(view);
             }
-            InsertNewLine => {
-                if self.insertion_stack_dirty {
-                    self.insertion_command_stack.clear();
-                    self.insertion_stack_dirty = false;
+            Surround(c) => {
+                let (open, close) = match c {
+                    b'(' | b')' => (b'(', b')'),
+                    b'{' | b'}' => (b'{', b'}'),
+                    b'[' | b']' => (b'[', b']'),
+                    b'<' | b'>' => (b'<', b'>'),
+                    _ => (c, c),
+                };
+
+                // Selections are wrapped back to front so that the insertions don't shift the others
+                let mut order: Vec<usize> = (0..view.cursors.len()).collect();
+                order.sort_by_key(|&i| {
+                    std::cmp::Reverse(min(view.cursors[i].anchor, view.cursors[i].position))
+                });
+
+                let mut content_changes = vec![];
+                let num_chars = self.piece_table.num_chars();
+                for i in order {
+                    let start = min(view.cursors[i].anchor, view.cursors[i].position);
+                    let mut end = min(
+                        max(view.cursors[i].anchor, view.cursors[i].position) + 1,
+                        num_chars,
+                    );
+                    // Line selections are wrapped before their final line break
+                    if end > start + 1 && self.piece_table.char_at(end - 1) == Some(b'\n') {
+                        end -= 1;
+                    }
+                    content_changes.push(self.insert_chars(view, end, &[close]));
+                    content_changes.push(self.insert_chars(view, start, &[open]));
+                    view.cursors[i].position = start;
+                    view.cursors[i].anchor = start;
+                }
+
+                self.lsp_change(content_changes);
+                self.syntect_change(view);
+            }
+            InsertUnicode(c) => {
+                self.record_insertion(InsertUnicode(c));
+
+                let mut bytes = [0; 4];
+                let bytes = c.encode_utf8(&mut bytes).as_bytes();
+                for i in 0..view.cursors.len() {
+                    let start = view.cursors[i].position;
+                    let changes = self.insert_chars(view, start, bytes);
+                    self.lsp_change(vec![changes]);
+                    view.cursors[i].position += bytes.len();
+                }
+
+                self.syntect_change(view);
+            }
+            // Runs before a character that can't be part of a word is typed, at every cursor. Ctrl+V
+            // types the character without expanding it
+            ExpandAbbreviation => {
+                let mut content_changes = vec![];
+                for i in 0..view.cursors.len() {
+                    let position = view.cursors[i].position;
+                    let Some((abbreviation, expansion)) = self.abbreviation_before(position) else {
+                        continue;
+                    };
+                    let start = position - abbreviation.len();
+                    content_changes.push(self.delete_chars(view, start, position));
+                    content_changes.push(self.insert_chars(view, start, expansion.as_bytes()));
+                    view.cursors[i].position = start + expansion.len();
+                    view.cursors[i].anchor = view.cursors[i].position;
+                }
+
+                if !content_changes.is_empty() {
+                    self.record_insertion(ExpandAbbreviation);
+                    self.lsp_change(content_changes);
+                    self.syntect_change(view);
+                }
+            }
+            InsertRegister(name) => {
+                // '"', '+' and '*' all refer to the clipboard, '-' to the text replaced by a visual paste
+                let text = if name == b'-' {
+                    self.deleted_register.clone()
+                } else {
+                    self.get_clipboard()
+                };
+
+                self.record_insertion(InsertRegister(name));
+
+                let mut content_changes = vec![];
+                for i in 0..view.cursors.len() {
+                    let start = view.cursors[i].position;
+                    content_changes.push(self.insert_chars(view, start, &text));
+                    view.cursors[i].position += text.len();
                 }
-                self.insertion_command_stack.push(InsertNewLine);
+
+                self.syntect_change(view);
+                self.lsp_change(content_changes);
+            }
+            InsertNewLine => {
+                self.record_insertion(InsertNewLine);
 
                 let mut content_changes = vec![];
 
-                for cursor in &mut self.cursors {
-                    cursor.reset_completion(&mut self.language_server);
-                    cursor.reset_signature_help(&mut self.language_server);
+                for cursor in &mut view.cursors {
+                    reset_completion(cursor, &mut self.language_server);
+                    reset_signature_help(cursor, &mut self.language_server);
                 }
 
-                for i in 0..self.cursors.len() {
-                    let cursor_position = self.cursors[i].position;
+                for i in 0..view.cursors.len() {
+                    let cursor_position = view.cursors[i].position;
 
                     let line_indent = self.piece_table.line_indent_width_at_char(cursor_position);
                     let mut chars = vec![b'\n'];
@@ -1165,75 +3699,67 @@ impl Buffer {
 
                     let mut cursor_offset = chars.len();
 
-                    if let Some(language) = &self.language {
-                        if let Some(indent_chars) = language.indent_chars {
-                            if let Some(char_before) =
-                                self.piece_table.char_at(cursor_position.saturating_sub(1))
-                            {
-                                if indent_chars.contains(&char_before) {
-                                    chars.append(&mut vec![b' '; self.piece_table.indent_width]);
-                                    cursor_offset = chars.len();
-
-                                    let char_after = self.piece_table.char_at(cursor_position);
-                                    match (char_before, char_after) {
-                                        (b'(', Some(b')'))
-                                        | (b'{', Some(b'}'))
-                                        | (b'[', Some(b'[')) => {
-                                            chars.push(b'\n');
-                                            chars.append(&mut vec![b' '; line_indent]);
-                                        }
-                                        _ => (),
-                                    }
-                                }
-                            }
-                        } else if let Some(indent_words) = language.indent_words {
-                            for word in indent_words {
-                                if self
-                                    .piece_table
-                                    .line_at_char_starts_with(cursor_position, word.as_bytes())
-                                {
-                                    chars.append(&mut vec![b' '; self.piece_table.indent_width]);
-                                    cursor_offset = chars.len();
-                                    break;
-                                }
-                            }
+                    if let (Some(language), Some(line)) = (
+                        &self.language,
+                        self.piece_table.line_at_char(cursor_position),
+                    ) {
+                        let before: Vec<u8> = self
+                            .piece_table
+                            .iter_chars_at(line.start)
+                            .take(cursor_position.saturating_sub(line.start))
+                            .collect();
+                        let after: Vec<u8> = self
+                            .piece_table
+                            .iter_chars_at(cursor_position)
+                            .take(line.end.saturating_sub(cursor_position))
+                            .collect();
+
+                        let indent = language.newline_rules.indent(&before, &after);
+                        if indent != NewlineIndent::None {
+                            chars.append(&mut vec![b' '; self.piece_table.indent_width]);
+                            cursor_offset = chars.len();
+                        }
+                        if indent == NewlineIndent::IndentBetweenPair {
+                            chars.push(b'\n');
+                            chars.append(&mut vec![b' '; line_indent]);
                         }
                     }
 
-                    content_changes.push(self.insert_chars(cursor_position, &chars));
-                    self.cursors[i].position += cursor_offset;
+                    content_changes.push(self.insert_chars(view, cursor_position, &chars));
+                    view.cursors[i].position += cursor_offset;
                 }
 
-                self.syntect_change();
+                self.syntect_change(view);
                 self.lsp_change(content_changes);
             }
             IndentLine => {
                 let mut content_changes = vec![];
 
-                for i in 0..self.cursors.len() {
-                    let line = self.piece_table.line_index(self.cursors[i].position);
-                    let anchor_line = self.piece_table.line_index(self.cursors[i].anchor);
+                for i in 0..view.cursors.len() {
+                    let line = self.piece_table.line_index(view.cursors[i].position);
+                    let anchor_line = self.piece_table.line_index(view.cursors[i].anchor);
 
                     for i in min(line, anchor_line)..=max(line, anchor_line) {
                         if let Some(line) = self.piece_table.line_at_index(i) {
                             content_changes.push(self.insert_chars(
+                                view,
                                 line.start,
                                 &vec![b' '; self.piece_table.indent_width],
                             ));
                         }
                     }
                 }
-                self.motion(ToFirstNonBlankChar);
+                self.motion(view, ToFirstNonBlankChar);
 
-                self.syntect_change();
+                self.syntect_change(view);
                 self.lsp_change(content_changes);
             }
             UnindentLine => {
                 let mut content_changes = vec![];
 
-                for i in 0..self.cursors.len() {
-                    let line = self.piece_table.line_index(self.cursors[i].position);
-                    let anchor_line = self.piece_table.line_index(self.cursors[i].anchor);
+                for i in 0..view.cursors.len() {
+                    let line = self.piece_table.line_index(view.cursors[i].position);
+                    let anchor_line = self.piece_table.line_index(view.cursors[i].anchor);
 
                     for i in min(line, anchor_line)..=max(line, anchor_line) {
                         if let Some(line) = self.piece_table.line_at_index(i) {
@@ -1244,14 +3770,14 @@ impl Buffer {
                                 .all(|c| c == b' ')
                             {
                                 let end = line.start + self.piece_table.indent_width;
-                                content_changes.push(self.delete_chars(line.start, end));
+                                content_changes.push(self.delete_chars(view, line.start, end));
                             }
                         }
                     }
                 }
-                self.motion(ToFirstNonBlankChar);
+                self.motion(view, ToFirstNonBlankChar);
 
-                self.syntect_change();
+                self.syntect_change(view);
                 self.lsp_change(content_changes);
             }
             // TODO: Improve performance: selecting many lines (1000+) is slow.
@@ -1268,9 +3794,9 @@ impl Buffer {
                 let mut uncomment = true;
 
                 // We only uncomment if and only if all lines start with a comment
-                for i in 0..self.cursors.len() {
-                    let line = self.piece_table.line_index(self.cursors[i].position);
-                    let anchor_line = self.piece_table.line_index(self.cursors[i].anchor);
+                for i in 0..view.cursors.len() {
+                    let line = self.piece_table.line_index(view.cursors[i].position);
+                    let anchor_line = self.piece_table.line_index(view.cursors[i].anchor);
 
                     for i in min(line, anchor_line)..=max(line, anchor_line) {
                         if let Some(line) = self.piece_table.line_at_index(i) {
@@ -1298,9 +3824,9 @@ impl Buffer {
                     }
                 }
 
-                for i in 0..self.cursors.len() {
-                    let line = self.piece_table.line_index(self.cursors[i].position);
-                    let anchor_line = self.piece_table.line_index(self.cursors[i].anchor);
+                for i in 0..view.cursors.len() {
+                    let line = self.piece_table.line_index(view.cursors[i].position);
+                    let anchor_line = self.piece_table.line_index(view.cursors[i].anchor);
 
                     for i in min(line, anchor_line)..=max(line, anchor_line) {
                         if let Some(line) = self.piece_table.line_at_index(i) {
@@ -1324,44 +3850,47 @@ impl Buffer {
                                 } else {
                                     start + length
                                 };
-                                content_changes.push(self.delete_chars(start, end));
+                                content_changes.push(self.delete_chars(view, start, end));
                             } else {
                                 let start = line.start + indent;
-                                content_changes
-                                    .push(self.insert_chars(start, line_comment_token.as_bytes()));
-                                content_changes.push(self.insert_chars(start + length, &[b' ']));
+                                content_changes.push(self.insert_chars(
+                                    view,
+                                    start,
+                                    line_comment_token.as_bytes(),
+                                ));
+                                content_changes.push(self.insert_chars(
+                                    view,
+                                    start + length,
+                                    &[b' '],
+                                ));
                             }
                         }
                     }
                 }
 
-                self.syntect_change();
+                self.syntect_change(view);
                 self.lsp_change(content_changes);
             }
             DeleteCharBack => {
-                if self.insertion_stack_dirty {
-                    self.insertion_command_stack.clear();
-                    self.insertion_stack_dirty = false;
-                }
-                self.insertion_command_stack.push(DeleteCharBack);
+                self.record_insertion(DeleteCharBack);
 
                 let mut content_changes = vec![];
 
-                for i in 0..self.cursors.len() {
+                for i in 0..view.cursors.len() {
                     // Special case for deleting bracket pairs (and if at end of file)
                     match (
                         self.piece_table
-                            .char_at(self.cursors[i].position.saturating_sub(1)),
-                        self.piece_table.char_at(self.cursors[i].position),
+                            .char_at(view.cursors[i].position.saturating_sub(1)),
+                        self.piece_table.char_at(view.cursors[i].position),
                     ) {
                         (Some(b'('), Some(b')'))
                         | (Some(b'{'), Some(b'}'))
                         | (Some(b'['), Some(b']'))
                         | (Some(b'<'), Some(b'>')) => {
-                            let start = self.cursors[i].position.saturating_sub(1);
-                            let end = self.cursors[i].position + 1;
-                            content_changes.push(self.delete_chars(start, end));
-                            self.cursors[i].position = start;
+                            let start = view.cursors[i].position.saturating_sub(1);
+                            let end = view.cursors[i].position + 1;
+                            content_changes.push(self.delete_chars(view, start, end));
+                            view.cursors[i].position = start;
                             continue;
                         }
                         _ => (),
@@ -1369,9 +3898,9 @@ impl Buffer {
 
                     let count = self
                         .piece_table
-                        .line_at_char(self.cursors[i].position)
+                        .line_at_char(view.cursors[i].position)
                         .and_then(|line| {
-                            let num = self.cursors[i].position - line.start;
+                            let num = view.cursors[i].position - line.start;
                             if num > 0
                                 && self
                                     .piece_table
@@ -1391,98 +3920,120 @@ impl Buffer {
                         })
                         .unwrap_or(1);
 
-                    let start = self.cursors[i].position.saturating_sub(count);
-                    let end = self.cursors[i].position;
-                    content_changes.push(self.delete_chars(start, end));
-                    self.cursors[i].position = start;
+                    let start = view.cursors[i].position.saturating_sub(count);
+                    let end = view.cursors[i].position;
+                    content_changes.push(self.delete_chars(view, start, end));
+                    view.cursors[i].position = start;
                 }
 
-                self.syntect_change();
+                self.syntect_change(view);
                 self.lsp_change(content_changes);
             }
             DeleteWordBack => {
-                if self.insertion_stack_dirty {
-                    self.insertion_command_stack.clear();
-                    self.insertion_stack_dirty = false;
-                }
-                self.insertion_command_stack.push(DeleteWordBack);
+                self.record_insertion(DeleteWordBack);
 
                 let mut content_changes = vec![];
 
-                for i in 0..self.cursors.len() {
-                    if let Some(line) = self.piece_table.line_at_char(self.cursors[i].position) {
-                        if self.cursors[i].position == line.start {
-                            let start = self.cursors[i].position.saturating_sub(1);
-                            let end = self.cursors[i].position;
-                            content_changes.push(self.delete_chars(start, end));
-                            self.cursors[i].position = start;
+                for i in 0..view.cursors.len() {
+                    if let Some(line) = self.piece_table.line_at_char(view.cursors[i].position) {
+                        if view.cursors[i].position == line.start {
+                            let start = view.cursors[i].position.saturating_sub(1);
+                            let end = view.cursors[i].position;
+                            content_changes.push(self.delete_chars(view, start, end));
+                            view.cursors[i].position = start;
                             continue;
                         }
 
                         if let Some(c) = self
                             .piece_table
-                            .char_at(self.cursors[i].position.saturating_sub(1))
+                            .char_at(view.cursors[i].position.saturating_sub(1))
                         {
                             let char_type = text_utils::char_type(c);
 
-                            let backward_match = self.cursors[i]
+                            let backward_match = view.cursors[i]
                                 .chars_until_pred_rev(&self.piece_table, |c| {
                                     text_utils::char_type(c) != char_type
                                 })
                                 .unwrap_or(line.length);
-                            let start = max(line.start, self.cursors[i].position - backward_match);
-                            let end = self.cursors[i].position;
-                            content_changes.push(self.delete_chars(start, end));
-                            self.cursors[i].position = start;
+                            let start = max(line.start, view.cursors[i].position - backward_match);
+                            let end = view.cursors[i].position;
+                            content_changes.push(self.delete_chars(view, start, end));
+                            view.cursors[i].position = start;
                         }
                     }
                 }
 
-                self.syntect_change();
+                self.syntect_change(view);
                 self.lsp_change(content_changes);
             }
-            DeleteWordFront => {
-                if self.insertion_stack_dirty {
-                    self.insertion_command_stack.clear();
-                    self.insertion_stack_dirty = false;
+            DeleteToLineStart => {
+                self.record_insertion(DeleteToLineStart);
+
+                let mut content_changes = vec![];
+
+                // Like vim, delete back to the indentation first and then to the start of the line
+                for i in 0..view.cursors.len() {
+                    let position = view.cursors[i].position;
+                    if let Some(line) = self.piece_table.line_at_char(position) {
+                        let indent = self
+                            .piece_table
+                            .iter_chars_at(line.start)
+                            .take(line.length)
+                            .take_while(|c| *c == b' ' || *c == b'\t')
+                            .count();
+                        let start = if position > line.start + indent {
+                            line.start + indent
+                        } else {
+                            line.start
+                        };
+                        if start < position {
+                            content_changes.push(self.delete_chars(view, start, position));
+                            view.cursors[i].position = start;
+                        }
+                    }
                 }
-                self.insertion_command_stack.push(DeleteWordFront);
+
+                self.syntect_change(view);
+                self.lsp_change(content_changes);
+            }
+            DeleteWordFront => {
+                self.record_insertion(DeleteWordFront);
 
                 let mut content_changes = vec![];
 
-                for i in 0..self.cursors.len() {
-                    if let Some(line) = self.piece_table.line_at_char(self.cursors[i].position) {
-                        if self.cursors[i].position == line.end {
-                            let start = self.cursors[i].position;
+                for i in 0..view.cursors.len() {
+                    if let Some(line) = self.piece_table.line_at_char(view.cursors[i].position) {
+                        if view.cursors[i].position == line.end {
+                            let start = view.cursors[i].position;
                             let end =
-                                min(self.cursors[i].position + 1, self.piece_table.num_chars());
-                            content_changes.push(self.delete_chars(start, end));
-                            self.cursors[i].position = start;
+                                min(view.cursors[i].position + 1, self.piece_table.num_chars());
+                            content_changes.push(self.delete_chars(view, start, end));
+                            view.cursors[i].position = start;
                             continue;
                         }
 
-                        if let Some(c) = self.piece_table.char_at(self.cursors[i].position) {
+                        if let Some(c) = self.piece_table.char_at(view.cursors[i].position) {
                             let char_type = text_utils::char_type(c);
 
-                            let forward_match = self.cursors[i]
+                            let forward_match = view.cursors[i]
                                 .chars_until_pred(&self.piece_table, |c| {
                                     text_utils::char_type(c) != char_type
                                 })
                                 .map(|x| x + 1)
                                 .unwrap_or(line.length);
-                            let start = self.cursors[i].position;
-                            let end = min(line.end, self.cursors[i].position + forward_match);
-                            content_changes.push(self.delete_chars(start, end));
-                            self.cursors[i].position = start;
+                            let start = view.cursors[i].position;
+                            let end = min(line.end, view.cursors[i].position + forward_match);
+                            content_changes.push(self.delete_chars(view, start, end));
+                            view.cursors[i].position = start;
                         }
                     }
                 }
 
-                self.syntect_change();
+                self.syntect_change(view);
                 self.lsp_change(content_changes);
             }
             Undo => {
-                let first_position = self
+                let first_position = view
                     .cursors
                     .iter()
                     .min_by(|x, y| x.position.cmp(&y.position))
@@ -1491,29 +4042,28 @@ impl Buffer {
 
                 self.clear_diagnostics();
                 if let Some(state) = self.undo_stack.pop() {
-                    self.redo_stack.push(BufferState {
-                        pieces: self.piece_table.pieces.clone(),
-                        cursors: self.cursors.clone(),
-                    });
-                    self.piece_table.pieces = state.pieces;
-                    self.cursors = state.cursors;
+                    let state = self.revert(view, state);
+                    self.redo_stack.push(state);
                 }
 
-                let second_position = self
+                let second_position = view
                     .cursors
                     .iter()
                     .min_by(|x, y| x.position.cmp(&y.position))
                     .map(|cursor| cursor.position)
                     .unwrap_or(0);
 
-                self.update_syntect(min(
-                    self.piece_table.line_index(first_position),
-                    self.piece_table.line_index(second_position),
-                ));
+                self.update_syntect(
+                    view,
+                    min(
+                        self.piece_table.line_index(first_position),
+                        self.piece_table.line_index(second_position),
+                    ),
+                );
                 self.lsp_reload();
             }
             Redo => {
-                let first_position = self
+                let first_position = view
                     .cursors
                     .iter()
                     .min_by(|x, y| x.position.cmp(&y.position))
@@ -1522,37 +4072,36 @@ impl Buffer {
 
                 self.clear_diagnostics();
                 if let Some(state) = self.redo_stack.pop() {
-                    self.undo_stack.push(BufferState {
-                        pieces: self.piece_table.pieces.clone(),
-                        cursors: self.cursors.clone(),
-                    });
-                    self.piece_table.pieces = state.pieces;
-                    self.cursors = state.cursors;
+                    let state = self.revert(view, state);
+                    self.undo_stack.push(state);
                 }
 
-                let second_position = self
+                let second_position = view
                     .cursors
                     .iter()
                     .min_by(|x, y| x.position.cmp(&y.position))
                     .map(|cursor| cursor.position)
                     .unwrap_or(0);
 
-                self.update_syntect(min(
-                    self.piece_table.line_index(first_position),
-                    self.piece_table.line_index(second_position),
-                ));
+                self.update_syntect(
+                    view,
+                    min(
+                        self.piece_table.line_index(first_position),
+                        self.piece_table.line_index(second_position),
+                    ),
+                );
                 self.lsp_reload();
             }
             StartCompletion => {
-                for i in 0..self.cursors.len() {
-                    let cursor_position = self.cursors[i].position;
+                for i in 0..view.cursors.len() {
+                    let cursor_position = view.cursors[i].position;
 
                     let offset = 0;
 
                     // Only show signature help for single cursor
-                    if self.cursors.len() == 1 {
+                    if view.cursors.len() == 1 {
                         lsp_signature_help(
-                            &mut self.cursors[i],
+                            &mut view.cursors[i],
                             None,
                             &mut self.language_server,
                             &self.piece_table,
@@ -1562,7 +4111,7 @@ impl Buffer {
                     }
 
                     lsp_complete(
-                        &mut self.cursors[i],
+                        &mut view.cursors[i],
                         None,
                         &mut self.language_server,
                         &self.piece_table,
@@ -1574,9 +4123,9 @@ impl Buffer {
             Complete => {
                 let mut content_changes = vec![];
 
-                for i in 0..self.cursors.len() {
-                    let cursor_position = self.cursors[i].position;
-                    if let Some(ref mut request) = self.cursors[i].completion_request {
+                for i in 0..view.cursors.len() {
+                    let cursor_position = view.cursors[i].position;
+                    if let Some(ref request) = view.cursors[i].completion_request {
                         let item = self.language_server.as_ref().and_then(|server| {
                             server.borrow().saved_completions.get(&request.id).map(
                                 |completion_list| {
@@ -1594,149 +4143,367 @@ impl Buffer {
                         if let Some(item) = item.flatten() {
                             if let Some(text_edit) = item.text_edit {
                                 let start = self
-                                    .piece_table
-                                    .char_index_from_line_col(
-                                        text_edit.range.start.line as usize,
-                                        text_edit.range.start.character as usize,
-                                    )
+                                    .char_index_from_lsp(&text_edit.range.start)
                                     .unwrap_or(cursor_position);
 
                                 // The end of the completion is the original text edit range
                                 // plus the difference in cursor position
                                 // (from when the completion was triggered until now)
                                 let end = self
-                                    .piece_table
-                                    .char_index_from_line_col(
-                                        text_edit.range.end.line as usize,
-                                        text_edit.range.end.character as usize,
-                                    )
+                                    .char_index_from_lsp(&text_edit.range.end)
                                     .unwrap_or(cursor_position)
                                     + (cursor_position.saturating_sub(request.position));
 
-                                content_changes.push(self.delete_chars(start, end));
-                                self.cursors[i].position = start;
+                                content_changes.push(self.delete_chars(view, start, end));
+                                view.cursors[i].position = start;
 
-                                content_changes
-                                    .push(self.insert_chars(start, text_edit.new_text.as_bytes()));
-                                self.cursors[i].position += text_edit.new_text.len();
-                                self.cursors[i].reset_completion(&mut self.language_server);
+                                content_changes.push(self.insert_chars(
+                                    view,
+                                    start,
+                                    text_edit.new_text.as_bytes(),
+                                ));
+                                view.cursors[i].position += text_edit.new_text.len();
+                                reset_completion(&mut view.cursors[i], &mut self.language_server);
                             }
                         }
                     }
                 }
 
-                self.syntect_change();
+                self.syntect_change(view);
                 self.lsp_change(content_changes)
             }
             CopySelection => {
-                let num_cursors = self.cursors.len();
+                let num_cursors = view.cursors.len();
                 let mut selection: Vec<u8> = vec![];
-                for cursor in &mut self.cursors {
-                    cursor.save_selection_to_clipboard(&self.piece_table);
-                    selection.extend(cursor.get_selection(&self.piece_table));
+                for i in 0..num_cursors {
+                    let cursor_selection = view.cursors[i].get_selection(&self.piece_table);
+                    selection.extend(&cursor_selection);
+                    self.set_cursor_clipboard(i, cursor_selection);
 
                     // Insert new lines between the concatenated clipboard content in multi-cursor mode
                     if num_cursors > 1 {
                         selection.push(b'\n');
                     }
                 }
-                self.platform_resources.set_clipboard(&selection);
+                self.set_clipboard(&selection);
+
+                let num_lines = selection.trim_end_with(|c| c == '\n').lines().count();
+                if num_lines > 1 {
+                    log::notify(format!("Copied {} lines", num_lines));
+                }
             }
             CopyLine => {
                 // Save positions
                 let mut cursor_positions = vec![];
-                for cursor in &self.cursors {
+                for cursor in &view.cursors {
                     cursor_positions.push((cursor.anchor, cursor.position));
                 }
 
-                self.motion(ExtendSelection);
-                self.command(CopySelection);
+                self.motion(view, ExtendSelection);
+                self.command(view, CopySelection);
 
                 // Restore positions
-                for (i, cursor) in self.cursors.iter_mut().enumerate() {
+                for (i, cursor) in view.cursors.iter_mut().enumerate() {
                     let (anchor, position) = cursor_positions[i];
                     cursor.anchor = anchor;
                     cursor.position = position;
                 }
             }
-            PasteSelection => {
-                for i in 0..self.cursors.len() {
-                    let text = self.platform_resources.get_clipboard();
+            PasteSelection | PasteSelectionReindented => {
+                for i in 0..view.cursors.len() {
+                    let mut text = self.get_clipboard();
                     let num_chars = self.piece_table.num_chars();
-                    let (start, count) = if text.last().is_some_and(|c| *c == b'\n') {
+                    let linewise = text.last().is_some_and(|c| *c == b'\n');
+                    if linewise && command == PasteSelectionReindented {
+                        text = self.reindent_lines(&text, view.cursors[i].position);
+                    }
+                    let (start, count) = if linewise {
                         (
                             self.piece_table
-                                .line_at_char(self.cursors[i].position)
+                                .line_at_char(view.cursors[i].position)
                                 .map(|line| min(line.end + 1, num_chars))
                                 .unwrap_or(num_chars),
                             text.len() - text.as_bstr().trim_ascii_start().len(),
                         )
                     } else {
-                        (min(self.cursors[i].position + 1, num_chars), text.len())
+                        (min(view.cursors[i].position + 1, num_chars), text.len())
+                    };
+
+                    if text.len() > LARGE_PASTE_SIZE && view.cursors.len() == 1 {
+                        self.start_large_paste(start, text, start + count);
+                        break;
+                    }
+
+                    let changes = self.insert_chars(view, start, &text);
+                    self.lsp_change(vec![changes]);
+                    self.syntect_change(view);
+                    view.cursors[i].position = start + count;
+                }
+            }
+            DuplicateSelection => {
+                for i in 0..view.cursors.len() {
+                    let cursor = view.cursors[i];
+                    let first = min(cursor.position, cursor.anchor);
+                    let last = max(cursor.position, cursor.anchor);
+                    let num_chars = self.piece_table.num_chars();
+
+                    // Whole lines are duplicated unless part of a line is selected
+                    let (start, end) = if view.mode == Visual {
+                        (first, min(last + 1, num_chars))
+                    } else if let (Some(first_line), Some(last_line)) = (
+                        self.piece_table.line_at_char(first),
+                        self.piece_table.line_at_char(last),
+                    ) {
+                        (first_line.start, min(last_line.end + 1, num_chars))
+                    } else {
+                        continue;
                     };
 
-                    let changes = self.insert_chars(start, &text);
+                    let mut text: Vec<u8> = self
+                        .piece_table
+                        .iter_chars_at(start)
+                        .take(end - start)
+                        .collect();
+                    // The last line of a file without a trailing newline needs one in between
+                    if view.mode != Visual && text.last() != Some(&b'\n') {
+                        text.insert(0, b'\n');
+                    }
+
+                    let changes = self.insert_chars(view, end, &text);
                     self.lsp_change(vec![changes]);
-                    self.syntect_change();
-                    self.cursors[i].position = start + count;
+                    self.syntect_change(view);
+                    view.cursors[i].position = cursor.position + text.len();
+                    view.cursors[i].anchor = cursor.anchor + text.len();
                 }
             }
             PasteCursorSelection => {
-                for i in 0..self.cursors.len() {
-                    let start = min(self.cursors[i].position + 1, self.piece_table.num_chars());
-                    let text = self.cursors[i].clipboard;
-                    let size = self.cursors[i].clipboard_size;
+                for i in 0..view.cursors.len() {
+                    let start = min(view.cursors[i].position + 1, self.piece_table.num_chars());
+                    let text = self.cursor_clipboards.get(i).cloned().unwrap_or_default();
 
-                    let changes = self.insert_chars(start, &text[0..size]);
+                    let changes = self.insert_chars(view, start, &text);
                     self.lsp_change(vec![changes]);
-                    self.syntect_change();
-                    self.cursors[i].position += size;
+                    self.syntect_change(view);
+                    view.cursors[i].position += text.len();
                 }
             }
             GotoDefinition => {
-                if let Some(last_cursor) = self.cursors.last() {
+                if let Some(last_cursor) = view.cursors.last() {
                     self.lsp_goto_definition(last_cursor.position);
                 }
             }
             GotoImplementation => {
-                if let Some(last_cursor) = self.cursors.last() {
+                if let Some(last_cursor) = view.cursors.last() {
                     self.lsp_goto_implementation(last_cursor.position);
                 }
             }
+            FindReferences => {
+                if let Some(last_cursor) = view.cursors.last() {
+                    self.lsp_find_references(last_cursor.position);
+                }
+            }
         }
 
-        for cursor in &mut self.cursors {
+        for cursor in &mut view.cursors {
             // Normal mode does not allow cursors to be on newlines
-            if self.mode == Normal && cursor.at_line_end(&self.piece_table) {
+            if view.mode == Normal && cursor.at_line_end(&self.piece_table) {
                 cursor.move_backward(&self.piece_table, 1);
             }
 
             // Remove completion requests if cursor is behind the request position
-            if self.mode == Insert
+            if view.mode == Insert
                 && cursor
                     .completion_request
                     .is_some_and(|request| request.position > cursor.position)
             {
-                cursor.reset_completion(&mut self.language_server);
+                reset_completion(cursor, &mut self.language_server);
             }
-            if self.mode == Insert
+            if view.mode == Insert
                 && cursor
                     .signature_help_request
                     .is_some_and(|request| request.position > cursor.position)
             {
-                cursor.reset_signature_help(&mut self.language_server);
+                reset_signature_help(cursor, &mut self.language_server);
             }
 
-            if self.mode == Insert || self.mode == Normal {
+            if view.mode == Insert || view.mode == Normal {
                 cursor.reset_anchor();
             }
 
             cursor.unstick_col(&self.piece_table);
-            cursor.reset_completion_view(&mut self.language_server);
+            reset_completion_view(cursor, &mut self.language_server);
+        }
+    }
+
+    pub fn update_substitution_preview(
+        &self,
+        view: &mut View,
+        first_line: usize,
+        last_line: usize,
+    ) {
+        let key = (
+            self.input.clone(),
+            self.version,
+            self.piece_table
+                .line_index(view.cursors.last().unwrap().position),
+            first_line,
+            last_line,
+        );
+        if view
+            .substitution_preview
+            .as_ref()
+            .is_some_and(|preview| preview.key == key)
+        {
+            return;
+        }
+        view.substitution_preview = self.substitution_preview(view, key);
+    }
+
+    fn substitution_preview(
+        &self,
+        view: &View,
+        key: (String, i32, usize, usize, usize),
+    ) -> Option<SubstitutionPreview> {
+        let (_, _, _, first_line, last_line) = key;
+        let substitution = parse_substitution(&self.input)?;
+        let matches = self.substitution_matches(view, &substitution, first_line, last_line);
+        let length = substitution.pattern.len();
+
+        // Each changed line is shown as it will be after the end of the line
+        let mut lines = vec![];
+        if let Some(replacement) = &substitution.replacement {
+            let mut remaining_matches = matches.as_slice();
+            while let Some(first_match) = remaining_matches.first() {
+                let line_index = self.piece_table.line_index(*first_match);
+                let num_line_matches = remaining_matches
+                    .iter()
+                    .take_while(|start| self.piece_table.line_index(**start) == line_index)
+                    .count();
+                let (line_matches, rest) = remaining_matches.split_at(num_line_matches);
+                remaining_matches = rest;
+                let Some(line) = self.piece_table.line_at_index(line_index) else {
+                    continue;
+                };
+                let mut text = vec![];
+                let mut position = line.start;
+                for start in line_matches {
+                    text.extend(
+                        self.piece_table
+                            .iter_chars_at(position)
+                            .take(start - position),
+                    );
+                    text.extend(replacement);
+                    position = start + length;
+                }
+                text.extend(
+                    self.piece_table
+                        .iter_chars_at(position)
+                        .take(line.end - position),
+                );
+                lines.push((line.start, format!("-> {}", text.trim().as_bstr())));
+            }
+        }
+
+        Some(SubstitutionPreview {
+            matches: matches.iter().map(|start| (*start, length)).collect(),
+            replaced: substitution.replacement.is_some(),
+            lines,
+            key,
+        })
+    }
+
+    fn substitute(&mut self, view: &mut View, substitution: &Substitution) {
+        let matches =
+            self.substitution_matches(view, substitution, 0, self.piece_table.num_lines());
+        if matches.is_empty() {
+            log::notify(format!(
+                "Pattern not found: {}",
+                substitution.pattern.as_bstr()
+            ));
+            return;
+        }
+
+        self.push_undo_state(view);
+        let replacement = substitution.replacement.clone().unwrap_or_default();
+        let mut content_changes = vec![];
+        for start in matches.iter().rev() {
+            content_changes.push(self.delete_chars(
+                view,
+                *start,
+                start + substitution.pattern.len(),
+            ));
+            if !replacement.is_empty() {
+                content_changes.push(self.insert_chars(view, *start, &replacement));
+            }
         }
+
+        view.cursors.truncate(1);
+        let position = min(matches[0], self.piece_table.num_chars().saturating_sub(1));
+        view.cursors[0].position = position;
+        view.cursors[0].anchor = position;
+        view.cursors[0].unstick_col(&self.piece_table);
+
+        self.lsp_change(content_changes);
+        self.syntect_change(view);
+        log::notify(format!("Replaced {} occurrences", matches.len()));
+    }
+
+    // The starts of the matches on the lines in scope of the substitution between first_line and
+    // last_line. The pattern only matches case-insensitively if it is all lowercase.
+    fn substitution_matches(
+        &self,
+        view: &View,
+        substitution: &Substitution,
+        first_line: usize,
+        last_line: usize,
+    ) -> Vec<usize> {
+        let (first_line, last_line) = if substitution.whole_file {
+            (first_line, last_line)
+        } else {
+            let line = self
+                .piece_table
+                .line_index(view.cursors.last().unwrap().position);
+            (max(first_line, line), min(last_line, line + 1))
+        };
+        let pattern = &substitution.pattern;
+        let ignore_case = text_utils::ignores_case(pattern);
+
+        let mut matches = vec![];
+        for index in first_line..last_line {
+            let Some(line) = self.piece_table.line_at_index(index) else {
+                break;
+            };
+            let text: Vec<u8> = self
+                .piece_table
+                .iter_chars_at(line.start)
+                .take(line.length)
+                .collect();
+
+            let mut col = 0;
+            while col + pattern.len() <= text.len() {
+                let candidate = &text[col..col + pattern.len()];
+                if candidate == pattern.as_slice()
+                    || (ignore_case && candidate.eq_ignore_ascii_case(pattern))
+                {
+                    matches.push(line.start + col);
+                    if !substitution.global {
+                        break;
+                    }
+                    col += pattern.len();
+                } else {
+                    col += 1;
+                }
+            }
+        }
+        matches
     }
 
-    fn delete_chars(&mut self, start: usize, end: usize) -> TextDocumentChangeEvent {
+    fn delete_chars(
+        &mut self,
+        view: &mut View,
+        start: usize,
+        end: usize,
+    ) -> TextDocumentChangeEvent {
         let old_diagnostic_positions = self.diagnostic_positions();
         let (line1, col1) = (
             self.piece_table.line_index(start),
@@ -1746,52 +4513,128 @@ impl Buffer {
             self.piece_table.line_index(end),
             self.piece_table.col_index(end),
         );
+        let range = Range {
+            start: self.lsp_position(line1, col1),
+            end: self.lsp_position(line2, col2),
+        };
+        self.record_delta(Delta::Insert {
+            position: start,
+            pieces: self.piece_table.pieces_between(start, end),
+        });
         self.piece_table.delete(start, end);
-        self.delete_rebalance(start, end, &old_diagnostic_positions);
+        self.delete_rebalance(view, start, end, &old_diagnostic_positions);
         TextDocumentChangeEvent {
-            range: Some(Range {
-                start: Position {
-                    line: line1 as u32,
-                    character: col1 as u32,
-                },
-                end: Position {
-                    line: line2 as u32,
-                    character: col2 as u32,
-                },
-            }),
+            range: Some(range),
             text: String::new(),
         }
     }
 
-    fn insert_chars(&mut self, start: usize, text: &[u8]) -> TextDocumentChangeEvent {
+    fn insert_chars(
+        &mut self,
+        view: &mut View,
+        start: usize,
+        text: &[u8],
+    ) -> TextDocumentChangeEvent {
         let old_diagnostic_positions = self.diagnostic_positions();
         self.piece_table.insert(start, text);
+        self.record_delta(Delta::Delete {
+            start,
+            end: start + text.len(),
+        });
         let (line, col) = (
             self.piece_table.line_index(start),
             self.piece_table.col_index(start),
         );
-        self.insert_rebalance(start, text.len(), &old_diagnostic_positions);
+        self.insert_rebalance(view, start, text.len(), &old_diagnostic_positions);
         TextDocumentChangeEvent {
-            range: Some(Range {
-                start: Position {
-                    line: line as u32,
-                    character: col as u32,
-                },
-                end: Position {
-                    line: line as u32,
-                    character: col as u32,
-                },
+            range: Some(Range {
+                start: self.lsp_position(line, col),
+                end: self.lsp_position(line, col),
             }),
             text: text.as_bstr().to_string(),
         }
     }
 
-    fn merge_cursors(&mut self) {
+    // The chunks are indexed on a worker and inserted a few per frame. The language server gets
+    // the whole document once the paste is done instead of one giant change
+    fn start_large_paste(&mut self, position: usize, text: Vec<u8>, cursor_position: usize) {
+        let (sender, receiver) = channel();
+        let size = text.len();
+        thread_pool::spawn(Priority::High, move || {
+            for chunk in text.chunks(PASTE_CHUNK_SIZE) {
+                let linebreaks = piece_table::linebreaks(chunk);
+                if sender.send((chunk.to_vec(), linebreaks)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        log::info(format!("Pasting {} KB into {}", size / 1024, self.path));
+        self.pending_paste = Some(PendingPaste {
+            chunks: receiver,
+            position,
+            inserted: 0,
+            size,
+            cursor_position,
+        });
+    }
+
+    // Returns true if anything was inserted
+    pub fn update_paste(&mut self, view: &mut View) -> bool {
+        let Some(mut paste) = self.pending_paste.take() else {
+            return false;
+        };
+
+        let start = Instant::now();
+        let mut inserted = false;
+        while start.elapsed() < PASTE_FRAME_BUDGET {
+            let Ok((chunk, linebreaks)) = paste.chunks.try_recv() else {
+                break;
+            };
+            let old_diagnostic_positions = self.diagnostic_positions();
+            self.piece_table
+                .insert_indexed(paste.position, &chunk, linebreaks);
+            self.record_delta(Delta::Delete {
+                start: paste.position,
+                end: paste.position + chunk.len(),
+            });
+            self.insert_rebalance(view, paste.position, chunk.len(), &old_diagnostic_positions);
+            paste.position += chunk.len();
+            paste.inserted += chunk.len();
+            inserted = true;
+        }
+
+        if paste.inserted < paste.size {
+            self.pending_paste = Some(paste);
+            return inserted;
+        }
+
+        if let Some(cursor) = view.cursors.last_mut() {
+            cursor.position = paste.cursor_position;
+            cursor.anchor = paste.cursor_position;
+        }
+        self.lsp_reload();
+        self.syntect_change(view);
+        log::info(format!(
+            "Pasted {} KB into {}",
+            paste.size / 1024,
+            self.path
+        ));
+        true
+    }
+
+    pub fn paste_progress(&self) -> Option<usize> {
+        self.pending_paste
+            .as_ref()
+            .map(|paste| paste.inserted * 100 / paste.size)
+    }
+
+    fn merge_cursors(&mut self, view: &mut View) {
         let mut merged = vec![];
-        let mut current_cursor = *self.cursors.first().unwrap();
+        let mut current_cursor = *view.cursors.first().unwrap();
 
         // Since we are always moving all cursors at once, cursors can only merge in the "same direction",
-        for cursor in &self.cursors[1..] {
+        for cursor in &view.cursors[1..] {
             if cursors_overlapping(&current_cursor, cursor) {
                 if cursor.moving_forward() {
                     current_cursor.position = cursor.position;
@@ -1805,61 +4648,368 @@ impl Buffer {
         }
         merged.push(current_cursor);
 
-        self.cursors = merged;
+        view.cursors = merged;
+    }
+
+    // Shifts the pasted lines by the difference between the indentation of the line at the position
+    // and that of the first pasted line that isn't blank. Tabs count up to the next multiple of 4
+    // like when a file is read, the new indentation is made of spaces
+    fn reindent_lines(&self, text: &[u8], position: usize) -> Vec<u8> {
+        let indentation = |line: &mut dyn Iterator<Item = u8>| {
+            let mut width: usize = 0;
+            let mut length = 0;
+            for c in line {
+                match c {
+                    b' ' => width += 1,
+                    b'\t' => width += 4 - width % 4,
+                    _ => break,
+                }
+                length += 1;
+            }
+            (width, length)
+        };
+        let is_blank = |line: &[u8]| line.iter().all(|c| *c == b' ' || *c == b'\t');
+
+        let (target_width, _) = self
+            .piece_table
+            .line_at_char(position)
+            .map_or((0, 0), |line| {
+                indentation(&mut self.piece_table.iter_chars_at(line.start).take(line.length))
+            });
+        let (first_width, _) = text
+            .split(|c| *c == b'\n')
+            .find(|line| !is_blank(line))
+            .map_or((0, 0), |line| indentation(&mut line.iter().copied()));
+
+        let mut reindented = Vec::with_capacity(text.len());
+        for (i, line) in text.split(|c| *c == b'\n').enumerate() {
+            if i > 0 {
+                reindented.push(b'\n');
+            }
+            if is_blank(line) {
+                reindented.extend_from_slice(line);
+                continue;
+            }
+            let (width, length) = indentation(&mut line.iter().copied());
+            let new_width = (width + target_width).saturating_sub(first_width);
+            reindented.resize(reindented.len() + new_width, b' ');
+            reindented.extend_from_slice(&line[length..]);
+        }
+        reindented
+    }
+
+    fn get_clipboard(&self) -> Vec<u8> {
+        match &self.platform_resources {
+            Some(platform_resources) => platform_resources.get_clipboard(),
+            None => self.headless_clipboard.clone(),
+        }
+    }
+
+    pub fn set_clipboard(&mut self, text: &[u8]) {
+        match &self.platform_resources {
+            Some(platform_resources) => platform_resources.set_clipboard(text),
+            None => self.headless_clipboard = text.to_vec(),
+        }
+    }
+
+    // Returns whether a pass ran, the piece count tells cheaply whether one is due
+    pub fn compact_piece_table(&mut self) -> bool {
+        if !self.piece_table.needs_compaction() {
+            return false;
+        }
+        self.piece_table.compact();
+        true
     }
 
-    fn push_undo_state(&mut self) {
-        let mut cursors = self.cursors.clone();
+    fn push_undo_state(&mut self, view: &mut View) {
+        let mut cursors = view.cursors.clone();
         for cursor in &mut cursors {
             cursor.position = cursor.anchor;
         }
         self.undo_stack.push(BufferState {
-            pieces: self.piece_table.pieces.clone(),
+            deltas: vec![],
             cursors,
         });
     }
 
-    fn switch_to_normal_mode(&mut self) {
-        self.mode = Normal;
+    // For states pushed before finding out that nothing changes. Changes made since then still
+    // have to be undone with the previous state
+    fn pop_undo_state(&mut self) {
+        if let Some(state) = self.undo_stack.pop() {
+            if let Some(previous) = self.undo_stack.last_mut() {
+                previous.deltas.extend(state.deltas);
+            }
+        }
+    }
+
+    // Every change of the text goes through here, a change invalidates what could be redone
+    fn record_delta(&mut self, delta: Delta) {
+        if let Some(state) = self.undo_stack.last_mut() {
+            state.deltas.push(delta);
+        }
+        self.redo_stack.clear();
+    }
+
+    // Goes back to the state, returns the state to go back to what it was before
+    fn revert(&mut self, view: &mut View, state: BufferState) -> BufferState {
+        let mut deltas = vec![];
+        for delta in state.deltas.into_iter().rev() {
+            let deleted = match delta {
+                Delta::Delete { start, end } => Some((start, end)),
+                Delta::Insert { .. } => None,
+            };
+            let reverted = self.piece_table.apply(delta);
+            match (deleted, &reverted) {
+                (Some((start, end)), _) => self.delete_rebalance(view, start, end, &None),
+                (None, Delta::Delete { start, end }) => {
+                    self.insert_rebalance(view, *start, end - start, &None)
+                }
+                (None, Delta::Insert { .. }) => (),
+            }
+            deltas.push(reverted);
+        }
+        BufferState {
+            deltas,
+            cursors: std::mem::replace(&mut view.cursors, state.cursors),
+        }
+    }
+
+    fn switch_to_normal_mode(&mut self, view: &mut View) {
+        if let Some(change) = self.pending_change.take() {
+            self.last_change = Some(change);
+        }
+        // Leaving insert mode any other way keeps the typed text as a plain edit
+        self.rename_session = None;
+        view.mode = Normal;
         self.input.clear();
-        for cursor in &mut self.cursors {
+        self.keymap_input.clear();
+        for cursor in &mut view.cursors {
             if cursor.at_line_end(&self.piece_table) {
                 cursor.move_backward(&self.piece_table, 1);
             }
 
-            cursor.reset_completion(&mut self.language_server);
-            cursor.reset_signature_help(&mut self.language_server);
+            reset_completion(cursor, &mut self.language_server);
+            reset_signature_help(cursor, &mut self.language_server);
             cursor.reset_anchor();
             cursor.unstick_col(&self.piece_table);
         }
     }
 
-    fn switch_to_insert_mode(&mut self) {
-        self.mode = Insert;
-        self.insertion_stack_dirty = true;
-        for cursor in &mut self.cursors {
+    fn switch_to_insert_mode(&mut self, view: &mut View) {
+        view.mode = Insert;
+        self.keymap_input.clear();
+        for cursor in &mut view.cursors {
             cursor.reset_anchor();
         }
     }
 
-    fn switch_to_visual_mode(&mut self) {
-        self.mode = Visual;
+    // The command runs again as if typed, keymaps aside, or its bound actions run again, and the
+    // text typed after it is replayed
+    fn repeat_change(&mut self, view: &mut View) {
+        let Some(change) = self.last_change.clone() else {
+            return;
+        };
+
+        let replaying_keymap = self.replaying_keymap;
+        self.repeating_change = true;
+        self.replaying_keymap = true;
+        match &change.actions {
+            Some(actions) => self.run_keymap_actions(view, actions),
+            None => {
+                if let Some(last_char) = change.command.chars().last() {
+                    self.input =
+                        change.command[..change.command.len() - last_char.len_utf8()].to_string();
+                    self.handle_char(view, last_char);
+                }
+            }
+        }
+        if view.mode == Insert {
+            for command in change.insertion {
+                self.command(view, command);
+            }
+            self.motion(view, Backward(1));
+            self.switch_to_normal_mode(view);
+        }
+        self.replaying_keymap = replaying_keymap;
+        self.repeating_change = false;
+    }
+
+    fn record_insertion(&mut self, command: BufferCommand) {
+        if let Some(change) = &mut self.pending_change {
+            change.insertion.push(command);
+        }
+    }
+
+    pub fn add_decoration(&mut self, decoration: Decoration) {
+        self.decorations.push(decoration);
+    }
+
+    pub fn clear_decorations(&mut self, namespace: &str) {
+        self.decorations
+            .retain(|decoration| decoration.namespace != namespace);
+    }
+
+    pub fn complete_word(
+        &mut self,
+        view: &mut View,
+        workspace_index: Option<&WorkspaceIndex>,
+        forward: bool,
+    ) {
+        let Some(position) = view.cursors.last().map(|cursor| cursor.position) else {
+            return;
+        };
+        let word = self.word_before(position);
+
+        let mut completion = match self.word_completion.take() {
+            Some(completion)
+                if completion.position == position && completion.current() == word.as_slice() =>
+            {
+                completion
+            }
+            _ => {
+                if word.is_empty() {
+                    return;
+                }
+                let candidates = self.word_candidates(&word, position, workspace_index);
+                if candidates.is_empty() {
+                    log::notify(format!("No words start with {}", word.as_bstr()));
+                    return;
+                }
+                WordCompletion {
+                    index: candidates.len(),
+                    prefix: word,
+                    candidates,
+                    position,
+                }
+            }
+        };
+
+        let num_choices = completion.candidates.len() + 1;
+        completion.index = if forward {
+            (completion.index + 1) % num_choices
+        } else {
+            (completion.index + num_choices - 1) % num_choices
+        };
+        let text = completion.current().to_vec();
+
+        let mut content_changes = vec![];
+        for i in 0..view.cursors.len() {
+            let end = view.cursors[i].position;
+            let start = end - self.word_before(end).len();
+            if start < end {
+                content_changes.push(self.delete_chars(view, start, end));
+            }
+            content_changes.push(self.insert_chars(view, start, &text));
+            view.cursors[i].position = start + text.len();
+            view.cursors[i].anchor = view.cursors[i].position;
+        }
+
+        completion.position = view.cursors.last().map_or(0, |cursor| cursor.position);
+        self.word_completion = Some(completion);
+        self.syntect_change(view);
+        self.lsp_change(content_changes);
+    }
+
+    fn word_before(&self, position: usize) -> Vec<u8> {
+        if position == 0 {
+            return vec![];
+        }
+        let mut word: Vec<u8> = self
+            .piece_table
+            .iter_chars_at_rev(position - 1)
+            .take_while(|c| char_type(*c) == CharType::Word)
+            .collect();
+        word.reverse();
+        word
+    }
+
+    // Words of the buffer closest to the cursor come first, then those of the workspace index
+    fn word_candidates(
+        &self,
+        prefix: &[u8],
+        position: usize,
+        workspace_index: Option<&WorkspaceIndex>,
+    ) -> Vec<Vec<u8>> {
+        let mut words = vec![];
+        let mut word = vec![];
+        for (i, c) in self
+            .piece_table
+            .iter_chars()
+            .chain(std::iter::once(b' '))
+            .enumerate()
+        {
+            if char_type(c) == CharType::Word {
+                word.push(c);
+                continue;
+            }
+            let candidate = std::mem::take(&mut word);
+            if candidate.len() > prefix.len()
+                && candidate.starts_with(prefix)
+                && !candidate[0].is_ascii_digit()
+            {
+                words.push((position.abs_diff(i), candidate));
+            }
+        }
+        words.sort_by_key(|(distance, _)| *distance);
+
+        let mut seen = HashSet::new();
+        let mut candidates: Vec<Vec<u8>> = words
+            .into_iter()
+            .map(|(_, word)| word)
+            .filter(|word| seen.insert(word.clone()))
+            .collect();
+
+        if let Some(workspace_index) = workspace_index {
+            let prefix = unsafe { std::str::from_utf8_unchecked(prefix) };
+            candidates.extend(
+                workspace_index
+                    .words_with_prefix(prefix, MAX_WORD_COMPLETIONS)
+                    .into_iter()
+                    .map(String::into_bytes)
+                    .filter(|word| seen.insert(word.clone())),
+            );
+        }
+
+        candidates.truncate(MAX_WORD_COMPLETIONS);
+        candidates
+    }
+
+    // Entering insert mode between the parentheses of an existing call shows its signature
+    pub fn signature_help_in_call(&mut self, view: &mut View) {
+        if view.mode != Insert || view.cursors.len() != 1 {
+            return;
+        }
+        let position = view.cursors[0].position;
+        if enclosing_call_paren(&self.piece_table, position).is_some() {
+            lsp_signature_help(
+                &mut view.cursors[0],
+                None,
+                &mut self.language_server,
+                &self.piece_table,
+                &self.uri,
+                position,
+            );
+        }
+    }
+
+    fn switch_to_visual_mode(&mut self, view: &mut View) {
+        view.mode = Visual;
         self.input.clear();
     }
 
-    fn switch_to_visual_line_mode(&mut self) {
-        self.mode = VisualLine;
+    fn switch_to_visual_line_mode(&mut self, view: &mut View) {
+        view.mode = VisualLine;
         self.input.clear();
     }
 
-    fn syntect_change(&mut self) {
-        let first_line = self
+    fn syntect_change(&mut self, view: &mut View) {
+        let first_line = view
             .cursors
             .iter()
             .map(|x| self.piece_table.line_index(x.position))
             .min()
             .unwrap_or(0);
-        self.update_syntect(first_line);
+        self.update_syntect(view, first_line);
     }
 
     fn lsp_reload(&mut self) {
@@ -1876,9 +5026,7 @@ impl Buffer {
                     },
                 }],
             };
-            server
-                .borrow_mut()
-                .send_notification("textDocument/didChange", change_params);
+            server.borrow_mut().send_change(change_params);
             self.version += 1;
         }
     }
@@ -1892,9 +5040,7 @@ impl Buffer {
                 },
                 content_changes,
             };
-            server
-                .borrow_mut()
-                .send_notification("textDocument/didChange", change_params);
+            server.borrow_mut().send_change(change_params);
             self.version += 1;
         }
     }
@@ -1909,10 +5055,7 @@ impl Buffer {
                 text_document: TextDocumentIdentifier {
                     uri: self.uri.to_string(),
                 },
-                position: Position {
-                    line: line as u32,
-                    character: col as u32,
-                },
+                position: self.lsp_position(line, col),
             };
             server
                 .borrow_mut()
@@ -1930,10 +5073,7 @@ impl Buffer {
                 text_document: TextDocumentIdentifier {
                     uri: self.uri.to_string(),
                 },
-                position: Position {
-                    line: line as u32,
-                    character: col as u32,
-                },
+                position: self.lsp_position(line, col),
             };
             server
                 .borrow_mut()
@@ -1941,30 +5081,339 @@ impl Buffer {
         }
     }
 
-    fn lsp_hover(&mut self, line: usize, col: usize) {
+    fn lsp_find_references(&mut self, position: usize) {
+        self.references_request = self.lsp_references(position);
+    }
+
+    // The references in this buffer are the regions of the rename session
+    fn start_rename_session(&mut self, view: &mut View) {
+        if let Some(last_cursor) = view.cursors.last() {
+            self.rename_request = self.lsp_references(last_cursor.position);
+        }
+    }
+
+    pub fn begin_rename_session(&mut self, view: &mut View, server: &mut RefMut<LanguageServer>) {
+        let Some(locations) = self
+            .rename_request
+            .and_then(|id| server.saved_references.remove(&id))
+        else {
+            return;
+        };
+        self.rename_request = None;
+        if view.mode != Normal {
+            return;
+        }
+
+        let mut regions: Vec<(usize, usize)> = locations
+            .iter()
+            .filter(|location| location.uri.to_lowercase() == self.uri.to_lowercase())
+            .filter_map(|location| {
+                let (start, end) = (&location.range.start, &location.range.end);
+                Some((
+                    self.piece_table
+                        .char_index_from_line_col(start.line as usize, self.col_from_lsp(start))?,
+                    self.piece_table
+                        .char_index_from_line_col(end.line as usize, self.col_from_lsp(end))?,
+                ))
+            })
+            .collect();
+        regions.sort();
+        regions.dedup();
+
+        let position = view.cursors.last().unwrap().position;
+        let Some(primary_region) = regions
+            .iter()
+            .position(|(start, end)| (*start..=*end).contains(&position))
+        else {
+            log::notify("Nothing to rename under the cursor".to_string());
+            return;
+        };
+        let text = |(start, end): (usize, usize)| -> Vec<u8> {
+            self.piece_table
+                .iter_chars_at(start)
+                .take(end - start)
+                .collect()
+        };
+        let original_name = text(regions[primary_region]);
+        let primary = regions[primary_region];
+        regions.retain(|region| *region == primary || text(*region) == original_name);
+        let primary_region = regions
+            .iter()
+            .position(|region| *region == primary)
+            .unwrap();
+
+        // The cursor of the primary region is the last one, at the same offset in every region
+        let offset = position - primary.0;
+        self.push_undo_state(view);
+        view.cursors = regions
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != primary_region)
+            .chain(std::iter::once((primary_region, &primary)))
+            .map(|(_, (start, _))| Cursor::new(start + offset))
+            .collect();
+        self.rename_session = Some(RenameSession {
+            regions,
+            primary_region,
+            original_name,
+            undo_depth: self.undo_stack.len(),
+        });
+        self.switch_to_insert_mode(view);
+    }
+
+    fn end_rename_session(&mut self, view: &mut View, confirm: bool) {
+        let Some(session) = self.rename_session.take() else {
+            return;
+        };
+        let (primary_start, primary_end) = session.regions[session.primary_region];
+        let new_name: Vec<u8> = self
+            .piece_table
+            .iter_chars_at(primary_start)
+            .take(primary_end - primary_start)
+            .collect();
+
+        // Restored back to front so that the positions of the remaining regions stay valid
+        let mut content_changes = vec![];
+        for (start, end) in session.regions.iter().rev() {
+            if self
+                .piece_table
+                .iter_chars_at(*start)
+                .take(end - start)
+                .eq(session.original_name.iter().copied())
+            {
+                continue;
+            }
+            if end > start {
+                content_changes.push(self.delete_chars(view, *start, *end));
+            }
+            content_changes.push(self.insert_chars(view, *start, &session.original_name));
+        }
+        if !content_changes.is_empty() {
+            self.lsp_change(content_changes);
+            self.syntect_change(view);
+        }
+
+        let position = session.regions[..session.primary_region]
+            .iter()
+            .fold(primary_start, |position, (start, end)| {
+                position + session.original_name.len() - (end - start)
+            });
+        view.cursors = vec![Cursor::new(position)];
+        self.undo_stack
+            .truncate(session.undo_depth.saturating_sub(1));
+        self.switch_to_normal_mode(view);
+
+        if confirm && !new_name.is_empty() && new_name != session.original_name {
+            self.lsp_rename(position, &String::from_utf8_lossy(&new_name));
+        }
+    }
+
+    fn lsp_references(&mut self, position: usize) -> Option<i32> {
         if let Some(server) = &self.language_server {
-            let hover_params = HoverParams {
+            let (line, col) = (
+                self.piece_table.line_index(position),
+                self.piece_table.col_index(position),
+            );
+            let reference_params = ReferenceParams {
                 text_document: TextDocumentIdentifier {
                     uri: self.uri.to_string(),
                 },
-                position: Position {
-                    line: line as u32,
-                    character: col as u32,
+                position: self.lsp_position(line, col),
+                context: ReferenceContext {
+                    include_declaration: true,
+                },
+            };
+            return server
+                .borrow_mut()
+                .send_request("textDocument/references", reference_params);
+        }
+        None
+    }
+
+    pub fn take_references(
+        &mut self,
+        server: &mut RefMut<LanguageServer>,
+    ) -> Option<Vec<Location>> {
+        let locations = server.saved_references.remove(&self.references_request?)?;
+        self.references_request = None;
+        Some(locations)
+    }
+
+    fn lsp_rename(&mut self, position: usize, new_name: &str) {
+        if let Some(server) = &self.language_server {
+            let (line, col) = (
+                self.piece_table.line_index(position),
+                self.piece_table.col_index(position),
+            );
+            let rename_params = RenameParams {
+                text_document: TextDocumentIdentifier {
+                    uri: self.uri.to_string(),
                 },
+                position: self.lsp_position(line, col),
+                new_name: new_name.to_string(),
             };
             server
+                .borrow_mut()
+                .send_request("textDocument/rename", rename_params);
+        }
+    }
+
+    fn lsp_format_document(&mut self) -> bool {
+        let Some(server) = &self.language_server else {
+            return false;
+        };
+
+        let formatting_params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier {
+                uri: self.uri.to_string(),
+            },
+            options: FormattingOptions {
+                tab_size: self.piece_table.indent_width as u32,
+                insert_spaces: true,
+            },
+        };
+        self.format_request = server
+            .borrow_mut()
+            .send_request("textDocument/formatting", formatting_params);
+        self.format_request.is_some()
+    }
+
+    fn lsp_format(&mut self, start_line: usize, end_line: usize) {
+        if let Some(server) = &self.language_server {
+            let end_col = self
+                .piece_table
+                .line_at_index(end_line)
+                .map(|line| line.length)
+                .unwrap_or(0);
+            let formatting_params = DocumentRangeFormattingParams {
+                text_document: TextDocumentIdentifier {
+                    uri: self.uri.to_string(),
+                },
+                range: Range {
+                    start: Position {
+                        line: start_line as u32,
+                        character: 0,
+                    },
+                    end: self.lsp_position(end_line, end_col),
+                },
+                options: FormattingOptions {
+                    tab_size: self.piece_table.indent_width as u32,
+                    insert_spaces: true,
+                },
+            };
+            self.format_request = server
+                .borrow_mut()
+                .send_request("textDocument/rangeFormatting", formatting_params);
+        }
+    }
+
+    fn lsp_code_action(&mut self, kind: &'static str, save: bool) -> bool {
+        let Some(server) = &self.language_server else {
+            return false;
+        };
+
+        let last_line = self.piece_table.num_lines().saturating_sub(1);
+        let end_col = self
+            .piece_table
+            .line_at_index(last_line)
+            .map(|line| line.length)
+            .unwrap_or(0);
+        let diagnostics = server
+            .borrow()
+            .saved_diagnostics
+            .get(&self.uri.to_lowercase())
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|mut diagnostic| {
+                diagnostic.range = Range {
+                    start: self.lsp_position(
+                        diagnostic.range.start.line as usize,
+                        diagnostic.range.start.character as usize,
+                    ),
+                    end: self.lsp_position(
+                        diagnostic.range.end.line as usize,
+                        diagnostic.range.end.character as usize,
+                    ),
+                };
+                diagnostic
+            })
+            .collect();
+        let code_action_params = CodeActionParams {
+            text_document: TextDocumentIdentifier {
+                uri: self.uri.to_string(),
+            },
+            range: Range {
+                start: Position {
+                    line: 0,
+                    character: 0,
+                },
+                end: self.lsp_position(last_line, end_col),
+            },
+            context: CodeActionContext {
+                diagnostics,
+                only: vec![kind.to_string()],
+            },
+        };
+        self.code_action_request = server
+            .borrow_mut()
+            .send_request("textDocument/codeAction", code_action_params)
+            .map(|id| CodeActionRequest { id, kind, save });
+        self.code_action_request.is_some()
+    }
+
+    fn lsp_hover(&mut self, line: usize, col: usize) {
+        self.cancel_hover();
+        if let Some(server) = &self.language_server {
+            let hover_params = HoverParams {
+                text_document: TextDocumentIdentifier {
+                    uri: self.uri.to_string(),
+                },
+                position: self.lsp_position(line, col),
+            };
+            self.hover_request = server
                 .borrow_mut()
                 .send_request("textDocument/hover", hover_params);
         }
     }
 
+    // Responses to cancelled or superseded requests are dropped
+    pub fn finish_hover(&mut self, id: i32) -> bool {
+        if self.hover_request == Some(id) {
+            self.hover_request = None;
+            return true;
+        }
+        false
+    }
+
+    pub fn cancel_hover(&mut self) {
+        if let (Some(id), Some(server)) = (self.hover_request.take(), &self.language_server) {
+            server.borrow_mut().cancel_request(id);
+        }
+    }
+
     fn insert_rebalance(
         &mut self,
+        view: &mut View,
         position: usize,
         count: usize,
         old_diagnostic_positions: &Option<Vec<(usize, usize)>>,
     ) {
-        cursors_insert_rebalance(&mut self.cursors, position, count);
+        self.catch_up(view);
+        cursors_insert_rebalance(&mut view.cursors, position, count);
+        self.cursor_rebalances
+            .push(CursorRebalance::Insert(position, count));
+        view.rebalanced = self.cursor_rebalances.len();
+        // Text typed at a mark goes in front of the character it marks
+        for (_, mark) in &mut self.marks {
+            if *mark >= position {
+                *mark += count;
+            }
+        }
+        decorations_insert_rebalance(&mut self.decorations, position, count);
+        if let Some(session) = &mut self.rename_session {
+            session.insert_rebalance(position, count);
+        }
         self.syntect_insert_rebalance(position, count);
         if let Some(positions) = old_diagnostic_positions {
             self.diagnostics_insert_rebalance(position, count, positions);
@@ -1973,11 +5422,27 @@ impl Buffer {
 
     fn delete_rebalance(
         &mut self,
+        view: &mut View,
         position: usize,
         end: usize,
         old_diagnostic_positions: &Option<Vec<(usize, usize)>>,
     ) {
-        cursors_delete_rebalance(&mut self.cursors, position, end);
+        self.catch_up(view);
+        cursors_delete_rebalance(&mut view.cursors, position, end);
+        self.cursor_rebalances
+            .push(CursorRebalance::Delete(position, end));
+        view.rebalanced = self.cursor_rebalances.len();
+        for (_, mark) in &mut self.marks {
+            if *mark >= end {
+                *mark -= end - position;
+            } else {
+                *mark = min(*mark, position);
+            }
+        }
+        decorations_delete_rebalance(&mut self.decorations, position, end);
+        if let Some(session) = &mut self.rename_session {
+            session.delete_rebalance(position, end);
+        }
         self.syntect_delete_rebalance(position, end);
         if let Some(positions) = old_diagnostic_positions {
             self.diagnostics_delete_rebalance(position, end, positions);
@@ -1996,6 +5461,24 @@ impl Buffer {
         }
     }
 
+    pub fn lsp_position(&self, line: usize, col: usize) -> Position {
+        lsp_position(&self.piece_table, self.utf16_positions, line, col)
+    }
+
+    pub fn col_from_lsp(&self, position: &Position) -> usize {
+        let (line, character) = (position.line as usize, position.character as usize);
+        if self.utf16_positions {
+            self.piece_table.col_from_utf16(line, character)
+        } else {
+            character
+        }
+    }
+
+    fn char_index_from_lsp(&self, position: &Position) -> Option<usize> {
+        self.piece_table
+            .char_index_from_line_col(position.line as usize, self.col_from_lsp(position))
+    }
+
     fn diagnostic_positions(&self) -> Option<Vec<(usize, usize)>> {
         if let Some(server) = &self.language_server {
             if let Some(diagnostics) = server
@@ -2097,6 +5580,24 @@ impl Buffer {
     }
 }
 
+// Columns are byte offsets, servers that only speak UTF-16 count code units instead
+fn lsp_position(
+    piece_table: &PieceTable,
+    utf16_positions: bool,
+    line: usize,
+    col: usize,
+) -> Position {
+    let character = if utf16_positions {
+        piece_table.utf16_col(line, col)
+    } else {
+        col
+    };
+    Position {
+        line: line as u32,
+        character: character as u32,
+    }
+}
+
 fn lsp_complete(
     cursor: &mut Cursor,
     character: Option<u8>,
@@ -2114,10 +5615,7 @@ fn lsp_complete(
             text_document: TextDocumentIdentifier {
                 uri: uri.to_string(),
             },
-            position: Position {
-                line: line as u32,
-                character: col as u32,
-            },
+            position: lsp_position(piece_table, server.borrow().utf16_positions, line, col),
         };
 
         let is_trigger_character =
@@ -2152,6 +5650,7 @@ fn lsp_complete(
                     initial_position: position,
                     selection_index: 0,
                     selection_view_offset: 0,
+                    documentation_offset: 0,
                     manually_triggered: character.is_none(),
                 });
             }
@@ -2168,7 +5667,7 @@ fn lsp_signature_help(
     position: usize,
 ) {
     if let Some(server) = &language_server {
-        if character.is_some_and(|c| {
+        if character.map_or(true, |c| {
             server
                 .borrow()
                 .signature_help_trigger_characters
@@ -2182,10 +5681,7 @@ fn lsp_signature_help(
                 text_document: TextDocumentIdentifier {
                     uri: uri.to_string(),
                 },
-                position: Position {
-                    line: line as u32,
-                    character: col as u32,
-                },
+                position: lsp_position(piece_table, server.borrow().utf16_positions, line, col),
                 context: SignatureHelpContext {
                     trigger_kind: if character.is_none() { 1 } else { 2 },
                     trigger_character: character.map(|c| c.to_string()),
@@ -2219,109 +5715,121 @@ fn lsp_signature_help(
     }
 }
 
-fn is_prefix_of_command(str: &str, mode: BufferMode) -> bool {
-    match mode {
-        BufferMode::Normal => {
-            NORMAL_MODE_COMMANDS.iter().any(|cmd| str.is_prefix_of(cmd))
-                || (str.starts_with('f') && str.len() <= 2)
-                || (str.starts_with('F') && str.len() <= 2)
-                || (str.starts_with('r') && str.len() <= 2)
-                || (str.starts_with('t') && str.len() <= 2)
-                || (str.starts_with('T') && str.len() <= 2)
-                || (str.starts_with("ci") && str.len() <= 3)
-                || (str.starts_with("di") && str.len() <= 3)
-                || (str.starts_with("ct") && str.len() <= 3)
-                || (str.starts_with("dt") && str.len() <= 3)
-                || (str.starts_with("cT") && str.len() <= 3)
-                || (str.starts_with("dT") && str.len() <= 3)
-        }
-        BufferMode::Visual => {
-            VISUAL_MODE_COMMANDS.iter().any(|cmd| str.is_prefix_of(cmd))
-                || (str.starts_with('f') && str.len() <= 2)
-                || (str.starts_with('F') && str.len() <= 2)
-                || (str.starts_with('t') && str.len() <= 2)
-                || (str.starts_with('T') && str.len() <= 2)
-                || (str.starts_with('i') && str.len() <= 2)
-        }
-        BufferMode::VisualLine => {
-            VISUAL_MODE_COMMANDS.iter().any(|cmd| str.is_prefix_of(cmd))
-                || (str.starts_with('f') && str.len() <= 2)
-                || (str.starts_with('F') && str.len() <= 2)
-                || (str.starts_with('t') && str.len() <= 2)
-                || (str.starts_with('T') && str.len() <= 2)
-        }
-        _ => false,
+// The opener that was just typed completes a closer that is missing its opener, either on the same
+// line or, for blocks, anywhere in the file
+fn has_unmatched_closer(piece_table: &PieceTable, position: usize, open: u8) -> bool {
+    let close = text_utils::matching_bracket(open);
+    let is_unbalanced = |chars: &mut dyn Iterator<Item = u8>| {
+        let (opened, closed) = chars.fold((0, 0), |(opened, closed), c| {
+            (
+                opened + usize::from(c == open),
+                closed + usize::from(c == close),
+            )
+        });
+        closed >= opened
+    };
+
+    if let Some(line) = piece_table.line_at_char(position.saturating_sub(1)) {
+        if is_unbalanced(&mut piece_table.iter_chars_at(line.start).take(line.length)) {
+            return true;
+        }
     }
+    open == b'{' && is_unbalanced(&mut piece_table.iter_chars())
 }
 
-const NORMAL_MODE_COMMANDS: [&str; 30] = [
-    "j", "k", "h", "l", "w", "b", "^", "$", "gg", "G", "x", "dd", "D", "J", "K", "v", "V", "u",
-    ">", "<", "p", "P", "yy", "zz", "n", "N", "/", "gd", "gi", ".",
-];
-const VISUAL_MODE_COMMANDS: [&str; 21] = [
-    "j", "k", "h", "l", "w", "b", "^", "$", "gg", "G", "x", "d", ">", "<", "y", "p", "P", "zz",
-    "n", "N", "/",
-];
-
-#[derive(Clone, Copy, PartialEq)]
-enum CutMotion {
-    Inside,
-    ForwardUntil,
-    ForwardTo,
-    BackwardUntil,
-    BackwardTo,
+// Scans backwards for the unmatched ( of a call, giving up at the start of a block or statement
+fn enclosing_call_paren(piece_table: &PieceTable, position: usize) -> Option<usize> {
+    let mut depth = 0;
+    let chars = piece_table.iter_chars_at_rev(position.checked_sub(1)?);
+    for (i, c) in chars.take(CALL_SCAN_LIMIT).enumerate() {
+        match c {
+            b')' | b']' | b'}' => depth += 1,
+            b'(' | b'[' | b'{' if depth > 0 => depth -= 1,
+            b'(' => {
+                let paren = position - 1 - i;
+                let callee = piece_table
+                    .iter_chars_at_rev(paren.checked_sub(1)?)
+                    .find(|c| !c.is_ascii_whitespace())?;
+                return (callee.is_ascii_alphanumeric() || matches!(callee, b'_' | b'>' | b'!'))
+                    .then_some(paren);
+            }
+            b'[' | b'{' | b';' => return None,
+            _ => (),
+        }
+    }
+    None
 }
 
-enum CursorMotion<'a> {
-    Forward(usize),
-    Backward(usize),
-    BackwardOnceWrapping,
-    Up(usize),
-    Down(usize),
-    ForwardByWord,
-    BackwardByWord,
-    ToStartOfLine,
-    ToEndOfLine,
-    ToStartOfFile,
-    ToEndOfFile,
-    ToFirstNonBlankChar,
-    ForwardToChar(u8),
-    BackwardToChar(u8),
-    ForwardUntilChar(u8),
-    BackwardUntilChar(u8),
-    ExtendSelection,
-    ExtendSelectionInside(u8),
-    GotoLine(usize),
-    SeekUntil(&'a [u8]),
-    SeekBackUntil(&'a [u8]),
-    SeekToSelf(&'a [u8]),
-    SeekBackToSelf(&'a [u8]),
+const LARGE_PASTE_SIZE: usize = 1024 * 1024;
+const PASTE_CHUNK_SIZE: usize = 256 * 1024;
+const PASTE_FRAME_BUDGET: Duration = Duration::from_millis(8);
+const MAX_QUEUED_HIGHLIGHT_CHUNKS: usize = 2;
+// How far back to look for the parenthesis of a call when entering insert mode
+const CALL_SCAN_LIMIT: usize = 4096;
+const MAX_WORD_COMPLETIONS: usize = 100;
+const WORD_HIGHLIGHT_DELAY: Duration = Duration::from_millis(500);
+const MAX_UNDO_STATES: usize = 10_000;
+
+// :N, :+N / :-N relative to the current line and :N% of the file
+fn goto_line_target(target: &str, current_line: usize, num_lines: usize) -> Option<usize> {
+    if let Some(offset) = target.strip_prefix('+') {
+        let line = current_line.saturating_add(offset.parse().ok()?);
+        return Some(min(line, max(num_lines, 1)));
+    }
+    if let Some(offset) = target.strip_prefix('-') {
+        return Some(max(current_line.saturating_sub(offset.parse().ok()?), 1));
+    }
+    if let Some(percentage) = target.strip_suffix('%') {
+        let percentage = min(percentage.parse::<usize>().ok()?, 100);
+        return Some(max((percentage * num_lines).div_ceil(100), 1));
+    }
+    if !target.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    target.parse().ok()
 }
 
-#[derive(Clone, Copy, PartialEq)]
-enum BufferCommand {
-    InsertCursorAbove,
-    InsertCursorBelow,
-    ReplaceChar(u8),
-    CutSelection,
-    CutSingleSelection,
-    CutMotion(u8, CutMotion, bool),
-    InsertChar(u8),
-    InsertNewLine,
-    IndentLine,
-    UnindentLine,
-    ToggleComment,
-    DeleteCharBack,
-    DeleteWordBack,
-    DeleteWordFront,
-    Undo,
-    Redo,
-    StartCompletion,
-    Complete,
-    CopySelection,
-    CopyLine,
-    PasteSelection,
-    PasteCursorSelection,
-    GotoDefinition,
-    GotoImplementation,
+// The parts are separated by unescaped slashes, \/ stands for a slash in the pattern or replacement
+fn parse_substitution(input: &str) -> Option<Substitution> {
+    let (whole_file, rest) = match input.strip_prefix(":%s/") {
+        Some(rest) => (true, rest),
+        None => (false, input.strip_prefix(":s/")?),
+    };
+
+    let mut parts: Vec<Vec<u8>> = vec![vec![]];
+    let mut escaped = false;
+    for c in rest.bytes() {
+        match c {
+            b'\\' if !escaped => escaped = true,
+            b'/' if !escaped && parts.len() < 3 => parts.push(vec![]),
+            c => {
+                let part = parts.last_mut().unwrap();
+                if escaped && c != b'/' && c != b'\\' {
+                    part.push(b'\\');
+                }
+                part.push(c);
+                escaped = false;
+            }
+        }
+    }
+    if escaped {
+        parts.last_mut().unwrap().push(b'\\');
+    }
+
+    let mut parts = parts.into_iter();
+    let pattern = parts.next().filter(|pattern| !pattern.is_empty())?;
+    let replacement = parts.next();
+    // Asking before each replacement (c) isn't supported, the command is refused rather than
+    // replacing everything without asking
+    let flags = parts.next().unwrap_or_default();
+    if flags.iter().any(|flag| *flag != b'g') {
+        return None;
+    }
+    let global = !flags.is_empty();
+    Some(Substitution {
+        whole_file,
+        pattern,
+        replacement,
+        global,
+    })
 }
@@ -0,0 +1,173 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    cursor::{CompletionRequest, Cursor},
+    language_server::LanguageServer,
+    language_server_types::{
+        CompletionDocumentation, CompletionItem, CompletionList, Hover, MarkupContent,
+        COMPLETION_ITEM_TAG_DEPRECATED,
+    },
+    piece_table::PieceTable,
+    text_utils::{self, CharType},
+    view::HoverMessage,
+};
+
+pub const MAX_SHOWN_DOCUMENTATION_LINES: usize = 12;
+
+static SHOW_DEPRECATED_COMPLETIONS: AtomicBool = AtomicBool::new(true);
+
+// Returns whether deprecated items are shown from now on
+pub fn toggle_deprecated_completions() -> bool {
+    !SHOW_DEPRECATED_COMPLETIONS.fetch_xor(true, Ordering::Relaxed)
+}
+
+pub fn is_deprecated(item: &CompletionItem) -> bool {
+    item.deprecated == Some(true)
+        || item
+            .tags
+            .as_ref()
+            .is_some_and(|tags| tags.contains(&COMPLETION_ITEM_TAG_DEPRECATED))
+}
+
+// Documentation is processed like a hover, markdown code fences are stripped
+pub fn completion_documentation(item: &CompletionItem) -> Option<HoverMessage> {
+    let contents = match item.documentation.clone()? {
+        CompletionDocumentation::String(value) => MarkupContent {
+            kind: String::from("plaintext"),
+            value,
+        },
+        CompletionDocumentation::MarkupContent(contents) => contents,
+    };
+    HoverMessage::from_hover(Hover { contents })
+}
+
+pub fn get_filtered_completions(
+    piece_table: &PieceTable,
+    completion_list: &CompletionList,
+    request: &CompletionRequest,
+    cursor_position: usize,
+) -> Vec<CompletionItem> {
+    let show_deprecated = SHOW_DEPRECATED_COMPLETIONS.load(Ordering::Relaxed);
+    let items: Vec<&CompletionItem> = completion_list
+        .items
+        .iter()
+        .filter(|item| show_deprecated || !is_deprecated(item))
+        .collect();
+
+    let mut merged_items: Vec<CompletionItem> = vec![];
+    for &item in &items {
+        if !merged_items.iter().any(|x| {
+            x.insert_text.as_ref().unwrap_or(&x.label)
+                == item.insert_text.as_ref().unwrap_or(&item.label)
+        }) {
+            merged_items.push(item.clone());
+        } else {
+            let existing_item = merged_items
+                .iter_mut()
+                .find(|x| {
+                    x.insert_text.as_ref().unwrap_or(&x.label)
+                        == item.insert_text.as_ref().unwrap_or(&item.label)
+                })
+                .unwrap();
+
+            if let Some(existing_details) = &mut existing_item.detail {
+                if let Some(details) = &item.detail {
+                    existing_item.label.push(b'\n' as char);
+                    existing_item.label.push_str(&item.label);
+                    existing_details.push(b'\n' as char);
+                    existing_details.push_str(details)
+                }
+            }
+        }
+    }
+
+    // Filter from start of word if manually triggered or
+    let request_position = if request.manually_triggered {
+        cursor_position.saturating_sub(
+            piece_table
+                .iter_chars_at_rev(cursor_position.saturating_sub(1))
+                .position(|c| text_utils::char_type(c) != CharType::Word)
+                .unwrap_or(0),
+        )
+    // Filter from start of request if triggered by a trigger character
+    } else {
+        request.initial_position
+    };
+
+    let match_string: Vec<u8> = piece_table
+        .iter_chars_at(request_position)
+        .take(cursor_position - request_position)
+        .collect();
+    let trimmed_match_string = match_string.trim_ascii();
+
+    let mut filtered_completions: Vec<CompletionItem> = merged_items
+        .iter()
+        .filter(|item| {
+            item.insert_text
+                .as_ref()
+                .unwrap_or(&item.label)
+                .starts_with(unsafe { std::str::from_utf8_unchecked(trimmed_match_string) })
+        })
+        .cloned()
+        .collect();
+
+    // If the match string doesn't match anything, show all entries
+    if filtered_completions.is_empty() {
+        filtered_completions = items.into_iter().cloned().collect();
+    }
+
+    if match_string.first() == Some(&b' ') {
+        for item in &mut filtered_completions {
+            item.label.insert(0, ' ');
+            if let Some(insert_text) = &mut item.insert_text {
+                insert_text.insert(0, ' ');
+            }
+        }
+    }
+
+    filtered_completions
+}
+
+pub fn reset_completion(
+    cursor: &mut Cursor,
+    language_server: &mut Option<Rc<RefCell<LanguageServer>>>,
+) {
+    if let Some(server) = &language_server {
+        if let Some(request) = cursor.completion_request {
+            server.borrow_mut().saved_completions.remove(&request.id);
+        }
+    }
+    cursor.completion_request = None;
+}
+
+pub fn reset_completion_view(
+    cursor: &mut Cursor,
+    language_server: &mut Option<Rc<RefCell<LanguageServer>>>,
+) {
+    if let Some(server) = &language_server {
+        if let Some(ref mut request) = cursor.completion_request {
+            request.selection_index = 0;
+            request.selection_view_offset = 0;
+            request.documentation_offset = 0;
+        }
+    }
+}
+
+pub fn reset_signature_help(
+    cursor: &mut Cursor,
+    language_server: &mut Option<Rc<RefCell<LanguageServer>>>,
+) {
+    if let Some(server) = &language_server {
+        if let Some(request) = cursor.signature_help_request {
+            server
+                .borrow_mut()
+                .saved_signature_helps
+                .remove(&request.id);
+        }
+    }
+    cursor.signature_help_request = None;
+}
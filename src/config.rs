@@ -0,0 +1,345 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{Map, Value};
+
+use crate::{log, view::JumpCenter, workspace_trust};
+
+// Settings that can be overridden per language and per workspace. Unset values fall through to
+// the next, less specific configuration
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    pub indent_width: Option<usize>,
+    pub format_on_save: Option<bool>,
+    pub organize_imports_on_save: Option<bool>,
+    pub save_summary: Option<bool>,
+    pub rulers: Option<Vec<usize>>,
+    pub hover_delay: Option<u64>,
+    pub word_highlight: Option<bool>,
+    pub lint: Option<bool>,
+    pub number: Option<bool>,
+    pub relative_number: Option<bool>,
+    pub primary_selection: Option<bool>,
+    pub jump_center: Option<JumpCenter>,
+    pub lsp_change_debounce: Option<u64>,
+    pub encrypted_files: Option<Vec<String>>,
+    // Typed text and what it expands to, the abbreviations of a language add to the global ones
+    pub abbreviations: Option<Vec<(String, String)>>,
+    pub lsp: Option<Value>,
+}
+
+impl Settings {
+    fn merge(&mut self, other: &Settings) {
+        if other.indent_width.is_some() {
+            self.indent_width = other.indent_width;
+        }
+        if other.format_on_save.is_some() {
+            self.format_on_save = other.format_on_save;
+        }
+        if other.organize_imports_on_save.is_some() {
+            self.organize_imports_on_save = other.organize_imports_on_save;
+        }
+        if other.save_summary.is_some() {
+            self.save_summary = other.save_summary;
+        }
+        if other.rulers.is_some() {
+            self.rulers = other.rulers.clone();
+        }
+        if other.hover_delay.is_some() {
+            self.hover_delay = other.hover_delay;
+        }
+        if other.word_highlight.is_some() {
+            self.word_highlight = other.word_highlight;
+        }
+        if other.lint.is_some() {
+            self.lint = other.lint;
+        }
+        if other.number.is_some() {
+            self.number = other.number;
+        }
+        if other.relative_number.is_some() {
+            self.relative_number = other.relative_number;
+        }
+        if other.primary_selection.is_some() {
+            self.primary_selection = other.primary_selection;
+        }
+        if other.jump_center.is_some() {
+            self.jump_center = other.jump_center;
+        }
+        if other.lsp_change_debounce.is_some() {
+            self.lsp_change_debounce = other.lsp_change_debounce;
+        }
+        if other.encrypted_files.is_some() {
+            self.encrypted_files = other.encrypted_files.clone();
+        }
+        if let Some(abbreviations) = &other.abbreviations {
+            for (abbreviation, expansion) in abbreviations {
+                self.add_abbreviation(abbreviation, expansion);
+            }
+        }
+        if let Some(lsp) = &other.lsp {
+            match &mut self.lsp {
+                Some(settings) => merge_json(settings, lsp),
+                None => self.lsp = Some(lsp.clone()),
+            }
+        }
+    }
+
+    fn add_abbreviation(&mut self, abbreviation: &str, expansion: &str) {
+        let abbreviations = self.abbreviations.get_or_insert_with(Vec::new);
+        abbreviations.retain(|(existing, _)| existing != abbreviation);
+        abbreviations.push((abbreviation.to_string(), expansion.to_string()));
+    }
+
+    fn set(&mut self, key: &str, value: Value, path: &Path) {
+        match (key, &value) {
+            ("indent_width", Value::Number(number)) if let Some(width) = number.as_u64() => {
+                self.indent_width = Some(width as usize);
+            }
+            ("format_on_save", Value::Bool(enabled)) => self.format_on_save = Some(*enabled),
+            ("organize_imports_on_save", Value::Bool(enabled)) => {
+                self.organize_imports_on_save = Some(*enabled)
+            }
+            ("save_summary", Value::Bool(enabled)) => self.save_summary = Some(*enabled),
+            ("word_highlight", Value::Bool(enabled)) => self.word_highlight = Some(*enabled),
+            ("lint", Value::Bool(enabled)) => self.lint = Some(*enabled),
+            ("number", Value::Bool(enabled)) => self.number = Some(*enabled),
+            ("relative_number", Value::Bool(enabled)) => self.relative_number = Some(*enabled),
+            ("primary_selection", Value::Bool(enabled)) => self.primary_selection = Some(*enabled),
+            ("jump_center", Value::String(name))
+                if let Some(jump_center) = JumpCenter::parse(name) =>
+            {
+                self.jump_center = Some(jump_center);
+            }
+            ("hover_delay", Value::Number(number)) if let Some(delay) = number.as_u64() => {
+                self.hover_delay = Some(delay);
+            }
+            ("lsp_change_debounce", Value::Number(number))
+                if let Some(debounce) = number.as_u64() =>
+            {
+                self.lsp_change_debounce = Some(debounce);
+            }
+            ("rulers", Value::Array(rulers)) => {
+                self.rulers = Some(
+                    rulers
+                        .iter()
+                        .filter_map(|ruler| ruler.as_u64().map(|ruler| ruler as usize))
+                        .collect(),
+                );
+            }
+            ("encrypted_files", Value::Array(patterns)) => {
+                self.encrypted_files = Some(
+                    patterns
+                        .iter()
+                        .filter_map(|pattern| pattern.as_str().map(|pattern| pattern.to_string()))
+                        .collect(),
+                );
+            }
+            _ => log::warning(format!(
+                "{}: unknown setting or invalid value for {}",
+                path.display(),
+                key
+            )),
+        }
+    }
+}
+
+// A config.toml with top-level settings for every language and [language.NAME] sections that
+// override them. Language server settings go in [lsp] and [language.NAME.lsp] sections, where
+// dotted keys become nested objects (checkOnSave.command = "clippy"), and insert mode
+// abbreviations in [abbreviations] and [language.NAME.abbreviations] sections
+#[derive(Debug, Default)]
+pub struct Config {
+    settings: Settings,
+    languages: HashMap<String, Settings>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Option<Self> {
+        let text = fs::read_to_string(path).ok()?;
+        let mut config = Config::default();
+        let mut section = String::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = name.trim().to_string();
+                continue;
+            }
+
+            let Some((key, value)) = line
+                .split_once('=')
+                .and_then(|(key, value)| Some((key.trim(), parse_value(value.trim())?)))
+            else {
+                log::warning(format!(
+                    "{}:{}: expected key = value",
+                    path.display(),
+                    i + 1
+                ));
+                continue;
+            };
+
+            let (language, table) = match section.strip_prefix("language.") {
+                Some(language) => match language.split_once('.') {
+                    Some((language, table)) => (Some(language), Some(table)),
+                    None => (Some(language), None),
+                },
+                None => (
+                    None,
+                    Some(section.as_str()).filter(|table| !table.is_empty()),
+                ),
+            };
+            let settings = match language {
+                Some(language) => config.languages.entry(language.to_string()).or_default(),
+                None => &mut config.settings,
+            };
+
+            if table == Some("lsp") {
+                let mut object = value;
+                for part in key.rsplit('.') {
+                    let mut map = Map::new();
+                    map.insert(part.trim_matches('"').to_string(), object);
+                    object = Value::Object(map);
+                }
+                match &mut settings.lsp {
+                    Some(lsp) => merge_json(lsp, &object),
+                    None => settings.lsp = Some(object),
+                }
+            } else if table == Some("abbreviations") {
+                match value {
+                    Value::String(expansion) => {
+                        settings.add_abbreviation(key.trim_matches('"'), &expansion)
+                    }
+                    _ => log::warning(format!(
+                        "{}:{}: abbreviations expand to strings",
+                        path.display(),
+                        i + 1
+                    )),
+                }
+            } else if table.is_none() {
+                settings.set(key, value, path);
+            } else {
+                log::warning(format!(
+                    "{}:{}: unknown section [{}]",
+                    path.display(),
+                    i + 1,
+                    section
+                ));
+            }
+        }
+
+        Some(config)
+    }
+
+    fn apply(&self, settings: &mut Settings, language: Option<&str>) {
+        settings.merge(&self.settings);
+        if let Some(language_settings) = language.and_then(|language| self.languages.get(language))
+        {
+            settings.merge(language_settings);
+        }
+    }
+}
+
+// The user's ~/.nimble/config.toml is overridden by the .nimble/config.toml of the workspace
+pub fn settings_for(workspace_path: Option<&str>, language: Option<&str>) -> Settings {
+    let mut settings = Settings::default();
+    for directory in config_directories(workspace_path) {
+        if let Some(config) = Config::load(&directory.join("config.toml")) {
+            config.apply(&mut settings, language);
+        }
+    }
+    settings
+}
+
+// The settings of a single file, used by the script runner
+pub fn settings_from_file(path: &Path, language: Option<&str>) -> Settings {
+    let mut settings = Settings::default();
+    if let Some(config) = Config::load(path) {
+        config.apply(&mut settings, language);
+    }
+    settings
+}
+
+// ~/.nimble and the .nimble directory of the workspace, least specific first
+// The workspace directory is skipped until the user trusts it
+pub fn config_directories(workspace_path: Option<&str>) -> Vec<PathBuf> {
+    let home = env::var("HOME").or_else(|_| env::var("USERPROFILE"));
+    let workspace_path =
+        workspace_path.filter(|path| workspace_trust::is_trusted(path) == Some(true));
+    home.ok()
+        .into_iter()
+        .chain(workspace_path.map(|path| path.to_string()))
+        .map(|path| Path::new(&path).join(".nimble"))
+        .collect()
+}
+
+fn merge_json(target: &mut Value, source: &Value) {
+    match (target, source) {
+        (Value::Object(target), Value::Object(source)) => {
+            for (key, value) in source {
+                match target.get_mut(key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, source) => *target = source.clone(),
+    }
+}
+
+pub fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+// Strings, integers, floats, booleans and (non-nested) arrays of those
+pub fn parse_value(value: &str) -> Option<Value> {
+    if let Some(items) = value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+    {
+        return items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(parse_value)
+            .collect::<Option<Vec<Value>>>()
+            .map(Value::Array);
+    }
+    if let Some(string) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        return Some(Value::String(
+            string.replace("\\\"", "\"").replace("\\\\", "\\"),
+        ));
+    }
+    match value {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => value
+            .parse::<i64>()
+            .map(Value::from)
+            .ok()
+            .or_else(|| value.parse::<f64>().ok().map(Value::from)),
+    }
+}
@@ -0,0 +1,67 @@
+use std::cmp::min;
+
+use crate::renderer::Color;
+
+// Anything drawn on top of the text of a buffer, anchored to character positions so that it moves
+// with edits. Features describe what to show and the renderer draws all of them the same way.
+#[derive(Clone, Debug)]
+pub struct Decoration {
+    // Lets a feature replace its own decorations without touching those of others
+    pub namespace: &'static str,
+    pub start: usize,
+    pub end: usize,
+    pub kind: DecorationKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum DecorationKind {
+    Style(DecorationStyle),
+    // Drawn after the end of the line that contains start, like inline diagnostics
+    VirtualText(String, Color),
+    // Drawn in the column left of the line number of the line that contains start
+    GutterSign(char, Color),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DecorationStyle {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub underline: Option<Color>,
+    pub strikethrough: Option<Color>,
+}
+
+// Text inserted at the start of a range moves it, text inserted inside of it extends it
+pub fn decorations_insert_rebalance(decorations: &mut [Decoration], position: usize, count: usize) {
+    for decoration in decorations {
+        if decoration.start >= position {
+            decoration.start += count;
+        }
+        if decoration.end > position {
+            decoration.end += count;
+        }
+        decoration.end = decoration.end.max(decoration.start);
+    }
+}
+
+pub fn decorations_delete_rebalance(
+    decorations: &mut Vec<Decoration>,
+    position: usize,
+    end: usize,
+) {
+    let rebalance = |p: usize| {
+        if p >= end {
+            p - (end - position)
+        } else {
+            min(p, position)
+        }
+    };
+    for decoration in decorations.iter_mut() {
+        decoration.start = rebalance(decoration.start);
+        decoration.end = rebalance(decoration.end);
+    }
+
+    // Nothing is left to style once all of the styled text is deleted
+    decorations.retain(|decoration| {
+        decoration.start < decoration.end || !matches!(decoration.kind, DecorationKind::Style(_))
+    });
+}
@@ -2,14 +2,15 @@ use std::{
     cell::RefCell,
     cmp::min,
     collections::HashMap,
-    ffi::{OsStr, OsString},
-    fs::File,
+    ffi::OsString,
+    fs::{self, File},
     io::{BufRead, BufReader},
+    path::{Component, Path, PathBuf},
     rc::Rc,
+    time::{Duration, Instant},
 };
 
 use url::Url;
-use walkdir::WalkDir;
 use winit::{
     dpi::LogicalPosition,
     event::{ModifiersState, VirtualKeyCode},
@@ -18,20 +19,44 @@ use winit::{
 
 use crate::{
     buffer::Buffer,
+    command::BufferMode,
+    config, encryption,
+    file_finder::{FileFinder, MAX_SHOWN_FILE_FINDER_ITEMS},
+    file_history::{self, HistoryPanel},
+    file_positions::{self, FilePosition},
+    file_watcher::FileWatcher,
+    keymap,
     language_server::LanguageServer,
-    language_server_types::{Hover, LocationType, VoidParams},
-    language_support::language_from_path,
-    platform_resources,
+    language_server_types::{
+        ApplyWorkspaceEditParams, ApplyWorkspaceEditResult, CodeActionOrCommand,
+        ExecuteCommandParams, FileRename, Hover, Location, LocationType, RenameFilesParams,
+        TextEdit, VoidParams, WorkspaceEdit,
+    },
+    language_support::{language_from_filetype, Language, RUST_IDENTIFIER},
+    log::{self, LogLevel},
+    picker::PickerEvent,
+    piece_table::PieceTable,
+    platform_resources::{self, PlatformResources},
     renderer::{RenderLayout, Renderer},
+    symbol_search::SymbolPanel,
     text_utils,
-    view::{HoverMessage, View, SCROLL_LINES_PER_ROLL},
+    theme::{THEMES, THEME_NAMES},
+    view::{self, HoverMessage, JumpCenter, View, SCROLL_LINES_PER_ROLL},
+    workspace_index::WorkspaceIndex,
+    workspace_search::SearchPanel,
+    workspace_trust,
 };
 
-pub const MAX_SHOWN_FILE_FINDER_ITEMS: usize = 10;
+pub const MAX_SHOWN_MESSAGES: usize = 20;
+const SYNTECT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+const HIGHLIGHT_FRAME_BUDGET: Duration = Duration::from_millis(2);
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+const MAX_SHOWN_TOASTS: usize = 3;
+const SAVE_HOOK_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub enum EditorCommand {
     CenterView,
-    CenterIfNotVisible,
+    RevealJump,
     ToggleSplitView,
     NextTab,
     PreviousTab,
@@ -39,31 +64,205 @@ pub enum EditorCommand {
     QuitAll,
     QuitNoCheck,
     QuitAllNoCheck,
+    QuitOthers,
+    QuitOthersNoCheck,
+    QuitSaved,
+    QuitHidden,
+    QuitHiddenNoCheck,
+    SaveAll,
+    SaveAndQuit,
+    SaveAllAndQuit,
+    SetFiletype(String),
+    ShowMessages(LogLevel),
+    ShowRegisters,
+    ShowHistory,
+    SetAnnounceMode(bool),
+    SetColorBlind(bool),
+    SetInlineDiagnostics(bool),
+    SetNumber(bool),
+    SetRelativeNumber(bool),
+    SetPrimarySelection(bool),
+    GotoFile(String, Option<(usize, usize)>),
+    OpenUrl(String),
+    RenameFile(String),
+    VerticalSplit(Option<String>),
+    DeleteFile,
+    RestoreFile,
+    SplitNextJump,
+    SetHoverDelay(u64),
+    Hover,
+    ShowDiagnostic,
+    CompleteWord(bool),
+    CopyPath(PathFormat),
+    RevealFile,
+    HexView,
+    TrustWorkspace,
+    SearchWorkspace(String),
+    SearchSymbols(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PathFormat {
+    Absolute,
+    Relative,
+    // path:line:col of the cursor, like compiler messages
+    Reference,
+}
+
+// A file rename waiting for the language server to update the references to the file
+struct PendingFileRename {
+    id: i32,
+    old_path: String,
+    new_path: String,
+}
+
+// The last file moved to the trash, :undelete brings it back out of the trash. The contents are
+// only kept for when the platform doesn't tell where the file went
+struct DeletedFile {
+    path: String,
+    trashed: Option<String>,
+    contents: Vec<u8>,
+}
+
+// :wq, :xa and :wqa quit once the language server has organized and formatted what they save.
+// Input is ignored until then, so that the quit closes the documents it was typed for
+struct PendingQuit {
+    all: bool,
+    started: Instant,
 }
 
 struct Document {
     uri: Url,
     buffer: Buffer,
-    view: View,
+    // The cursors, mode and scroll position of the document in the left and the right split view
+    views: [View; 2],
+    // Decides whose highlights are evicted first, see evict_highlights
+    last_viewed: Instant,
+}
+
+impl Document {
+    // The buffer and the state of the document in a split view, caught up with the edits made
+    // through the other view
+    fn view(&mut self, view_index: usize) -> (&mut Buffer, &mut View) {
+        self.buffer.sync_views(&mut self.views);
+        (&mut self.buffer, &mut self.views[view_index])
+    }
+
+    // Both views, for updates that concern the cursors of either
+    fn views(&mut self) -> (&mut Buffer, &mut [View; 2]) {
+        self.buffer.sync_views(&mut self.views);
+        (&mut self.buffer, &mut self.views)
+    }
+
+    // A view that didn't show the document yet starts out where the other view is
+    fn split(&mut self, view_index: usize) {
+        self.buffer.sync_views(&mut self.views);
+        let other = &self.views[1 - view_index];
+        self.views[view_index] = View {
+            cursors: other.cursors.clone(),
+            line_offset: other.line_offset,
+            col_offset: other.col_offset,
+            ..View::new()
+        };
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct FileIdentifier {
     pub name: OsString,
     pub path: OsString,
+    // Line and column of symbols of the workspace index and of references
+    pub position: Option<(usize, usize)>,
 }
 
-pub struct FileFinder {
-    pub files: Vec<FileIdentifier>,
-    pub search_string: String,
+#[derive(Clone, Copy, PartialEq)]
+pub enum ContextMenuItem {
+    Cut,
+    Copy,
+    Paste,
+    GotoDefinition,
+    FindReferences,
+    Rename,
+    FormatSelection,
+}
+
+pub const CONTEXT_MENU_ITEMS: [ContextMenuItem; 7] = [
+    ContextMenuItem::Cut,
+    ContextMenuItem::Copy,
+    ContextMenuItem::Paste,
+    ContextMenuItem::GotoDefinition,
+    ContextMenuItem::FindReferences,
+    ContextMenuItem::Rename,
+    ContextMenuItem::FormatSelection,
+];
+
+impl ContextMenuItem {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ContextMenuItem::Cut => "Cut",
+            ContextMenuItem::Copy => "Copy",
+            ContextMenuItem::Paste => "Paste",
+            ContextMenuItem::GotoDefinition => "Go to Definition",
+            ContextMenuItem::FindReferences => "Find References",
+            ContextMenuItem::Rename => "Rename",
+            ContextMenuItem::FormatSelection => "Format Selection",
+        }
+    }
+}
+
+// The labels are padded by a column on each side
+pub fn context_menu_width() -> usize {
+    CONTEXT_MENU_ITEMS
+        .iter()
+        .map(|item| item.label().len())
+        .max()
+        .unwrap_or(0)
+        + 2
+}
+
+pub struct ContextMenu {
+    pub row: usize,
+    pub col: usize,
     pub selection_index: usize,
-    pub selection_view_offset: usize,
+}
+
+// Themes are listed most recently used first and previewed while the selection moves
+pub struct ThemeSwitcher {
+    pub themes: Vec<usize>,
+    pub selection_index: usize,
+    original_theme: usize,
+}
+
+pub struct MessagesPanel {
+    pub min_level: LogLevel,
+    pub line_offset: usize,
+}
+
+pub struct RegistersPanel {
+    pub registers: Vec<(String, Vec<u8>)>,
+}
+
+// Encrypted files are opened once their passphrase is entered. Files matching the encrypted_files
+// patterns that are still stored as plain text get a new passphrase, which is typed twice
+pub struct PassphrasePrompt {
+    pub path: String,
+    pub passphrase: String,
+    pub encrypted: bool,
+    // The first entry of a new passphrase while it is repeated
+    pub confirmation: Option<String>,
+    position: Option<(usize, usize)>,
+}
+
+// Asked when a workspace with its own .nimble configuration is opened for the first time
+pub struct TrustPrompt {
+    pub path: String,
 }
 
 pub struct Workspace {
     pub uri: Url,
     pub path: String,
     pub gitignore_paths: Vec<String>,
+    pub include_paths: Vec<String>,
 }
 
 #[derive(Default, Debug)]
@@ -76,22 +275,66 @@ struct DocumentLayout {
 pub struct Editor {
     renderer: Renderer,
     workspace: Option<Workspace>,
+    workspace_index: Option<WorkspaceIndex>,
     file_finder: Option<FileFinder>,
+    messages_panel: Option<MessagesPanel>,
+    registers_panel: Option<RegistersPanel>,
+    search_panel: Option<SearchPanel>,
+    symbol_panel: Option<SymbolPanel>,
+    history_panel: Option<HistoryPanel>,
+    passphrase_prompt: Option<PassphrasePrompt>,
+    trust_prompt: Option<TrustPrompt>,
+    context_menu: Option<ContextMenu>,
+    theme_switcher: Option<ThemeSwitcher>,
+    recent_themes: Vec<usize>,
     active_view: usize,
     split_view: bool,
     open_documents: Vec<Document>,
     visible_documents: [Vec<usize>; 2],
     visible_documents_layouts: [DocumentLayout; 2],
     file_finder_layout: RenderLayout,
-    language_servers: HashMap<&'static str, Rc<RefCell<LanguageServer>>>,
+    // Servers are started per language and project root
+    language_servers: HashMap<(&'static str, String), Rc<RefCell<LanguageServer>>>,
+    window_title: String,
+    platform_resources: PlatformResources,
+    announce_mode_changes: bool,
+    announced_state: Option<(usize, usize, BufferMode, Option<usize>)>,
+    pending_file_rename: Option<PendingFileRename>,
+    split_next_jump: bool,
+    deleted_file: Option<DeletedFile>,
+    pending_quit: Option<PendingQuit>,
+    hover_delay: Duration,
+    // After Ctrl + W the next key is a window command, the character typed by it is skipped
+    window_command_pending: bool,
+    skip_char: bool,
+    toast_times: Vec<Instant>,
+    file_watcher: FileWatcher,
+    // Selecting text makes it the primary selection of the platform, which middle-click pastes
+    primary_selection_enabled: bool,
+    primary_selection_source: Option<(usize, Vec<(usize, usize)>)>,
 }
 
 impl Editor {
     pub fn new(window: &Window) -> Self {
+        keymap::load(None);
+        let mut renderer = Renderer::new(window);
+        load_line_numbers(&mut renderer, None);
+        load_jump_center(None);
         Self {
-            renderer: Renderer::new(window),
+            renderer,
             workspace: None,
+            workspace_index: None,
             file_finder: None,
+            messages_panel: None,
+            registers_panel: None,
+            search_panel: None,
+            symbol_panel: None,
+            history_panel: None,
+            passphrase_prompt: None,
+            trust_prompt: None,
+            context_menu: None,
+            theme_switcher: None,
+            recent_themes: (0..THEMES.len()).collect(),
             open_documents: vec![],
             active_view: 0,
             split_view: false,
@@ -99,18 +342,227 @@ impl Editor {
             visible_documents_layouts: [DocumentLayout::default(), DocumentLayout::default()],
             file_finder_layout: RenderLayout::default(),
             language_servers: HashMap::default(),
+            window_title: String::default(),
+            platform_resources: PlatformResources::new(window),
+            announce_mode_changes: false,
+            announced_state: None,
+            pending_file_rename: None,
+            split_next_jump: false,
+            deleted_file: None,
+            pending_quit: None,
+            hover_delay: hover_delay(None),
+            window_command_pending: false,
+            skip_char: false,
+            toast_times: vec![],
+            file_watcher: FileWatcher::new(),
+            primary_selection_enabled: primary_selection(None),
+            primary_selection_source: None,
+        }
+    }
+
+    pub fn update_pastes(&mut self) -> bool {
+        let mut inserted = false;
+        for i in 0..self.open_documents.len() {
+            let view_index = self.view_index_of(i);
+            let (buffer, view) = self.open_documents[i].view(view_index);
+            inserted |= buffer.update_paste(view);
         }
+        inserted
     }
 
+    // Only the documents on screen are highlighted, the focused one first. Whatever doesn't fit in
+    // the frame budget is picked up in the next frame
     pub fn update_highlights(&mut self) -> bool {
-        if let Some(i) = self.visible_documents[self.active_view].last() {
-            return self.open_documents[*i].buffer.update_highlights();
+        let deadline = Instant::now() + HIGHLIGHT_FRAME_BUDGET;
+        let num_views = if self.split_view { 2 } else { 1 };
+
+        let mut updated = false;
+        let mut highlighted = vec![];
+        for view_index in [self.active_view, 1 - self.active_view]
+            .into_iter()
+            .take(num_views)
+        {
+            let Some(i) = self.visible_documents[view_index].last().copied() else {
+                continue;
+            };
+            if highlighted.contains(&i) {
+                continue;
+            }
+            highlighted.push(i);
+
+            let document = &mut self.open_documents[i];
+            document.last_viewed = Instant::now();
+            document.buffer.prioritize_highlights(
+                document.views[view_index].line_offset,
+                self.visible_documents_layouts[view_index].layout.num_rows,
+            );
+            updated |= document.buffer.update_highlights(deadline);
+        }
+        updated
+    }
+
+    pub fn update_lsp_changes(&mut self) {
+        for language_server in self.language_servers.values() {
+            language_server.borrow_mut().update_changes();
+        }
+    }
+
+    pub fn update_search_panel(&mut self) -> bool {
+        self.search_panel
+            .as_mut()
+            .is_some_and(|search_panel| search_panel.update())
+    }
+
+    pub fn update_file_finder(&mut self) -> bool {
+        match (&mut self.file_finder, &self.workspace_index) {
+            (Some(file_finder), Some(workspace_index)) => file_finder.refresh(workspace_index),
+            _ => false,
+        }
+    }
+
+    // Toasts appear and expire on their own, the editor is redrawn whenever the shown ones change
+    pub fn update_toasts(&mut self) -> bool {
+        let toast_times: Vec<Instant> = log::toasts(TOAST_DURATION, MAX_SHOWN_TOASTS)
+            .iter()
+            .map(|toast| toast.time)
+            .collect();
+        if toast_times == self.toast_times {
+            return false;
+        }
+        self.toast_times = toast_times;
+        true
+    }
+
+    // Documents of files that other programs changed are reloaded, the language servers and
+    // highlighting are updated like for any other change. Files created or removed below the
+    // workspace update its index
+    pub fn update_disk_changes(&mut self) -> bool {
+        let open_documents = &self.open_documents;
+        let changes = self.file_watcher.poll(|| {
+            open_documents
+                .iter()
+                .map(|document| document.buffer.path.clone())
+                .collect()
+        });
+
+        // Files created or removed below the workspace show up in the file finder and completions
+        if let (Some(workspace), Some(workspace_index)) =
+            (&self.workspace, &mut self.workspace_index)
+        {
+            if changes.rescan {
+                workspace_index.scan(workspace);
+            } else {
+                workspace_index.update_files(workspace, changes.created, changes.removed);
+            }
+        }
+
+        let mut reloaded = vec![];
+        for (path, modified) in changes.modified_times {
+            for i in 0..self.open_documents.len() {
+                let view_index = self.view_index_of(i);
+                let (buffer, view) = self.open_documents[i].view(view_index);
+                if buffer.path == path && buffer.reload_if_changed(view, modified) {
+                    reloaded.push(i);
+                }
+            }
+        }
+
+        for view_index in 0..2 {
+            if let Some(&i) = self.visible_documents[view_index]
+                .last()
+                .filter(|i| reloaded.contains(i))
+            {
+                let (buffer, view) = self.open_documents[i].view(view_index);
+                view.adjust(buffer, &self.visible_documents_layouts[view_index].layout);
+            }
+        }
+        !reloaded.is_empty()
+    }
+
+    // Follows the visual selection of the focused buffer, the last selected text stays the primary
+    // selection after the selection is gone
+    pub fn update_primary_selection(&mut self) {
+        if !self.primary_selection_enabled {
+            return;
+        }
+        let Some(&i) = self.visible_documents[self.active_view].last() else {
+            return;
+        };
+        let (buffer, view) = self.open_documents[i].view(self.active_view);
+        let ranges = buffer.visual_selections(view);
+        if ranges.is_empty() {
+            self.primary_selection_source = None;
+            return;
+        }
+        if self.primary_selection_source == Some((i, ranges.clone())) {
+            return;
+        }
+        self.platform_resources
+            .set_primary_selection(&self.open_documents[i].buffer.text_in_ranges(&ranges));
+        self.primary_selection_source = Some((i, ranges));
+    }
+
+    pub fn update_word_highlights(&mut self) -> bool {
+        let mut changed = false;
+        for i in 0..self.open_documents.len() {
+            let view_index = self.view_index_of(i);
+            let (buffer, view) = self.open_documents[i].view(view_index);
+            changed |= buffer.update_word_highlight(view);
+        }
+        changed
+    }
+
+    // Run while idle so that compacting a heavily edited buffer never delays input. At most one
+    // buffer is compacted per pass to keep each pass short
+    pub fn compact_piece_tables(&mut self) {
+        for document in &mut self.open_documents {
+            if document.buffer.compact_piece_table() {
+                break;
+            }
+        }
+    }
+
+    // The highlights of the documents that were viewed the longest ago are dropped while all of them
+    // together take more than SYNTECT_MEMORY_BUDGET. Visible documents are always kept.
+    pub fn evict_highlights(&mut self) {
+        let mut memory_usage: usize = self
+            .open_documents
+            .iter()
+            .map(|document| document.buffer.syntect_memory_usage())
+            .sum();
+        if memory_usage <= SYNTECT_MEMORY_BUDGET {
+            return;
+        }
+
+        let mut hidden_documents: Vec<usize> = (0..self.open_documents.len())
+            .filter(|i| {
+                !self
+                    .visible_documents
+                    .iter()
+                    .any(|documents| documents.last() == Some(i))
+            })
+            .collect();
+        hidden_documents.sort_by_key(|i| self.open_documents[*i].last_viewed);
+        for i in hidden_documents {
+            if memory_usage <= SYNTECT_MEMORY_BUDGET {
+                break;
+            }
+            let buffer = &mut self.open_documents[i].buffer;
+            memory_usage = memory_usage.saturating_sub(buffer.syntect_memory_usage());
+            buffer.evict_highlights();
         }
-        false
+    }
+
+    pub fn resize(&mut self, window: &Window) {
+        self.renderer.resize(window);
+        self.update_layouts(window);
     }
 
     pub fn update_layouts(&mut self, window: &Window) {
-        self.renderer.ensure_size(window);
+        // Keep the previous layouts while minimized so views don't scroll against zero rows
+        if window.inner_size().width == 0 || window.inner_size().height == 0 {
+            return;
+        }
 
         let window_size = (
             window.inner_size().width as f64 / window.scale_factor(),
@@ -120,11 +572,9 @@ impl Editor {
 
         self.visible_documents_layouts[0] = if let Some(i) = self.visible_documents[0].last() {
             let left_document = &mut self.open_documents[*i];
-            let left_numbers_num_cols = (0..)
-                .take_while(|i| 10usize.pow(*i) <= left_document.buffer.piece_table.num_lines())
-                .count()
-                .max(4)
-                + 2;
+            let left_numbers_num_cols = self
+                .renderer
+                .gutter_width(left_document.buffer.piece_table.num_lines());
 
             let left_layout = RenderLayout {
                 row_offset: 0,
@@ -180,11 +630,9 @@ impl Editor {
 
         self.visible_documents_layouts[1] = if let Some(i) = self.visible_documents[1].last() {
             let right_document = &mut self.open_documents[*i];
-            let right_numbers_num_cols = (0..)
-                .take_while(|i| 10usize.pow(*i) <= right_document.buffer.piece_table.num_lines())
-                .count()
-                .max(4)
-                + 2;
+            let right_numbers_num_cols = self
+                .renderer
+                .gutter_width(right_document.buffer.piece_table.num_lines());
 
             let right_layout = RenderLayout {
                 row_offset: 0,
@@ -232,7 +680,7 @@ impl Editor {
             }
         };
 
-        if let (Some(workspace), Some(file_finder)) = (&self.workspace, &self.file_finder) {
+        if self.file_finder.is_some() {
             let num_cols = (window_size.0 / font_size.0).ceil() as usize;
             self.file_finder_layout = RenderLayout {
                 row_offset: 0,
@@ -243,39 +691,173 @@ impl Editor {
         }
     }
 
+    // Edits that don't come from a view, like those of language servers, go through the focused
+    // view unless only the other one shows the document
+    fn view_index_of(&self, i: usize) -> usize {
+        let other_view = 1 - self.active_view;
+        if self.visible_documents[self.active_view].last() != Some(&i)
+            && self.visible_documents[other_view].last() == Some(&i)
+        {
+            other_view
+        } else {
+            self.active_view
+        }
+    }
+
+    // Targets opened with a split modifier go to the other view, enabling the split if needed
+    fn focus_other_view(&mut self) {
+        self.split_view = true;
+        self.active_view = 1 - self.active_view;
+    }
+
+    pub fn update_window_title(&mut self, window: &Window) {
+        let mut title = String::default();
+        if let Some(i) = self.visible_documents[self.active_view].last() {
+            let buffer = &self.open_documents[*i].buffer;
+            if let Some(file_name) = Path::new(&buffer.path).file_name() {
+                title.push_str(&file_name.to_string_lossy());
+                if buffer.piece_table.dirty {
+                    title.push_str(" \u{25CF}");
+                }
+                let (num_errors, num_warnings) = buffer.diagnostic_counts();
+                if num_errors > 0 {
+                    title.push_str(&format!(" \u{2717}{}", num_errors));
+                }
+                if num_warnings > 0 {
+                    title.push_str(&format!(" \u{26A0}{}", num_warnings));
+                }
+                if let Some(progress) = buffer.paste_progress() {
+                    title.push_str(&format!(" (pasting {}%)", progress));
+                }
+                title.push_str(" \u{2014} ");
+            }
+        }
+        if let Some(workspace) = &self.workspace {
+            if let Some(workspace_name) = Path::new(&workspace.path).file_name() {
+                title.push_str(&workspace_name.to_string_lossy());
+                title.push_str(" \u{2014} ");
+            }
+        }
+        title.push_str("Nimble");
+
+        // Only notify the OS when the title actually changes
+        if title != self.window_title {
+            window.set_title(&title);
+            self.window_title = title;
+        }
+    }
+
+    // Screen readers are told about the current line, mode and context menu item as they change
+    pub fn update_announcements(&mut self) {
+        let Some(i) = self.visible_documents[self.active_view].last() else {
+            return;
+        };
+        let (buffer, view) = self.open_documents[*i].view(self.active_view);
+        let line = view
+            .cursors
+            .last()
+            .map_or(0, |cursor| buffer.piece_table.line_index(cursor.position));
+        let context_menu_item = self
+            .context_menu
+            .as_ref()
+            .map(|context_menu| context_menu.selection_index);
+        let state = (*i, line, view.mode, context_menu_item);
+
+        let Some(announced_state) = self.announced_state.replace(state) else {
+            return;
+        };
+        if announced_state == state {
+            return;
+        }
+
+        let announcement = if let Some(index) = context_menu_item {
+            CONTEXT_MENU_ITEMS[index].label().to_string()
+        } else if self.announce_mode_changes && announced_state.2 != view.mode {
+            match view.mode {
+                BufferMode::Normal => "Normal mode",
+                BufferMode::Insert => "Insert mode",
+                BufferMode::Visual => "Visual mode",
+                BufferMode::VisualLine => "Visual line mode",
+            }
+            .to_string()
+        } else if announced_state.0 != *i || announced_state.1 != line {
+            let text = buffer.piece_table.text_between_lines(line, line);
+            let text = String::from_utf8_lossy(&text);
+            if text.trim().is_empty() {
+                format!("Line {}, blank", line + 1)
+            } else {
+                format!("Line {}, {}", line + 1, text.trim())
+            }
+        } else {
+            return;
+        };
+        self.platform_resources.announce(&announcement);
+    }
+
     pub fn open_workspace(&mut self, window: &Window) -> bool {
         if let Some(path) = platform_resources::open_folder(window) {
-            self.workspace = Some(Workspace::new(&path));
+            platform_resources::add_recent_workspace(&path);
+            log::info(format!("Opened workspace {}", path));
+            let workspace = Workspace::new(&path);
+            self.workspace_index = Some(WorkspaceIndex::new(&workspace));
+            self.file_watcher.watch_directory(&path);
+            self.workspace = Some(workspace);
+            self.hover_delay = hover_delay(Some(&path));
+            load_line_numbers(&mut self.renderer, Some(&path));
+            load_jump_center(Some(&path));
+            self.primary_selection_enabled = primary_selection(Some(&path));
+            keymap::load(Some(&path));
+            self.prompt_workspace_trust(false);
             return true;
         }
         false
     }
 
-    pub fn handle_lsp_responses(
-        &mut self,
-        mouse_position: Option<LogicalPosition<f64>>,
-        window: &Window,
-    ) -> bool {
+    pub fn handle_lsp_responses(&mut self, window: &Window) -> bool {
         let mut require_redraw = false;
 
-        let window_size = (
-            window.inner_size().width as f64 / window.scale_factor(),
-            window.inner_size().height as f64 / window.scale_factor(),
-        );
-
         let mut goto_location = None;
-        for (identifier, server) in &mut self.language_servers {
-            let mut server = server.borrow_mut();
+        let mut split_jump = false;
+        let mut workspace_edit = None;
+        let mut file_rename = None;
+        let mut apply_edits = vec![];
+        let mut references = None;
+        let mut published_diagnostics = vec![];
+        for language_server in self.language_servers.values() {
+            let mut server = language_server.borrow_mut();
             match server.handle_responses() {
-                Some((responses, notifications)) => {
+                Some((responses, notifications, requests)) => {
+                    for request in requests {
+                        if request.method == "workspace/applyEdit" {
+                            match request.value.and_then(|value| {
+                                serde_json::from_value::<ApplyWorkspaceEditParams>(value).ok()
+                            }) {
+                                Some(params) => apply_edits.push((
+                                    Rc::clone(language_server),
+                                    request.id,
+                                    params.edit,
+                                )),
+                                None => server.send_response(
+                                    request.id,
+                                    ApplyWorkspaceEditResult {
+                                        applied: false,
+                                        failure_reason: Some("Malformed edit".to_string()),
+                                    },
+                                ),
+                            }
+                        }
+                    }
                     for response in responses {
                         match response.method {
                             "initialize" => {
-                                for document in &self.open_documents {
-                                    if let Some(language) = document.buffer.language {
-                                        if *identifier == language.identifier {
-                                            document.buffer.send_did_open(&mut server);
-                                        }
+                                for document in &mut self.open_documents {
+                                    if document
+                                        .buffer
+                                        .language_server
+                                        .as_ref()
+                                        .is_some_and(|server| Rc::ptr_eq(server, language_server))
+                                    {
+                                        document.buffer.send_did_open(&mut server);
                                     }
                                 }
                             }
@@ -284,7 +866,10 @@ impl Editor {
                                     server.save_completions(response.id, value);
                                 }
                                 for document in &mut self.open_documents {
-                                    document.buffer.update_completions(&mut server);
+                                    let (buffer, views) = document.views();
+                                    for view in views {
+                                        buffer.update_completions(view, &mut server);
+                                    }
                                 }
                                 require_redraw = true;
                             }
@@ -293,11 +878,15 @@ impl Editor {
                                     server.save_signature_help(response.id, value);
                                 }
                                 for document in &mut self.open_documents {
-                                    document.buffer.update_signature_helps(&mut server);
+                                    let (buffer, views) = document.views();
+                                    for view in views {
+                                        buffer.update_signature_helps(view, &mut server);
+                                    }
                                 }
                                 require_redraw = true;
                             }
                             "textDocument/definition" | "textDocument/implementation" => {
+                                split_jump = std::mem::take(&mut self.split_next_jump);
                                 if let Some(value) = response.value {
                                     if let Ok(value) = serde_json::from_value::<LocationType>(value)
                                     {
@@ -313,84 +902,175 @@ impl Editor {
                                 }
                                 require_redraw = true;
                             }
-                            "textDocument/hover" => {
-                                if let Some(value) = response.value {
-                                    if let Ok(hover) = serde_json::from_value::<Hover>(value) {
-                                        if let Some(mouse_position) = &mouse_position {
-                                            let hover_view =
-                                                if mouse_position.x < window_size.0 / 2.0 {
-                                                    0
-                                                } else {
-                                                    1
-                                                };
-
-                                            if let Some(i) =
-                                                self.visible_documents[hover_view].last()
-                                            {
-                                                match hover.contents.kind.as_str() {
-                                                    "plaintext" => {
-                                                        let num_lines = hover
-                                                            .contents
-                                                            .value
-                                                            .as_bytes()
-                                                            .iter()
-                                                            .filter(|&c| *c == b'\n')
-                                                            .count();
-                                                        self.open_documents[*i]
-                                                            .view
-                                                            .hover_message = Some(HoverMessage {
-                                                            message: hover.contents.value,
-                                                            code_block_ranges: vec![],
-                                                            line_offset: 0,
-                                                            num_lines,
-                                                        });
-                                                    }
-                                                    "markdown" => {
-                                                        let markdown = hover.contents.value;
-
-                                                        let mut processed_markdown =
-                                                            String::default();
-
-                                                        let mut code_block_ranges = vec![];
-                                                        let mut offset = 0;
-                                                        let mut code_block_start = None;
-                                                        for line in markdown.lines() {
-                                                            if line.starts_with("```") {
-                                                                if let Some(start) =
-                                                                    code_block_start
-                                                                {
-                                                                    code_block_ranges
-                                                                        .push((start, offset));
-                                                                    code_block_start = None;
-                                                                } else {
-                                                                    code_block_start = Some(offset);
-                                                                }
-                                                            } else {
-                                                                processed_markdown.push_str(line);
-                                                                processed_markdown.push('\n');
-                                                                offset = processed_markdown.len();
-                                                            }
-                                                        }
-
-                                                        let num_lines = processed_markdown
-                                                            .as_bytes()
-                                                            .iter()
-                                                            .filter(|&c| *c == b'\n')
-                                                            .count();
-                                                        self.open_documents[*i]
-                                                            .view
-                                                            .hover_message = Some(HoverMessage {
-                                                            message: processed_markdown,
-                                                            code_block_ranges,
-                                                            line_offset: 0,
-                                                            num_lines,
-                                                        });
-                                                    }
-                                                    _ => (),
+                            "textDocument/references" => {
+                                server.save_references(
+                                    response.id,
+                                    response.value.unwrap_or_default(),
+                                );
+                                for i in 0..self.open_documents.len() {
+                                    let view_index = self.view_index_of(i);
+                                    let (buffer, view) = self.open_documents[i].view(view_index);
+                                    if buffer
+                                        .language_server
+                                        .as_ref()
+                                        .is_some_and(|server| Rc::ptr_eq(server, language_server))
+                                    {
+                                        if let Some(locations) = buffer.take_references(&mut server)
+                                        {
+                                            references = Some((locations, server.utf16_positions));
+                                        }
+                                        buffer.begin_rename_session(view, &mut server);
+                                    }
+                                }
+                                require_redraw = true;
+                            }
+                            "workspace/symbol" => {
+                                if let Some(symbol_panel) = &mut self.symbol_panel {
+                                    if symbol_panel.is_from_server(language_server) {
+                                        require_redraw |= symbol_panel
+                                            .update_symbols(response.id, response.value);
+                                    }
+                                }
+                            }
+                            "textDocument/rename" => {
+                                if let Some(edit) = response.value.and_then(|value| {
+                                    serde_json::from_value::<WorkspaceEdit>(value).ok()
+                                }) {
+                                    workspace_edit = Some(edit);
+                                }
+                                require_redraw = true;
+                            }
+                            "workspace/willRenameFiles" => {
+                                if self
+                                    .pending_file_rename
+                                    .as_ref()
+                                    .is_some_and(|rename| rename.id == response.id)
+                                {
+                                    if let Some(edit) = response.value.and_then(|value| {
+                                        serde_json::from_value::<WorkspaceEdit>(value).ok()
+                                    }) {
+                                        workspace_edit = Some(edit);
+                                    }
+                                    file_rename = self.pending_file_rename.take();
+                                }
+                                require_redraw = true;
+                            }
+                            "textDocument/rangeFormatting" | "textDocument/formatting" => {
+                                if let Some(i) = self.open_documents.iter().position(|document| {
+                                    document.buffer.format_request == Some(response.id)
+                                }) {
+                                    let view_index = self.view_index_of(i);
+                                    let (buffer, view) = self.open_documents[i].view(view_index);
+                                    buffer.format_request = None;
+                                    if let Some(edits) = response.value.and_then(|value| {
+                                        serde_json::from_value::<Vec<TextEdit>>(value).ok()
+                                    }) {
+                                        buffer.apply_text_edits(view, &edits);
+                                    }
+                                    if buffer.save_after_format {
+                                        buffer.save_after_format = false;
+                                        buffer.save();
+                                    }
+                                }
+                                require_redraw = true;
+                            }
+                            "textDocument/codeAction" => {
+                                if let Some(i) = self.open_documents.iter().position(|document| {
+                                    document
+                                        .buffer
+                                        .code_action_request
+                                        .is_some_and(|request| request.id == response.id)
+                                }) {
+                                    let view_index = self.view_index_of(i);
+                                    let (buffer, view) = self.open_documents[i].view(view_index);
+                                    let request = buffer.code_action_request.take().unwrap();
+                                    let action = response
+                                        .value
+                                        .and_then(|value| {
+                                            serde_json::from_value::<Vec<CodeActionOrCommand>>(
+                                                value,
+                                            )
+                                            .ok()
+                                        })
+                                        .and_then(|actions| {
+                                            actions.into_iter().find_map(|action| match action {
+                                                CodeActionOrCommand::CodeAction(action)
+                                                    if (action.edit.is_some()
+                                                        || action.command.is_some())
+                                                        && action.kind.as_ref().is_some_and(
+                                                            |kind| kind.starts_with(request.kind),
+                                                        ) =>
+                                                {
+                                                    Some(action)
+                                                }
+                                                _ => None,
+                                            })
+                                        });
+                                    // The edit is applied first, commands may send further edits
+                                    // back through workspace/applyEdit
+                                    match action {
+                                        Some(action) => {
+                                            let changes =
+                                                action.edit.map(WorkspaceEdit::into_changes);
+                                            if let Some(Err(kind)) = &changes {
+                                                log::error(format!(
+                                                    "Unable to apply a code action that needs to {} a file",
+                                                    kind
+                                                ));
+                                            }
+                                            if let Some(Ok(changes)) = changes {
+                                                let (own, others): (Vec<_>, Vec<_>) =
+                                                    changes.into_iter().partition(|(uri, _)| {
+                                                        uri.to_lowercase()
+                                                            == buffer.uri.to_lowercase()
+                                                    });
+                                                for (_, edits) in own {
+                                                    buffer.apply_text_edits(view, &edits);
                                                 }
+                                                if !others.is_empty() {
+                                                    workspace_edit =
+                                                        Some(WorkspaceEdit::from_changes(others));
+                                                }
+                                            }
+                                            if let Some(command) = action.command {
+                                                server.send_request(
+                                                    "workspace/executeCommand",
+                                                    ExecuteCommandParams {
+                                                        command: command.command,
+                                                        arguments: command.arguments,
+                                                    },
+                                                );
                                             }
                                         }
+                                        None => log::info(format!(
+                                            "No {} code action available",
+                                            request.kind
+                                        )),
+                                    }
+
+                                    if request.save {
+                                        buffer.save_with_hooks(false);
+                                    }
+                                }
+                                require_redraw = true;
+                            }
+                            "textDocument/hover" => {
+                                for document in &mut self.open_documents {
+                                    if !document.buffer.finish_hover(response.id) {
+                                        continue;
+                                    }
+                                    // The message goes to the view the hover was started in
+                                    if let Some(view) =
+                                        document.views.iter_mut().find(|view| view.hover.is_some())
+                                    {
+                                        view.hover_message = response
+                                            .value
+                                            .and_then(|value| {
+                                                serde_json::from_value::<Hover>(value).ok()
+                                            })
+                                            .and_then(HoverMessage::from_hover);
                                     }
+                                    break;
                                 }
                                 require_redraw = true;
                             }
@@ -400,7 +1080,30 @@ impl Editor {
                     for notification in notifications {
                         if notification.method.as_str() == "textDocument/publishDiagnostics" {
                             if let Some(value) = notification.value {
-                                server.save_diagnostics(value);
+                                let uri = server.save_diagnostics(value);
+                                published_diagnostics.push(uri.clone());
+
+                                // Diagnostics are kept in byte columns so that edits can rebalance them
+                                if let (Some(document), Some(diagnostics)) = (
+                                    self.open_documents
+                                        .iter()
+                                        .find(|document| document.buffer.uri.to_lowercase() == uri),
+                                    server.saved_diagnostics.get_mut(&uri),
+                                ) {
+                                    for diagnostic in diagnostics {
+                                        diagnostic.range.start.character =
+                                            document.buffer.col_from_lsp(&diagnostic.range.start)
+                                                as u32;
+                                        diagnostic.range.end.character =
+                                            document.buffer.col_from_lsp(&diagnostic.range.end)
+                                                as u32;
+                                    }
+                                }
+                            }
+                            require_redraw = true;
+                        } else if notification.method.as_str() == "$/progress" {
+                            if let Some(value) = notification.value {
+                                server.save_progress(value);
                             }
                             require_redraw = true;
                         }
@@ -410,34 +1113,87 @@ impl Editor {
             }
         }
 
+        for document in &mut self.open_documents {
+            if published_diagnostics.contains(&document.buffer.uri.to_lowercase()) {
+                self.renderer
+                    .update_diagnostic_decorations(&mut document.buffer);
+            }
+        }
+
+        if let Some(edit) = workspace_edit {
+            self.apply_workspace_edit(edit, window);
+        }
+
+        // Edits requested by the server are answered with whether they could be applied
+        for (server, id, edit) in apply_edits {
+            let applied = self.apply_workspace_edit(edit, window);
+            server.borrow_mut().send_response(
+                id,
+                ApplyWorkspaceEditResult {
+                    applied,
+                    failure_reason: (!applied)
+                        .then(|| "Edit could not be applied to all files".to_string()),
+                },
+            );
+            require_redraw = true;
+        }
+
+        // The references are updated while the file is still at its old path
+        if let Some(rename) = file_rename {
+            self.finish_file_rename(&rename.old_path, &rename.new_path);
+        }
+
+        // A single reference is jumped to directly, several are listed in the file finder
+        if let Some((mut locations, utf16_positions)) = references {
+            log::notify(format!("Found {} references", locations.len()));
+            if locations.len() == 1 {
+                goto_location = locations.pop();
+            } else if !locations.is_empty() {
+                self.file_finder = Some(self.references_finder(locations, utf16_positions));
+            }
+        }
+
         if let Some(location) = goto_location {
-            if let Ok(path) = Url::parse(&location.uri) {
-                if let Ok(file_path) = path.to_file_path() {
-                    if let Some(file_path) = file_path.to_str() {
-                        self.open_file(file_path, window);
-                        let active_document_layout =
-                            &self.visible_documents_layouts[self.active_view];
-                        if let Some(i) = self.visible_documents[self.active_view].last() {
-                            let document = &mut self.open_documents[*i];
-                            document.buffer.set_cursor(
-                                location.range.start.line as usize,
-                                location.range.start.character as usize,
-                            );
-                            document.view.center_if_not_visible(
-                                &document.buffer,
-                                &active_document_layout.layout,
-                            );
-                            document.buffer.update_syntect(0);
-                        }
-                    }
-                }
+            if split_jump {
+                self.focus_other_view();
             }
+            self.goto_location(&location, window);
         }
 
         require_redraw
     }
 
+    fn goto_location(&mut self, location: &Location, window: &Window) {
+        let Some(path) = Url::parse(&location.uri)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+        else {
+            return;
+        };
+        let Some(file_path) = path.to_str() else {
+            return;
+        };
+
+        // The column can only be converted once the file is open
+        self.open_file(file_path, window);
+        let col = self.visible_documents[self.active_view]
+            .last()
+            .map(|i| {
+                self.open_documents[*i]
+                    .buffer
+                    .col_from_lsp(&location.range.start)
+            })
+            .unwrap_or(location.range.start.character as usize);
+        self.open_file_at(file_path, location.range.start.line as usize, col, window);
+    }
+
     pub fn render(&mut self, window: &Window) {
+        // Nothing to draw into while the window is minimized
+        if window.inner_size().width == 0 || window.inner_size().height == 0 {
+            return;
+        }
+
+        self.dismiss_stale_hovers();
         self.renderer.start_draw();
 
         let window_size = (
@@ -447,48 +1203,66 @@ impl Editor {
         let font_size = self.renderer.get_font_size();
 
         if let Some(left_document) = self.visible_documents[0].last() {
+            let document = &mut self.open_documents[*left_document];
+            let uri = document.uri.clone();
+            let (buffer, view) = document.view(0);
+            self.renderer.update_view_decorations(
+                buffer,
+                &self.visible_documents_layouts[0].layout,
+                view,
+                self.active_view == 0,
+            );
             self.renderer.draw_buffer(
-                &self.open_documents[*left_document].buffer,
+                buffer,
                 &self.visible_documents_layouts[0].layout,
-                &self.open_documents[*left_document].view,
-                &self.open_documents[*left_document].buffer.language_server,
+                view,
                 self.active_view == 0,
             );
 
             self.renderer.draw_numbers(
-                &self.open_documents[*left_document].buffer,
+                buffer,
                 &self.visible_documents_layouts[0].numbers_layout,
-                &self.open_documents[*left_document].view,
+                view,
             );
 
             self.renderer.draw_status_line(
                 &self.workspace,
-                Some(self.open_documents[*left_document].uri.clone()),
+                Some(uri),
                 &self.visible_documents_layouts[0].status_line_layout,
                 self.active_view == 0,
+                Some((buffer, view)),
             );
         }
 
         if let Some(right_document) = self.visible_documents[1].last() {
+            let document = &mut self.open_documents[*right_document];
+            let uri = document.uri.clone();
+            let (buffer, view) = document.view(1);
+            self.renderer.update_view_decorations(
+                buffer,
+                &self.visible_documents_layouts[1].layout,
+                view,
+                self.active_view == 1,
+            );
             self.renderer.draw_buffer(
-                &self.open_documents[*right_document].buffer,
+                buffer,
                 &self.visible_documents_layouts[1].layout,
-                &self.open_documents[*right_document].view,
-                &self.open_documents[*right_document].buffer.language_server,
+                view,
                 self.active_view == 1,
             );
 
             self.renderer.draw_numbers(
-                &self.open_documents[*right_document].buffer,
+                buffer,
                 &self.visible_documents_layouts[1].numbers_layout,
-                &self.open_documents[*right_document].view,
+                view,
             );
 
             self.renderer.draw_status_line(
                 &self.workspace,
-                Some(self.open_documents[*right_document].uri.clone()),
+                Some(uri),
                 &self.visible_documents_layouts[1].status_line_layout,
                 self.active_view == 1,
+                Some((buffer, view)),
             );
         }
 
@@ -499,6 +1273,7 @@ impl Editor {
                     None,
                     &self.visible_documents_layouts[0].status_line_layout,
                     self.active_view == 0,
+                    None,
                 );
             }
             if self.visible_documents[1].is_empty() {
@@ -507,6 +1282,7 @@ impl Editor {
                     None,
                     &self.visible_documents_layouts[1].status_line_layout,
                     self.active_view == 1,
+                    None,
                 );
             }
             self.renderer.draw_split(window);
@@ -521,40 +1297,174 @@ impl Editor {
                     num_cols: (window_size.0 / font_size.0).ceil() as usize,
                 },
                 true,
-            );
-        }
-
-        if let (Some(workspace), Some(file_finder)) = (&self.workspace, &self.file_finder) {
-            self.renderer.draw_file_finder(
-                &mut self.file_finder_layout,
-                &workspace.path,
-                file_finder,
+                None,
             );
         }
 
         if let Some(left_document) = self.visible_documents[0].last() {
-            self.renderer.draw_buffer_hovers(
-                &self.open_documents[*left_document].buffer,
+            let (buffer, view) = self.open_documents[*left_document].view(0);
+            self.renderer.draw_buffer_popups(
+                buffer,
+                &self.visible_documents_layouts[0].layout,
+                view,
+            );
+            view.hover_bounds = self.renderer.draw_buffer_hovers(
+                buffer,
                 &self.visible_documents_layouts[0].layout,
-                &self.open_documents[*left_document].view,
-                &self.open_documents[*left_document].buffer.language_server,
+                view,
+                &buffer.language_server,
             );
         }
 
         if let Some(right_document) = self.visible_documents[1].last() {
-            self.renderer.draw_buffer_hovers(
-                &self.open_documents[*right_document].buffer,
+            let (buffer, view) = self.open_documents[*right_document].view(1);
+            self.renderer.draw_buffer_popups(
+                buffer,
+                &self.visible_documents_layouts[1].layout,
+                view,
+            );
+            view.hover_bounds = self.renderer.draw_buffer_hovers(
+                buffer,
                 &self.visible_documents_layouts[1].layout,
-                &self.open_documents[*right_document].view,
-                &self.open_documents[*right_document].buffer.language_server,
+                view,
+                &buffer.language_server,
             );
         }
 
-        self.renderer.end_draw();
+        if let Some(file_finder) = &self.file_finder {
+            self.renderer
+                .draw_file_finder(&mut self.file_finder_layout, file_finder);
+        }
+
+        if let Some(messages_panel) = &self.messages_panel {
+            self.renderer.draw_messages(
+                &RenderLayout {
+                    row_offset: 0,
+                    col_offset: 0,
+                    num_rows: (window_size.1 / font_size.1).ceil() as usize,
+                    num_cols: (window_size.0 / font_size.0).ceil() as usize,
+                },
+                messages_panel,
+            );
+        }
+
+        if let Some(registers_panel) = &self.registers_panel {
+            self.renderer.draw_registers(
+                &RenderLayout {
+                    row_offset: 0,
+                    col_offset: 0,
+                    num_rows: (window_size.1 / font_size.1).ceil() as usize,
+                    num_cols: (window_size.0 / font_size.0).ceil() as usize,
+                },
+                registers_panel,
+            );
+        }
+
+        if let Some(search_panel) = &self.search_panel {
+            self.renderer.draw_search_panel(
+                &RenderLayout {
+                    row_offset: 0,
+                    col_offset: 0,
+                    num_rows: (window_size.1 / font_size.1).ceil() as usize,
+                    num_cols: (window_size.0 / font_size.0).ceil() as usize,
+                },
+                search_panel,
+            );
+        }
+
+        if let Some(symbol_panel) = &self.symbol_panel {
+            self.renderer.draw_symbol_panel(
+                &RenderLayout {
+                    row_offset: 0,
+                    col_offset: 0,
+                    num_rows: (window_size.1 / font_size.1).ceil() as usize,
+                    num_cols: (window_size.0 / font_size.0).ceil() as usize,
+                },
+                symbol_panel,
+                self.workspace
+                    .as_ref()
+                    .map(|workspace| workspace.path.as_str()),
+            );
+        }
+
+        if let Some(history_panel) = &self.history_panel {
+            self.renderer.draw_history_panel(
+                &RenderLayout {
+                    row_offset: 0,
+                    col_offset: 0,
+                    num_rows: (window_size.1 / font_size.1).ceil() as usize,
+                    num_cols: (window_size.0 / font_size.0).ceil() as usize,
+                },
+                history_panel,
+            );
+        }
+
+        if let Some(passphrase_prompt) = &self.passphrase_prompt {
+            self.renderer.draw_passphrase_prompt(
+                &RenderLayout {
+                    row_offset: 0,
+                    col_offset: 0,
+                    num_rows: (window_size.1 / font_size.1).ceil() as usize,
+                    num_cols: (window_size.0 / font_size.0).ceil() as usize,
+                },
+                passphrase_prompt,
+            );
+        }
+
+        if let Some(trust_prompt) = &self.trust_prompt {
+            self.renderer.draw_trust_prompt(
+                &RenderLayout {
+                    row_offset: 0,
+                    col_offset: 0,
+                    num_rows: (window_size.1 / font_size.1).ceil() as usize,
+                    num_cols: (window_size.0 / font_size.0).ceil() as usize,
+                },
+                trust_prompt,
+            );
+        }
+
+        if let Some(context_menu) = &self.context_menu {
+            self.renderer.draw_context_menu(
+                &RenderLayout {
+                    row_offset: 0,
+                    col_offset: 0,
+                    num_rows: (window_size.1 / font_size.1).ceil() as usize,
+                    num_cols: (window_size.0 / font_size.0).ceil() as usize,
+                },
+                context_menu,
+            );
+        }
+
+        if let Some(theme_switcher) = &self.theme_switcher {
+            self.renderer.draw_theme_switcher(
+                &RenderLayout {
+                    row_offset: 0,
+                    col_offset: 0,
+                    num_rows: (window_size.1 / font_size.1).ceil() as usize,
+                    num_cols: (window_size.0 / font_size.0).ceil() as usize,
+                },
+                theme_switcher,
+            );
+        }
+
+        // The messages panel already shows the whole history
+        if self.messages_panel.is_none() {
+            self.renderer.draw_toasts(
+                &RenderLayout {
+                    row_offset: 0,
+                    col_offset: 0,
+                    num_rows: (window_size.1 / font_size.1).ceil() as usize,
+                    num_cols: (window_size.0 / font_size.0).ceil() as usize,
+                },
+                &log::toasts(TOAST_DURATION, MAX_SHOWN_TOASTS),
+            );
+        }
+
+        self.renderer.end_draw();
     }
 
     pub fn lsp_shutdown(&mut self) {
-        for (identifier, server) in &mut self.language_servers {
+        for server in self.language_servers.values() {
             let mut server = server.borrow_mut();
             // According to the spec clients should wait for LSP response,
             // but we don't have time for that..
@@ -564,12 +1474,197 @@ impl Editor {
         }
     }
 
+    pub fn handle_mouse_right_click(
+        &mut self,
+        mouse_position: LogicalPosition<f64>,
+        window: &Window,
+    ) {
+        let window_size = (
+            window.inner_size().width as f64 / window.scale_factor(),
+            window.inner_size().height as f64 / window.scale_factor(),
+        );
+
+        if self.split_view {
+            self.active_view = if mouse_position.x < window_size.0 / 2.0 {
+                0
+            } else {
+                1
+            }
+        }
+
+        let active_document_layout = &self.visible_documents_layouts[self.active_view];
+        let font_size = self.renderer.get_font_size();
+        if let Some(i) = self.visible_documents[self.active_view].last() {
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            view.exit_hover();
+
+            let (line, col) = view.get_line_col(
+                buffer,
+                &active_document_layout.layout,
+                mouse_position,
+                font_size,
+            );
+
+            // Keep the selection if the click was inside of it, otherwise act on the clicked position
+            if !buffer.selection_contains(view, line, col) {
+                buffer.set_cursor(view, line, col);
+            }
+
+            let (num_rows, num_cols) = (
+                (window_size.1 / font_size.1).floor() as usize,
+                (window_size.0 / font_size.0).floor() as usize,
+            );
+            let width = context_menu_width();
+            self.context_menu = Some(ContextMenu {
+                row: min(
+                    (mouse_position.y / font_size.1) as usize,
+                    num_rows.saturating_sub(CONTEXT_MENU_ITEMS.len()),
+                ),
+                col: min(
+                    (mouse_position.x / font_size.0) as usize,
+                    num_cols.saturating_sub(width),
+                ),
+                selection_index: 0,
+            });
+        }
+    }
+
+    pub fn handle_mouse_middle_click(
+        &mut self,
+        mouse_position: LogicalPosition<f64>,
+        window: &Window,
+    ) {
+        if !self.primary_selection_enabled
+            || self.file_finder.is_some()
+            || self.search_panel.is_some()
+            || self.symbol_panel.is_some()
+            || self.history_panel.is_some()
+            || self.passphrase_prompt.is_some()
+            || self.trust_prompt.is_some()
+        {
+            return;
+        }
+        let text = self.platform_resources.get_primary_selection();
+        if text.is_empty() {
+            return;
+        }
+        self.context_menu = None;
+
+        let window_width = window.inner_size().width as f64 / window.scale_factor();
+        if self.split_view {
+            self.active_view = if mouse_position.x < window_width / 2.0 {
+                0
+            } else {
+                1
+            }
+        }
+
+        let active_document_layout = &self.visible_documents_layouts[self.active_view];
+        let font_size = self.renderer.get_font_size();
+        if let Some(i) = self.visible_documents[self.active_view].last() {
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            view.exit_hover();
+
+            let (line, col) = view.get_line_col(
+                buffer,
+                &active_document_layout.layout,
+                mouse_position,
+                font_size,
+            );
+            buffer.paste_at(view, line, col, &text);
+        }
+    }
+
+    fn preview_theme(&mut self, theme_index: usize) {
+        self.renderer.set_theme(theme_index);
+        for document in &mut self.open_documents {
+            document.buffer.syntect_reload(&self.renderer.theme);
+            self.renderer
+                .update_diagnostic_decorations(&mut document.buffer);
+        }
+    }
+
+    fn run_context_menu_item(&mut self, item: ContextMenuItem) {
+        self.context_menu = None;
+        if let Some(i) = self.visible_documents[self.active_view].last() {
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            buffer.run_context_menu_item(view, item);
+        }
+    }
+
     pub fn handle_mouse_pressed(
         &mut self,
         mouse_position: LogicalPosition<f64>,
         modifiers: Option<ModifiersState>,
         window: &Window,
     ) {
+        if self.passphrase_prompt.is_some()
+            || self.trust_prompt.is_some()
+            || self.pending_quit.is_some()
+        {
+            return;
+        }
+
+        if let Some(context_menu) = &self.context_menu {
+            let font_size = self.renderer.get_font_size();
+            let (row, col) = (
+                (mouse_position.y / font_size.1) as usize,
+                (mouse_position.x / font_size.0) as usize,
+            );
+            let item = row
+                .checked_sub(context_menu.row)
+                .filter(|_| {
+                    (context_menu.col..context_menu.col + context_menu_width()).contains(&col)
+                })
+                .and_then(|index| CONTEXT_MENU_ITEMS.get(index));
+            match item {
+                Some(item) => self.run_context_menu_item(*item),
+                None => self.context_menu = None,
+            }
+            return;
+        }
+
+        if let Some(symbol_panel) = &mut self.symbol_panel {
+            // The symbols start below the query at the top of the panel
+            let row = mouse_position.y / self.renderer.get_font_size().1 - 1.25;
+            if row >= 0.0 && symbol_panel.picker.select_row(row as usize) {
+                let in_split =
+                    modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::CTRL));
+                self.open_symbol(in_split, window);
+            }
+            return;
+        }
+
+        if let Some(search_panel) = &mut self.search_panel {
+            // The results start below the query at the top of the panel
+            let row = mouse_position.y / self.renderer.get_font_size().1 - 1.25;
+            if row >= 0.0 && search_panel.picker.select_row(row as usize) {
+                let in_split =
+                    modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::CTRL));
+                self.open_search_result(in_split, window);
+            }
+            return;
+        }
+
+        // Clicking a version only shows its diff, restoring it takes Enter
+        if let Some(history_panel) = &mut self.history_panel {
+            let row = mouse_position.y / self.renderer.get_font_size().1 - 1.25;
+            if row >= 0.0 {
+                history_panel.select_row(row as usize);
+            }
+            return;
+        }
+
+        // Clicking a hover popup copies its contents instead of moving the cursor
+        if let Some((view_index, i)) = self.hover_at(mouse_position) {
+            if let Some(hover_message) = &self.open_documents[i].views[view_index].hover_message {
+                self.platform_resources
+                    .set_clipboard(hover_message.message.trim_end().as_bytes());
+                log::notify("Copied hover contents to clipboard");
+            }
+            return;
+        }
+
         let window_size = (
             window.inner_size().width as f64 / window.scale_factor(),
             window.inner_size().height as f64 / window.scale_factor(),
@@ -586,18 +1681,25 @@ impl Editor {
         let active_document_layout = &self.visible_documents_layouts[self.active_view];
         let font_size = self.renderer.get_font_size();
         if let Some(i) = self.visible_documents[self.active_view].last() {
-            self.open_documents[*i].view.exit_hover();
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            view.exit_hover();
 
-            let (line, col) = self.open_documents[*i].view.get_line_col(
+            let (line, col) = view.get_line_col(
+                buffer,
                 &active_document_layout.layout,
                 mouse_position,
                 font_size,
             );
 
-            if modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::SHIFT)) {
-                self.open_documents[*i].buffer.insert_cursor(line, col);
+            if modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::CTRL)) {
+                view.definition_link = None;
+                buffer.handle_mouse_goto_definition(view, line, col);
+            } else if modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::ALT)) {
+                buffer.insert_cursor(view, line, col);
+            } else if modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::SHIFT)) {
+                buffer.extend_selection_to(view, line, col);
             } else {
-                self.open_documents[*i].buffer.set_cursor(line, col);
+                buffer.set_cursor(view, line, col);
             }
         }
     }
@@ -607,18 +1709,20 @@ impl Editor {
         mouse_position: LogicalPosition<f64>,
         modifiers: Option<ModifiersState>,
     ) {
-        if modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::SHIFT)) {
+        if modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::ALT)) {
             return;
         }
         let active_document_layout = &self.visible_documents_layouts[self.active_view];
         let font_size = self.renderer.get_font_size();
         if let Some(i) = self.visible_documents[self.active_view].last() {
-            let (line, col) = self.open_documents[*i].view.get_line_col(
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            let (line, col) = view.get_line_col(
+                buffer,
                 &active_document_layout.layout,
                 mouse_position,
                 font_size,
             );
-            self.open_documents[*i].buffer.set_drag(line, col);
+            buffer.set_drag(view, line, col);
         }
     }
 
@@ -643,27 +1747,29 @@ impl Editor {
         let active_document_layout = &self.visible_documents_layouts[self.active_view];
         let font_size = self.renderer.get_font_size();
         if let Some(i) = self.visible_documents[self.active_view].last() {
-            let (line, col) = self.open_documents[*i].view.get_line_col(
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            let (line, col) = view.get_line_col(
+                buffer,
                 &active_document_layout.layout,
                 mouse_position,
                 font_size,
             );
-            if modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::SHIFT)) {
-                self.open_documents[*i].buffer.insert_cursor(line, col);
-            } else if self.open_documents[*i]
-                .buffer
-                .handle_mouse_double_click(line, col)
-            {
+            if modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::ALT)) {
+                buffer.insert_cursor(view, line, col);
+            } else if modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::SHIFT)) {
+                buffer.extend_selection_to(view, line, col);
+            } else if buffer.handle_mouse_double_click(view, line, col) {
                 return true;
             }
         }
         false
     }
 
-    pub fn handle_scroll(
+    // Triple-click selects the line, any further click the surrounding paragraph
+    pub fn handle_mouse_multi_click(
         &mut self,
         mouse_position: LogicalPosition<f64>,
-        sign: isize,
+        clicks: usize,
         window: &Window,
     ) {
         let window_size = (
@@ -678,18 +1784,114 @@ impl Editor {
                 1
             }
         }
+        let active_document_layout = &self.visible_documents_layouts[self.active_view];
+        let font_size = self.renderer.get_font_size();
+        if let Some(i) = self.visible_documents[self.active_view].last() {
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            let (line, _) = view.get_line_col(
+                buffer,
+                &active_document_layout.layout,
+                mouse_position,
+                font_size,
+            );
+            if clicks == 3 {
+                buffer.handle_mouse_triple_click(view, line);
+            } else {
+                buffer.handle_mouse_quadruple_click(view, line);
+            }
+        }
+    }
+
+    pub fn handle_scroll(
+        &mut self,
+        mouse_position: LogicalPosition<f64>,
+        sign: isize,
+        window: &Window,
+    ) {
+        self.focus_view_under_mouse(mouse_position, window);
+
+        if let Some(search_panel) = &mut self.search_panel {
+            search_panel.picker.scroll(-sign * SCROLL_LINES_PER_ROLL);
+            return;
+        }
+        if let Some(symbol_panel) = &mut self.symbol_panel {
+            symbol_panel.picker.scroll(-sign * SCROLL_LINES_PER_ROLL);
+            return;
+        }
+        if let Some(history_panel) = &mut self.history_panel {
+            history_panel.scroll(-sign * SCROLL_LINES_PER_ROLL);
+            return;
+        }
+
+        if let Some((hover_view, i)) = self.hover_at(mouse_position) {
+            self.open_documents[i].views[hover_view].scroll_hover_message(
+                -sign * SCROLL_LINES_PER_ROLL,
+                self.visible_documents_layouts[hover_view].layout.num_rows / 2,
+            );
+            return;
+        }
+
+        if let Some(i) = self.visible_documents[self.active_view].last() {
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            let old_offset = view.line_offset;
+            view.handle_scroll(buffer, sign);
+            if view.line_offset != old_offset {
+                view.exit_hover();
+                buffer.cancel_hover();
+            }
+        }
+    }
+
+    // Panels only scroll vertically
+    pub fn handle_horizontal_scroll(
+        &mut self,
+        mouse_position: LogicalPosition<f64>,
+        sign: isize,
+        window: &Window,
+    ) {
+        self.focus_view_under_mouse(mouse_position, window);
+        if self.search_panel.is_some()
+            || self.symbol_panel.is_some()
+            || self.history_panel.is_some()
+        {
+            return;
+        }
 
         if let Some(i) = self.visible_documents[self.active_view].last() {
-            let document = &mut self.open_documents[*i];
-            let old_offset = document.view.line_offset;
-            document.view.handle_scroll(&document.buffer, sign);
-            if document.view.line_offset != old_offset {
-                document.view.exit_hover();
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            let old_offset = view.col_offset;
+            view.handle_horizontal_scroll(
+                buffer,
+                sign,
+                &self.visible_documents_layouts[self.active_view].layout,
+            );
+            if view.col_offset != old_offset {
+                view.exit_hover();
+                buffer.cancel_hover();
+            }
+        }
+    }
+
+    fn focus_view_under_mouse(&mut self, mouse_position: LogicalPosition<f64>, window: &Window) {
+        let window_size = (
+            window.inner_size().width as f64 / window.scale_factor(),
+            window.inner_size().height as f64 / window.scale_factor(),
+        );
+
+        if self.split_view {
+            self.active_view = if mouse_position.x < window_size.0 / 2.0 {
+                0
+            } else {
+                1
             }
         }
     }
 
     pub fn handle_mouse_hover(&mut self, mouse_position: LogicalPosition<f64>, window: &Window) {
+        if self.hover_at(mouse_position).is_some() {
+            return;
+        }
+
         let window_size = (
             window.inner_size().width as f64 / window.scale_factor(),
             window.inner_size().height as f64 / window.scale_factor(),
@@ -707,17 +1909,12 @@ impl Editor {
             .get(hover_view)
             .and_then(|documents| documents.last())
         {
-            let document = &mut self.open_documents[*i];
+            let (buffer, view) = self.open_documents[*i].view(hover_view);
             let document_layout = &self.visible_documents_layouts[hover_view];
-            document
-                .view
-                .hover(&document_layout.layout, mouse_position, font_size);
-
             let (line, col) =
-                document
-                    .view
-                    .get_line_col(&document_layout.layout, mouse_position, font_size);
-            document.buffer.handle_mouse_hover(line, col);
+                view.get_line_col(buffer, &document_layout.layout, mouse_position, font_size);
+            view.hover(buffer, line, col, false);
+            buffer.request_hover(line, col);
         }
     }
 
@@ -732,43 +1929,197 @@ impl Editor {
         );
 
         if let Some(mouse_position) = &mouse_position {
+            if self.hover_at(*mouse_position).is_some() {
+                return;
+            }
+
             let hover_view = if mouse_position.x < window_size.0 / 2.0 {
                 0
             } else {
                 1
             };
 
+            // Hovers shown with K stay open until the buffer or view changes
             if let Some(i) = self.visible_documents[hover_view].last() {
-                self.open_documents[*i].view.exit_hover();
+                let document = &mut self.open_documents[*i];
+                if !document.views[hover_view].hover_from_keyboard() {
+                    document.views[hover_view].exit_hover();
+                    document.buffer.cancel_hover();
+                }
             }
         }
     }
 
-    pub fn hovering(
-        &mut self,
-        mouse_position: Option<LogicalPosition<f64>>,
-        window: &Window,
-    ) -> bool {
-        let window_size = (
-            window.inner_size().width as f64 / window.scale_factor(),
-            window.inner_size().height as f64 / window.scale_factor(),
-        );
-
-        if let Some(mouse_position) = &mouse_position {
-            let hover_view = if mouse_position.x < window_size.0 / 2.0 {
+    fn run_window_command(&mut self, key_code: VirtualKeyCode, window: &Window) {
+        match key_code {
+            VirtualKeyCode::S | VirtualKeyCode::V => {
+                self.run_delayed_command(EditorCommand::VerticalSplit(None), window);
+            }
+            VirtualKeyCode::C | VirtualKeyCode::Q if self.split_view => {
+                // The remaining view becomes the left one
+                if self.active_view == 0 {
+                    self.swap_views();
+                }
+                self.split_view = false;
+                self.active_view = 0;
+            }
+            VirtualKeyCode::W | VirtualKeyCode::P if self.split_view => {
+                self.active_view = 1 - self.active_view;
+            }
+            VirtualKeyCode::H | VirtualKeyCode::Left => self.active_view = 0,
+            VirtualKeyCode::L | VirtualKeyCode::Right if self.split_view => self.active_view = 1,
+            VirtualKeyCode::R | VirtualKeyCode::X if self.split_view => {
+                self.swap_views();
+                self.active_view = 1 - self.active_view;
+            }
+            VirtualKeyCode::M => {
+                let other_view = 1 - self.active_view;
+                if let Some(i) = self.visible_documents[self.active_view].pop() {
+                    // The document takes its cursors and scroll position along
+                    self.open_documents[i].views.swap(0, 1);
+                    self.visible_documents[other_view].retain(|&x| x != i);
+                    self.visible_documents[other_view].push(i);
+                    self.split_view = true;
+                    self.active_view = other_view;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Documents take their view state along to the other side
+    fn swap_views(&mut self) {
+        self.visible_documents.swap(0, 1);
+        for document in &mut self.open_documents {
+            document.views.swap(0, 1);
+        }
+    }
+
+    pub fn hover_delay(&self) -> Duration {
+        self.hover_delay
+    }
+
+    fn hover_at_cursor(&mut self, request_hover: bool) {
+        if let Some(i) = self.visible_documents[self.active_view].last() {
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            let position = view.cursors.last().unwrap().position;
+            let (line, col) = (
+                buffer.piece_table.line_index(position),
+                buffer.piece_table.col_index(position),
+            );
+            view.hover(buffer, line, col, true);
+            if request_hover {
+                buffer.request_hover(line, col);
+            }
+        }
+    }
+
+    fn dismiss_stale_hovers(&mut self) {
+        for document in &mut self.open_documents {
+            for view in &mut document.views {
+                if view.hover_is_stale(&document.buffer) {
+                    view.exit_hover();
+                    document.buffer.cancel_hover();
+                }
+            }
+        }
+    }
+
+    // Hovers are suppressed while typing, including the ones still waiting for the server
+    pub fn cancel_hover(&mut self) {
+        for document in &mut self.open_documents {
+            for view in &mut document.views {
+                view.exit_hover();
+            }
+            document.buffer.cancel_hover();
+        }
+    }
+
+    pub fn hovering(
+        &mut self,
+        mouse_position: Option<LogicalPosition<f64>>,
+        window: &Window,
+    ) -> bool {
+        let window_size = (
+            window.inner_size().width as f64 / window.scale_factor(),
+            window.inner_size().height as f64 / window.scale_factor(),
+        );
+
+        if let Some(mouse_position) = &mouse_position {
+            let hover_view = if mouse_position.x < window_size.0 / 2.0 {
                 0
             } else {
                 1
             };
 
             if let Some(i) = self.visible_documents[hover_view].last() {
-                return self.open_documents[*i].view.hover.is_some();
+                return self.open_documents[*i].views[hover_view].hover.is_some();
             }
         }
 
         false
     }
 
+    // Underlines the symbol under the mouse while Ctrl is held, returns true if it changed
+    pub fn update_definition_link(
+        &mut self,
+        mouse_position: Option<LogicalPosition<f64>>,
+        modifiers: Option<ModifiersState>,
+        window: &Window,
+    ) -> bool {
+        let window_size = (
+            window.inner_size().width as f64 / window.scale_factor(),
+            window.inner_size().height as f64 / window.scale_factor(),
+        );
+        let font_size = self.renderer.get_font_size();
+
+        let mut changed = false;
+        for view_index in 0..2 {
+            let Some(i) = self.visible_documents[view_index].last() else {
+                continue;
+            };
+            let (buffer, view) = self.open_documents[*i].view(view_index);
+
+            let mut link = None;
+            if let Some(mouse_position) = mouse_position {
+                let hover_view = if self.split_view && mouse_position.x >= window_size.0 / 2.0 {
+                    1
+                } else {
+                    0
+                };
+                if hover_view == view_index
+                    && modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::CTRL))
+                {
+                    let (line, col) = view.get_line_col(
+                        buffer,
+                        &self.visible_documents_layouts[view_index].layout,
+                        mouse_position,
+                        font_size,
+                    );
+                    link = buffer
+                        .word_at(line, col)
+                        .map(|(start, length)| (line, start, length));
+                }
+            }
+
+            if view.definition_link != link {
+                view.definition_link = link;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // The view and the document whose hover popup is under the mouse
+    fn hover_at(&self, mouse_position: LogicalPosition<f64>) -> Option<(usize, usize)> {
+        (0..2).find_map(|view_index| {
+            let i = *self.visible_documents[view_index].last()?;
+            self.open_documents[i].views[view_index]
+                .mouse_in_hover(mouse_position)
+                .then_some((view_index, i))
+        })
+    }
+
     pub fn has_moved_cell(
         &mut self,
         cached_mouse_position: LogicalPosition<f64>,
@@ -777,13 +2128,17 @@ impl Editor {
         let active_document_layout = &self.visible_documents_layouts[self.active_view];
         let font_size = self.renderer.get_font_size();
         if let Some(i) = self.visible_documents[self.active_view].last() {
-            let (line, col) = self.open_documents[*i].view.get_line_col(
+            let document = &self.open_documents[*i];
+            let view = &document.views[self.active_view];
+            let (line, col) = view.get_line_col(
+                &document.buffer,
                 &active_document_layout.layout,
                 mouse_position,
                 font_size,
             );
             return (line, col)
-                != self.open_documents[*i].view.get_line_col(
+                != view.get_line_col(
+                    &document.buffer,
                     &active_document_layout.layout,
                     cached_mouse_position,
                     font_size,
@@ -799,12 +2154,185 @@ impl Editor {
         key_code: VirtualKeyCode,
         modifiers: Option<ModifiersState>,
     ) -> bool {
+        if self.pending_quit.is_some() {
+            return true;
+        }
         let window_size = (
             window.inner_size().width as f64 / window.scale_factor(),
             window.inner_size().height as f64 / window.scale_factor(),
         );
 
+        // Escape asks again the next time the workspace is opened
+        if self.trust_prompt.is_some() {
+            if key_code == VirtualKeyCode::Escape {
+                self.trust_prompt = None;
+            }
+            return true;
+        }
+
+        if let Some(passphrase_prompt) = &mut self.passphrase_prompt {
+            match key_code {
+                VirtualKeyCode::Back => {
+                    passphrase_prompt.passphrase.pop();
+                }
+                VirtualKeyCode::Return => self.submit_passphrase(window),
+                VirtualKeyCode::Escape => self.cancel_passphrase_prompt(window),
+                _ => (),
+            }
+            return true;
+        }
+
+        if let Some(context_menu) = &mut self.context_menu {
+            match key_code {
+                VirtualKeyCode::J | VirtualKeyCode::Down => {
+                    context_menu.selection_index = min(
+                        context_menu.selection_index + 1,
+                        CONTEXT_MENU_ITEMS.len() - 1,
+                    );
+                }
+                VirtualKeyCode::K | VirtualKeyCode::Up => {
+                    context_menu.selection_index = context_menu.selection_index.saturating_sub(1);
+                }
+                VirtualKeyCode::Return => {
+                    let item = CONTEXT_MENU_ITEMS[context_menu.selection_index];
+                    self.run_context_menu_item(item);
+                }
+                VirtualKeyCode::Escape => self.context_menu = None,
+                _ => (),
+            }
+            return true;
+        }
+
+        if let Some(theme_switcher) = &mut self.theme_switcher {
+            let num_themes = theme_switcher.themes.len();
+            match key_code {
+                VirtualKeyCode::J | VirtualKeyCode::Down | VirtualKeyCode::Tab => {
+                    theme_switcher.selection_index =
+                        (theme_switcher.selection_index + 1) % num_themes;
+                    let theme_index = theme_switcher.themes[theme_switcher.selection_index];
+                    self.preview_theme(theme_index);
+                }
+                VirtualKeyCode::K | VirtualKeyCode::Up => {
+                    theme_switcher.selection_index =
+                        (theme_switcher.selection_index + num_themes - 1) % num_themes;
+                    let theme_index = theme_switcher.themes[theme_switcher.selection_index];
+                    self.preview_theme(theme_index);
+                }
+                VirtualKeyCode::Return => {
+                    let theme_index = theme_switcher.themes[theme_switcher.selection_index];
+                    self.theme_switcher = None;
+                    self.recent_themes.retain(|&i| i != theme_index);
+                    self.recent_themes.insert(0, theme_index);
+                    log::notify(format!("Switched to {}", THEME_NAMES[theme_index]));
+                }
+                VirtualKeyCode::Escape => {
+                    let original_theme = theme_switcher.original_theme;
+                    self.theme_switcher = None;
+                    self.preview_theme(original_theme);
+                }
+                _ => (),
+            }
+            return true;
+        }
+
+        if let Some(messages_panel) = &mut self.messages_panel {
+            match key_code {
+                VirtualKeyCode::J | VirtualKeyCode::Down => {
+                    let num_messages = log::messages(messages_panel.min_level).len();
+                    messages_panel.line_offset = min(
+                        messages_panel.line_offset + 1,
+                        num_messages.saturating_sub(1),
+                    );
+                }
+                VirtualKeyCode::K | VirtualKeyCode::Up => {
+                    messages_panel.line_offset = messages_panel.line_offset.saturating_sub(1);
+                }
+                VirtualKeyCode::Escape | VirtualKeyCode::Q => self.messages_panel = None,
+                _ => (),
+            }
+            return true;
+        }
+
+        if self.registers_panel.is_some() {
+            if matches!(key_code, VirtualKeyCode::Escape | VirtualKeyCode::Q) {
+                self.registers_panel = None;
+            }
+            return true;
+        }
+
+        if let Some(symbol_panel) = &mut self.symbol_panel {
+            match symbol_panel.picker.handle_key(key_code, modifiers) {
+                PickerEvent::QueryChanged => symbol_panel.search(),
+                PickerEvent::Open(in_split) => self.open_symbol(in_split, window),
+                PickerEvent::Close => self.symbol_panel = None,
+                PickerEvent::None => (),
+            }
+            return true;
+        }
+
+        if let Some(search_panel) = &mut self.search_panel {
+            match search_panel.picker.handle_key(key_code, modifiers) {
+                PickerEvent::QueryChanged => search_panel.search(),
+                PickerEvent::Open(in_split) => self.open_search_result(in_split, window),
+                PickerEvent::Close => self.search_panel = None,
+                PickerEvent::None => (),
+            }
+            return true;
+        }
+
+        if let Some(history_panel) = &mut self.history_panel {
+            match key_code {
+                VirtualKeyCode::J | VirtualKeyCode::Down => history_panel.select_next(),
+                VirtualKeyCode::K | VirtualKeyCode::Up => history_panel.select_previous(),
+                VirtualKeyCode::Return => self.restore_version(),
+                VirtualKeyCode::Escape | VirtualKeyCode::Q => self.history_panel = None,
+                _ => (),
+            }
+            return true;
+        }
+
+        if self.window_command_pending {
+            if !matches!(
+                key_code,
+                VirtualKeyCode::LControl
+                    | VirtualKeyCode::RControl
+                    | VirtualKeyCode::LShift
+                    | VirtualKeyCode::RShift
+                    | VirtualKeyCode::LAlt
+                    | VirtualKeyCode::RAlt
+            ) {
+                self.window_command_pending = false;
+                self.skip_char = !modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL))
+                    && window_command_key_types_char(key_code);
+                self.run_window_command(key_code, window);
+            }
+            return true;
+        }
+
+        // In insert mode Ctrl + W deletes the word before the cursor instead
+        if key_code == VirtualKeyCode::W
+            && modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL))
+            && self.file_finder.is_none()
+            && !self.visible_documents[self.active_view]
+                .last()
+                .is_some_and(|i| {
+                    self.open_documents[*i].views[self.active_view].mode == BufferMode::Insert
+                })
+        {
+            self.window_command_pending = true;
+            return true;
+        }
+
         match key_code {
+            // Ctrl + T alone toggles the split view
+            VirtualKeyCode::T
+                if modifiers.is_some_and(|m| {
+                    m.contains(ModifiersState::CTRL) && m.contains(ModifiersState::SHIFT)
+                }) =>
+            {
+                return self
+                    .run_delayed_command(EditorCommand::SearchSymbols(String::default()), window);
+            }
             VirtualKeyCode::T if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
                 self.split_view = !self.split_view;
                 if !self.split_view {
@@ -812,13 +2340,20 @@ impl Editor {
                 }
                 return true;
             }
+            VirtualKeyCode::S
+                if modifiers.is_some_and(|m| {
+                    m.contains(ModifiersState::CTRL) && m.contains(ModifiersState::SHIFT)
+                }) =>
+            {
+                self.save_all();
+                return true;
+            }
             VirtualKeyCode::C if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
-                self.renderer.cycle_theme();
-
-                for document in &mut self.open_documents {
-                    document.buffer.syntect_reload(&self.renderer.theme);
-                }
-
+                self.theme_switcher = Some(ThemeSwitcher {
+                    themes: self.recent_themes.clone(),
+                    selection_index: 0,
+                    original_theme: self.renderer.theme_index(),
+                });
                 return true;
             }
             VirtualKeyCode::O if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
@@ -835,9 +2370,50 @@ impl Editor {
             }
             VirtualKeyCode::P
                 if self.workspace.is_some()
-                    && modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) =>
+                    && modifiers.is_some_and(|m| {
+                        m.contains(ModifiersState::CTRL) && m.contains(ModifiersState::SHIFT)
+                    }) =>
+            {
+                if let (Some(workspace), Some(workspace_index)) =
+                    (&self.workspace, &mut self.workspace_index)
+                {
+                    workspace_index.refresh_if_stale(workspace);
+                    if workspace_index.is_ready() {
+                        self.symbol_panel =
+                            Some(SymbolPanel::from_index(workspace_index.symbols(), ""));
+                    } else {
+                        log::notify("The workspace is still being indexed".to_string());
+                    }
+                }
+                return true;
+            }
+            VirtualKeyCode::F
+                if self.workspace.is_some()
+                    && modifiers.is_some_and(|m| {
+                        m.contains(ModifiersState::CTRL) && m.contains(ModifiersState::SHIFT)
+                    }) =>
+            {
+                return self.run_delayed_command(
+                    EditorCommand::SearchWorkspace(String::default()),
+                    window,
+                );
+            }
+            // In insert mode Ctrl + P completes the previous word instead
+            VirtualKeyCode::P
+                if self.workspace.is_some()
+                    && modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL))
+                    && !self.visible_documents[self.active_view]
+                        .last()
+                        .is_some_and(|i| {
+                            self.open_documents[*i].views[self.active_view].mode
+                                == BufferMode::Insert
+                        }) =>
             {
-                self.file_finder = Some(FileFinder::new(self.workspace.as_ref().unwrap()));
+                let workspace = self.workspace.as_ref().unwrap();
+                if let Some(workspace_index) = &mut self.workspace_index {
+                    workspace_index.refresh_if_stale(workspace);
+                }
+                self.file_finder = Some(FileFinder::new(workspace, self.workspace_index.as_ref()));
                 return true;
             }
             VirtualKeyCode::J if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
@@ -862,17 +2438,10 @@ impl Editor {
                     };
 
                     if let Some(i) = self.visible_documents[hover_view].last() {
-                        if let Some(hover_message) = &mut self.open_documents[*i].view.hover_message
-                        {
-                            let popup_max_height =
-                                self.visible_documents_layouts[hover_view].layout.num_rows / 2;
-                            hover_message.line_offset = min(
-                                hover_message.line_offset + SCROLL_LINES_PER_ROLL as usize,
-                                hover_message.num_lines.saturating_sub(
-                                    popup_max_height.saturating_sub(SCROLL_LINES_PER_ROLL as usize),
-                                ),
-                            );
-                        }
+                        self.open_documents[*i].views[hover_view].scroll_hover_message(
+                            SCROLL_LINES_PER_ROLL,
+                            self.visible_documents_layouts[hover_view].layout.num_rows / 2,
+                        );
                     }
                 }
             }
@@ -891,18 +2460,17 @@ impl Editor {
                     };
 
                     if let Some(i) = self.visible_documents[hover_view].last() {
-                        if let Some(hover_message) = &mut self.open_documents[*i].view.hover_message
-                        {
-                            hover_message.line_offset = hover_message
-                                .line_offset
-                                .saturating_sub(SCROLL_LINES_PER_ROLL as usize);
-                        }
+                        self.open_documents[*i].views[hover_view].scroll_hover_message(
+                            -SCROLL_LINES_PER_ROLL,
+                            self.visible_documents_layouts[hover_view].layout.num_rows / 2,
+                        );
                     }
                 }
             }
             VirtualKeyCode::Back if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) => {
                 if let Some(file_finder) = &mut self.file_finder {
                     file_finder.search_string.clear();
+                    file_finder.filter_files();
                     file_finder.selection_index = 0;
                     file_finder.selection_view_offset = 0;
                     return true;
@@ -919,12 +2487,19 @@ impl Editor {
             }
             VirtualKeyCode::Return => {
                 if let Some(file_finder) = &mut self.file_finder {
-                    if let Some(path) = file_finder.files[file_finder.selection_index]
-                        .path
-                        .clone()
-                        .to_str()
-                    {
-                        self.open_file(path, window);
+                    let file = file_finder.files.get(file_finder.selection_index).cloned();
+                    if let Some(file) = file {
+                        if let Some(path) = file.path.to_str() {
+                            if modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL)) {
+                                self.focus_other_view();
+                            }
+                            match file.position {
+                                Some((line, col)) => self.open_file_at(path, line, col, window),
+                                None => {
+                                    self.open_file(path, window);
+                                }
+                            }
+                        }
                     }
 
                     self.file_finder = None;
@@ -945,21 +2520,19 @@ impl Editor {
         let font_size = self.renderer.get_font_size();
         let mut delayed_command = None;
         if let Some(i) = self.visible_documents[self.active_view].last() {
-            let document = &mut self.open_documents[*i];
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            let mode = view.mode;
 
-            if let Some(editor_command) = document.buffer.handle_key(
-                key_code,
-                modifiers,
-                &document.view,
-                &active_document_layout.layout,
-            ) {
+            if let Some(editor_command) =
+                buffer.handle_key(key_code, modifiers, view, &active_document_layout.layout)
+            {
                 match editor_command {
-                    EditorCommand::CenterView => document
-                        .view
-                        .center(&document.buffer, &active_document_layout.layout),
-                    EditorCommand::CenterIfNotVisible => document
-                        .view
-                        .center_if_not_visible(&document.buffer, &active_document_layout.layout),
+                    EditorCommand::CenterView => {
+                        view.center(buffer, &active_document_layout.layout)
+                    }
+                    EditorCommand::RevealJump => {
+                        view.reveal_jump(buffer, &active_document_layout.layout)
+                    }
                     EditorCommand::ToggleSplitView => {
                         self.split_view = !self.split_view;
                         if !self.split_view {
@@ -980,20 +2553,61 @@ impl Editor {
                     }
                     x => delayed_command = Some(x),
                 }
-                document
-                    .view
-                    .adjust(&document.buffer, &active_document_layout.layout)
             }
+            if mode != BufferMode::Insert && view.mode == BufferMode::Insert {
+                buffer.signature_help_in_call(view);
+            }
+            view.adjust(buffer, &active_document_layout.layout)
         }
 
         if let Some(command) = delayed_command {
-            return self.run_editor_quit_command(command);
+            return self.run_delayed_command(command, window);
         }
 
         true
     }
 
     pub fn handle_char(&mut self, window: &Window, c: char) -> bool {
+        if std::mem::take(&mut self.skip_char) || self.pending_quit.is_some() {
+            return true;
+        }
+        if self.trust_prompt.is_some() {
+            match c {
+                'y' | 'Y' => self.decide_workspace_trust(true),
+                'n' | 'N' => self.decide_workspace_trust(false),
+                _ => (),
+            }
+            return true;
+        }
+        if let Some(passphrase_prompt) = &mut self.passphrase_prompt {
+            if !c.is_control() {
+                passphrase_prompt.passphrase.push(c);
+            }
+            return true;
+        }
+        if self.messages_panel.is_some()
+            || self.registers_panel.is_some()
+            || self.history_panel.is_some()
+            || self.context_menu.is_some()
+            || self.theme_switcher.is_some()
+        {
+            return true;
+        }
+
+        if let Some(search_panel) = &mut self.search_panel {
+            if let PickerEvent::QueryChanged = search_panel.picker.handle_char(c) {
+                search_panel.search();
+            }
+            return true;
+        }
+
+        if let Some(symbol_panel) = &mut self.symbol_panel {
+            if let PickerEvent::QueryChanged = symbol_panel.picker.handle_char(c) {
+                symbol_panel.search();
+            }
+            return true;
+        }
+
         if let Some(file_finder) = &mut self.file_finder {
             if c as u8 >= 0x20 && c as u8 <= 0x7E {
                 file_finder.search_string.push(c);
@@ -1008,16 +2622,17 @@ impl Editor {
         let font_size = self.renderer.get_font_size();
         let mut delayed_command = None;
         if let Some(i) = self.visible_documents[self.active_view].last() {
-            let document = &mut self.open_documents[*i];
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            let mode = view.mode;
 
-            if let Some(editor_command) = document.buffer.handle_char(c) {
+            if let Some(editor_command) = buffer.handle_char(view, c) {
                 match editor_command {
-                    EditorCommand::CenterView => document
-                        .view
-                        .center(&document.buffer, &active_document_layout.layout),
-                    EditorCommand::CenterIfNotVisible => document
-                        .view
-                        .center_if_not_visible(&document.buffer, &active_document_layout.layout),
+                    EditorCommand::CenterView => {
+                        view.center(buffer, &active_document_layout.layout)
+                    }
+                    EditorCommand::RevealJump => {
+                        view.reveal_jump(buffer, &active_document_layout.layout)
+                    }
                     EditorCommand::ToggleSplitView => {
                         self.split_view = !self.split_view;
                         if !self.split_view {
@@ -1039,108 +2654,539 @@ impl Editor {
                     x => delayed_command = Some(x),
                 }
             }
-            document
-                .view
-                .adjust(&document.buffer, &active_document_layout.layout)
+            if mode != BufferMode::Insert && view.mode == BufferMode::Insert {
+                buffer.signature_help_in_call(view);
+            }
+            view.adjust(buffer, &active_document_layout.layout)
         }
 
         if let Some(command) = delayed_command {
-            return self.run_editor_quit_command(command);
+            return self.run_delayed_command(command, window);
         }
 
         true
     }
 
-    fn run_editor_quit_command(&mut self, quit_command: EditorCommand) -> bool {
-        match quit_command {
-            EditorCommand::Quit => {
-                let ready_to_quit = self.visible_documents[self.active_view]
+    fn run_delayed_command(&mut self, command: EditorCommand, window: &Window) -> bool {
+        match command {
+            EditorCommand::GotoFile(path, location) => {
+                self.goto_file(&path, location, false, window);
+                true
+            }
+            EditorCommand::RenameFile(path) => {
+                self.rename_file(&path);
+                true
+            }
+            EditorCommand::DeleteFile => {
+                self.delete_file();
+                true
+            }
+            EditorCommand::CopyPath(format) => {
+                self.copy_path(format);
+                true
+            }
+            EditorCommand::TrustWorkspace => {
+                self.prompt_workspace_trust(true);
+                true
+            }
+            EditorCommand::RevealFile => {
+                if let Some(i) = self.visible_documents[self.active_view].last() {
+                    let path = &self.open_documents[*i].buffer.path;
+                    if !platform_resources::reveal_file(path) {
+                        log::error(format!("Failed to reveal {}", path));
+                    }
+                }
+                true
+            }
+            EditorCommand::RestoreFile => {
+                self.restore_file(window);
+                true
+            }
+            EditorCommand::HexView => {
+                self.open_hex_view(window);
+                true
+            }
+            EditorCommand::SearchSymbols(query) => {
+                let server = self.visible_documents[self.active_view]
                     .last()
-                    .is_some_and(|i| self.open_documents[*i].buffer.ready_to_quit());
-
-                if ready_to_quit {
-                    let active_document_index =
-                        *self.visible_documents[self.active_view].last().unwrap();
-                    self.open_documents.remove(active_document_index);
-
-                    if self.open_documents.is_empty() {
-                        self.visible_documents[0].clear();
-                        self.visible_documents[1].clear();
-                    } else {
-                        self.visible_documents[self.active_view].pop();
-                        let documents = self.visible_documents.split_array_mut::<1>();
-                        for index in documents.0[0].iter_mut().chain(documents.1[0].iter_mut()) {
-                            if *index >= active_document_index {
-                                *index = min(
-                                    index.saturating_sub(1),
-                                    self.open_documents.len().saturating_sub(1),
-                                );
-                            }
-                        }
+                    .and_then(|i| self.open_documents[*i].buffer.language_server.clone());
+                match server {
+                    Some(server) => {
+                        self.symbol_panel = Some(SymbolPanel::from_server(server, &query))
                     }
+                    None => log::notify("Workspace symbols need a language server".to_string()),
                 }
                 true
             }
-            EditorCommand::QuitNoCheck => {
-                let active_document_index =
-                    *self.visible_documents[self.active_view].last().unwrap();
-                self.open_documents.remove(active_document_index);
-
-                if self.open_documents.is_empty() {
-                    self.visible_documents[0].clear();
-                    self.visible_documents[1].clear();
-                } else {
-                    self.visible_documents[self.active_view].pop();
-                    let documents = self.visible_documents.split_array_mut::<1>();
-                    for index in documents.0[0].iter_mut().chain(documents.1[0].iter_mut()) {
-                        if *index >= active_document_index {
-                            *index = min(
-                                index.saturating_sub(1),
-                                self.open_documents.len().saturating_sub(1),
-                            );
-                        }
+            EditorCommand::SearchWorkspace(query) => {
+                match &self.workspace {
+                    Some(workspace) => {
+                        self.search_panel = Some(SearchPanel::new(workspace, &query));
                     }
+                    None => log::notify("Open a workspace to search it".to_string()),
                 }
                 true
             }
-            EditorCommand::QuitAll => {
-                let ready_to_quit = self.ready_to_quit();
-                self.open_documents.clear();
+            EditorCommand::VerticalSplit(Some(path)) => {
+                self.goto_file(&path, None, true, window);
+                true
+            }
+            // Without a path the other view shows the current document
+            EditorCommand::VerticalSplit(None) => {
+                if let Some(i) = self.visible_documents[self.active_view].last() {
+                    let path = self.open_documents[*i].buffer.path.clone();
+                    self.focus_other_view();
+                    self.open_file(&path, window);
+                }
+                true
+            }
+            EditorCommand::SplitNextJump => {
+                self.split_next_jump = true;
+                true
+            }
+            EditorCommand::Hover => {
+                self.hover_at_cursor(true);
+                true
+            }
+            // Diagnostics under the hover are shown before the hover information
+            EditorCommand::ShowDiagnostic => {
+                self.hover_at_cursor(false);
+                true
+            }
+            EditorCommand::CompleteWord(forward) => {
+                if let Some(&i) = self.visible_documents[self.active_view].last() {
+                    let (buffer, view) = self.open_documents[i].view(self.active_view);
+                    buffer.complete_word(view, self.workspace_index.as_ref(), forward);
+                    view.adjust(
+                        buffer,
+                        &self.visible_documents_layouts[self.active_view].layout,
+                    );
+                }
+                true
+            }
+            EditorCommand::SetHoverDelay(milliseconds) => {
+                self.hover_delay = Duration::from_millis(milliseconds);
+                true
+            }
+            EditorCommand::OpenUrl(url) => {
+                if platform_resources::open_url(&url) {
+                    log::info(format!("Opened {}", url));
+                } else {
+                    log::error(format!("Failed to open {}", url));
+                }
+                true
+            }
+            EditorCommand::SetFiletype(filetype) => {
+                self.set_active_filetype(&filetype);
+                true
+            }
+            EditorCommand::SaveAll => {
+                self.save_all();
+                true
+            }
+            EditorCommand::SaveAndQuit => {
+                if let Some(i) = self.visible_documents[self.active_view].last() {
+                    self.open_documents[*i].buffer.save_with_hooks(true);
+                }
+                self.pending_quit = Some(PendingQuit {
+                    all: false,
+                    started: Instant::now(),
+                });
+                self.update_pending_quit()
+            }
+            EditorCommand::SaveAllAndQuit => {
+                self.save_all();
+                self.pending_quit = Some(PendingQuit {
+                    all: true,
+                    started: Instant::now(),
+                });
+                self.update_pending_quit()
+            }
+            EditorCommand::ShowMessages(min_level) => {
+                let num_messages = log::messages(min_level).len();
+                self.messages_panel = Some(MessagesPanel {
+                    min_level,
+                    line_offset: num_messages.saturating_sub(MAX_SHOWN_MESSAGES),
+                });
+                true
+            }
+            EditorCommand::SetColorBlind(enabled) => {
+                self.renderer.set_color_blind(enabled);
+                for document in &mut self.open_documents {
+                    document.buffer.syntect_reload(&self.renderer.theme);
+                    self.renderer
+                        .update_diagnostic_decorations(&mut document.buffer);
+                }
+                true
+            }
+            EditorCommand::SetInlineDiagnostics(enabled) => {
+                self.renderer.set_inline_diagnostics(enabled);
+                for document in &mut self.open_documents {
+                    self.renderer
+                        .update_diagnostic_decorations(&mut document.buffer);
+                }
+                true
+            }
+            EditorCommand::SetNumber(enabled) => {
+                let (_, relative_number) = self.renderer.line_numbers();
+                self.renderer.set_line_numbers(enabled, relative_number);
+                self.update_layouts(window);
+                true
+            }
+            EditorCommand::SetRelativeNumber(enabled) => {
+                let (number, _) = self.renderer.line_numbers();
+                self.renderer.set_line_numbers(number, enabled);
+                self.update_layouts(window);
+                true
+            }
+            EditorCommand::SetPrimarySelection(enabled) => {
+                self.primary_selection_enabled = enabled;
+                true
+            }
+            EditorCommand::SetAnnounceMode(enabled) => {
+                self.announce_mode_changes = enabled;
+                true
+            }
+            EditorCommand::ShowRegisters => {
+                if let Some(i) = self.visible_documents[self.active_view].last() {
+                    let document = &self.open_documents[*i];
+                    self.registers_panel = Some(RegistersPanel {
+                        registers: document.buffer.registers(&document.views[self.active_view]),
+                    });
+                }
+                true
+            }
+            EditorCommand::ShowHistory => {
+                if let Some(i) = self.visible_documents[self.active_view].last() {
+                    let buffer = &self.open_documents[*i].buffer;
+                    self.history_panel =
+                        HistoryPanel::new(&buffer.path, buffer.piece_table.iter_chars().collect());
+                    if self.history_panel.is_none() {
+                        log::notify(format!("No saved versions of {}", buffer.path));
+                    }
+                }
+                true
+            }
+            command => self.run_editor_quit_command(command),
+        }
+    }
+
+    fn run_editor_quit_command(&mut self, quit_command: EditorCommand) -> bool {
+        match quit_command {
+            EditorCommand::Quit => {
+                let ready_to_quit = self.visible_documents[self.active_view]
+                    .last()
+                    .is_some_and(|i| self.open_documents[*i].buffer.ready_to_quit());
+
+                if ready_to_quit {
+                    let active_document_index =
+                        *self.visible_documents[self.active_view].last().unwrap();
+                    remember_position(
+                        &self.open_documents[active_document_index],
+                        self.active_view,
+                    );
+                    self.open_documents.remove(active_document_index);
+
+                    if self.open_documents.is_empty() {
+                        self.visible_documents[0].clear();
+                        self.visible_documents[1].clear();
+                    } else {
+                        self.visible_documents[self.active_view].pop();
+                        let documents = self.visible_documents.split_array_mut::<1>();
+                        for index in documents.0[0].iter_mut().chain(documents.1[0].iter_mut()) {
+                            if *index >= active_document_index {
+                                *index = min(
+                                    index.saturating_sub(1),
+                                    self.open_documents.len().saturating_sub(1),
+                                );
+                            }
+                        }
+                    }
+                }
+                true
+            }
+            EditorCommand::QuitNoCheck => {
+                let active_document_index =
+                    *self.visible_documents[self.active_view].last().unwrap();
+                remember_position(
+                    &self.open_documents[active_document_index],
+                    self.active_view,
+                );
+                self.open_documents.remove(active_document_index);
+
+                if self.open_documents.is_empty() {
+                    self.visible_documents[0].clear();
+                    self.visible_documents[1].clear();
+                } else {
+                    self.visible_documents[self.active_view].pop();
+                    let documents = self.visible_documents.split_array_mut::<1>();
+                    for index in documents.0[0].iter_mut().chain(documents.1[0].iter_mut()) {
+                        if *index >= active_document_index {
+                            *index = min(
+                                index.saturating_sub(1),
+                                self.open_documents.len().saturating_sub(1),
+                            );
+                        }
+                    }
+                }
+                true
+            }
+            EditorCommand::QuitAll => {
+                let ready_to_quit = self.ready_to_quit();
+                self.remember_positions();
+                self.open_documents.clear();
                 self.active_view = 0;
                 self.visible_documents[0].clear();
                 self.visible_documents[1].clear();
                 false
             }
             EditorCommand::QuitAllNoCheck => {
+                self.remember_positions();
                 self.open_documents.clear();
                 self.active_view = 0;
                 self.visible_documents[0].clear();
                 self.visible_documents[1].clear();
                 false
             }
+            EditorCommand::QuitOthers | EditorCommand::QuitOthersNoCheck => {
+                if let Some(&active_document_index) =
+                    self.visible_documents[self.active_view].last()
+                {
+                    let others = (0..self.open_documents.len())
+                        .filter(|i| *i != active_document_index)
+                        .collect();
+                    self.close_documents(others, matches!(quit_command, EditorCommand::QuitOthers));
+                }
+                true
+            }
+            EditorCommand::QuitSaved => {
+                let saved = (0..self.open_documents.len())
+                    .filter(|i| !self.open_documents[*i].buffer.piece_table.dirty)
+                    .collect();
+                self.close_documents(saved, true);
+                true
+            }
+            // Documents at the top of a view are visible, the second view only counts when split
+            EditorCommand::QuitHidden | EditorCommand::QuitHiddenNoCheck => {
+                let visible: Vec<usize> = [0, 1]
+                    .into_iter()
+                    .filter(|view| self.split_view || *view == self.active_view)
+                    .filter_map(|view| self.visible_documents[view].last().copied())
+                    .collect();
+                let hidden = (0..self.open_documents.len())
+                    .filter(|i| !visible.contains(i))
+                    .collect();
+                self.close_documents(hidden, matches!(quit_command, EditorCommand::QuitHidden));
+                true
+            }
             _ => panic!(),
         }
     }
 
+    // Closing stops at the first document the user doesn't want to close after being asked to
+    // save it, the documents before it are still closed
+    fn close_documents(&mut self, indices: Vec<usize>, check: bool) {
+        let mut closed = vec![];
+        for i in indices {
+            if check && !self.open_documents[i].buffer.ready_to_quit() {
+                break;
+            }
+            remember_position(&self.open_documents[i], self.view_index_of(i));
+            closed.push(i);
+        }
+
+        let mut i = 0;
+        self.open_documents.retain(|_| {
+            i += 1;
+            !closed.contains(&(i - 1))
+        });
+        for documents in &mut self.visible_documents {
+            documents.retain(|i| !closed.contains(i));
+            for index in documents.iter_mut() {
+                *index -= closed.iter().filter(|i| **i < *index).count();
+            }
+        }
+
+        if closed.is_empty() {
+            log::notify("No files to close");
+        } else {
+            log::notify(format!("Closed {} files", closed.len()));
+        }
+    }
+
+    // Saves every modified document through its save hooks, failures are logged per file by the
+    // piece table
+    fn save_all(&mut self) {
+        let mut num_saved = 0;
+        let mut num_failed = 0;
+        let mut num_pending = 0;
+        for document in &mut self.open_documents {
+            if !document.buffer.piece_table.dirty {
+                continue;
+            }
+            match document.buffer.save_with_hooks(true) {
+                Some(true) => num_saved += 1,
+                Some(false) => num_failed += 1,
+                None => num_pending += 1,
+            }
+        }
+
+        if num_failed > 0 {
+            log::error(format!(
+                "Failed to save {} of {} modified files",
+                num_failed,
+                num_saved + num_failed + num_pending
+            ));
+        } else if num_saved + num_pending == 0 {
+            log::notify("No modified files to save");
+        } else if num_pending > 0 {
+            log::notify(format!(
+                "Saved {} files, {} more once the language server has answered",
+                num_saved, num_pending
+            ));
+        } else {
+            log::notify(format!("Saved {} files", num_saved));
+        }
+    }
+
+    // Returns false once the editor has quit. Saves the language server doesn't answer for in
+    // time are made without their hooks, and anything that failed to save stays open so that the
+    // error can be seen
+    pub fn update_pending_quit(&mut self) -> bool {
+        let Some(pending_quit) = &self.pending_quit else {
+            return true;
+        };
+        let waiting = self
+            .open_documents
+            .iter()
+            .any(|document| document.buffer.save_pending());
+        if waiting && pending_quit.started.elapsed() < SAVE_HOOK_TIMEOUT {
+            return true;
+        }
+        let all = pending_quit.all;
+        self.pending_quit = None;
+
+        if waiting {
+            log::warning("The language server didn't answer in time, saving without formatting");
+            for document in &mut self.open_documents {
+                if document.buffer.save_pending() {
+                    document.buffer.save_without_hooks();
+                }
+            }
+        }
+
+        if all {
+            if self
+                .open_documents
+                .iter()
+                .any(|document| document.buffer.piece_table.dirty)
+            {
+                return true;
+            }
+            return self.run_editor_quit_command(EditorCommand::QuitAllNoCheck);
+        }
+        match self.visible_documents[self.active_view].last() {
+            Some(i) if !self.open_documents[*i].buffer.piece_table.dirty => {
+                self.run_editor_quit_command(EditorCommand::QuitNoCheck)
+            }
+            _ => true,
+        }
+    }
+
+    pub fn remember_positions(&self) {
+        for (i, document) in self.open_documents.iter().enumerate() {
+            remember_position(document, self.view_index_of(i));
+        }
+    }
+
     pub fn ready_to_quit(&mut self) -> bool {
         self.open_documents
             .iter_mut()
             .all(|document| document.buffer.ready_to_quit())
     }
 
-    pub fn open_file(&mut self, path: &str, window: &Window) {
-        let language_server = language_from_path(path).map(|language| {
-            if !self.language_servers.contains_key(language.identifier) {
-                LanguageServer::new(language, self.workspace.as_ref().unwrap()).and_then(
-                    |server| {
-                        self.language_servers
-                            .insert(language.identifier, Rc::new(RefCell::new(server)))
-                    },
-                );
+    // rust-analyzer only discovers Cargo projects at its root, so Rust files get a server for
+    // their Cargo workspace and files outside of any Cargo project are opened as detached files
+    fn start_language_server(
+        &mut self,
+        language: &'static Language,
+        path: &str,
+    ) -> Option<Rc<RefCell<LanguageServer>>> {
+        let workspace_path = self
+            .workspace
+            .as_ref()
+            .map(|workspace| workspace.path.clone());
+        let (root, detached_file) = if language.identifier == RUST_IDENTIFIER {
+            match cargo_root(Path::new(path)) {
+                Some(root) => (root, None),
+                None => (Path::new(path).parent()?.to_path_buf(), Some(path)),
             }
-            Rc::clone(self.language_servers.get(language.identifier).unwrap())
-        });
+        } else {
+            (PathBuf::from(workspace_path.as_ref()?), None)
+        };
+        // Files outside of any Cargo project share a server rooted at the first of them, which is
+        // told about each of them through detachedFiles
+        let key = (
+            language.identifier,
+            match detached_file {
+                Some(_) => String::new(),
+                None => root.to_string_lossy().to_string(),
+            },
+        );
+        if let Some(detached_file) = detached_file {
+            log::info(format!(
+                "No Cargo.toml found for {}, opening it as a detached file",
+                detached_file
+            ));
+        }
+
+        if let Some(server) = self.language_servers.get(&key) {
+            if let Some(detached_file) = detached_file {
+                server.borrow_mut().add_detached_file(detached_file);
+            }
+        } else {
+            let language_settings = config::settings_for(
+                workspace_path.as_deref().or(root.to_str()),
+                Some(language.identifier),
+            );
+            let mut settings = language_settings.lsp;
+            if let Some(detached_file) = detached_file {
+                let settings = settings.get_or_insert_with(|| serde_json::json!({}));
+                if let Some(settings) = settings.as_object_mut() {
+                    settings.insert(
+                        String::from("detachedFiles"),
+                        serde_json::json!([detached_file]),
+                    );
+                }
+            }
+
+            let root_uri = Url::from_directory_path(&root).ok()?.to_string();
+            LanguageServer::new(language, root_uri, settings).and_then(|mut server| {
+                server.change_debounce =
+                    Duration::from_millis(language_settings.lsp_change_debounce.unwrap_or(0));
+                self.language_servers
+                    .insert(key.clone(), Rc::new(RefCell::new(server)))
+            });
+        }
+        self.language_servers.get(&key).map(Rc::clone)
+    }
+
+    fn set_active_filetype(&mut self, filetype: &str) {
+        let Some(i) = self.visible_documents[self.active_view].last().copied() else {
+            return;
+        };
+        let path = self.open_documents[i].buffer.path.clone();
+        let language_server = language_from_filetype(filetype)
+            .filter(|_| self.open_documents[i].buffer.passphrase.is_none())
+            .and_then(|language| self.start_language_server(language, &path));
+
+        self.open_documents[i]
+            .buffer
+            .set_filetype(filetype, &self.renderer.theme, language_server);
+    }
 
+    // Returns false if the file is waiting for a passphrase instead
+    pub fn open_file(&mut self, path: &str, window: &Window) -> bool {
         let uri = Url::from_file_path(path).unwrap();
 
         if let Some(i) = self
@@ -1148,30 +3194,678 @@ impl Editor {
             .iter()
             .position(|document| document.uri == uri)
         {
+            if !self.visible_documents[self.active_view].contains(&i) {
+                self.open_documents[i].split(self.active_view);
+            }
             self.visible_documents[self.active_view].retain(|&x| x != i);
             self.visible_documents[self.active_view].push(i);
+            return true;
+        }
+
+        let encrypted = encryption::is_encrypted(path);
+        let settings = config::settings_for(
+            self.workspace
+                .as_ref()
+                .map(|workspace| workspace.path.as_str()),
+            None,
+        );
+        if encrypted
+            || settings
+                .encrypted_files
+                .is_some_and(|patterns| encryption::should_encrypt(path, &patterns))
+        {
+            self.passphrase_prompt = Some(PassphrasePrompt {
+                path: path.to_string(),
+                passphrase: String::default(),
+                encrypted,
+                confirmation: None,
+                position: None,
+            });
+            return false;
+        }
+
+        // The language is only known once the buffer has had a chance to inspect the content
+        let buffer = Buffer::new(Some(window), path, &self.renderer.theme, None);
+        self.open_buffer(buffer, uri);
+        true
+    }
+
+    // A wrong passphrase or a repetition that doesn't match starts over
+    fn submit_passphrase(&mut self, window: &Window) {
+        let Some(passphrase_prompt) = &mut self.passphrase_prompt else {
+            return;
+        };
+        if passphrase_prompt.passphrase.is_empty() {
+            return;
+        }
+
+        let piece_table = if passphrase_prompt.encrypted {
+            let text = fs::read(&passphrase_prompt.path)
+                .ok()
+                .and_then(|data| encryption::decrypt(&data, &passphrase_prompt.passphrase));
+            let Some(text) = text else {
+                log::error(format!(
+                    "Failed to decrypt {}, the passphrase may be wrong",
+                    passphrase_prompt.path
+                ));
+                passphrase_prompt.passphrase.clear();
+                return;
+            };
+            PieceTable::from_bytes(&text)
         } else {
-            self.open_documents.push(Document {
-                uri,
-                buffer: Buffer::new(window, path, &self.renderer.theme, language_server),
-                view: View::new(),
+            match &passphrase_prompt.confirmation {
+                None => {
+                    passphrase_prompt.confirmation =
+                        Some(std::mem::take(&mut passphrase_prompt.passphrase));
+                    return;
+                }
+                Some(confirmation) if *confirmation != passphrase_prompt.passphrase => {
+                    log::error("The passphrases don't match".to_string());
+                    passphrase_prompt.confirmation = None;
+                    passphrase_prompt.passphrase.clear();
+                    return;
+                }
+                Some(_) => PieceTable::from_file(&passphrase_prompt.path),
+            }
+        };
+
+        let Some(passphrase_prompt) = self.passphrase_prompt.take() else {
+            return;
+        };
+        let mut buffer = Buffer::with_piece_table(
+            Some(window),
+            &passphrase_prompt.path,
+            &self.renderer.theme,
+            None,
+            piece_table,
+        );
+        buffer.passphrase = Some(passphrase_prompt.passphrase);
+        if !passphrase_prompt.encrypted {
+            log::notify(format!(
+                "{} will be encrypted when it is saved",
+                passphrase_prompt.path
+            ));
+        }
+        self.open_buffer(
+            buffer,
+            Url::from_file_path(&passphrase_prompt.path).unwrap(),
+        );
+        if let Some((line, col)) = passphrase_prompt.position {
+            self.open_file_at(&passphrase_prompt.path, line, col, window);
+        }
+    }
+
+    // Without ask_again only workspaces the user hasn't decided on yet are asked about
+    fn prompt_workspace_trust(&mut self, ask_again: bool) {
+        let Some(workspace) = &self.workspace else {
+            log::notify("No workspace open");
+            return;
+        };
+        if !workspace_trust::has_workspace_config(&workspace.path) {
+            if ask_again {
+                log::notify(format!("{} has no .nimble configuration", workspace.path));
+            }
+            return;
+        }
+        if ask_again || workspace_trust::is_trusted(&workspace.path).is_none() {
+            self.trust_prompt = Some(TrustPrompt {
+                path: workspace.path.clone(),
             });
-            self.visible_documents[self.active_view]
-                .push(self.open_documents.len().saturating_sub(1));
-
-            if let Some(language) = language_from_path(path) {
-                if let Some(server) = self.language_servers.get(language.identifier) {
-                    let mut server = server.borrow_mut();
-                    self.open_documents
-                        .last_mut()
-                        .unwrap()
-                        .buffer
-                        .send_did_open(&mut server);
+        }
+    }
+
+    // Language servers that are already running keep the settings they were started with
+    fn decide_workspace_trust(&mut self, trusted: bool) {
+        let Some(trust_prompt) = self.trust_prompt.take() else {
+            return;
+        };
+        workspace_trust::remember(&trust_prompt.path, trusted);
+        log::notify(match trusted {
+            true => format!("Using the configuration of {}", trust_prompt.path),
+            false => format!("Ignoring the configuration of {}", trust_prompt.path),
+        });
+
+        self.hover_delay = hover_delay(Some(&trust_prompt.path));
+        load_line_numbers(&mut self.renderer, Some(&trust_prompt.path));
+        load_jump_center(Some(&trust_prompt.path));
+        self.primary_selection_enabled = primary_selection(Some(&trust_prompt.path));
+        keymap::load(Some(&trust_prompt.path));
+        for document in &mut self.open_documents {
+            document.buffer.apply_settings(&config::settings_for(
+                Some(&trust_prompt.path),
+                document.buffer.language.map(|language| language.identifier),
+            ));
+        }
+    }
+
+    // The dump of the text is opened as a new document next to the file and only written if it is
+    // saved. An open dump is brought back as it is
+    fn open_hex_view(&mut self, window: &Window) {
+        let Some(i) = self.visible_documents[self.active_view].last() else {
+            return;
+        };
+        let buffer = &self.open_documents[*i].buffer;
+        if buffer.passphrase.is_some() {
+            log::warning(format!("{} is encrypted, it can't be dumped", buffer.path));
+            return;
+        }
+
+        let path = format!("{}.hex", buffer.path);
+        let uri = Url::from_file_path(&path).unwrap();
+        if self
+            .open_documents
+            .iter()
+            .any(|document| document.uri == uri)
+        {
+            self.open_file(&path, window);
+            return;
+        }
+
+        let text: Vec<u8> = buffer.piece_table.iter_chars().collect();
+        let hex_buffer = Buffer::with_piece_table(
+            Some(window),
+            &path,
+            &self.renderer.theme,
+            None,
+            PieceTable::from_bytes(&text_utils::hex_dump(&text)),
+        );
+        self.open_buffer(hex_buffer, uri);
+    }
+
+    // Files that aren't encrypted yet are still opened, they just stay plain text on disk
+    fn cancel_passphrase_prompt(&mut self, window: &Window) {
+        let Some(passphrase_prompt) = self.passphrase_prompt.take() else {
+            return;
+        };
+        if passphrase_prompt.encrypted {
+            return;
+        }
+
+        log::warning(format!(
+            "{} is not encrypted, it is saved as plain text",
+            passphrase_prompt.path
+        ));
+        let buffer = Buffer::new(
+            Some(window),
+            &passphrase_prompt.path,
+            &self.renderer.theme,
+            None,
+        );
+        self.open_buffer(
+            buffer,
+            Url::from_file_path(&passphrase_prompt.path).unwrap(),
+        );
+        if let Some((line, col)) = passphrase_prompt.position {
+            self.open_file_at(&passphrase_prompt.path, line, col, window);
+        }
+    }
+
+    fn open_buffer(&mut self, mut buffer: Buffer, uri: Url) {
+        let path = buffer.path.clone();
+        buffer.apply_settings(&config::settings_for(
+            self.workspace
+                .as_ref()
+                .map(|workspace| workspace.path.as_str()),
+            buffer.language.map(|language| language.identifier),
+        ));
+        log::info(format!(
+            "Opened {} ({})",
+            path,
+            buffer.filetype.as_deref().unwrap_or("plain text")
+        ));
+        // The plain text of encrypted files isn't handed to language servers
+        buffer.language_server = buffer
+            .language
+            .filter(|_| buffer.passphrase.is_none())
+            .and_then(|language| self.start_language_server(language, &path));
+        if let Some(server) = buffer.language_server.clone() {
+            buffer.send_did_open(&mut server.borrow_mut());
+        }
+
+        let mut view = View::new();
+        if let Some(position) = file_positions::load(&path) {
+            buffer.set_cursor(&mut view, position.line, position.col);
+            buffer.last_close_position = Some((position.line, position.col));
+            view.line_offset = min(
+                position.line_offset,
+                buffer.piece_table.num_lines().saturating_sub(1),
+            );
+            view.col_offset = position.col_offset;
+        }
+
+        self.open_documents.push(Document {
+            uri,
+            buffer,
+            views: [view, View::new()],
+            last_viewed: Instant::now(),
+        });
+        self.visible_documents[self.active_view].push(self.open_documents.len().saturating_sub(1));
+    }
+
+    // Files touched by the edit are opened so the changes can be reviewed before saving
+    fn apply_workspace_edit(&mut self, edit: WorkspaceEdit, window: &Window) -> bool {
+        let changes = match edit.into_changes() {
+            Ok(changes) => changes,
+            Err(kind) => {
+                log::error(format!(
+                    "Unable to apply a workspace edit that needs to {} a file",
+                    kind
+                ));
+                return false;
+            }
+        };
+
+        let active_path = self.visible_documents[self.active_view]
+            .last()
+            .map(|i| self.open_documents[*i].buffer.path.clone());
+
+        let mut applied = true;
+        for (uri, edits) in changes {
+            let path = Url::parse(&uri)
+                .ok()
+                .and_then(|url| url.to_file_path().ok());
+            match path.as_ref().and_then(|path| path.to_str()) {
+                Some(file_path) if self.open_file(file_path, window) => {
+                    if let Some(i) = self.visible_documents[self.active_view].last() {
+                        let (buffer, view) = self.open_documents[*i].view(self.active_view);
+                        buffer.apply_text_edits(view, &edits);
+                    }
+                }
+                _ => {
+                    log::error(format!("Unable to apply edits to {}", uri));
+                    applied = false;
+                }
+            }
+        }
+
+        if let Some(path) = active_path {
+            self.open_file(&path, window);
+        }
+        applied
+    }
+
+    // The list starts at the first reference after the cursor
+    fn references_finder(&self, mut locations: Vec<Location>, utf16_positions: bool) -> FileFinder {
+        locations.sort_by_key(|location| {
+            (
+                location.uri.to_lowercase(),
+                location.range.start.line,
+                location.range.start.character,
+            )
+        });
+        let cursor = self.visible_documents[self.active_view].last().map(|i| {
+            let buffer = &self.open_documents[*i].buffer;
+            let view = &self.open_documents[*i].views[self.active_view];
+            let position = view.cursors.last().unwrap().position;
+            let position = buffer.lsp_position(
+                buffer.piece_table.line_index(position),
+                buffer.piece_table.col_index(position),
+            );
+            (buffer.uri.to_lowercase(), position.line, position.character)
+        });
+
+        let mut files = vec![];
+        let mut selection_index = None;
+        let mut contents: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+        for location in &locations {
+            let Some(path) = Url::parse(&location.uri)
+                .ok()
+                .and_then(|uri| uri.to_file_path().ok())
+            else {
+                continue;
+            };
+
+            // Open files may have unsaved changes
+            let line = location.range.start.line as usize;
+            let text = match self.open_documents.iter().find(|document| {
+                document.uri.as_str().to_lowercase() == location.uri.to_lowercase()
+            }) {
+                Some(document) => {
+                    let piece_table = &document.buffer.piece_table;
+                    piece_table.line_at_index(line).map(|line| {
+                        piece_table
+                            .iter_chars_at(line.start)
+                            .take(line.length)
+                            .collect()
+                    })
                 }
+                None => contents
+                    .entry(path.clone())
+                    .or_insert_with(|| fs::read(&path).unwrap_or_default())
+                    .split(|c| *c == b'\n')
+                    .nth(line)
+                    .map(<[u8]>::to_vec),
+            }
+            .unwrap_or_default();
+            let text = String::from_utf8_lossy(&text);
+            let character = location.range.start.character as usize;
+            let col = if utf16_positions {
+                col_from_utf16(&text, character)
+            } else {
+                character
+            };
+
+            if selection_index.is_none()
+                && cursor.as_ref().is_some_and(|(uri, line, col)| {
+                    location.uri.to_lowercase() == *uri
+                        && (location.range.start.line, location.range.start.character)
+                            > (*line, *col)
+                })
+            {
+                selection_index = Some(files.len());
+            }
+
+            let name = self
+                .workspace
+                .as_ref()
+                .and_then(|workspace| path.strip_prefix(&workspace.path).ok())
+                .unwrap_or(&path);
+            files.push(FileIdentifier {
+                name: format!("{}:{}: {}", name.display(), line + 1, text.trim()).into(),
+                path: path.clone().into_os_string(),
+                position: Some((line, col)),
+            });
+        }
+
+        FileFinder::with_files(files, selection_index.unwrap_or(0))
+    }
+
+    fn open_symbol(&mut self, in_split: bool, window: &Window) {
+        let Some(symbol_panel) = self.symbol_panel.take() else {
+            return;
+        };
+        if let Some(symbol) = symbol_panel.picker.selected() {
+            if in_split {
+                self.focus_other_view();
+            }
+            self.goto_location(&symbol.location, window);
+        }
+    }
+
+    fn restore_version(&mut self) {
+        let Some(history_panel) = self.history_panel.take() else {
+            return;
+        };
+        let (Some(text), Some(snapshot)) = (
+            history_panel.selected_text(),
+            history_panel.snapshots.get(history_panel.selection_index),
+        ) else {
+            return;
+        };
+        if let Some(i) = self
+            .open_documents
+            .iter()
+            .position(|document| document.buffer.path == history_panel.path)
+        {
+            let view_index = self.view_index_of(i);
+            let (buffer, view) = self.open_documents[i].view(view_index);
+            buffer.restore_text(view, &text);
+            log::notify(format!(
+                "Restored the version of {} saved {}",
+                history_panel.path,
+                file_history::age(snapshot.time)
+            ));
+        }
+    }
+
+    fn open_search_result(&mut self, in_split: bool, window: &Window) {
+        let Some(search_panel) = self.search_panel.take() else {
+            return;
+        };
+        if let Some(result) = search_panel.picker.selected() {
+            if in_split {
+                self.focus_other_view();
             }
+            self.open_file_at(&result.path, result.line, result.col, window);
         }
     }
 
+    fn open_file_at(&mut self, path: &str, line: usize, col: usize, window: &Window) {
+        if !self.open_file(path, window) {
+            if let Some(passphrase_prompt) = &mut self.passphrase_prompt {
+                passphrase_prompt.position = Some((line, col));
+            }
+            return;
+        }
+        let active_document_layout = &self.visible_documents_layouts[self.active_view];
+        if let Some(i) = self.visible_documents[self.active_view].last() {
+            let (buffer, view) = self.open_documents[*i].view(self.active_view);
+            buffer.set_cursor(view, line, col);
+            view.reveal_jump(buffer, &active_document_layout.layout);
+            buffer.update_syntect(view, 0);
+        }
+    }
+
+    // Relative paths are looked up next to the current file, then in the workspace and its include paths
+    fn goto_file(
+        &mut self,
+        path: &str,
+        location: Option<(usize, usize)>,
+        in_split: bool,
+        window: &Window,
+    ) {
+        let Some(i) = self.visible_documents[self.active_view].last() else {
+            return;
+        };
+
+        let mut roots: Vec<PathBuf> = Path::new(&self.open_documents[*i].buffer.path)
+            .parent()
+            .map(Path::to_path_buf)
+            .into_iter()
+            .collect();
+        if let Some(workspace) = &self.workspace {
+            roots.push(PathBuf::from(&workspace.path));
+            roots.extend(workspace.include_paths.iter().map(PathBuf::from));
+        }
+
+        let file_path = if Path::new(path).is_absolute() {
+            Some(PathBuf::from(path)).filter(|file_path| file_path.is_file())
+        } else {
+            roots
+                .iter()
+                .map(|root| normalize_path(&root.join(path)))
+                .find(|file_path| file_path.is_file())
+        };
+
+        if file_path.is_some() && in_split {
+            self.focus_other_view();
+        }
+        match (
+            file_path.as_ref().and_then(|file_path| file_path.to_str()),
+            location,
+        ) {
+            (Some(file_path), Some((line, col))) => self.open_file_at(
+                file_path,
+                line.saturating_sub(1),
+                col.saturating_sub(1),
+                window,
+            ),
+            (Some(file_path), None) => {
+                self.open_file(file_path, window);
+            }
+            (None, _) => log::warning(format!("File not found: {}", path)),
+        }
+    }
+
+    // Relative paths are relative to the directory of the current file. Servers that support
+    // workspace/willRenameFiles get to update imports and module paths before the file is moved
+    fn rename_file(&mut self, path: &str) {
+        let Some(i) = self.visible_documents[self.active_view].last() else {
+            return;
+        };
+        let buffer = &self.open_documents[*i].buffer;
+
+        let old_path = buffer.path.clone();
+        let new_path = match Path::new(&old_path).parent() {
+            Some(parent) if !Path::new(path).is_absolute() => normalize_path(&parent.join(path)),
+            _ => PathBuf::from(path),
+        };
+        if new_path.exists() {
+            log::error(format!(
+                "Cannot rename {} to {}, the file already exists",
+                old_path,
+                new_path.display()
+            ));
+            return;
+        }
+        let Some(new_path) = new_path.to_str().map(|new_path| new_path.to_string()) else {
+            return;
+        };
+
+        let id = buffer.language_server.as_ref().and_then(|server| {
+            server.borrow_mut().send_request(
+                "workspace/willRenameFiles",
+                RenameFilesParams {
+                    files: vec![FileRename {
+                        old_uri: buffer.uri.clone(),
+                        new_uri: Url::from_file_path(&new_path).unwrap().to_string(),
+                    }],
+                },
+            )
+        });
+        match id {
+            Some(id) => {
+                self.pending_file_rename = Some(PendingFileRename {
+                    id,
+                    old_path,
+                    new_path,
+                })
+            }
+            None => self.finish_file_rename(&old_path, &new_path),
+        }
+    }
+
+    fn finish_file_rename(&mut self, old_path: &str, new_path: &str) {
+        if let Err(error) = fs::rename(old_path, new_path) {
+            log::error(format!(
+                "Failed to rename {} to {}: {}",
+                old_path, new_path, error
+            ));
+            return;
+        }
+
+        let (old_uri, new_uri) = (
+            Url::from_file_path(old_path).unwrap(),
+            Url::from_file_path(new_path).unwrap(),
+        );
+        if let Some(document) = self
+            .open_documents
+            .iter_mut()
+            .find(|document| document.uri == old_uri)
+        {
+            document.uri = new_uri.clone();
+            document.buffer.set_path(new_path);
+            if let Some(server) = &document.buffer.language_server {
+                server.borrow_mut().send_notification(
+                    "workspace/didRenameFiles",
+                    RenameFilesParams {
+                        files: vec![FileRename {
+                            old_uri: old_uri.to_string(),
+                            new_uri: new_uri.to_string(),
+                        }],
+                    },
+                );
+            }
+        }
+        log::notify(format!("Renamed {} to {}", old_path, new_path));
+    }
+
+    // Relative paths and references fall back to the absolute path outside of the workspace
+    fn copy_path(&mut self, format: PathFormat) {
+        let Some(i) = self.visible_documents[self.active_view].last() else {
+            return;
+        };
+        let (buffer, view) = self.open_documents[*i].view(self.active_view);
+        // Joining an absolute path replaces the current directory
+        let absolute_path = std::env::current_dir()
+            .ok()
+            .map(|directory| directory.join(&buffer.path))
+            .and_then(|path| path.to_str().map(str::to_string))
+            .unwrap_or_else(|| buffer.path.clone());
+        let relative_path = self
+            .workspace
+            .as_ref()
+            .and_then(|workspace| Path::new(&absolute_path).strip_prefix(&workspace.path).ok())
+            .and_then(Path::to_str)
+            .unwrap_or(&absolute_path);
+
+        let text = match format {
+            PathFormat::Absolute => absolute_path.clone(),
+            PathFormat::Relative => relative_path.to_string(),
+            PathFormat::Reference => {
+                let position = view.cursors.last().unwrap().position;
+                format!(
+                    "{}:{}:{}",
+                    relative_path,
+                    buffer.piece_table.line_index(position) + 1,
+                    buffer.piece_table.col_index(position) + 1
+                )
+            }
+        };
+        buffer.set_clipboard(text.as_bytes());
+        log::notify(format!("Copied {}", text));
+    }
+
+    fn delete_file(&mut self) {
+        let Some(i) = self.visible_documents[self.active_view].last() else {
+            return;
+        };
+        let path = self.open_documents[*i].buffer.path.clone();
+
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(error) => {
+                log::error(format!("Failed to delete {}: {}", path, error));
+                return;
+            }
+        };
+        let Ok(trashed) = platform_resources::move_to_trash(&path) else {
+            log::error(format!("Failed to move {} to the trash", path));
+            return;
+        };
+
+        self.run_editor_quit_command(EditorCommand::QuitNoCheck);
+        log::notify(format!(
+            "Moved {} to the trash, :undelete restores it",
+            path
+        ));
+        self.deleted_file = Some(DeletedFile {
+            path,
+            trashed,
+            contents,
+        });
+    }
+
+    // Unsaved changes are discarded, the restored file has the contents last saved to disk
+    fn restore_file(&mut self, window: &Window) {
+        // The deleted file is only let go of once it has been restored
+        let Some(deleted_file) = &self.deleted_file else {
+            log::warning("No deleted file to restore");
+            return;
+        };
+        let path = deleted_file.path.clone();
+        if Path::new(&path).exists() {
+            log::error(format!("Cannot restore {}, the file already exists", path));
+            return;
+        }
+
+        let restored = Path::new(&path)
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| match &deleted_file.trashed {
+                Some(trashed) => platform_resources::restore_from_trash(trashed, &path),
+                None => fs::write(&path, &deleted_file.contents),
+            });
+        if let Err(error) = restored {
+            log::error(format!("Failed to restore {}: {}", path, error));
+            return;
+        }
+
+        self.deleted_file = None;
+        self.open_file(&path, window);
+        log::notify(format!("Restored {}", path));
+    }
+
     fn active_document_layout(&self) -> &DocumentLayout {
         &self.visible_documents_layouts[self.active_view]
     }
@@ -1190,52 +3884,136 @@ impl Workspace {
             vec![]
         };
 
+        // Include directories (-I) of C/C++ projects are searched by gf as well
+        let include_paths = fs::read_to_string(path.to_string() + "/compile_flags.txt")
+            .map(|flags| {
+                flags
+                    .lines()
+                    .filter_map(|flag| flag.trim().strip_prefix("-I"))
+                    .map(|include_path| {
+                        Path::new(path)
+                            .join(include_path.trim())
+                            .to_string_lossy()
+                            .to_string()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
         Self {
             uri: Url::from_directory_path(path).unwrap(),
             path: path.to_string(),
             gitignore_paths,
+            include_paths,
         }
     }
 }
 
-impl FileFinder {
-    pub fn new(workspace: &Workspace) -> Self {
-        let files: Vec<FileIdentifier> = WalkDir::new(&workspace.path)
-            .into_iter()
-            .filter_entry(|e| {
-                e.file_name() != OsStr::new(".git")
-                    && !workspace
-                        .gitignore_paths
-                        .iter()
-                        .any(|entry| entry == e.file_name().to_str().unwrap())
-            })
-            .flatten()
-            .filter(|e| e.file_type().is_file())
-            .map(|e| FileIdentifier {
-                name: e.file_name().to_os_string(),
-                path: e.path().as_os_str().to_os_string(),
-            })
-            .take(1000)
-            .collect();
+fn col_from_utf16(text: &str, utf16_col: usize) -> usize {
+    let mut units = 0;
+    for (start, c) in text.char_indices() {
+        if units >= utf16_col {
+            return start;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
 
-        Self {
-            files,
-            search_string: String::default(),
-            selection_index: 0,
-            selection_view_offset: 0,
+fn window_command_key_types_char(key_code: VirtualKeyCode) -> bool {
+    matches!(
+        key_code,
+        VirtualKeyCode::S
+            | VirtualKeyCode::V
+            | VirtualKeyCode::C
+            | VirtualKeyCode::Q
+            | VirtualKeyCode::W
+            | VirtualKeyCode::P
+            | VirtualKeyCode::H
+            | VirtualKeyCode::L
+            | VirtualKeyCode::R
+            | VirtualKeyCode::X
+            | VirtualKeyCode::M
+    )
+}
+
+// Files opened in both views are remembered as they are in the focused one
+fn remember_position(document: &Document, view_index: usize) {
+    let view = &document.views[view_index];
+    let Some(cursor) = view.cursors.last() else {
+        return;
+    };
+    let piece_table = &document.buffer.piece_table;
+    file_positions::save(
+        &document.buffer.path,
+        FilePosition {
+            line: piece_table.line_index(cursor.position),
+            col: piece_table.col_index(cursor.position),
+            line_offset: view.line_offset,
+            col_offset: view.col_offset,
+        },
+    );
+}
+
+fn hover_delay(workspace_path: Option<&str>) -> Duration {
+    Duration::from_millis(
+        config::settings_for(workspace_path, None)
+            .hover_delay
+            .unwrap_or(300),
+    )
+}
+
+fn load_line_numbers(renderer: &mut Renderer, workspace_path: Option<&str>) {
+    let settings = config::settings_for(workspace_path, None);
+    renderer.set_line_numbers(
+        settings.number.unwrap_or(true),
+        settings.relative_number.unwrap_or(false),
+    );
+}
+
+fn load_jump_center(workspace_path: Option<&str>) {
+    view::set_jump_center(
+        config::settings_for(workspace_path, None)
+            .jump_center
+            .unwrap_or(JumpCenter::ScrollOff),
+    );
+}
+
+// Neither Windows nor macOS has a primary selection of its own, so middle-click pasting is opt-in
+fn primary_selection(workspace_path: Option<&str>) -> bool {
+    config::settings_for(workspace_path, None)
+        .primary_selection
+        .unwrap_or(false)
+}
+
+// The directory of the nearest Cargo.toml, or of the Cargo workspace that contains it
+fn cargo_root(path: &Path) -> Option<PathBuf> {
+    let mut root = None;
+    for directory in path.ancestors().skip(1) {
+        let manifest = directory.join("Cargo.toml");
+        if !manifest.is_file() {
+            continue;
+        }
+        let is_workspace = fs::read_to_string(&manifest)
+            .is_ok_and(|manifest| manifest.lines().any(|line| line.trim() == "[workspace]"));
+        if root.is_none() || is_workspace {
+            root = Some(directory.to_path_buf());
         }
     }
+    root
+}
 
-    pub fn filter_files(&mut self) {
-        self.files.sort_by(|file1, file2| {
-            if let (Some(name1), Some(name2)) = (file1.name.to_str(), file2.name.to_str()) {
-                let score1 =
-                    text_utils::fuzzy_match(self.search_string.as_bytes(), name1.as_bytes());
-                let score2 =
-                    text_utils::fuzzy_match(self.search_string.as_bytes(), name2.as_bytes());
-                return score2.cmp(&score1);
+// Resolves "." and ".." without touching the file system, so that the same file isn't opened twice
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
             }
-            0.cmp(&0)
-        });
+            component => normalized.push(component),
+        }
     }
+    normalized
 }
@@ -0,0 +1,90 @@
+use std::{fs::File, io::Read, path::Path};
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use argon2::Argon2;
+
+// Encrypted files start with this header, followed by the salt of the key derivation, the nonce
+// and the AES-256-GCM ciphertext of the file
+const MAGIC: &[u8] = b"NIMBLE-ENCRYPTED-1\n";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+pub fn is_encrypted(path: &str) -> bool {
+    let mut header = [0; MAGIC.len()];
+    File::open(path).is_ok_and(|mut file| file.read_exact(&mut header).is_ok() && header == MAGIC)
+}
+
+// Patterns are matched against the file name, '*' matches any number of characters
+pub fn should_encrypt(path: &str, patterns: &[String]) -> bool {
+    let Some(name) = Path::new(path).file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    patterns
+        .iter()
+        .any(|pattern| wildcard_match(pattern.as_bytes(), name.as_bytes()))
+}
+
+pub fn encrypt(text: &[u8], passphrase: &str) -> Option<Vec<u8>> {
+    let mut salt = [0; SALT_LENGTH];
+    OsRng.try_fill_bytes(&mut salt).ok()?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?);
+    let ciphertext = cipher.encrypt(&nonce, text).ok()?;
+
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    Some(data)
+}
+
+// Fails if the passphrase is wrong or the file was tampered with
+pub fn decrypt(data: &[u8], passphrase: &str) -> Option<Vec<u8>> {
+    let data = data.strip_prefix(MAGIC)?;
+    if data.len() < SALT_LENGTH + NONCE_LENGTH {
+        return None;
+    }
+    let (salt, data) = data.split_at(SALT_LENGTH);
+    let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, salt)?);
+    cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Option<Key<Aes256Gcm>> {
+    let mut key = Key::<Aes256Gcm>::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .ok()?;
+    Some(key)
+}
+
+// On a mismatch the last * takes one more byte and matching resumes after it, which keeps patterns
+// with many stars linear instead of exponential
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                last_star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match last_star {
+                Some((star, star_t)) => {
+                    last_star = Some((star, star_t + 1));
+                    p = star + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
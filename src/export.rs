@@ -0,0 +1,130 @@
+use std::fmt::Write;
+
+use crate::renderer::{Color, TextEffect, TextEffectKind};
+
+pub fn to_html(
+    text: &[u8],
+    effects: &[TextEffect],
+    foreground: Color,
+    background: Color,
+) -> String {
+    let mut html = format!(
+        "<pre style=\"font-family: Consolas, Menlo, monospace; color: {}; background-color: {}; padding: 8px;\">",
+        hex_color(foreground),
+        hex_color(background)
+    );
+
+    for (segment, color) in segments(text, effects) {
+        let segment = String::from_utf8_lossy(segment);
+        let mut escaped = String::with_capacity(segment.len());
+        for c in segment.chars() {
+            match c {
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '&' => escaped.push_str("&amp;"),
+                '"' => escaped.push_str("&quot;"),
+                c => escaped.push(c),
+            }
+        }
+
+        match color {
+            Some(color) if color != foreground => {
+                write!(
+                    html,
+                    "<span style=\"color: {};\">{}</span>",
+                    hex_color(color),
+                    escaped
+                )
+                .unwrap();
+            }
+            _ => html.push_str(&escaped),
+        }
+    }
+
+    html.push_str("</pre>");
+    html
+}
+
+pub fn to_rtf(text: &[u8], effects: &[TextEffect], foreground: Color) -> String {
+    let segments = segments(text, effects);
+
+    // RTF color tables are 1-indexed, entry 0 is the reader's default color
+    let mut colors = vec![foreground];
+    for (_, color) in &segments {
+        if let Some(color) = color {
+            if !colors.contains(color) {
+                colors.push(*color);
+            }
+        }
+    }
+
+    let mut rtf =
+        String::from("{\\rtf1\\ansi\\deff0{\\fonttbl{\\f0\\fmodern Consolas;}}{\\colortbl;");
+    for color in &colors {
+        write!(
+            rtf,
+            "\\red{}\\green{}\\blue{};",
+            color.r_u8, color.g_u8, color.b_u8
+        )
+        .unwrap();
+    }
+    rtf.push_str("}\\f0\\fs20 ");
+
+    for (segment, color) in segments {
+        let index = colors
+            .iter()
+            .position(|c| *c == color.unwrap_or(foreground))
+            .unwrap_or(0)
+            + 1;
+        write!(rtf, "{{\\cf{} ", index).unwrap();
+        for c in String::from_utf8_lossy(segment).chars() {
+            match c {
+                '\\' => rtf.push_str("\\\\"),
+                '{' => rtf.push_str("\\{"),
+                '}' => rtf.push_str("\\}"),
+                '\n' => rtf.push_str("\\line\n"),
+                c if c.is_ascii() => rtf.push(c),
+                c => {
+                    let mut units = [0; 2];
+                    for unit in c.encode_utf16(&mut units) {
+                        write!(rtf, "\\u{}?", *unit as i16).unwrap();
+                    }
+                }
+            }
+        }
+        rtf.push('}');
+    }
+
+    rtf.push('}');
+    rtf
+}
+
+// Splits the text into runs that are either plain or colored by a single effect
+fn segments<'a>(text: &'a [u8], effects: &[TextEffect]) -> Vec<(&'a [u8], Option<Color>)> {
+    let mut effects = effects.to_vec();
+    effects.sort_by_key(|effect| effect.start);
+
+    let mut segments = vec![];
+    let mut offset = 0;
+    for effect in effects {
+        if effect.start < offset || effect.start >= text.len() {
+            continue;
+        }
+        if effect.start > offset {
+            segments.push((&text[offset..effect.start], None));
+        }
+        let end = (effect.start + effect.length).min(text.len());
+        let TextEffectKind::ForegroundColor(color) = effect.kind;
+        segments.push((&text[effect.start..end], Some(color)));
+        offset = end;
+    }
+    if offset < text.len() {
+        segments.push((&text[offset..], None));
+    }
+
+    segments
+}
+
+fn hex_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r_u8, color.g_u8, color.b_u8)
+}
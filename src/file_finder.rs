@@ -0,0 +1,147 @@
+use std::{cmp::Reverse, path::Path};
+
+use crate::{
+    editor::{FileIdentifier, Workspace},
+    text_utils,
+    workspace_index::{walk_workspace, WorkspaceIndex},
+};
+
+pub const MAX_SHOWN_FILE_FINDER_ITEMS: usize = 10;
+
+// Matches in the file name always rank above matches that need the rest of the path
+const NAME_MATCH_BONUS: isize = 1000;
+const SEPARATOR_MATCH_BONUS: isize = 10;
+const ADJACENT_MATCH_BONUS: isize = 5;
+
+pub struct FileFinder {
+    pub files: Vec<FileIdentifier>,
+    pub search_string: String,
+    pub selection_index: usize,
+    pub selection_view_offset: usize,
+    // Files of the workspace are also matched by their path relative to it, and are replaced once
+    // the workspace index finishes a new scan
+    workspace_path: Option<String>,
+    index_generation: Option<usize>,
+}
+
+impl FileFinder {
+    // Until the workspace index is ready the first files found are listed
+    pub fn new(workspace: &Workspace, workspace_index: Option<&WorkspaceIndex>) -> Self {
+        let (files, index_generation) = match workspace_index.and_then(WorkspaceIndex::files) {
+            Some((generation, files)) => (files, Some(generation)),
+            None => (
+                walk_workspace(&workspace.path, &workspace.gitignore_paths)
+                    .map(|e| FileIdentifier {
+                        name: e.file_name().to_os_string(),
+                        path: e.path().as_os_str().to_os_string(),
+                        position: None,
+                    })
+                    .take(1000)
+                    .collect(),
+                None,
+            ),
+        };
+
+        Self {
+            files,
+            search_string: String::default(),
+            selection_index: 0,
+            selection_view_offset: 0,
+            workspace_path: Some(workspace.path.clone()),
+            index_generation,
+        }
+    }
+
+    pub fn with_files(files: Vec<FileIdentifier>, selection_index: usize) -> Self {
+        Self {
+            files,
+            search_string: String::default(),
+            selection_index,
+            selection_view_offset: selection_index.saturating_sub(MAX_SHOWN_FILE_FINDER_ITEMS - 1),
+            workspace_path: None,
+            index_generation: None,
+        }
+    }
+
+    // Returns whether the files changed, the selected file stays selected if it still exists
+    pub fn refresh(&mut self, workspace_index: &WorkspaceIndex) -> bool {
+        if self.workspace_path.is_none() || workspace_index.generation() == self.index_generation {
+            return false;
+        }
+        let Some((generation, files)) = workspace_index.files() else {
+            return false;
+        };
+
+        let selected = self
+            .files
+            .get(self.selection_index)
+            .map(|file| file.path.clone());
+        self.files = files;
+        self.index_generation = Some(generation);
+        self.filter_files();
+
+        self.selection_index = selected
+            .and_then(|selected| self.files.iter().position(|file| file.path == selected))
+            .unwrap_or(0);
+        self.selection_view_offset = self
+            .selection_index
+            .saturating_sub(MAX_SHOWN_FILE_FINDER_ITEMS - 1);
+        true
+    }
+
+    // Files that don't match are kept at the bottom in their previous order
+    pub fn filter_files(&mut self) {
+        let workspace_path = self.workspace_path.as_deref();
+        let search_string = self.search_string.as_bytes();
+        self.files.sort_by_cached_key(|file| {
+            let Some(name) = file.name.to_str() else {
+                return Reverse(isize::MIN);
+            };
+            let path = workspace_path
+                .and_then(|workspace_path| Path::new(&file.path).strip_prefix(workspace_path).ok())
+                .and_then(Path::to_str);
+            Reverse(match_score(search_string, name, path))
+        });
+    }
+}
+
+fn match_score(pattern: &[u8], name: &str, path: Option<&str>) -> isize {
+    // The recursive matcher is only used on names, it gets slow on long paths
+    let name_score = text_utils::fuzzy_match(pattern, name.as_bytes());
+    if name_score != isize::MIN {
+        return name_score.saturating_add(NAME_MATCH_BONUS);
+    }
+    path.and_then(|path| path_score(pattern, path.as_bytes()))
+        .unwrap_or(isize::MIN)
+}
+
+// Every character of the pattern is matched at its first occurrence in the path, matches at the
+// start of a directory or word and runs of matches count more, longer paths count less
+fn path_score(pattern: &[u8], path: &[u8]) -> Option<isize> {
+    let mut pattern = pattern.iter().peekable();
+    let mut score = 0;
+    let mut previous = None;
+    let mut adjacent = false;
+    for &c in path {
+        let Some(&&p) = pattern.peek() else {
+            break;
+        };
+        if c.eq_ignore_ascii_case(&p) {
+            score += match previous {
+                None | Some(b'/' | b'\\' | b'_' | b'-' | b'.') => SEPARATOR_MATCH_BONUS,
+                _ if adjacent => ADJACENT_MATCH_BONUS,
+                _ => 1,
+            };
+            pattern.next();
+            adjacent = true;
+        } else {
+            adjacent = false;
+        }
+        previous = Some(c);
+    }
+
+    match pattern.peek() {
+        Some(_) => None,
+        None => Some(score - path.len() as isize / 4),
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    cmp::{min, Reverse},
+    env, fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config, log,
+    thread_pool::{self, Priority},
+};
+
+pub const MAX_SHOWN_SNAPSHOTS: usize = 10;
+pub const MAX_SHOWN_DIFF_LINES: usize = 30;
+const MAX_SNAPSHOTS: usize = 50;
+const MAX_SNAPSHOT_SIZE: usize = 8 * 1024 * 1024;
+const DIFF_CONTEXT_LINES: usize = 2;
+// Changes needing more line comparisons than this are shown as the old lines followed by the new
+const MAX_DIFF_COMPARISONS: usize = 1_000_000;
+
+// A saved version of a file, named after the time it was saved at in milliseconds
+pub struct Snapshot {
+    pub time: u64,
+    pub size: u64,
+    path: PathBuf,
+}
+
+pub enum DiffLine {
+    Unchanged(String),
+    Removed(String),
+    Added(String),
+    // Unchanged lines between two changes that are left out
+    Skipped,
+}
+
+// The versions of the file in the buffer, newest first. The selected one is compared with the
+// buffer, the diff shows what restoring it would change
+pub struct HistoryPanel {
+    pub path: String,
+    pub snapshots: Vec<Snapshot>,
+    pub selection_index: usize,
+    pub selection_view_offset: usize,
+    pub diff: Vec<DiffLine>,
+    text: Vec<u8>,
+}
+
+impl HistoryPanel {
+    pub fn new(path: &str, text: Vec<u8>) -> Option<Self> {
+        let snapshots = history_directory(path).map(snapshots_in)?;
+        if snapshots.is_empty() {
+            return None;
+        }
+        let mut history_panel = Self {
+            path: path.to_string(),
+            snapshots,
+            selection_index: 0,
+            selection_view_offset: 0,
+            diff: vec![],
+            text,
+        };
+        history_panel.update_diff();
+        Some(history_panel)
+    }
+
+    pub fn selected_text(&self) -> Option<Vec<u8>> {
+        let snapshot = self.snapshots.get(self.selection_index)?;
+        match fs::read(&snapshot.path) {
+            Ok(text) => Some(text),
+            Err(error) => {
+                log::error(format!(
+                    "Failed to read a saved version of {}: {}",
+                    self.path, error
+                ));
+                None
+            }
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        self.selection_index = min(
+            self.selection_index + 1,
+            self.snapshots.len().saturating_sub(1),
+        );
+        if self.selection_index >= self.selection_view_offset + MAX_SHOWN_SNAPSHOTS {
+            self.selection_view_offset += 1;
+        }
+        self.update_diff();
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selection_index = self.selection_index.saturating_sub(1);
+        if self.selection_index < self.selection_view_offset {
+            self.selection_view_offset -= 1;
+        }
+        self.update_diff();
+    }
+
+    // Scrolling keeps the selection in view
+    pub fn scroll(&mut self, lines: isize) {
+        let max_offset = self.snapshots.len().saturating_sub(MAX_SHOWN_SNAPSHOTS);
+        self.selection_view_offset = min(
+            self.selection_view_offset.saturating_add_signed(lines),
+            max_offset,
+        );
+        let selection_index = self
+            .selection_index
+            .clamp(
+                self.selection_view_offset,
+                self.selection_view_offset + MAX_SHOWN_SNAPSHOTS - 1,
+            )
+            .min(self.snapshots.len().saturating_sub(1));
+        if selection_index != self.selection_index {
+            self.selection_index = selection_index;
+            self.update_diff();
+        }
+    }
+
+    // Rows are counted from the first shown snapshot
+    pub fn select_row(&mut self, row: usize) -> bool {
+        let index = self.selection_view_offset + row;
+        if row >= MAX_SHOWN_SNAPSHOTS || index >= self.snapshots.len() {
+            return false;
+        }
+        self.selection_index = index;
+        self.update_diff();
+        true
+    }
+
+    fn update_diff(&mut self) {
+        self.diff = match self.selected_text() {
+            Some(snapshot) => diff(&self.text, &snapshot),
+            None => vec![],
+        };
+    }
+}
+
+// Every save of a file is kept in ~/.nimble/history, in a directory per file named after a hash of
+// its path. Saves that didn't change the file since the last one are skipped and the oldest
+// versions are dropped. Writing happens on a worker, in order for each file
+pub fn record(path: &str, text: Vec<u8>) {
+    if text.len() > MAX_SNAPSHOT_SIZE {
+        return;
+    }
+    let Some(directory) = history_directory(path) else {
+        return;
+    };
+    let key = key(path);
+    thread_pool::spawn_pinned(hash(&key) as usize, Priority::Low, move || {
+        let latest = snapshots_in(directory.clone()).into_iter().next();
+        if latest.is_some_and(|snapshot| fs::read(&snapshot.path).is_ok_and(|last| last == text)) {
+            return;
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis());
+        let written = fs::create_dir_all(&directory)
+            .and_then(|_| fs::write(directory.join("path"), &key))
+            .and_then(|_| fs::write(directory.join(time.to_string()), &text));
+        if let Err(error) = written {
+            log::warning(format!("Failed to keep a copy of {}: {}", key, error));
+            return;
+        }
+
+        for snapshot in snapshots_in(directory).into_iter().skip(MAX_SNAPSHOTS) {
+            let _ = fs::remove_file(snapshot.path);
+        }
+    });
+}
+
+// "just now", "5 minutes ago", "3 days ago"
+pub fn age(time: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64);
+    let seconds = now.saturating_sub(time) / 1000;
+    let (count, unit) = match seconds {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    format!(
+        "{} {}{} ago",
+        count,
+        unit,
+        if count == 1 { "" } else { "s" }
+    )
+}
+
+fn snapshots_in(directory: PathBuf) -> Vec<Snapshot> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return vec![];
+    };
+    let mut snapshots: Vec<Snapshot> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            Some(Snapshot {
+                time: entry.file_name().to_str()?.parse().ok()?,
+                size: entry.metadata().ok()?.len(),
+                path: entry.path(),
+            })
+        })
+        .collect();
+    snapshots.sort_by_key(|snapshot| Reverse(snapshot.time));
+    snapshots
+}
+
+// The unchanged lines at the start and end are compared first, the lines in between are matched up
+// by their longest common subsequence. Only a few unchanged lines are kept around each change
+fn diff(old: &[u8], new: &[u8]) -> Vec<DiffLine> {
+    let old_lines: Vec<&[u8]> = old.split(|c| *c == b'\n').collect();
+    let new_lines: Vec<&[u8]> = new.split(|c| *c == b'\n').collect();
+    let prefix = old_lines
+        .iter()
+        .zip(&new_lines)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let old_changed = &old_lines[prefix..old_lines.len() - suffix];
+    let new_changed = &new_lines[prefix..new_lines.len() - suffix];
+
+    // None for unchanged lines, Some(true) for added and Some(false) for removed ones
+    let mut lines: Vec<(Option<bool>, &[u8])> = old_lines[..prefix]
+        .iter()
+        .map(|line| (None, *line))
+        .collect();
+    if old_changed.len() * new_changed.len() > MAX_DIFF_COMPARISONS {
+        lines.extend(old_changed.iter().map(|line| (Some(false), *line)));
+        lines.extend(new_changed.iter().map(|line| (Some(true), *line)));
+    } else {
+        let (n, m) = (old_changed.len(), new_changed.len());
+        let mut common = vec![0u32; (n + 1) * (m + 1)];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                common[i * (m + 1) + j] = if old_changed[i] == new_changed[j] {
+                    common[(i + 1) * (m + 1) + j + 1] + 1
+                } else {
+                    common[(i + 1) * (m + 1) + j].max(common[i * (m + 1) + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_changed[i] == new_changed[j] {
+                lines.push((None, old_changed[i]));
+                (i, j) = (i + 1, j + 1);
+            } else if j == m
+                || (i < n && common[(i + 1) * (m + 1) + j] >= common[i * (m + 1) + j + 1])
+            {
+                lines.push((Some(false), old_changed[i]));
+                i += 1;
+            } else {
+                lines.push((Some(true), new_changed[j]));
+                j += 1;
+            }
+        }
+    }
+    lines.extend(
+        old_lines[old_lines.len() - suffix..]
+            .iter()
+            .map(|line| (None, *line)),
+    );
+
+    let changes: Vec<usize> = (0..lines.len()).filter(|i| lines[*i].0.is_some()).collect();
+    let near_change = |i: usize| {
+        let first_after = changes.partition_point(|change| *change < i);
+        changes
+            .get(first_after)
+            .is_some_and(|change| change - i <= DIFF_CONTEXT_LINES)
+            || first_after
+                .checked_sub(1)
+                .is_some_and(|before| i - changes[before] <= DIFF_CONTEXT_LINES)
+    };
+
+    let mut diff = vec![];
+    let mut skipping = false;
+    for (i, (change, line)) in lines.into_iter().enumerate() {
+        let line = String::from_utf8_lossy(line).to_string();
+        match change {
+            Some(true) => diff.push(DiffLine::Added(line)),
+            Some(false) => diff.push(DiffLine::Removed(line)),
+            None if near_change(i) => diff.push(DiffLine::Unchanged(line)),
+            None if !skipping && !diff.is_empty() => diff.push(DiffLine::Skipped),
+            None => (),
+        }
+        skipping = change.is_none() && !near_change(i);
+    }
+    if matches!(diff.last(), Some(DiffLine::Skipped)) {
+        diff.pop();
+    }
+    diff
+}
+
+fn history_directory(path: &str) -> Option<PathBuf> {
+    config::config_directories(None)
+        .into_iter()
+        .next()
+        .map(|directory| {
+            directory
+                .join("history")
+                .join(format!("{:016x}", hash(&key(path))))
+        })
+}
+
+// The same file may be opened through different relative paths
+fn key(path: &str) -> String {
+    env::current_dir()
+        .ok()
+        .map(|directory| directory.join(path))
+        .and_then(|path| path.to_str().map(str::to_string))
+        .unwrap_or_else(|| path.to_string())
+}
+
+// FNV-1a, the names of the directories have to stay the same between versions of the editor
+fn hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325, |hash, c| {
+        (hash ^ c as u64).wrapping_mul(0x100000001b3)
+    })
+}
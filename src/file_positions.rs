@@ -0,0 +1,89 @@
+use std::{env, fs, path::PathBuf};
+
+use crate::{config, log};
+
+const MAX_REMEMBERED_FILES: usize = 1000;
+
+// Where the cursor and the view of a file were when it was last closed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilePosition {
+    pub line: usize,
+    pub col: usize,
+    pub line_offset: usize,
+    pub col_offset: usize,
+}
+
+pub fn load(path: &str) -> Option<FilePosition> {
+    let key = key(path);
+    read_positions()
+        .into_iter()
+        .find(|(path, _)| *path == key)
+        .map(|(_, position)| position)
+}
+
+// The most recently closed files come first, the oldest are forgotten
+pub fn save(path: &str, position: FilePosition) {
+    let Some(positions_path) = positions_path() else {
+        return;
+    };
+    let key = key(path);
+    let mut positions = read_positions();
+    positions.retain(|(path, _)| *path != key);
+    positions.insert(0, (key, position));
+    positions.truncate(MAX_REMEMBERED_FILES);
+
+    let mut text = String::default();
+    for (path, position) in positions {
+        text.push_str(&format!(
+            "{} {} {} {} {}\n",
+            position.line, position.col, position.line_offset, position.col_offset, path
+        ));
+    }
+
+    let written = positions_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&positions_path, text));
+    if let Err(error) = written {
+        log::warning(format!(
+            "Failed to remember the position in {}: {}",
+            path, error
+        ));
+    }
+}
+
+// Each line is "LINE COL LINE_OFFSET COL_OFFSET PATH"
+fn read_positions() -> Vec<(String, FilePosition)> {
+    let Some(text) = positions_path().and_then(|path| fs::read_to_string(path).ok()) else {
+        return vec![];
+    };
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(5, ' ');
+            let mut number = || parts.next()?.parse().ok();
+            let position = FilePosition {
+                line: number()?,
+                col: number()?,
+                line_offset: number()?,
+                col_offset: number()?,
+            };
+            Some((parts.next()?.to_string(), position))
+        })
+        .collect()
+}
+
+fn positions_path() -> Option<PathBuf> {
+    config::config_directories(None)
+        .into_iter()
+        .next()
+        .map(|directory| directory.join("positions"))
+}
+
+// The same file may be opened through different relative paths
+fn key(path: &str) -> String {
+    env::current_dir()
+        .ok()
+        .map(|directory| directory.join(path))
+        .and_then(|path| path.to_str().map(str::to_string))
+        .unwrap_or_else(|| path.to_string())
+}
@@ -0,0 +1,122 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    log,
+    platform_resources::{self, DirectoryWatch},
+    thread_pool::{self, Priority},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub enum FileChange {
+    Created(String),
+    Modified(String),
+    Removed(String),
+    // More changed than the platform could report, the directory has to be scanned again
+    Overflowed,
+}
+
+#[derive(Default)]
+pub struct DiskChanges {
+    pub modified_times: Vec<(String, SystemTime)>,
+    pub created: Vec<String>,
+    pub removed: Vec<String>,
+    pub rescan: bool,
+}
+
+// The platform reports changes below the workspace as they happen. Open files outside of it are
+// checked by reading their modification times on a worker, which keeps slow drives from stalling
+// the editor. The changes are handed out once on the next frame after they were found
+pub struct FileWatcher {
+    changes: Arc<Mutex<DiskChanges>>,
+    directory: Option<(String, DirectoryWatch)>,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    pub fn new() -> Self {
+        Self {
+            changes: Arc::new(Mutex::new(DiskChanges::default())),
+            directory: None,
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn watch_directory(&mut self, path: &str) {
+        // The previous watch is stopped first
+        self.directory = None;
+
+        let changes = self.changes.clone();
+        let watch = platform_resources::watch_directory(path, move |change| {
+            // Created files may have replaced an open file, their modification time is read as well
+            let modified_time = match &change {
+                FileChange::Created(path) | FileChange::Modified(path) => fs::metadata(path)
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .map(|modified| (path.clone(), modified)),
+                _ => None,
+            };
+
+            let mut changes = changes.lock().unwrap();
+            changes.modified_times.extend(modified_time);
+            match change {
+                FileChange::Created(path) => changes.created.push(path),
+                FileChange::Removed(path) => changes.removed.push(path),
+                FileChange::Overflowed => changes.rescan = true,
+                FileChange::Modified(_) => (),
+            }
+        });
+
+        match watch {
+            Some(watch) => self.directory = Some((path.to_string(), watch)),
+            None => log::warning(format!(
+                "Unable to watch {} for changes, only open files are checked",
+                path
+            )),
+        }
+    }
+
+    // Files that can't be read are left out, a deleted file keeps its document as it is
+    pub fn poll(&mut self, paths: impl FnOnce() -> Vec<String>) -> DiskChanges {
+        let changes = std::mem::take(&mut *self.changes.lock().unwrap());
+        // Changes the platform couldn't report may include open files below the workspace
+        if changes.rescan || self.last_poll.elapsed() >= POLL_INTERVAL {
+            self.last_poll = Instant::now();
+            let paths: Vec<String> = paths()
+                .into_iter()
+                .filter(|path| changes.rescan || !self.is_watched(path))
+                .collect();
+            if paths.is_empty() {
+                return changes;
+            }
+
+            let results = self.changes.clone();
+            thread_pool::spawn(Priority::Low, move || {
+                let modified_times: Vec<(String, SystemTime)> = paths
+                    .into_iter()
+                    .filter_map(|path| {
+                        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                        Some((path, modified))
+                    })
+                    .collect();
+                results
+                    .lock()
+                    .unwrap()
+                    .modified_times
+                    .extend(modified_times);
+            });
+        }
+        changes
+    }
+
+    fn is_watched(&self, path: &str) -> bool {
+        self.directory
+            .as_ref()
+            .is_some_and(|(directory, _)| Path::new(path).starts_with(directory))
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    path::Path,
+    process::Command,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    file_history, log,
+    thread_pool::{self, Priority},
+};
+
+// The status of a file in its repository as shown by git status --porcelain, like "M" or "??".
+// Files that are unchanged, outside of a repository or seen without a git executable have none
+#[derive(Clone, Default)]
+pub struct FileStatus(Arc<Mutex<Option<String>>>);
+
+impl FileStatus {
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+// The queries run git on a worker, a slow repository never holds up the editor
+pub fn query_status(path: &str, status: &FileStatus) {
+    let path = path.to_string();
+    let status = status.clone();
+    thread_pool::spawn(Priority::Low, move || {
+        let output = git(&path, &["status", "--porcelain", "--"]);
+        *status.0.lock().unwrap() = output.as_deref().and_then(parse_status);
+    });
+}
+
+// Who last changed the line of the saved file and when is shown as a notification once git has
+// answered
+pub fn blame(path: &str, line: usize) {
+    let path = path.to_string();
+    thread_pool::spawn(Priority::High, move || {
+        let range = format!("{},{}", line + 1, line + 1);
+        let blamed = git(&path, &["blame", "--porcelain", "-L", &range, "--"]);
+        match blamed.as_deref().and_then(parse_blame) {
+            Some((author, time, summary)) => log::notify(format!(
+                "Line {}: {}, {}: {}",
+                line + 1,
+                author,
+                file_history::age(time * 1000),
+                summary
+            )),
+            None => log::warning(format!("No git history for line {} of {}", line + 1, path)),
+        }
+    });
+}
+
+// Runs in the directory of the file with the file name as the last argument
+fn git(path: &str, arguments: &[&str]) -> Option<String> {
+    let path = Path::new(path);
+    let mut command = Command::new("git");
+    command
+        .current_dir(path.parent()?)
+        .args(arguments)
+        .arg(path.file_name()?);
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(windows::Win32::System::Threading::CREATE_NO_WINDOW.0);
+    }
+
+    let output = command.output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// "XY path", where X is the staged and Y the unstaged status
+fn parse_status(output: &str) -> Option<String> {
+    let status = output.lines().next()?.get(..2)?.trim();
+    (!status.is_empty()).then(|| status.to_string())
+}
+
+// The first line holds the commit, the author, the time and the summary follow as "key value" lines
+fn parse_blame(output: &str) -> Option<(String, u64, String)> {
+    let field = |key: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(' '))
+    };
+    Some((
+        field("author")?.to_string(),
+        field("author-time")?.parse().ok()?,
+        field("summary")?.to_string(),
+    ))
+}
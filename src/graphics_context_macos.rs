@@ -20,7 +20,8 @@ use objc::{class, msg_send, runtime::Object, sel, sel_impl};
 use winit::window::Window;
 
 use crate::{
-    renderer::{Color, RenderLayout, TextEffect, TextEffectKind},
+    renderer::{self, Color, PopupBounds, RenderLayout, TextEffect, TextEffectKind},
+    text_utils,
     theme::Theme,
     view::View,
 };
@@ -105,7 +106,11 @@ impl GraphicsContext {
         }
     }
 
-    pub fn ensure_size(&mut self, window: &Window) {
+    pub fn resize(&mut self, window: &Window) {
+        if window.inner_size().width == 0 || window.inner_size().height == 0 {
+            return;
+        }
+
         self.window_size = (
             window.inner_size().width as f64 / window.scale_factor(),
             window.inner_size().height as f64 / window.scale_factor(),
@@ -216,6 +221,37 @@ impl GraphicsContext {
         ));
     }
 
+    pub fn strike_cells(
+        &self,
+        row: usize,
+        col: usize,
+        layout: &RenderLayout,
+        count: usize,
+        color: Color,
+    ) {
+        let context = get_current_context();
+
+        let (row_offset, col_offset) = (
+            (row + layout.row_offset) as f64 * self.font_size.1,
+            (col + layout.col_offset) as f64 * self.font_size.0,
+        );
+
+        context.set_fill_color(&CGColor::rgb(
+            color.r as f64,
+            color.g as f64,
+            color.b as f64,
+            1.0,
+        ));
+
+        context.fill_rect(CGRect::new(
+            &CGPoint::new(
+                col_offset,
+                self.window_size.1 - self.font_size.1 * 0.55 - row_offset,
+            ),
+            &CGSize::new(self.font_size.0 * count as f64, self.font_size.1 * 0.08),
+        ));
+    }
+
     fn get_text_size(&self, x: f64, y: f64, layout: &RenderLayout, text: &[u8]) -> CGSize {
         let utf8_str = unsafe { std::str::from_utf8_unchecked(text) };
         let string = CFAttributedString::new(&CFString::from_str(utf8_str).unwrap());
@@ -331,23 +367,22 @@ impl GraphicsContext {
         col_offset: usize,
         align_right: bool,
     ) {
-        let utf8_str = unsafe { std::str::from_utf8_unchecked(text) };
-        let string = CFAttributedString::new(&CFString::from_str(utf8_str).unwrap());
-        let string_len = utf8_str.len();
+        // The effects are given in bytes and moved onto the placeholders and decoded characters
+        let (display_text, positions) = text_utils::display_text(text);
+        let string = CFAttributedString::new(&CFString::from_str(&display_text).unwrap());
 
         for effect in effects {
             match &effect.kind {
                 TextEffectKind::ForegroundColor(color) => {
                     let text_color =
                         CGColor::rgb(color.r as f64, color.g as f64, color.b as f64, 1.0);
+                    let start = min(effect.start, text.len());
+                    let end = min(effect.start + effect.length, text.len());
+                    let range = text_utils::display_range(&positions, start, end);
                     unsafe {
                         CFAttributedStringSetAttribute(
                             string.to_void() as *const _,
-                            CFRange::init(
-                                effect.start as isize,
-                                min(string_len.saturating_sub(effect.start), effect.length)
-                                    as isize,
-                            ),
+                            CFRange::init(range.start as isize, range.len() as isize),
                             core_text::string_attributes::kCTForegroundColorAttributeName,
                             text_color.to_void() as *const _,
                         );
@@ -422,6 +457,35 @@ impl GraphicsContext {
 
     pub fn set_word_wrapping(&self, wrap: bool) {}
 
+    // Keeps a popup of the given size (in cells) inside its layout, see renderer::place_popup
+    fn place_popup(
+        &self,
+        layout: &RenderLayout,
+        row_offset: f64,
+        col_offset: f64,
+        (width, height): (usize, usize),
+        prefer_above: bool,
+    ) -> (f64, f64) {
+        let (popup_width, popup_height) = (
+            self.font_size.0 * width as f64 + self.font_size.1,
+            self.font_size.1 * (height + 1) as f64,
+        );
+        let bounds = PopupBounds {
+            left: layout.col_offset as f64 * self.font_size.0,
+            top: layout.row_offset as f64 * self.font_size.1,
+            right: (layout.col_offset + layout.num_cols) as f64 * self.font_size.0,
+            bottom: (layout.row_offset + layout.num_rows) as f64 * self.font_size.1,
+        };
+
+        renderer::place_popup(
+            &bounds,
+            (row_offset, col_offset),
+            (popup_width, popup_height),
+            self.font_size.1,
+            prefer_above,
+        )
+    }
+
     pub fn draw_popup_below(
         &self,
         row: usize,
@@ -432,8 +496,8 @@ impl GraphicsContext {
         inner_color: Color,
         effects: Option<&[TextEffect]>,
         theme: &Theme,
-    ) {
-        let (mut row_offset, col_offset) = (
+    ) -> PopupBounds {
+        let (row_offset, col_offset) = (
             (row + layout.row_offset) as f64 * self.font_size.1,
             (col + layout.col_offset) as f64 * self.font_size.0,
         );
@@ -445,15 +509,14 @@ impl GraphicsContext {
             text,
         );
 
-        if row_offset + size.height > self.window_size.1 {
-            row_offset -= size.height + self.font_size.1 + self.font_size.1;
-        }
-
         let (width, height) = (
             (size.width / self.font_size.0).round() as usize,
             (size.height / self.font_size.1).round() as usize,
         );
 
+        let (row_offset, col_offset) =
+            self.place_popup(layout, row_offset, col_offset, (width, height), false);
+
         let context = get_current_context();
         context.set_fill_color(&CGColor::rgb(
             outer_color.r as f64,
@@ -499,6 +562,13 @@ impl GraphicsContext {
             effects.unwrap_or(&[]),
             theme,
         );
+
+        PopupBounds {
+            left: col_offset,
+            top: row_offset,
+            right: col_offset + self.font_size.0 * width as f64 + self.font_size.1,
+            bottom: row_offset + self.font_size.1 * (height + 1) as f64,
+        }
     }
 
     pub fn draw_popup_above(
@@ -512,7 +582,7 @@ impl GraphicsContext {
         effects: Option<&[TextEffect]>,
         theme: &Theme,
     ) {
-        let (mut row_offset, col_offset) = (
+        let (row_offset, col_offset) = (
             (row + layout.row_offset) as f64 * self.font_size.1,
             (col + layout.col_offset) as f64 * self.font_size.0,
         );
@@ -524,15 +594,14 @@ impl GraphicsContext {
             text,
         );
 
-        if row_offset - size.height > 0.0 {
-            row_offset -= size.height + self.font_size.1 + self.font_size.1;
-        }
-
         let (width, height) = (
             (size.width / self.font_size.0).round() as usize,
             (size.height / self.font_size.1).round() as usize,
         );
 
+        let (row_offset, col_offset) =
+            self.place_popup(layout, row_offset, col_offset, (width, height), true);
+
         let context = get_current_context();
         context.set_fill_color(&CGColor::rgb(
             outer_color.r as f64,
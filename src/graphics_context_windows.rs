@@ -1,3 +1,5 @@
+use std::cmp::min;
+
 use windows::{
     core::ComInterface,
     w,
@@ -33,13 +35,16 @@ use windows::{
 use winit::{platform::windows::WindowExtWindows, window::Window};
 
 use crate::{
-    renderer::{Color, RenderLayout, TextEffect, TextEffectKind},
+    renderer::{self, Color, PopupBounds, RenderLayout, TextEffect, TextEffectKind},
+    text_utils,
     theme::Theme,
     view::View,
 };
 
 pub struct GraphicsContext {
     window_size: (f32, f32),
+    pixel_size: (u32, u32),
+    scale_factor: f64,
     render_target: ID2D1HwndRenderTarget,
     dwrite_factory: IDWriteFactory,
     text_format: IDWriteTextFormat,
@@ -124,6 +129,8 @@ impl GraphicsContext {
 
         Self {
             window_size,
+            pixel_size: (window.inner_size().width, window.inner_size().height),
+            scale_factor: window.scale_factor(),
             dwrite_factory,
             render_target,
             text_format,
@@ -132,14 +139,35 @@ impl GraphicsContext {
         }
     }
 
-    pub fn ensure_size(&mut self, window: &Window) {
-        unsafe {
-            self.render_target
-                .Resize(&D2D_SIZE_U {
-                    width: window.inner_size().width,
-                    height: window.inner_size().height,
-                })
-                .unwrap();
+    pub fn resize(&mut self, window: &Window) {
+        let pixel_size = (window.inner_size().width, window.inner_size().height);
+
+        // A minimized window reports a zero size, keep the old target until it is restored
+        if pixel_size.0 == 0 || pixel_size.1 == 0 {
+            return;
+        }
+
+        // Moving between monitors can change the scale factor without changing the pixel size
+        if window.scale_factor() != self.scale_factor {
+            self.scale_factor = window.scale_factor();
+            unsafe {
+                self.render_target.SetDpi(
+                    96.0 * self.scale_factor as f32,
+                    96.0 * self.scale_factor as f32,
+                );
+            }
+        }
+
+        if pixel_size != self.pixel_size {
+            self.pixel_size = pixel_size;
+            unsafe {
+                self.render_target
+                    .Resize(&D2D_SIZE_U {
+                        width: pixel_size.0,
+                        height: pixel_size.1,
+                    })
+                    .unwrap();
+            }
         }
 
         self.window_size = (
@@ -287,6 +315,75 @@ impl GraphicsContext {
         }
     }
 
+    pub fn strike_cells(
+        &self,
+        row: usize,
+        col: usize,
+        layout: &RenderLayout,
+        count: usize,
+        color: Color,
+    ) {
+        let (row_offset, col_offset) = (
+            (row + layout.row_offset) as f32 * self.font_size.1,
+            (col + layout.col_offset) as f32 * self.font_size.0,
+        );
+
+        unsafe {
+            let brush = self
+                .render_target
+                .CreateSolidColorBrush(
+                    &D2D1_COLOR_F {
+                        r: color.r,
+                        g: color.g,
+                        b: color.b,
+                        a: 1.0,
+                    },
+                    Some(&DEFAULT_BRUSH_PROPERTIES),
+                )
+                .unwrap();
+
+            self.render_target.FillRectangle(
+                &D2D_RECT_F {
+                    left: col_offset - 0.5,
+                    top: row_offset + self.font_size.1 * 0.5 - 0.5,
+                    right: col_offset + self.font_size.0 * count as f32 + 0.5,
+                    bottom: row_offset + self.font_size.1 * 0.52 + 0.5,
+                },
+                &brush,
+            );
+        }
+    }
+
+    // Keeps a popup of the given size (in cells) inside its layout, see renderer::place_popup
+    fn place_popup(
+        &self,
+        layout: &RenderLayout,
+        row_offset: f32,
+        col_offset: f32,
+        (width, height): (usize, usize),
+        prefer_above: bool,
+    ) -> (f32, f32) {
+        let (popup_width, popup_height) = (
+            self.font_size.0 * width as f32 + self.font_size.1 * 0.5,
+            self.font_size.1 * height as f32 + self.font_size.1 * 0.5,
+        );
+        let bounds = PopupBounds {
+            left: (layout.col_offset as f32 * self.font_size.0) as f64,
+            top: (layout.row_offset as f32 * self.font_size.1) as f64,
+            right: ((layout.col_offset + layout.num_cols) as f32 * self.font_size.0) as f64,
+            bottom: ((layout.row_offset + layout.num_rows) as f32 * self.font_size.1) as f64,
+        };
+
+        let (row_offset, col_offset) = renderer::place_popup(
+            &bounds,
+            (row_offset as f64, col_offset as f64),
+            (popup_width as f64, popup_height as f64),
+            self.font_size.1 as f64,
+            prefer_above,
+        );
+        (row_offset as f32, col_offset as f32)
+    }
+
     fn get_text_width_height(
         &self,
         x: f32,
@@ -464,11 +561,9 @@ impl GraphicsContext {
         col_offset: usize,
         align_right: bool,
     ) {
-        // Col offset text will not use conversion because only ASCII is allowed
-        let mut wide_text = vec![];
-        for c in text {
-            wide_text.push(*c as u16);
-        }
+        // The effects are given in bytes and moved onto the placeholders and decoded characters
+        let (display_text, positions) = text_utils::display_text(text);
+        let wide_text: Vec<u16> = display_text.encode_utf16().collect();
 
         let text_layout = unsafe {
             self.dwrite_factory
@@ -519,12 +614,15 @@ impl GraphicsContext {
                         )
                         .unwrap();
 
+                    let start = min(effect.start, text.len());
+                    let end = min(effect.start + effect.length, text.len());
+                    let range = text_utils::display_range(&positions, start, end);
                     text_layout
                         .SetDrawingEffect(
                             &brush,
                             DWRITE_TEXT_RANGE {
-                                startPosition: effect.start as u32,
-                                length: effect.length as u32,
+                                startPosition: range.start as u32,
+                                length: range.len() as u32,
                             },
                         )
                         .unwrap();
@@ -622,10 +720,10 @@ impl GraphicsContext {
         effects: Option<&[TextEffect]>,
         theme: &Theme,
         restrict: bool,
-    ) {
+    ) -> PopupBounds {
         self.set_word_wrapping(true);
 
-        let (mut row_offset, col_offset) = (
+        let (row_offset, col_offset) = (
             (row + layout.row_offset) as f32 * self.font_size.1,
             (col + layout.col_offset) as f32 * self.font_size.0,
         );
@@ -634,9 +732,9 @@ impl GraphicsContext {
 
         if restrict {
             restricted_layout.num_rows =
-                (self.window_size.1 / self.font_size.1).ceil() as usize / 2;
+                ((self.window_size.1 / self.font_size.1).ceil() as usize / 2).min(layout.num_rows);
             restricted_layout.num_cols =
-                (self.window_size.0 / self.font_size.0).ceil() as usize / 2;
+                ((self.window_size.0 / self.font_size.0).ceil() as usize / 2).min(layout.num_cols);
         }
 
         let (width, height) = self.get_text_width_height(
@@ -651,10 +749,8 @@ impl GraphicsContext {
             ((height / self.font_size.1 as f64).round() as usize).min(restricted_layout.num_rows),
         );
 
-        if row_offset + (height as f32 * self.font_size.1) > self.window_size.1 {
-            row_offset -=
-                (height as f32 * self.font_size.1) + self.font_size.1 * 0.5 + self.font_size.1;
-        }
+        let (row_offset, col_offset) =
+            self.place_popup(layout, row_offset, col_offset, (width, height), false);
 
         unsafe {
             let outer_brush = self
@@ -746,6 +842,13 @@ impl GraphicsContext {
         unsafe {
             self.render_target.PopAxisAlignedClip();
         }
+
+        PopupBounds {
+            left: col_offset as f64,
+            top: row_offset as f64,
+            right: (col_offset + self.font_size.0 * width as f32 + self.font_size.1 * 0.5) as f64,
+            bottom: (row_offset + self.font_size.1 * height as f32 + self.font_size.1 * 0.5) as f64,
+        }
     }
 
     pub fn draw_popup_above(
@@ -762,7 +865,7 @@ impl GraphicsContext {
     ) {
         self.set_word_wrapping(true);
 
-        let (mut row_offset, col_offset) = (
+        let (row_offset, col_offset) = (
             (row + layout.row_offset) as f32 * self.font_size.1,
             (col + layout.col_offset) as f32 * self.font_size.0,
         );
@@ -771,9 +874,9 @@ impl GraphicsContext {
 
         if restrict {
             restricted_layout.num_rows =
-                (self.window_size.1 / self.font_size.1).ceil() as usize / 2;
+                ((self.window_size.1 / self.font_size.1).ceil() as usize / 2).min(layout.num_rows);
             restricted_layout.num_cols =
-                (self.window_size.0 / self.font_size.0).ceil() as usize / 2;
+                ((self.window_size.0 / self.font_size.0).ceil() as usize / 2).min(layout.num_cols);
         }
 
         let (width, height) = self.get_text_width_height(
@@ -788,10 +891,8 @@ impl GraphicsContext {
             ((height / self.font_size.1 as f64).round() as usize).min(restricted_layout.num_rows),
         );
 
-        if row_offset - (height as f32 * self.font_size.1) > 0.0 {
-            row_offset -=
-                (height as f32 * self.font_size.1) + self.font_size.1 * 0.5 + self.font_size.1;
-        }
+        let (row_offset, col_offset) =
+            self.place_popup(layout, row_offset, col_offset, (width, height), true);
 
         unsafe {
             self.render_target.PushAxisAlignedClip(
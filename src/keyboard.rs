@@ -0,0 +1,73 @@
+use winit::event::VirtualKeyCode;
+
+// Physical positions of the US layout punctuation keys (PC set 1 scancodes)
+#[cfg(not(target_os = "macos"))]
+const PUNCTUATION_SCANCODES: [(u32, VirtualKeyCode); 11] = [
+    (0x0C, VirtualKeyCode::Minus),
+    (0x0D, VirtualKeyCode::Equals),
+    (0x1A, VirtualKeyCode::LBracket),
+    (0x1B, VirtualKeyCode::RBracket),
+    (0x27, VirtualKeyCode::Semicolon),
+    (0x28, VirtualKeyCode::Apostrophe),
+    (0x29, VirtualKeyCode::Grave),
+    (0x2B, VirtualKeyCode::Backslash),
+    (0x33, VirtualKeyCode::Comma),
+    (0x34, VirtualKeyCode::Period),
+    (0x35, VirtualKeyCode::Slash),
+];
+
+// Physical positions of the US layout punctuation keys (macOS virtual key codes)
+#[cfg(target_os = "macos")]
+const PUNCTUATION_SCANCODES: [(u32, VirtualKeyCode); 11] = [
+    (0x1B, VirtualKeyCode::Minus),
+    (0x18, VirtualKeyCode::Equals),
+    (0x21, VirtualKeyCode::LBracket),
+    (0x1E, VirtualKeyCode::RBracket),
+    (0x29, VirtualKeyCode::Semicolon),
+    (0x27, VirtualKeyCode::Apostrophe),
+    (0x32, VirtualKeyCode::Grave),
+    (0x2A, VirtualKeyCode::Backslash),
+    (0x2B, VirtualKeyCode::Comma),
+    (0x2F, VirtualKeyCode::Period),
+    (0x2C, VirtualKeyCode::Slash),
+];
+
+// Letters, digits and named keys are taken from the active layout so that e.g. Ctrl + Z
+// follows the key labeled Z. Punctuation moves around (or disappears) between layouts,
+// so bindings such as Ctrl + / are resolved by the physical key position instead.
+pub fn resolve_key_code(
+    virtual_keycode: Option<VirtualKeyCode>,
+    scancode: u32,
+) -> Option<VirtualKeyCode> {
+    match virtual_keycode {
+        Some(key_code) if !is_punctuation(key_code) => Some(key_code),
+        _ => PUNCTUATION_SCANCODES
+            .iter()
+            .find(|(position, _)| *position == scancode)
+            .map(|(_, key_code)| *key_code)
+            .or(virtual_keycode),
+    }
+}
+
+fn is_punctuation(key_code: VirtualKeyCode) -> bool {
+    matches!(
+        key_code,
+        VirtualKeyCode::Apostrophe
+            | VirtualKeyCode::Asterisk
+            | VirtualKeyCode::At
+            | VirtualKeyCode::Backslash
+            | VirtualKeyCode::Caret
+            | VirtualKeyCode::Colon
+            | VirtualKeyCode::Comma
+            | VirtualKeyCode::Equals
+            | VirtualKeyCode::Grave
+            | VirtualKeyCode::LBracket
+            | VirtualKeyCode::Minus
+            | VirtualKeyCode::OEM102
+            | VirtualKeyCode::Period
+            | VirtualKeyCode::Plus
+            | VirtualKeyCode::RBracket
+            | VirtualKeyCode::Semicolon
+            | VirtualKeyCode::Slash
+    )
+}
@@ -0,0 +1,305 @@
+use std::{fs, path::Path, sync::RwLock};
+
+use serde_json::Value;
+
+use crate::{
+    command::{BufferCommand, BufferMode, CutMotion},
+    config, log,
+    motion::CursorMotion,
+};
+
+// What keys are bound to: a motion or command of the buffer, written like its variant with the
+// arguments in parentheses (Down(5), ForwardToChar(;), CutMotion(w, Inside, true)), or a switch to
+// the Normal, Insert, Visual or VisualLine mode
+#[derive(Clone, Copy)]
+pub enum KeymapAction {
+    Motion(CursorMotion<'static>),
+    Command(BufferCommand),
+    Mode(BufferMode),
+}
+
+pub enum KeymapMatch {
+    Complete(Vec<KeymapAction>),
+    Prefix,
+    None,
+}
+
+// A keymap.toml with [normal], [visual] and [insert] sections of "KEYS" = "ACTION" or
+// "KEYS" = ["ACTION", ...] bindings, the actions run one after another and [] binds to nothing
+#[derive(Default)]
+struct Keymap {
+    normal: Vec<(String, Vec<KeymapAction>)>,
+    visual: Vec<(String, Vec<KeymapAction>)>,
+    insert: Vec<(String, Vec<KeymapAction>)>,
+}
+
+static KEYMAP: RwLock<Keymap> = RwLock::new(Keymap {
+    normal: Vec::new(),
+    visual: Vec::new(),
+    insert: Vec::new(),
+});
+
+// The user's ~/.nimble/keymap.toml is extended and overridden by the .nimble/keymap.toml of the
+// workspace
+pub fn load(workspace_path: Option<&str>) {
+    let mut keymap = Keymap::default();
+    for directory in config::config_directories(workspace_path) {
+        keymap.load(&directory.join("keymap.toml"));
+    }
+    *KEYMAP.write().unwrap() = keymap;
+}
+
+pub fn load_file(path: &Path) {
+    let mut keymap = Keymap::default();
+    keymap.load(path);
+    *KEYMAP.write().unwrap() = keymap;
+}
+
+pub fn lookup(mode: BufferMode, keys: &str) -> KeymapMatch {
+    let keymap = KEYMAP.read().unwrap();
+    let mappings = match mode {
+        BufferMode::Normal => &keymap.normal,
+        BufferMode::Visual | BufferMode::VisualLine => &keymap.visual,
+        BufferMode::Insert => &keymap.insert,
+    };
+
+    if let Some((_, actions)) = mappings.iter().find(|(lhs, _)| lhs == keys) {
+        KeymapMatch::Complete(actions.clone())
+    } else if mappings.iter().any(|(lhs, _)| lhs.starts_with(keys)) {
+        KeymapMatch::Prefix
+    } else {
+        KeymapMatch::None
+    }
+}
+
+impl Keymap {
+    fn load(&mut self, path: &Path) {
+        let Ok(text) = fs::read_to_string(path) else {
+            return;
+        };
+        let mut section = String::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = config::strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = name.trim().to_string();
+                continue;
+            }
+
+            let mappings = match section.as_str() {
+                "normal" => &mut self.normal,
+                "visual" => &mut self.visual,
+                "insert" => &mut self.insert,
+                _ => {
+                    log::warning(format!(
+                        "{}:{}: unknown section [{}]",
+                        path.display(),
+                        i + 1,
+                        section
+                    ));
+                    continue;
+                }
+            };
+
+            // Only printable characters reach the command input of a buffer
+            let Some((keys, value)) = parse_mapping(line)
+                .filter(|(keys, _)| !keys.is_empty() && keys.bytes().all(|c| c.is_ascii_graphic()))
+            else {
+                log::warning(format!(
+                    "{}:{}: expected \"KEYS\" = \"ACTION\" or [\"ACTION\", ...]",
+                    path.display(),
+                    i + 1
+                ));
+                continue;
+            };
+
+            let names = match value {
+                Value::String(name) => vec![Value::String(name)],
+                Value::Array(names) => names,
+                _ => vec![value],
+            };
+            let actions = names
+                .iter()
+                .map(|name| {
+                    let action = name.as_str().and_then(parse_action);
+                    if action.is_none() {
+                        log::warning(format!(
+                            "{}:{}: unknown action {}",
+                            path.display(),
+                            i + 1,
+                            name
+                        ));
+                    }
+                    action
+                })
+                .collect::<Option<Vec<KeymapAction>>>();
+            let Some(actions) = actions else {
+                continue;
+            };
+
+            mappings.retain(|(lhs, _)| *lhs != keys);
+            mappings.push((keys, actions));
+        }
+    }
+}
+
+fn parse_mapping(line: &str) -> Option<(String, Value)> {
+    let (keys, rest) = match line.strip_prefix('"') {
+        Some(line) => {
+            let end = line.find('"')?;
+            (&line[..end], &line[end + 1..])
+        }
+        None => {
+            let (keys, _) = line.split_once('=')?;
+            (keys.trim(), &line[keys.len()..])
+        }
+    };
+
+    let value = config::parse_value(rest.trim().strip_prefix('=')?.trim())?;
+    Some((keys.to_string(), value))
+}
+
+fn parse_action(text: &str) -> Option<KeymapAction> {
+    let (name, arguments) = match text.trim().strip_suffix(')') {
+        Some(text) => {
+            let (name, arguments) = text.split_once('(')?;
+            (name.trim(), Some(arguments))
+        }
+        None => (text.trim(), None),
+    };
+
+    // Single characters are taken as they are, so that spaces and commas can be passed as well
+    let char_argument = || {
+        let argument = arguments?;
+        let argument = if argument.len() == 1 {
+            argument
+        } else {
+            argument.trim()
+        };
+        (argument.len() == 1).then(|| argument.as_bytes()[0])
+    };
+    let count_argument = || arguments?.trim().parse::<usize>().ok();
+
+    let action = match (name, arguments) {
+        ("Normal", None) => KeymapAction::Mode(BufferMode::Normal),
+        ("Insert", None) => KeymapAction::Mode(BufferMode::Insert),
+        ("Visual", None) => KeymapAction::Mode(BufferMode::Visual),
+        ("VisualLine", None) => KeymapAction::Mode(BufferMode::VisualLine),
+
+        ("Forward", _) => KeymapAction::Motion(CursorMotion::Forward(count_argument()?)),
+        ("Backward", _) => KeymapAction::Motion(CursorMotion::Backward(count_argument()?)),
+        ("BackwardOnceWrapping", None) => KeymapAction::Motion(CursorMotion::BackwardOnceWrapping),
+        ("Up", _) => KeymapAction::Motion(CursorMotion::Up(count_argument()?)),
+        ("Down", _) => KeymapAction::Motion(CursorMotion::Down(count_argument()?)),
+        ("ForwardByWord", None) => KeymapAction::Motion(CursorMotion::ForwardByWord),
+        ("BackwardByWord", None) => KeymapAction::Motion(CursorMotion::BackwardByWord),
+        ("ToStartOfLine", None) => KeymapAction::Motion(CursorMotion::ToStartOfLine),
+        ("ToEndOfLine", None) => KeymapAction::Motion(CursorMotion::ToEndOfLine),
+        ("ToStartOfFile", None) => KeymapAction::Motion(CursorMotion::ToStartOfFile),
+        ("ToEndOfFile", None) => KeymapAction::Motion(CursorMotion::ToEndOfFile),
+        ("ToFirstNonBlankChar", None) => KeymapAction::Motion(CursorMotion::ToFirstNonBlankChar),
+        ("ToEndOfWord", None) => KeymapAction::Motion(CursorMotion::ToEndOfWord),
+        ("ToNextParagraph", None) => KeymapAction::Motion(CursorMotion::ToNextParagraph),
+        ("ToPreviousParagraph", None) => KeymapAction::Motion(CursorMotion::ToPreviousParagraph),
+        ("ForwardToChar", _) => KeymapAction::Motion(CursorMotion::ForwardToChar(char_argument()?)),
+        ("BackwardToChar", _) => {
+            KeymapAction::Motion(CursorMotion::BackwardToChar(char_argument()?))
+        }
+        ("ForwardUntilChar", _) => {
+            KeymapAction::Motion(CursorMotion::ForwardUntilChar(char_argument()?))
+        }
+        ("BackwardUntilChar", _) => {
+            KeymapAction::Motion(CursorMotion::BackwardUntilChar(char_argument()?))
+        }
+        ("ExtendSelection", None) => KeymapAction::Motion(CursorMotion::ExtendSelection),
+        ("ExtendSelectionInside", _) => {
+            KeymapAction::Motion(CursorMotion::ExtendSelectionInside(char_argument()?))
+        }
+        ("GotoLine", _) => KeymapAction::Motion(CursorMotion::GotoLine(count_argument()?)),
+
+        ("InsertCursorAbove", None) => KeymapAction::Command(BufferCommand::InsertCursorAbove),
+        ("InsertCursorBelow", None) => KeymapAction::Command(BufferCommand::InsertCursorBelow),
+        ("ReplaceChar", _) => KeymapAction::Command(BufferCommand::ReplaceChar(char_argument()?)),
+        ("Surround", _) => KeymapAction::Command(BufferCommand::Surround(char_argument()?)),
+        ("CutSelection", None) => KeymapAction::Command(BufferCommand::CutSelection),
+        ("CutSingleSelection", None) => KeymapAction::Command(BufferCommand::CutSingleSelection),
+        ("CutMotion", Some(arguments)) => {
+            // The character comes first and may be a comma itself
+            let c = arguments
+                .trim_start()
+                .as_bytes()
+                .first()
+                .copied()
+                .filter(u8::is_ascii)?;
+            let mut rest = arguments.trim_start()[1..].split(',').map(str::trim);
+            let (None, Some(kind), Some(change), None) = (
+                rest.next().filter(|s| !s.is_empty()),
+                rest.next(),
+                rest.next(),
+                rest.next(),
+            ) else {
+                return None;
+            };
+            let kind = match kind {
+                "Inside" => CutMotion::Inside,
+                "ForwardUntil" => CutMotion::ForwardUntil,
+                "ForwardTo" => CutMotion::ForwardTo,
+                "BackwardUntil" => CutMotion::BackwardUntil,
+                "BackwardTo" => CutMotion::BackwardTo,
+                _ => return None,
+            };
+            let change = change.parse::<bool>().ok()?;
+            KeymapAction::Command(BufferCommand::CutMotion(c, kind, change))
+        }
+        ("InsertChar", _) => KeymapAction::Command(BufferCommand::InsertChar(char_argument()?)),
+        ("InsertUnicode", Some(argument)) => {
+            let mut chars = argument.chars();
+            let (Some(c), None) = (chars.next(), chars.next()) else {
+                return None;
+            };
+            KeymapAction::Command(BufferCommand::InsertUnicode(c))
+        }
+        ("ExpandAbbreviation", None) => KeymapAction::Command(BufferCommand::ExpandAbbreviation),
+        ("InsertRegister", _) => {
+            let register = char_argument()?;
+            if !matches!(register, b'"' | b'+' | b'*' | b'-') {
+                return None;
+            }
+            KeymapAction::Command(BufferCommand::InsertRegister(register))
+        }
+        ("InsertNewLine", None) => KeymapAction::Command(BufferCommand::InsertNewLine),
+        ("IndentLine", None) => KeymapAction::Command(BufferCommand::IndentLine),
+        ("UnindentLine", None) => KeymapAction::Command(BufferCommand::UnindentLine),
+        ("ToggleComment", None) => KeymapAction::Command(BufferCommand::ToggleComment),
+        ("DeleteCharBack", None) => KeymapAction::Command(BufferCommand::DeleteCharBack),
+        ("DeleteWordBack", None) => KeymapAction::Command(BufferCommand::DeleteWordBack),
+        ("DeleteWordFront", None) => KeymapAction::Command(BufferCommand::DeleteWordFront),
+        ("DeleteToLineStart", None) => KeymapAction::Command(BufferCommand::DeleteToLineStart),
+        ("Undo", None) => KeymapAction::Command(BufferCommand::Undo),
+        ("Redo", None) => KeymapAction::Command(BufferCommand::Redo),
+        ("StartCompletion", None) => KeymapAction::Command(BufferCommand::StartCompletion),
+        ("Complete", None) => KeymapAction::Command(BufferCommand::Complete),
+        ("CopySelection", None) => KeymapAction::Command(BufferCommand::CopySelection),
+        ("CopyLine", None) => KeymapAction::Command(BufferCommand::CopyLine),
+        ("PasteSelection", None) => KeymapAction::Command(BufferCommand::PasteSelection),
+        ("PasteSelectionReindented", None) => {
+            KeymapAction::Command(BufferCommand::PasteSelectionReindented)
+        }
+        ("PasteCursorSelection", None) => {
+            KeymapAction::Command(BufferCommand::PasteCursorSelection)
+        }
+        ("DuplicateSelection", None) => KeymapAction::Command(BufferCommand::DuplicateSelection),
+        ("GotoDefinition", None) => KeymapAction::Command(BufferCommand::GotoDefinition),
+        ("GotoImplementation", None) => KeymapAction::Command(BufferCommand::GotoImplementation),
+        ("FindReferences", None) => KeymapAction::Command(BufferCommand::FindReferences),
+        _ => return None,
+    };
+    Some(action)
+}
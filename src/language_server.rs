@@ -15,6 +15,7 @@ use std::{
         Arc, Mutex,
     },
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use bstr::ByteSlice;
@@ -26,16 +27,48 @@ use windows::Win32::{
 };
 
 use crate::{
-    editor::Workspace,
     language_server_types::{
-        ClientCapabilities, CompletionList, Diagnostic, GeneralClientCapabilities,
-        HoverClientCapabilities, InitializeParams, InitializeResult, InitializedParams,
-        MarkdownClientCapabilities, Notification, PublishDiagnosticParams, Request, ServerMessage,
-        SignatureHelp, TextDocumentClientCapabilities,
+        CancelParams, ClientCapabilities, CodeActionClientCapabilities, CodeActionKindValueSet,
+        CodeActionLiteralSupport, CompletionClientCapabilities, CompletionItemClientCapabilities,
+        CompletionItemTagSupport, CompletionList, ConfigurationParams, Diagnostic,
+        DidChangeConfigurationParams, DidChangeTextDocumentParams, FileOperationClientCapabilities,
+        GeneralClientCapabilities, HoverClientCapabilities, InitializeParams, InitializeResult,
+        InitializedParams, Location, LocationType, MarkdownClientCapabilities, Notification,
+        ProgressParams, PublishDiagnosticParams, Request, Response, ResponseError, ServerMessage,
+        SignatureHelp, TextDocumentClientCapabilities, WindowClientCapabilities,
+        WorkspaceClientCapabilities, COMPLETION_ITEM_TAG_DEPRECATED,
     },
     language_support::Language,
+    log,
 };
 
+// Requests without a response are cancelled after the timeout so that a hung server never leaves
+// completions or other requests waiting forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Requests failing with ContentModified or ServerCancelled are retried with exponential backoff
+const TRANSIENT_ERROR_CODES: [i32; 2] = [-32801, -32802];
+const MAX_RETRIES: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+// Server requests that only need an acknowledgement
+const ACKNOWLEDGED_REQUESTS: [&str; 4] = [
+    "client/registerCapability",
+    "client/unregisterCapability",
+    "window/workDoneProgress/create",
+    "window/showMessageRequest",
+];
+const METHOD_NOT_FOUND: i32 = -32601;
+
+struct PendingRequest {
+    // The id handed out by send_request, retries are sent with new ids but keep reporting this one
+    id: i32,
+    method: &'static str,
+    params: Value,
+    sent: Instant,
+    attempt: u32,
+}
+
 pub struct ServerResponse {
     pub method: &'static str,
     pub id: i32,
@@ -47,23 +80,70 @@ pub struct ServerNotification {
     pub value: Option<Value>,
 }
 
+// Requests from the server that the editor has to answer with send_response
+pub struct ServerRequest {
+    pub id: Value,
+    pub method: String,
+    pub value: Option<Value>,
+}
+
+pub enum ServerStatus {
+    Starting,
+    Ready,
+    // The title of the oldest work in progress, e.g. indexing
+    Busy(String),
+    NotResponding,
+    Terminated,
+}
+
+// Work the server reported through $/progress that hasn't ended yet
+struct Progress {
+    token: String,
+    title: String,
+    percentage: Option<u32>,
+}
+
 pub struct LanguageServer {
     language: &'static Language,
     sender: Sender<String>,
-    requests: HashMap<i32, &'static str>,
+    requests: HashMap<i32, PendingRequest>,
+    retries: Vec<(Instant, PendingRequest)>,
     request_id: i32,
+    not_responding: bool,
     responses: Arc<Mutex<VecDeque<ServerMessage>>>,
     initialized: bool,
     terminated: bool,
+    progress: Vec<Progress>,
     pub saved_completions: HashMap<i32, CompletionList>,
     pub saved_signature_helps: HashMap<i32, SignatureHelp>,
+    pub saved_references: HashMap<i32, Vec<Location>>,
     pub saved_diagnostics: HashMap<String, Vec<Diagnostic>>,
     pub trigger_characters: Vec<u8>,
     pub signature_help_trigger_characters: Vec<u8>,
+    // Servers that don't negotiate UTF-8 count columns in UTF-16 code units
+    pub utf16_positions: bool,
+    // Settings from the config files, also sent as initialization options
+    settings: Option<Value>,
+    // Edits made within the debounce of each other go out as a single didChange
+    pub change_debounce: Duration,
+    pending_changes: Vec<DidChangeTextDocumentParams>,
+    last_change: Option<Instant>,
 }
 
 impl LanguageServer {
-    pub fn new(language: &'static Language, workspace: &Workspace) -> Option<Self> {
+    pub fn new(
+        language: &'static Language,
+        root_uri: String,
+        settings: Option<Value>,
+    ) -> Option<Self> {
+        let Some(lsp_executable) = language.lsp_executable else {
+            log::info(format!(
+                "No language server configured for {}",
+                language.identifier
+            ));
+            return None;
+        };
+
         let (process_id, stdin, stdout) = if cfg!(target_os = "windows") {
             let mut stdin_read = HANDLE::default();
             let mut stdin_write = HANDLE::default();
@@ -90,12 +170,18 @@ impl LanguageServer {
                     0,
                 );
 
-                let process = Command::new(language.lsp_executable?)
+                let process = Command::new(lsp_executable)
                     .stdin(Stdio::from_raw_handle(stdin_read.0 as *mut _))
                     .stdout(Stdio::from_raw_handle(stdout_write.0 as *mut _))
                     .stderr(Stdio::null())
                     .creation_flags(CREATE_NO_WINDOW.0)
                     .spawn()
+                    .map_err(|error| {
+                        log::error(format!(
+                            "Failed to start language server {}: {}",
+                            lsp_executable, error
+                        ))
+                    })
                     .ok()?;
                 (
                     process.id(),
@@ -104,11 +190,17 @@ impl LanguageServer {
                 )
             }
         } else {
-            let mut process = Command::new(language.lsp_executable?)
+            let mut process = Command::new(lsp_executable)
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
                 .spawn()
+                .map_err(|error| {
+                    log::error(format!(
+                        "Failed to start language server {}: {}",
+                        lsp_executable, error
+                    ))
+                })
                 .ok()?;
             (
                 process.id(),
@@ -117,62 +209,196 @@ impl LanguageServer {
             )
         };
 
+        log::info(format!(
+            "Started language server {} for {}",
+            lsp_executable, root_uri
+        ));
+
         let responses = Arc::new(Mutex::new(VecDeque::new()));
 
         let (mut sender, receiver) = channel();
         start_reader_thread(stdout, language, Arc::clone(&responses));
         start_writer_thread(stdin, receiver);
 
-        send_request(
-            &mut sender,
-            0,
-            "initialize",
-            InitializeParams {
-                process_id,
-                root_uri: Some(workspace.uri.to_string()),
-                capabilities: ClientCapabilities {
-                    general: GeneralClientCapabilities {
-                        position_encodings: vec!["utf-8".to_string()],
-                        markdown: MarkdownClientCapabilities {
-                            parser: String::from("Python-Markdown"),
-                            version: String::from("3.2.2"),
-                        },
+        let initialize_params = serde_json::to_value(InitializeParams {
+            process_id,
+            root_uri: Some(root_uri.clone()),
+            initialization_options: settings.clone(),
+            capabilities: ClientCapabilities {
+                general: GeneralClientCapabilities {
+                    position_encodings: vec!["utf-8".to_string(), "utf-16".to_string()],
+                    markdown: MarkdownClientCapabilities {
+                        parser: String::from("Python-Markdown"),
+                        version: String::from("3.2.2"),
                     },
-                    text_document: TextDocumentClientCapabilities {
-                        hover: HoverClientCapabilities {
-                            content_format: vec![
+                },
+                text_document: TextDocumentClientCapabilities {
+                    hover: HoverClientCapabilities {
+                        content_format: vec![String::from("markdown"), String::from("plaintext")],
+                    },
+                    completion: CompletionClientCapabilities {
+                        completion_item: CompletionItemClientCapabilities {
+                            documentation_format: vec![
                                 String::from("markdown"),
                                 String::from("plaintext"),
                             ],
+                            deprecated_support: true,
+                            tag_support: CompletionItemTagSupport {
+                                value_set: vec![COMPLETION_ITEM_TAG_DEPRECATED],
+                            },
+                        },
+                    },
+                    code_action: CodeActionClientCapabilities {
+                        code_action_literal_support: CodeActionLiteralSupport {
+                            code_action_kind: CodeActionKindValueSet {
+                                value_set: vec![
+                                    String::from("source.organizeImports"),
+                                    String::from("source.fixAll"),
+                                ],
+                            },
                         },
                     },
                 },
+                workspace: WorkspaceClientCapabilities {
+                    apply_edit: true,
+                    configuration: true,
+                    file_operations: FileOperationClientCapabilities {
+                        will_rename: true,
+                        did_rename: true,
+                    },
+                },
+                window: WindowClientCapabilities {
+                    work_done_progress: true,
+                },
+            },
+        })
+        .unwrap();
+        send_request(&mut sender, 0, "initialize", &initialize_params).ok()?;
+        let mut server = Self::with_sender(language, sender, responses, settings);
+        server.requests.insert(
+            0,
+            PendingRequest {
+                id: 0,
+                method: "initialize",
+                params: initialize_params,
+                sent: Instant::now(),
+                attempt: 0,
             },
-        )
-        .ok()?;
-        let mut requests = HashMap::new();
-        requests.insert(0, "initialize");
+        );
+        Some(server)
+    }
 
-        Some(Self {
+    // A server without a process that hands everything the editor sends to the receiver, used by
+    // the script runner to check the notifications of a buffer
+    pub fn recorder(language: &'static Language) -> (Self, Receiver<String>) {
+        let (sender, receiver) = channel();
+        let responses = Arc::new(Mutex::new(VecDeque::new()));
+        let mut server = Self::with_sender(language, sender, responses, None);
+        server.initialized = true;
+        (server, receiver)
+    }
+
+    fn with_sender(
+        language: &'static Language,
+        sender: Sender<String>,
+        responses: Arc<Mutex<VecDeque<ServerMessage>>>,
+        settings: Option<Value>,
+    ) -> Self {
+        Self {
             language,
             sender,
-            requests,
+            requests: HashMap::new(),
+            retries: vec![],
             request_id: 1,
+            not_responding: false,
             responses,
             initialized: false,
             terminated: false,
+            progress: vec![],
             saved_completions: HashMap::new(),
             saved_signature_helps: HashMap::new(),
+            saved_references: HashMap::new(),
             saved_diagnostics: HashMap::new(),
             trigger_characters: Vec::new(),
             signature_help_trigger_characters: Vec::new(),
-        })
+            utf16_positions: false,
+            settings,
+            change_debounce: Duration::ZERO,
+            pending_changes: vec![],
+            last_change: None,
+        }
+    }
+
+    // Servers are told about files they aren't started with through the configuration, which they
+    // read back with workspace/configuration
+    pub fn add_detached_file(&mut self, path: &str) {
+        let settings = self.settings.get_or_insert_with(|| serde_json::json!({}));
+        let Some(detached_files) = settings.as_object_mut().map(|settings| {
+            settings
+                .entry("detachedFiles")
+                .or_insert_with(|| serde_json::json!([]))
+        }) else {
+            return;
+        };
+        let Some(detached_files) = detached_files.as_array_mut() else {
+            return;
+        };
+        if detached_files.iter().any(|file| file == path) {
+            return;
+        }
+        detached_files.push(Value::from(path));
+
+        let settings = settings.clone();
+        self.send_notification(
+            "workspace/didChangeConfiguration",
+            DidChangeConfigurationParams { settings },
+        );
     }
 
-    pub fn save_diagnostics(&mut self, value: serde_json::Value) {
+    pub fn save_diagnostics(&mut self, value: serde_json::Value) -> String {
         let params = serde_json::from_value::<PublishDiagnosticParams>(value).unwrap();
+        let uri = params.uri.to_lowercase();
         self.saved_diagnostics
-            .insert(params.uri.to_lowercase(), params.diagnostics);
+            .insert(uri.clone(), params.diagnostics);
+        uri
+    }
+
+    pub fn save_progress(&mut self, value: serde_json::Value) {
+        let Ok(params) = serde_json::from_value::<ProgressParams>(value) else {
+            return;
+        };
+        let token = params.token.to_string();
+        match params.value.kind.as_str() {
+            "begin" => self.progress.push(Progress {
+                token,
+                title: params.value.title.unwrap_or_default(),
+                percentage: params.value.percentage,
+            }),
+            "report" => {
+                if let Some(progress) = self.progress.iter_mut().find(|p| p.token == token) {
+                    progress.percentage = params.value.percentage.or(progress.percentage);
+                }
+            }
+            "end" => self.progress.retain(|progress| progress.token != token),
+            _ => (),
+        }
+    }
+
+    pub fn status(&self) -> ServerStatus {
+        if self.terminated {
+            ServerStatus::Terminated
+        } else if self.not_responding {
+            ServerStatus::NotResponding
+        } else if !self.initialized {
+            ServerStatus::Starting
+        } else if let Some(progress) = self.progress.first() {
+            ServerStatus::Busy(match progress.percentage {
+                Some(percentage) => format!("{} {}%", progress.title, percentage),
+                None => progress.title.clone(),
+            })
+        } else {
+            ServerStatus::Ready
+        }
     }
 
     pub fn save_completions(&mut self, request_id: i32, value: serde_json::Value) {
@@ -182,6 +408,16 @@ impl LanguageServer {
         );
     }
 
+    // A null result means that there are no references
+    pub fn save_references(&mut self, request_id: i32, value: serde_json::Value) {
+        let locations = match serde_json::from_value::<LocationType>(value) {
+            Ok(LocationType::Location(location)) => vec![location],
+            Ok(LocationType::LocationArray(locations)) => locations,
+            Err(_) => vec![],
+        };
+        self.saved_references.insert(request_id, locations);
+    }
+
     pub fn save_signature_help(&mut self, request_id: i32, value: serde_json::Value) {
         let signature_help = serde_json::from_value::<SignatureHelp>(value).unwrap();
         self.saved_signature_helps
@@ -194,40 +430,288 @@ impl LanguageServer {
         params: T,
     ) -> Option<i32> {
         if self.initialized {
-            match send_request(&mut self.sender, self.request_id, method, params) {
-                Ok(()) => {
-                    self.requests.insert(self.request_id, method);
-                    self.request_id += 1;
-                    return Some(self.request_id - 1);
-                }
-                Err(_) => self.terminated = true,
+            // Requests must see the latest version of the document
+            self.flush_changes();
+            let params = serde_json::to_value(params).ok()?;
+            let id = self.request_id;
+            if self.send_pending_request(PendingRequest {
+                id,
+                method,
+                params,
+                sent: Instant::now(),
+                attempt: 0,
+            }) {
+                return Some(id);
             }
         }
         None
     }
 
+    fn send_pending_request(&mut self, mut request: PendingRequest) -> bool {
+        match send_request(
+            &mut self.sender,
+            self.request_id,
+            request.method,
+            &request.params,
+        ) {
+            Ok(()) => {
+                request.sent = Instant::now();
+                self.requests.insert(self.request_id, request);
+                self.request_id += 1;
+                true
+            }
+            Err(_) => {
+                log::error(format!(
+                    "Language server for {} terminated unexpectedly",
+                    self.language.identifier
+                ));
+                self.terminated = true;
+                false
+            }
+        }
+    }
+
+    // The response of a cancelled request is dropped, also while it is waiting to be retried
+    pub fn cancel_request(&mut self, id: i32) {
+        self.retries.retain(|(_, request)| request.id != id);
+        let wire_id = self
+            .requests
+            .iter()
+            .find(|(_, request)| request.id == id)
+            .map(|(wire_id, _)| *wire_id);
+        if let Some(wire_id) = wire_id {
+            self.requests.remove(&wire_id);
+            self.send_notification("$/cancelRequest", CancelParams { id: wire_id });
+        }
+    }
+
+    fn send_due_retries(&mut self) {
+        let now = Instant::now();
+        let (due, waiting) = std::mem::take(&mut self.retries)
+            .into_iter()
+            .partition(|(time, _)| *time <= now);
+        self.retries = waiting;
+        for (_, request) in due {
+            self.send_pending_request(request);
+        }
+    }
+
+    fn cancel_timed_out_requests(&mut self, server_responses: &mut Vec<ServerResponse>) {
+        let timed_out: Vec<i32> = self
+            .requests
+            .iter()
+            .filter(|(_, request)| {
+                request.method != "initialize" && request.sent.elapsed() > REQUEST_TIMEOUT
+            })
+            .map(|(wire_id, _)| *wire_id)
+            .collect();
+
+        for wire_id in timed_out {
+            let request = self.requests.remove(&wire_id).unwrap();
+            self.send_notification("$/cancelRequest", CancelParams { id: wire_id });
+
+            // Empty results let pending completions and signature helps move on
+            match request.method {
+                "textDocument/completion" => {
+                    self.saved_completions.insert(
+                        request.id,
+                        CompletionList {
+                            is_incomplete: false,
+                            items: vec![],
+                        },
+                    );
+                }
+                "textDocument/signatureHelp" => {
+                    self.saved_signature_helps.insert(
+                        request.id,
+                        SignatureHelp {
+                            signatures: vec![],
+                            active_signature: None,
+                            active_parameter: None,
+                        },
+                    );
+                }
+                _ => (),
+            }
+            server_responses.push(ServerResponse {
+                method: request.method,
+                id: request.id,
+                value: None,
+            });
+
+            if !self.not_responding {
+                log::warning(format!(
+                    "Language server for {} is not responding ({} timed out)",
+                    self.language.identifier, request.method
+                ));
+                self.not_responding = true;
+            }
+        }
+    }
+
+    pub fn send_response<T: serde::Serialize>(&mut self, id: Value, result: T) {
+        let response = Response {
+            jsonrpc: "2.0",
+            id,
+            result: Some(serde_json::to_value(result).unwrap()),
+            error: None,
+        };
+        if send_response(&mut self.sender, response).is_err() {
+            self.terminated = true;
+        }
+    }
+
     pub fn send_notification<T: serde::Serialize>(&mut self, method: &'static str, params: T) {
+        self.flush_changes();
+        self.notify(method, params);
+    }
+
+    pub fn send_change(&mut self, params: DidChangeTextDocumentParams) {
+        if self.change_debounce.is_zero() {
+            self.notify("textDocument/didChange", params);
+            return;
+        }
+
+        // Consecutive changes to the same document are applied in order, so the events can be
+        // concatenated and sent with the newest version
+        match self.pending_changes.last_mut() {
+            Some(pending) if pending.text_document.uri == params.text_document.uri => {
+                pending.text_document.version = params.text_document.version;
+                pending.content_changes.extend(params.content_changes);
+            }
+            _ => self.pending_changes.push(params),
+        }
+        self.last_change = Some(Instant::now());
+    }
+
+    pub fn update_changes(&mut self) {
+        if self
+            .last_change
+            .is_some_and(|last_change| last_change.elapsed() >= self.change_debounce)
+        {
+            self.flush_changes();
+        }
+    }
+
+    fn flush_changes(&mut self) {
+        self.last_change = None;
+        for params in std::mem::take(&mut self.pending_changes) {
+            self.notify("textDocument/didChange", params);
+        }
+    }
+
+    fn notify<T: serde::Serialize>(&mut self, method: &'static str, params: T) {
         if self.initialized {
             match send_notification(&mut self.sender, method, params) {
                 Ok(()) => (),
-                Err(_) => self.terminated = true,
+                Err(_) => {
+                    log::error(format!(
+                        "Language server for {} terminated unexpectedly",
+                        self.language.identifier
+                    ));
+                    self.terminated = true;
+                }
             }
         }
     }
 
-    pub fn handle_responses(&mut self) -> Option<(Vec<ServerResponse>, Vec<ServerNotification>)> {
+    pub fn handle_responses(
+        &mut self,
+    ) -> Option<(
+        Vec<ServerResponse>,
+        Vec<ServerNotification>,
+        Vec<ServerRequest>,
+    )> {
         if self.terminated {
             return None;
         }
 
         let mut server_responses = vec![];
         let mut server_notifications = vec![];
+        let mut server_requests = vec![];
         if let Ok(ref mut responses) = self.responses.try_lock() {
             while let Some(message) = responses.pop_front() {
                 match message {
-                    ServerMessage::Response { id, result, .. } => {
-                        match self.requests.get(&id) {
-                            Some(&"initialize") => {
+                    ServerMessage::Request {
+                        id, method, params, ..
+                    } => match method.as_str() {
+                        "workspace/applyEdit" => server_requests.push(ServerRequest {
+                            id,
+                            method,
+                            value: params,
+                        }),
+                        // The settings are the section of the server itself, so every item is
+                        // answered with all of them
+                        "workspace/configuration" => {
+                            let items = params
+                                .and_then(|params| {
+                                    serde_json::from_value::<ConfigurationParams>(params).ok()
+                                })
+                                .map_or(0, |params| params.items.len());
+                            let settings = self.settings.clone().unwrap_or(Value::Null);
+                            let response = Response {
+                                jsonrpc: "2.0",
+                                id,
+                                result: Some(Value::Array(vec![settings; items])),
+                                error: None,
+                            };
+                            send_response(&mut self.sender, response).ok()?;
+                        }
+                        method if ACKNOWLEDGED_REQUESTS.contains(&method) => {
+                            let response = Response {
+                                jsonrpc: "2.0",
+                                id,
+                                result: Some(Value::Null),
+                                error: None,
+                            };
+                            send_response(&mut self.sender, response).ok()?;
+                        }
+                        method => {
+                            let response = Response {
+                                jsonrpc: "2.0",
+                                id,
+                                result: None,
+                                error: Some(ResponseError {
+                                    code: METHOD_NOT_FOUND,
+                                    message: format!("Unhandled method {}", method),
+                                    data: None,
+                                }),
+                            };
+                            send_response(&mut self.sender, response).ok()?;
+                        }
+                    },
+                    ServerMessage::Response {
+                        id, result, error, ..
+                    } => {
+                        if self.not_responding {
+                            log::info(format!(
+                                "Language server for {} is responding again",
+                                self.language.identifier
+                            ));
+                            self.not_responding = false;
+                        }
+
+                        let Some(mut request) = self.requests.remove(&id) else {
+                            continue;
+                        };
+                        if let Some(error) = error.filter(|error| {
+                            TRANSIENT_ERROR_CODES.contains(&error.code)
+                                && request.method != "initialize"
+                        }) {
+                            if request.attempt < MAX_RETRIES {
+                                let backoff = RETRY_BACKOFF * 2u32.pow(request.attempt);
+                                request.attempt += 1;
+                                self.retries.push((Instant::now() + backoff, request));
+                                continue;
+                            }
+                            log::warning(format!(
+                                "{} failed after {} retries: {}",
+                                request.method, MAX_RETRIES, error.message
+                            ));
+                        }
+
+                        match request.method {
+                            "initialize" => {
                                 send_notification(
                                     &mut self.sender,
                                     "initialized",
@@ -239,6 +723,10 @@ impl LanguageServer {
                                     if let Ok(result) =
                                         serde_json::from_value::<InitializeResult>(result)
                                     {
+                                        self.utf16_positions =
+                                            result.capabilities.position_encoding.as_deref()
+                                                != Some("utf-8");
+
                                         if let Some(completion_provider) =
                                             result.capabilities.completion_provider
                                         {
@@ -268,20 +756,26 @@ impl LanguageServer {
                                 }
 
                                 self.initialized = true;
+                                if let Some(settings) = self.settings.clone() {
+                                    send_notification(
+                                        &mut self.sender,
+                                        "workspace/didChangeConfiguration",
+                                        DidChangeConfigurationParams { settings },
+                                    )
+                                    .ok()?;
+                                }
                                 server_responses.push(ServerResponse {
                                     method: "initialize",
                                     id,
                                     value: result,
                                 });
                             }
-                            Some(x) => server_responses.push(ServerResponse {
-                                method: x,
-                                id,
+                            method => server_responses.push(ServerResponse {
+                                method,
+                                id: request.id,
                                 value: result,
                             }),
-                            None => (),
                         }
-                        self.requests.remove(&id);
                     }
                     ServerMessage::Notification { method, params, .. } => server_notifications
                         .push(ServerNotification {
@@ -291,7 +785,10 @@ impl LanguageServer {
                 }
             }
         }
-        Some((server_responses, server_notifications))
+
+        self.send_due_retries();
+        self.cancel_timed_out_requests(&mut server_responses);
+        Some((server_responses, server_notifications, server_requests))
     }
 }
 
@@ -330,9 +827,12 @@ fn start_reader_thread(
                         {
                             let mut content = vec![0; content_length];
                             if reader.read_exact(&mut content).is_ok() {
-                                let message =
-                                    serde_json::from_slice::<ServerMessage>(&content).unwrap();
-                                responses.lock().unwrap().borrow_mut().push_back(message);
+                                // Skip malformed messages instead of taking the reader down
+                                if let Ok(message) =
+                                    serde_json::from_slice::<ServerMessage>(&content)
+                                {
+                                    responses.lock().unwrap().borrow_mut().push_back(message);
+                                }
                                 continue;
                             }
                         }
@@ -357,6 +857,13 @@ pub fn send_request<T: serde::Serialize>(
     sender.send(composed)
 }
 
+fn send_response(sender: &mut Sender<String>, response: Response) -> Result<(), SendError<String>> {
+    let message = serde_json::to_string(&response).unwrap();
+    let header = format!("Content-Length: {}\r\n\r\n", message.len());
+    let composed = header + message.as_str();
+    sender.send(composed)
+}
+
 fn send_notification<T: serde::Serialize>(
     sender: &mut Sender<String>,
     method: &'static str,
@@ -1,9 +1,18 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged, rename_all = "camelCase")]
 pub enum ServerMessage {
+    // Requests sent by the server, the id can be a number or a string
+    Request {
+        jsonrpc: String,
+        id: Value,
+        method: String,
+        params: Option<Value>,
+    },
     Response {
         jsonrpc: String,
         id: i32,
@@ -42,6 +51,19 @@ where
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub jsonrpc: &'static str,
+    pub id: Value,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ResponseError>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Notification<T: Serialize> {
@@ -76,6 +98,46 @@ pub struct GeneralClientCapabilities {
 #[serde(rename_all = "camelCase")]
 pub struct TextDocumentClientCapabilities {
     pub hover: HoverClientCapabilities,
+    pub completion: CompletionClientCapabilities,
+    pub code_action: CodeActionClientCapabilities,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionClientCapabilities {
+    pub completion_item: CompletionItemClientCapabilities,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionItemClientCapabilities {
+    pub documentation_format: Vec<String>,
+    pub deprecated_support: bool,
+    pub tag_support: CompletionItemTagSupport,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionItemTagSupport {
+    pub value_set: Vec<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeActionClientCapabilities {
+    pub code_action_literal_support: CodeActionLiteralSupport,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeActionLiteralSupport {
+    pub code_action_kind: CodeActionKindValueSet,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeActionKindValueSet {
+    pub value_set: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -96,6 +158,29 @@ pub struct HoverClientCapabilities {
 pub struct ClientCapabilities {
     pub general: GeneralClientCapabilities,
     pub text_document: TextDocumentClientCapabilities,
+    pub workspace: WorkspaceClientCapabilities,
+    pub window: WindowClientCapabilities,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowClientCapabilities {
+    pub work_done_progress: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceClientCapabilities {
+    pub apply_edit: bool,
+    pub configuration: bool,
+    pub file_operations: FileOperationClientCapabilities,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileOperationClientCapabilities {
+    pub will_rename: bool,
+    pub did_rename: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -106,13 +191,34 @@ pub struct InitializeParams {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub root_uri: Option<String>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initialization_options: Option<Value>,
+
     pub capabilities: ClientCapabilities,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChangeConfigurationParams {
+    pub settings: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigurationParams {
+    pub items: Vec<Value>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InitializedParams {}
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelParams {
+    pub id: i32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TextDocumentItem {
@@ -133,6 +239,12 @@ pub struct DidOpenTextDocumentParams {
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct DidCloseTextDocumentParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Diagnostic {
     pub range: Range,
     pub message: String,
@@ -148,6 +260,21 @@ pub struct PublishDiagnosticParams {
     pub diagnostics: Vec<Diagnostic>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressParams {
+    pub token: Value,
+    pub value: WorkDoneProgress,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkDoneProgress {
+    pub kind: String,
+    pub title: Option<String>,
+    pub percentage: Option<u32>,
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Position {
@@ -169,6 +296,23 @@ pub struct Location {
     pub range: Range,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceSymbolParams {
+    pub query: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolInformation {
+    pub name: String,
+    pub kind: u32,
+    pub location: Location,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_name: Option<String>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum LocationType {
@@ -220,13 +364,208 @@ pub struct ImplementationParams {
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct HoverParams {
+pub struct ReferenceContext {
+    pub include_declaration: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceParams {
     pub text_document: TextDocumentIdentifier,
     pub position: Position,
+    pub context: ReferenceContext,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct RenameParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+    pub new_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceEdit {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changes: Option<HashMap<String, Vec<TextEdit>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document_changes: Option<Vec<DocumentChange>>,
+}
+
+impl WorkspaceEdit {
+    pub fn from_changes(changes: Vec<(String, Vec<TextEdit>)>) -> Self {
+        Self {
+            changes: None,
+            document_changes: Some(
+                changes
+                    .into_iter()
+                    .map(|(uri, edits)| {
+                        DocumentChange::Edit(TextDocumentEdit {
+                            text_document: TextDocumentIdentifier { uri },
+                            edits,
+                        })
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    // The edits of each file in the order they apply, documentChanges take precedence over changes.
+    // Creating, renaming and deleting files isn't supported, the kind of such an operation is
+    // returned as the error
+    pub fn into_changes(self) -> Result<Vec<(String, Vec<TextEdit>)>, String> {
+        match self.document_changes {
+            Some(document_changes) => document_changes
+                .into_iter()
+                .map(|change| match change {
+                    DocumentChange::Edit(edit) => Ok((edit.text_document.uri, edit.edits)),
+                    DocumentChange::Operation(operation) => Err(operation.kind),
+                })
+                .collect(),
+            None => Ok(self.changes.unwrap_or_default().into_iter().collect()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DocumentChange {
+    Edit(TextDocumentEdit),
+    Operation(ResourceOperation),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentEdit {
+    pub text_document: TextDocumentIdentifier,
+    pub edits: Vec<TextEdit>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceOperation {
+    pub kind: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileRename {
+    pub old_uri: String,
+    pub new_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameFilesParams {
+    pub files: Vec<FileRename>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeActionContext {
+    pub diagnostics: Vec<Diagnostic>,
+    pub only: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeActionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+    pub context: CodeActionContext,
+}
+
+// Servers may also answer with bare commands, which have a title but no kind or edit
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeAction {
+    pub title: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edit: Option<WorkspaceEdit>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CodeActionOrCommand {
+    Command(Command),
+    CodeAction(CodeAction),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Command {
+    pub title: String,
+    pub command: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Vec<Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecuteCommandParams {
+    pub command: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Vec<Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyWorkspaceEditParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub edit: WorkspaceEdit,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyWorkspaceEditResult {
+    pub applied: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormattingOptions {
+    pub tab_size: u32,
+    pub insert_spaces: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentFormattingParams {
+    pub text_document: TextDocumentIdentifier,
+    pub options: FormattingOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentRangeFormattingParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+    pub options: FormattingOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HoverParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MarkupContent {
     pub kind: String,
     pub value: String,
@@ -325,6 +664,24 @@ pub struct CompletionItem {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub text_edit: Option<TextEdit>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<CompletionDocumentation>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<i32>>,
+}
+
+pub const COMPLETION_ITEM_TAG_DEPRECATED: i32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CompletionDocumentation {
+    String(String),
+    MarkupContent(MarkupContent),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -357,6 +714,9 @@ pub struct ServerCapabilities {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub completion_provider: Option<CompletionOptions>,
     pub signature_help_provider: Option<SignatureHelpOptions>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position_encoding: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
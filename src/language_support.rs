@@ -1,32 +1,108 @@
 use std::path::Path;
 
+use bstr::ByteSlice;
+
 pub const RUST_LINE_COMMENT_TOKEN: &str = "//";
 pub const RUST_MULTI_LINE_COMMENT_TOKEN_PAIR: [&str; 2] = ["/*", "*/"];
 pub const RUST_LANGUAGE_SERVER: &str = "rust-analyzer";
 pub const RUST_FILE_EXTENSIONS: [&str; 1] = ["rs"];
 pub const RUST_IDENTIFIER: &str = "rust";
-pub const RUST_INDENT_CHARS: [u8; 3] = [b'{', b'(', b'['];
+pub const RUST_NEWLINE_RULES: NewlineRules = NewlineRules {
+    pairs: &BRACKET_PAIRS,
+    continuations: &["=>"],
+    words: &[],
+};
 
 pub const CPP_LINE_COMMENT_TOKEN: &str = "//";
 pub const CPP_MULTI_LINE_COMMENT_TOKEN_PAIR: [&str; 2] = ["/*", "*/"];
 pub const CPP_LANGUAGE_SERVER: &str = "clangd";
 pub const CPP_FILE_EXTENSIONS: [&str; 6] = ["c", "h", "cpp", "hpp", "cc", "cxx"];
 pub const CPP_IDENTIFIER: &str = "cpp";
-pub const CPP_INDENT_WORDS: [&str; 6] = ["if", "else", "while", "do", "for", "switch"];
-pub const CPP_INDENT_CHARS: [u8; 3] = [b'{', b'(', b'['];
+pub const CPP_NEWLINE_RULES: NewlineRules = NewlineRules {
+    pairs: &BRACKET_PAIRS,
+    continuations: &[],
+    words: &["if", "else", "while", "do", "for", "switch"],
+};
 
 pub const PYTHON_LINE_COMMENT_TOKEN: &str = "#";
 pub const PYTHON_FILE_EXTENSIONS: [&str; 1] = ["py"];
 pub const PYTHON_IDENTIFIER: &str = "python";
-pub const PYTHON_INDENT_CHARS: [u8; 1] = [b':'];
+pub const PYTHON_NEWLINE_RULES: NewlineRules = NewlineRules {
+    pairs: &BRACKET_PAIRS,
+    continuations: &[":", "\\"],
+    words: &[],
+};
+
+pub const BRACKET_PAIRS: [(&str, &str); 3] = [("{", "}"), ("(", ")"), ("[", "]")];
+
+// How pressing enter indents the new line, based on the text before and after the cursor
+pub struct NewlineRules {
+    // After an opening token the new line is indented, when the cursor is right before the
+    // closing token that is moved to a line of its own as well
+    pub pairs: &'static [(&'static str, &'static str)],
+    // Line endings that continue on the next line, e.g. a match arm or a Python block
+    pub continuations: &'static [&'static str],
+    // Statements that indent a single line body without braces
+    pub words: &'static [&'static str],
+}
+
+#[derive(PartialEq)]
+pub enum NewlineIndent {
+    None,
+    Indent,
+    IndentBetweenPair,
+}
+
+impl NewlineRules {
+    pub fn indent(&self, before: &[u8], after: &[u8]) -> NewlineIndent {
+        let before = before.trim_end();
+        if let Some((_, close)) = self
+            .pairs
+            .iter()
+            .find(|(open, _)| before.ends_with_str(open))
+        {
+            return if after.trim_start().starts_with_str(close) {
+                NewlineIndent::IndentBetweenPair
+            } else {
+                NewlineIndent::Indent
+            };
+        }
+
+        if self
+            .continuations
+            .iter()
+            .any(|continuation| before.ends_with_str(continuation))
+        {
+            return NewlineIndent::Indent;
+        }
+
+        let statement = before.trim_start();
+        let is_word_start = |word: &str| {
+            statement
+                .strip_prefix(word.as_bytes())
+                .is_some_and(|rest| !rest.first().is_some_and(|c| is_word_char(*c)))
+        };
+        if !statement.ends_with(b";")
+            && !statement.ends_with(b"}")
+            && self.words.iter().any(|word| is_word_start(word))
+        {
+            return NewlineIndent::Indent;
+        }
+
+        NewlineIndent::None
+    }
+}
+
+fn is_word_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
 
 pub struct Language {
     pub identifier: &'static str,
     pub lsp_executable: Option<&'static str>,
     pub line_comment_token: Option<&'static str>,
     pub multi_line_comment_token_pair: Option<[&'static str; 2]>,
-    pub indent_words: Option<&'static [&'static str]>,
-    pub indent_chars: Option<&'static [u8]>,
+    pub newline_rules: &'static NewlineRules,
 }
 
 pub const CPP_LANGUAGE: Language = Language {
@@ -34,8 +110,7 @@ pub const CPP_LANGUAGE: Language = Language {
     lsp_executable: Some(CPP_LANGUAGE_SERVER),
     line_comment_token: Some(CPP_LINE_COMMENT_TOKEN),
     multi_line_comment_token_pair: Some(CPP_MULTI_LINE_COMMENT_TOKEN_PAIR),
-    indent_words: Some(&CPP_INDENT_WORDS),
-    indent_chars: Some(&CPP_INDENT_CHARS),
+    newline_rules: &CPP_NEWLINE_RULES,
 };
 
 pub const RUST_LANGUAGE: Language = Language {
@@ -43,8 +118,7 @@ pub const RUST_LANGUAGE: Language = Language {
     lsp_executable: Some(RUST_LANGUAGE_SERVER),
     line_comment_token: Some(RUST_LINE_COMMENT_TOKEN),
     multi_line_comment_token_pair: Some(RUST_MULTI_LINE_COMMENT_TOKEN_PAIR),
-    indent_words: None,
-    indent_chars: Some(&RUST_INDENT_CHARS),
+    newline_rules: &RUST_NEWLINE_RULES,
 };
 
 pub const PYTHON_LANGUAGE: Language = Language {
@@ -52,21 +126,89 @@ pub const PYTHON_LANGUAGE: Language = Language {
     lsp_executable: None,
     line_comment_token: Some(PYTHON_LINE_COMMENT_TOKEN),
     multi_line_comment_token_pair: None,
-    indent_words: None,
-    indent_chars: Some(&PYTHON_INDENT_CHARS),
+    newline_rules: &PYTHON_NEWLINE_RULES,
 };
 
 pub fn language_from_path(path: &str) -> Option<&'static Language> {
     if let Some(os_str) = Path::new(path).extension() {
         if let Some(extension) = os_str.to_str() {
-            if CPP_FILE_EXTENSIONS.contains(&extension) {
-                return Some(&CPP_LANGUAGE);
-            } else if RUST_FILE_EXTENSIONS.contains(&extension) {
-                return Some(&RUST_LANGUAGE);
-            } else if PYTHON_FILE_EXTENSIONS.contains(&extension) {
-                return Some(&PYTHON_LANGUAGE);
-            }
+            return language_from_filetype(extension);
         }
     }
     None
 }
+
+// A filetype is the file extension used to look up the syntax definition,
+// the language identifiers are accepted as aliases (e.g. `:set filetype=rust`)
+pub fn language_from_filetype(filetype: &str) -> Option<&'static Language> {
+    if filetype == CPP_IDENTIFIER || CPP_FILE_EXTENSIONS.contains(&filetype) {
+        Some(&CPP_LANGUAGE)
+    } else if filetype == RUST_IDENTIFIER || RUST_FILE_EXTENSIONS.contains(&filetype) {
+        Some(&RUST_LANGUAGE)
+    } else if filetype == PYTHON_IDENTIFIER || PYTHON_FILE_EXTENSIONS.contains(&filetype) {
+        Some(&PYTHON_LANGUAGE)
+    } else {
+        None
+    }
+}
+
+pub fn normalize_filetype(filetype: &str) -> &str {
+    match filetype {
+        CPP_IDENTIFIER => CPP_FILE_EXTENSIONS[2],
+        RUST_IDENTIFIER => RUST_FILE_EXTENSIONS[0],
+        PYTHON_IDENTIFIER => PYTHON_FILE_EXTENSIONS[0],
+        "bash" | "shell" => "sh",
+        "javascript" => "js",
+        filetype => filetype,
+    }
+}
+
+pub fn filetype_from_content(text: &[u8]) -> Option<&'static str> {
+    let first_line = text.lines().next()?.trim_ascii();
+
+    // Shebangs come in both the `#!/usr/bin/python3` and `#!/usr/bin/env python3` forms
+    if let Some(shebang) = first_line.strip_prefix(b"#!") {
+        let interpreter = shebang
+            .fields()
+            .map(|field| field.rsplit_str("/").next().unwrap_or(field))
+            .find(|program| *program != b"env" && !program.starts_with(b"-"))?;
+
+        return match interpreter {
+            program if program.starts_with(b"python") => Some(PYTHON_FILE_EXTENSIONS[0]),
+            b"sh" | b"bash" | b"zsh" | b"dash" => Some("sh"),
+            b"node" | b"deno" => Some("js"),
+            b"rust-script" | b"cargo" => Some(RUST_FILE_EXTENSIONS[0]),
+            program if program.starts_with(b"perl") => Some("pl"),
+            program if program.starts_with(b"ruby") => Some("rb"),
+            _ => None,
+        };
+    }
+
+    if first_line.starts_with(b"<?xml") {
+        return Some("xml");
+    }
+
+    let lowercase_line = first_line.to_ascii_lowercase();
+    if lowercase_line.starts_with(b"<!doctype html") || lowercase_line.starts_with(b"<html") {
+        return Some("html");
+    }
+
+    // Fall back to looking for telltale lines near the top of the file
+    for line in text.lines().take(50).map(|line| line.trim_ascii()) {
+        if line.starts_with(b"#include") || line.starts_with(b"#pragma once") {
+            return Some(CPP_FILE_EXTENSIONS[2]);
+        } else if line.starts_with(b"use std::")
+            || line.starts_with(b"fn main()")
+            || line.starts_with(b"#![")
+        {
+            return Some(RUST_FILE_EXTENSIONS[0]);
+        } else if line.starts_with(b"def ") && line.ends_with(b":")
+            || line.starts_with(b"import ") && !line.ends_with(b";")
+            || line.starts_with(b"if __name__ ==")
+        {
+            return Some(PYTHON_FILE_EXTENSIONS[0]);
+        }
+    }
+
+    None
+}
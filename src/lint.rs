@@ -0,0 +1,57 @@
+// Whitespace and line width problems that are flagged without a language server. Fixed by :trim
+// and :retab, long lines are left to the user
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LintKind {
+    TrailingWhitespace,
+    MixedIndentation,
+    LongLine,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LintIssue {
+    pub kind: LintKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+// The text has to start at the beginning of a line, the issues are offsets into it. Widths are
+// counted in bytes like the columns of rulers
+pub fn lint_lines(text: &[u8], max_width: Option<usize>) -> Vec<LintIssue> {
+    let is_blank = |c: &u8| *c == b' ' || *c == b'\t';
+    let mut issues = vec![];
+    let mut line_start = 0;
+    for line in text.split(|c| *c == b'\n') {
+        let content = line.strip_suffix(b"\r").unwrap_or(line);
+        let content_end = line_start + content.len();
+
+        let indentation = &content[..content.iter().take_while(|c| is_blank(c)).count()];
+        if indentation.contains(&b' ') && indentation.contains(&b'\t') {
+            issues.push(LintIssue {
+                kind: LintKind::MixedIndentation,
+                start: line_start,
+                end: line_start + indentation.len(),
+            });
+        }
+
+        let trailing = content.iter().rev().take_while(|c| is_blank(c)).count();
+        if trailing > 0 {
+            issues.push(LintIssue {
+                kind: LintKind::TrailingWhitespace,
+                start: content_end - trailing,
+                end: content_end,
+            });
+        }
+
+        if let Some(width) = max_width.filter(|width| content.len() > *width) {
+            issues.push(LintIssue {
+                kind: LintKind::LongLine,
+                start: line_start + width,
+                end: content_end,
+            });
+        }
+
+        line_start += line.len() + 1;
+    }
+    issues
+}
@@ -12,17 +12,34 @@
 #![feature(int_roundings)]
 
 mod buffer;
-mod cursor;
+mod completion;
+mod config;
+mod decoration;
 mod editor;
+mod encryption;
+mod export;
+mod file_finder;
+mod file_history;
+mod file_positions;
+mod file_watcher;
+mod git;
+mod keyboard;
+mod keymap;
 mod language_server;
 mod language_server_types;
 mod language_support;
-mod piece_table;
+mod lint;
+mod picker;
 mod renderer;
+mod script;
+mod symbol_search;
 mod syntect;
-mod text_utils;
 mod theme;
+mod thread_pool;
 mod view;
+mod workspace_index;
+mod workspace_search;
+mod workspace_trust;
 
 #[cfg_attr(target_os = "windows", path = "graphics_context_windows.rs")]
 #[cfg_attr(target_os = "macos", path = "graphics_context_macos.rs")]
@@ -35,18 +52,30 @@ mod platform_resources;
 use std::time::{Duration, Instant};
 
 use editor::Editor;
+use nimble_core::{command, cursor, log, motion, piece_table, text_utils};
 #[cfg(target_os = "macos")]
 use objc::{msg_send, runtime::YES, sel, sel_impl};
 #[cfg(target_os = "macos")]
 use winit::platform::macos::WindowExtMacOS;
 use winit::{
     dpi::{LogicalSize, PhysicalPosition},
-    event::{ElementState, Event, ModifiersState, MouseButton, MouseScrollDelta, WindowEvent},
+    event::{
+        ElementState, Event, ModifiersState, MouseButton, MouseScrollDelta, StartCause, WindowEvent,
+    },
     event_loop::{ControlFlow, EventLoop},
     window::{Window, WindowBuilder},
 };
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, flag, path] = args.as_slice() {
+        if flag == "--script" {
+            std::process::exit(if script::run(path) == 0 { 0 } else { 1 });
+        }
+    }
+
+    crate::syntect::preload_syntax_set();
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("Nimble")
@@ -66,50 +95,87 @@ fn main() {
     let mut left_mouse_button_state: Option<ElementState> = None;
     let mut left_mouse_button_timer = Instant::now();
     let mut double_click_timer = Instant::now();
+    let mut click_count = 0;
     let mut hover_timer = Some(Instant::now());
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::WaitUntil(Instant::now() + Duration::from_micros(8333));
 
         editor.update_layouts(&window);
+        editor.update_window_title(&window);
+        editor.update_announcements();
+        editor.update_primary_selection();
+
+        editor.update_lsp_changes();
 
         // Handle incoming responses, re-render if necessary
-        if editor.handle_lsp_responses(
-            mouse_position.map(|position| position.to_logical(window.scale_factor())),
-            &window,
-        ) {
+        if editor.handle_lsp_responses(&window) {
             editor.render(&window);
         }
 
+        if !editor.update_pending_quit() {
+            editor.lsp_shutdown();
+            control_flow.set_exit();
+        }
+
+        if editor.update_pastes() {
+            request_redraw(&window);
+        }
+
         if editor.update_highlights() {
             request_redraw(&window);
         }
 
+        if editor.update_word_highlights() {
+            request_redraw(&window);
+        }
+
+        if editor.update_search_panel() {
+            request_redraw(&window);
+        }
+
+        if editor.update_file_finder() {
+            request_redraw(&window);
+        }
+
+        if editor.update_toasts() {
+            request_redraw(&window);
+        }
+
+        if editor.update_disk_changes() {
+            request_redraw(&window);
+        }
+
         match event {
             Event::RedrawRequested(_) => {
                 editor.render(&window);
             }
+            Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+                editor.compact_piece_tables();
+                editor.evict_highlights();
+            }
             Event::WindowEvent {
                 event: WindowEvent::MouseWheel { delta, .. },
                 ..
             } => {
-                match delta {
-                    MouseScrollDelta::LineDelta(_, lines) => {
-                        if let Some(position) = mouse_position {
-                            editor.handle_scroll(
-                                position.to_logical(window.scale_factor()),
-                                (lines as isize).signum(),
-                                &window,
-                            );
-                        };
+                let (x, y) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (x as isize, y as isize),
+                    MouseScrollDelta::PixelDelta(pos) => (pos.x as isize, pos.y as isize),
+                };
+                // Shift turns the wheel of a mouse without a horizontal one sideways
+                let (x, y) = if x == 0
+                    && modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::SHIFT))
+                {
+                    (y, 0)
+                } else {
+                    (x, y)
+                };
+                if let Some(position) = mouse_position {
+                    let position = position.to_logical(window.scale_factor());
+                    if y != 0 {
+                        editor.handle_scroll(position, y.signum(), &window);
                     }
-                    MouseScrollDelta::PixelDelta(pos) => {
-                        if let Some(position) = mouse_position {
-                            editor.handle_scroll(
-                                position.to_logical(window.scale_factor()),
-                                (pos.y as isize).signum(),
-                                &window,
-                            );
-                        }
+                    if x != 0 {
+                        editor.handle_horizontal_scroll(position, x.signum(), &window);
                     }
                 }
                 request_redraw(&window);
@@ -118,7 +184,13 @@ fn main() {
                 event: WindowEvent::ReceivedCharacter(chr),
                 ..
             } => {
-                if !modifiers.is_some_and(|modifiers| modifiers.contains(ModifiersState::CTRL)) {
+                // AltGr is reported as Ctrl + Alt, it is needed for characters like [ $ { on many layouts
+                if !modifiers.is_some_and(|modifiers| {
+                    modifiers.contains(ModifiersState::CTRL)
+                        && !modifiers.contains(ModifiersState::ALT)
+                }) {
+                    hover_timer = None;
+                    editor.cancel_hover();
                     if !editor.handle_char(&window, chr) {
                         editor.lsp_shutdown();
                         control_flow.set_exit();
@@ -131,7 +203,9 @@ fn main() {
                 ..
             } => {
                 if input.state == ElementState::Pressed {
-                    if let Some(key_code) = input.virtual_keycode {
+                    if let Some(key_code) =
+                        keyboard::resolve_key_code(input.virtual_keycode, input.scancode)
+                    {
                         if !editor.handle_key(
                             mouse_position
                                 .map(|position| position.to_logical(window.scale_factor())),
@@ -150,24 +224,61 @@ fn main() {
                 event: WindowEvent::MouseInput { state, button, .. },
                 ..
             } => {
+                if button == MouseButton::Right && state == ElementState::Pressed {
+                    if let Some(position) = mouse_position {
+                        editor.handle_mouse_right_click(
+                            position.to_logical(window.scale_factor()),
+                            &window,
+                        );
+                        request_redraw(&window);
+                    }
+                }
+                if button == MouseButton::Middle && state == ElementState::Pressed {
+                    if let Some(position) = mouse_position {
+                        editor.handle_mouse_middle_click(
+                            position.to_logical(window.scale_factor()),
+                            &window,
+                        );
+                        request_redraw(&window);
+                    }
+                }
                 if button == MouseButton::Left {
                     left_mouse_button_state = Some(state);
                     if state == ElementState::Pressed {
                         if let Some(position) = mouse_position {
-                            if left_mouse_button_timer.elapsed() < Duration::from_millis(500) {
-                                if editor.handle_mouse_double_click(
+                            click_count =
+                                if left_mouse_button_timer.elapsed() < Duration::from_millis(500) {
+                                    click_count + 1
+                                } else {
+                                    1
+                                };
+
+                            match click_count {
+                                1 => editor.handle_mouse_pressed(
                                     position.to_logical(window.scale_factor()),
                                     modifiers,
                                     &window,
-                                ) {
+                                ),
+                                2 => {
+                                    if editor.handle_mouse_double_click(
+                                        position.to_logical(window.scale_factor()),
+                                        modifiers,
+                                        &window,
+                                    ) {
+                                        double_click_timer = Instant::now();
+                                    } else {
+                                        // The click landed elsewhere and only moved the cursor
+                                        click_count = 1;
+                                    }
+                                }
+                                _ => {
+                                    editor.handle_mouse_multi_click(
+                                        position.to_logical(window.scale_factor()),
+                                        click_count,
+                                        &window,
+                                    );
                                     double_click_timer = Instant::now();
                                 }
-                            } else {
-                                editor.handle_mouse_pressed(
-                                    position.to_logical(window.scale_factor()),
-                                    modifiers,
-                                    &window,
-                                );
                             }
                         }
                         left_mouse_button_timer = Instant::now();
@@ -183,6 +294,13 @@ fn main() {
                 ..
             } => {
                 modifiers = Some(modifiers_state);
+                if editor.update_definition_link(
+                    mouse_position.map(|position| position.to_logical(window.scale_factor())),
+                    modifiers,
+                    &window,
+                ) {
+                    request_redraw(&window);
+                }
             }
             Event::WindowEvent {
                 event: WindowEvent::CursorMoved { position, .. },
@@ -203,6 +321,14 @@ fn main() {
                         ) {
                             request_redraw(&window);
                         }
+                        if editor.update_definition_link(
+                            mouse_position
+                                .map(|position| position.to_logical(window.scale_factor())),
+                            modifiers,
+                            &window,
+                        ) {
+                            request_redraw(&window);
+                        }
                         hover_timer = Some(Instant::now());
                         editor.handle_mouse_exit_hover(
                             mouse_position
@@ -224,11 +350,19 @@ fn main() {
                     }
                 }
             }
+            Event::WindowEvent {
+                event: WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. },
+                ..
+            } => {
+                editor.resize(&window);
+                request_redraw(&window);
+            }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
                 if editor.ready_to_quit() {
+                    editor.remember_positions();
                     editor.lsp_shutdown();
                     control_flow.set_exit();
                 }
@@ -239,7 +373,7 @@ fn main() {
         if let Some(mouse_position) = mouse_position {
             if let Some(timer) = hover_timer {
                 if left_mouse_button_state.is_some_and(|state| state != ElementState::Pressed)
-                    && timer.elapsed() > Duration::from_millis(300)
+                    && timer.elapsed() > editor.hover_delay()
                 {
                     editor.handle_mouse_hover(
                         mouse_position.to_logical(window.scale_factor()),
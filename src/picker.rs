@@ -0,0 +1,124 @@
+use std::cmp::min;
+
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+pub const MAX_SHOWN_PICKER_ITEMS: usize = 20;
+
+pub enum PickerEvent {
+    None,
+    QueryChanged,
+    // Holding Ctrl opens the selected item in the other view
+    Open(bool),
+    Close,
+}
+
+// The query and the list of a panel that searches while the query is typed, like the workspace
+// search and the symbol search. The panel fills the items, the list keeps the selection in view
+pub struct Picker<T> {
+    pub query: String,
+    pub items: Vec<T>,
+    pub selection_index: usize,
+    pub selection_view_offset: usize,
+}
+
+impl<T> Picker<T> {
+    pub fn new(query: &str) -> Self {
+        Self {
+            query: query.to_string(),
+            items: vec![],
+            selection_index: 0,
+            selection_view_offset: 0,
+        }
+    }
+
+    pub fn set_items(&mut self, items: Vec<T>) {
+        self.items = items;
+        self.selection_index = 0;
+        self.selection_view_offset = 0;
+    }
+
+    pub fn selected(&self) -> Option<&T> {
+        self.items.get(self.selection_index)
+    }
+
+    pub fn shown_items(&self) -> impl Iterator<Item = (bool, &T)> {
+        self.items
+            .iter()
+            .enumerate()
+            .skip(self.selection_view_offset)
+            .take(MAX_SHOWN_PICKER_ITEMS)
+            .map(|(i, item)| (i == self.selection_index, item))
+    }
+
+    pub fn handle_key(
+        &mut self,
+        key_code: VirtualKeyCode,
+        modifiers: Option<ModifiersState>,
+    ) -> PickerEvent {
+        let ctrl = modifiers.is_some_and(|m| m.contains(ModifiersState::CTRL));
+        match key_code {
+            VirtualKeyCode::Down => self.select_next(),
+            VirtualKeyCode::J if ctrl => self.select_next(),
+            VirtualKeyCode::Up => self.select_previous(),
+            VirtualKeyCode::K if ctrl => self.select_previous(),
+            VirtualKeyCode::Back if ctrl => {
+                self.query.clear();
+                return PickerEvent::QueryChanged;
+            }
+            VirtualKeyCode::Back => {
+                self.query.pop();
+                return PickerEvent::QueryChanged;
+            }
+            VirtualKeyCode::Return => return PickerEvent::Open(ctrl),
+            VirtualKeyCode::Escape => return PickerEvent::Close,
+            _ => (),
+        }
+        PickerEvent::None
+    }
+
+    pub fn handle_char(&mut self, c: char) -> PickerEvent {
+        if c as u8 >= 0x20 && c as u8 <= 0x7E {
+            self.query.push(c);
+            return PickerEvent::QueryChanged;
+        }
+        PickerEvent::None
+    }
+
+    pub fn select_next(&mut self) {
+        self.selection_index = min(self.selection_index + 1, self.items.len().saturating_sub(1));
+        if self.selection_index >= self.selection_view_offset + MAX_SHOWN_PICKER_ITEMS {
+            self.selection_view_offset += 1;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selection_index = self.selection_index.saturating_sub(1);
+        if self.selection_index < self.selection_view_offset {
+            self.selection_view_offset -= 1;
+        }
+    }
+
+    // Scrolling keeps the selection in view
+    pub fn scroll(&mut self, lines: isize) {
+        let max_offset = self.items.len().saturating_sub(MAX_SHOWN_PICKER_ITEMS);
+        self.selection_view_offset = min(
+            self.selection_view_offset.saturating_add_signed(lines),
+            max_offset,
+        );
+        self.selection_index = self.selection_index.clamp(
+            self.selection_view_offset,
+            self.selection_view_offset + MAX_SHOWN_PICKER_ITEMS - 1,
+        );
+        self.selection_index = min(self.selection_index, self.items.len().saturating_sub(1));
+    }
+
+    // Rows are counted from the first shown item
+    pub fn select_row(&mut self, row: usize) -> bool {
+        let index = self.selection_view_offset + row;
+        if row >= MAX_SHOWN_PICKER_ITEMS || index >= self.items.len() {
+            return false;
+        }
+        self.selection_index = index;
+        true
+    }
+}
@@ -1,16 +1,87 @@
-use std::ffi::{c_char, c_long};
+use std::{
+    ffi::{c_char, c_long, c_void, CStr},
+    fs, io,
+    path::Path,
+    ptr::{null, null_mut},
+};
 
+use core_foundation::{array::CFArray, base::TCFType, string::CFString};
 use objc::{
     class, msg_send,
-    runtime::{Object, Sel, NO, YES},
+    runtime::{Object, Sel, BOOL, NO, YES},
     sel, sel_impl,
 };
 use winit::window::Window;
 
+use crate::file_watcher::FileChange;
+
 extern "C" {
     pub static NSPasteboardTypeString: Sel;
+    pub static NSPasteboardTypeHTML: Sel;
+    pub static NSPasteboardTypeRTF: Sel;
+    pub static NSAccessibilityAnnouncementRequestedNotification: Sel;
+    pub static NSAccessibilityAnnouncementKey: Sel;
+    pub static NSAccessibilityPriorityKey: Sel;
+    fn NSAccessibilityPostNotificationWithUserInfo(
+        element: *mut Object,
+        notification: Sel,
+        user_info: *mut Object,
+    );
+}
+
+// macOS has no primary selection, a named pasteboard shares it between running instances of nimble
+const PRIMARY_SELECTION_PASTEBOARD: &str = "nimble.primary-selection";
+
+#[repr(C)]
+struct FSEventStreamContext {
+    version: isize,
+    info: *mut c_void,
+    retain: *const c_void,
+    release: *const c_void,
+    copy_description: *const c_void,
+}
+
+type FSEventStreamCallback =
+    extern "C" fn(*mut c_void, *mut c_void, usize, *const *const c_char, *const u32, *const u64);
+
+#[link(name = "CoreServices", kind = "framework")]
+extern "C" {
+    fn FSEventStreamCreate(
+        allocator: *const c_void,
+        callback: FSEventStreamCallback,
+        context: *const FSEventStreamContext,
+        paths_to_watch: *const c_void,
+        since_when: u64,
+        latency: f64,
+        flags: u32,
+    ) -> *mut c_void;
+    fn FSEventStreamSetDispatchQueue(stream: *mut c_void, queue: *mut c_void);
+    fn FSEventStreamStart(stream: *mut c_void) -> u8;
+    fn FSEventStreamStop(stream: *mut c_void);
+    fn FSEventStreamInvalidate(stream: *mut c_void);
+    fn FSEventStreamRelease(stream: *mut c_void);
+}
+
+extern "C" {
+    fn dispatch_queue_create(label: *const c_char, attributes: *const c_void) -> *mut c_void;
+    fn dispatch_sync_f(queue: *mut c_void, context: *mut c_void, work: extern "C" fn(*mut c_void));
+    fn dispatch_release(object: *mut c_void);
 }
 
+const FS_EVENT_STREAM_EVENT_ID_SINCE_NOW: u64 = u64::MAX;
+const FS_EVENT_STREAM_CREATE_FLAG_NO_DEFER: u32 = 0x2;
+const FS_EVENT_STREAM_CREATE_FLAG_FILE_EVENTS: u32 = 0x10;
+const FS_EVENT_STREAM_EVENT_FLAG_MUST_SCAN_SUB_DIRS: u32 = 0x1;
+const FS_EVENT_STREAM_EVENT_FLAG_ITEM_CREATED: u32 = 0x100;
+const FS_EVENT_STREAM_EVENT_FLAG_ITEM_REMOVED: u32 = 0x200;
+const FS_EVENT_STREAM_EVENT_FLAG_ITEM_RENAMED: u32 = 0x800;
+
+// Seconds that events are collected for before they are delivered
+const FS_EVENTS_LATENCY: f64 = 0.1;
+
+// NSAccessibilityPriorityHigh
+const ACCESSIBILITY_PRIORITY_HIGH: c_long = 90;
+
 pub fn open_folder(window: &Window) -> Option<String> {
     let panel: *mut Object = unsafe { msg_send![class!(NSOpenPanel), openPanel] };
     let _: () = unsafe { msg_send![panel, setCanChooseFiles: NO] };
@@ -30,6 +101,190 @@ pub fn open_folder(window: &Window) -> Option<String> {
     )
 }
 
+pub fn add_recent_workspace(path: &str) {
+    // Registering the folder with the document controller makes it show up in the dock menu
+    unsafe {
+        let path_string: *mut Object = msg_send![class!(NSString), alloc];
+        let path_allocated_string: *mut Object =
+            msg_send![path_string, initWithBytes:path.as_ptr() length:path.len() encoding:4];
+        let url: *mut Object = msg_send![class!(NSURL), fileURLWithPath: path_allocated_string];
+        let document_controller: *mut Object =
+            msg_send![class!(NSDocumentController), sharedDocumentController];
+        let _: () = msg_send![document_controller, noteNewRecentDocumentURL: url];
+    }
+}
+
+pub fn open_url(url: &str) -> bool {
+    unsafe {
+        let url_string: *mut Object = msg_send![class!(NSString), alloc];
+        let url_allocated_string: *mut Object =
+            msg_send![url_string, initWithBytes:url.as_ptr() length:url.len() encoding:4];
+        let url: *mut Object = msg_send![class!(NSURL), URLWithString: url_allocated_string];
+        if url.is_null() {
+            return false;
+        }
+        let workspace: *mut Object = msg_send![class!(NSWorkspace), sharedWorkspace];
+        let opened: BOOL = msg_send![workspace, openURL: url];
+        opened == YES
+    }
+}
+
+// Opens a Finder window with the file selected
+pub fn reveal_file(path: &str) -> bool {
+    unsafe {
+        let path_string: *mut Object = msg_send![class!(NSString), alloc];
+        let path_allocated_string: *mut Object =
+            msg_send![path_string, initWithBytes:path.as_ptr() length:path.len() encoding:4];
+        let url: *mut Object = msg_send![class!(NSURL), fileURLWithPath: path_allocated_string];
+        let urls: *mut Object = msg_send![class!(NSArray), arrayWithObject: url];
+        let workspace: *mut Object = msg_send![class!(NSWorkspace), sharedWorkspace];
+        let _: () = msg_send![workspace, activateFileViewerSelectingURLs: urls];
+    }
+    true
+}
+
+// The trash tells where the item ended up, so that it can be put back
+pub fn move_to_trash(path: &str) -> Result<Option<String>, ()> {
+    unsafe {
+        let path_string: *mut Object = msg_send![class!(NSString), alloc];
+        let path_allocated_string: *mut Object =
+            msg_send![path_string, initWithBytes:path.as_ptr() length:path.len() encoding:4];
+        let url: *mut Object = msg_send![class!(NSURL), fileURLWithPath: path_allocated_string];
+        let file_manager: *mut Object = msg_send![class!(NSFileManager), defaultManager];
+        let mut trashed_url: *mut Object = null_mut();
+        let trashed: BOOL = msg_send![file_manager, trashItemAtURL:url resultingItemURL:&mut trashed_url as *mut *mut Object error:null_mut::<*mut Object>()];
+        if trashed != YES {
+            return Err(());
+        }
+        if trashed_url.is_null() {
+            return Ok(None);
+        }
+        let trashed_path: *mut Object = msg_send![trashed_url, path];
+        let bytes: *const c_char = msg_send![trashed_path, UTF8String];
+        Ok(Some(CStr::from_ptr(bytes).to_string_lossy().to_string()))
+    }
+}
+
+pub fn restore_from_trash(trashed: &str, path: &str) -> io::Result<()> {
+    fs::rename(trashed, path)
+}
+
+pub struct DirectoryWatch {
+    stream: *mut c_void,
+    queue: *mut c_void,
+    on_change: *mut Box<dyn Fn(FileChange) + Send>,
+}
+
+// FSEvents reports the changes below the directory on a dispatch queue of its own until the watch
+// is dropped
+pub fn watch_directory(
+    path: &str,
+    on_change: impl Fn(FileChange) + Send + 'static,
+) -> Option<DirectoryWatch> {
+    let on_change: *mut Box<dyn Fn(FileChange) + Send> =
+        Box::into_raw(Box::new(Box::new(on_change)));
+    let context = FSEventStreamContext {
+        version: 0,
+        info: on_change as *mut c_void,
+        retain: null(),
+        release: null(),
+        copy_description: null(),
+    };
+    let paths = CFArray::from_CFTypes(&[CFString::new(path)]);
+    unsafe {
+        let stream = FSEventStreamCreate(
+            null(),
+            fs_events_callback,
+            &context,
+            paths.as_concrete_TypeRef() as *const c_void,
+            FS_EVENT_STREAM_EVENT_ID_SINCE_NOW,
+            FS_EVENTS_LATENCY,
+            FS_EVENT_STREAM_CREATE_FLAG_NO_DEFER | FS_EVENT_STREAM_CREATE_FLAG_FILE_EVENTS,
+        );
+        if stream.is_null() {
+            drop(Box::from_raw(on_change));
+            return None;
+        }
+        let queue =
+            dispatch_queue_create(b"nimble.file-watcher\0".as_ptr() as *const c_char, null());
+        FSEventStreamSetDispatchQueue(stream, queue);
+        if FSEventStreamStart(stream) == 0 {
+            FSEventStreamInvalidate(stream);
+            FSEventStreamRelease(stream);
+            dispatch_release(queue);
+            drop(Box::from_raw(on_change));
+            return None;
+        }
+        Some(DirectoryWatch {
+            stream,
+            queue,
+            on_change,
+        })
+    }
+}
+
+impl Drop for DirectoryWatch {
+    fn drop(&mut self) {
+        unsafe {
+            FSEventStreamStop(self.stream);
+            FSEventStreamInvalidate(self.stream);
+            FSEventStreamRelease(self.stream);
+            // Waits for a callback that is still running before its closure is freed
+            dispatch_sync_f(self.queue, null_mut(), dispatch_nothing);
+            dispatch_release(self.queue);
+            drop(Box::from_raw(self.on_change));
+        }
+    }
+}
+
+extern "C" fn dispatch_nothing(_context: *mut c_void) {}
+
+// Events of one file are coalesced, whether it still exists tells if it was created or removed last
+extern "C" fn fs_events_callback(
+    _stream: *mut c_void,
+    info: *mut c_void,
+    count: usize,
+    paths: *const *const c_char,
+    flags: *const u32,
+    _ids: *const u64,
+) {
+    let on_change = unsafe { &*(info as *const Box<dyn Fn(FileChange) + Send>) };
+    for i in 0..count {
+        let (path, flags) = unsafe {
+            (
+                CStr::from_ptr(*paths.add(i)).to_string_lossy().to_string(),
+                *flags.add(i),
+            )
+        };
+        if flags & FS_EVENT_STREAM_EVENT_FLAG_MUST_SCAN_SUB_DIRS != 0 {
+            on_change(FileChange::Overflowed);
+        } else if flags
+            & (FS_EVENT_STREAM_EVENT_FLAG_ITEM_CREATED
+                | FS_EVENT_STREAM_EVENT_FLAG_ITEM_REMOVED
+                | FS_EVENT_STREAM_EVENT_FLAG_ITEM_RENAMED)
+            != 0
+        {
+            if Path::new(&path).exists() {
+                on_change(FileChange::Created(path));
+            } else {
+                on_change(FileChange::Removed(path));
+            }
+        } else {
+            on_change(FileChange::Modified(path));
+        }
+    }
+}
+
+fn primary_selection_pasteboard() -> *mut Object {
+    let name = PRIMARY_SELECTION_PASTEBOARD;
+    unsafe {
+        let string: *mut Object = msg_send![class!(NSString), alloc];
+        let allocated_string: *mut Object =
+            msg_send![string, initWithBytes:name.as_ptr() length:name.len() encoding:4];
+        msg_send![class!(NSPasteboard), pasteboardWithName: allocated_string]
+    }
+}
+
 pub struct PlatformResources {}
 
 impl PlatformResources {
@@ -50,6 +305,28 @@ impl PlatformResources {
         }
     }
 
+    pub fn set_clipboard_rich(&self, text: &[u8], html: &[u8], rtf: &[u8]) {
+        let clipboard: *mut Object = unsafe { msg_send![class!(NSPasteboard), generalPasteboard] };
+
+        unsafe {
+            let string: *mut Object = msg_send![class!(NSString), alloc];
+            let allocated_string: *mut Object =
+                msg_send![string, initWithBytes:text.as_ptr() length:text.len() encoding:4];
+            let html_string: *mut Object = msg_send![class!(NSString), alloc];
+            let allocated_html_string: *mut Object =
+                msg_send![html_string, initWithBytes:html.as_ptr() length:html.len() encoding:4];
+            let rtf_data: *mut Object =
+                msg_send![class!(NSData), dataWithBytes:rtf.as_ptr() length:rtf.len()];
+
+            let _: () = msg_send![clipboard, clearContents];
+            let _: () =
+                msg_send![clipboard, setString:allocated_string forType:NSPasteboardTypeString];
+            let _: () =
+                msg_send![clipboard, setString:allocated_html_string forType:NSPasteboardTypeHTML];
+            let _: () = msg_send![clipboard, setData:rtf_data forType:NSPasteboardTypeRTF];
+        }
+    }
+
     pub fn get_clipboard(&self) -> Vec<u8> {
         unsafe {
             let clipboard: *mut Object = msg_send![class!(NSPasteboard), generalPasteboard];
@@ -59,6 +336,32 @@ impl PlatformResources {
             std::slice::from_raw_parts(bytes as *mut u8, len).to_vec()
         }
     }
+
+    pub fn set_primary_selection(&self, text: &[u8]) {
+        let pasteboard = primary_selection_pasteboard();
+        unsafe {
+            let string: *mut Object = msg_send![class!(NSString), alloc];
+            let allocated_string: *mut Object =
+                msg_send![string, initWithBytes:text.as_ptr() length:text.len() encoding:4];
+            let _: () = msg_send![pasteboard, clearContents];
+            let _: () =
+                msg_send![pasteboard, setString:allocated_string forType:NSPasteboardTypeString];
+        }
+    }
+
+    pub fn get_primary_selection(&self) -> Vec<u8> {
+        let pasteboard = primary_selection_pasteboard();
+        unsafe {
+            let string: *mut Object = msg_send![pasteboard, stringForType: NSPasteboardTypeString];
+            if string.is_null() {
+                return vec![];
+            }
+            let bytes: *const c_char = msg_send![string, UTF8String];
+            let len = msg_send![string, lengthOfBytesUsingEncoding:4];
+            std::slice::from_raw_parts(bytes as *mut u8, len).to_vec()
+        }
+    }
+
     pub fn confirm_quit(&self, path: &str) -> Option<bool> {
         unsafe {
             let panel: *mut Object = msg_send![class!(NSAlert), new];
@@ -100,4 +403,61 @@ impl PlatformResources {
             }
         }
     }
+
+    pub fn confirm_reload(&self, path: &str) -> bool {
+        unsafe {
+            let panel: *mut Object = msg_send![class!(NSAlert), new];
+
+            let prompt = format!(
+                "{} was changed on disk. Do you want to reload it and lose your unsaved changes?",
+                path
+            );
+            let title = "Reload file?";
+            let yes = "Yes";
+            let no = "No";
+
+            let prompt_string: *mut Object = msg_send![class!(NSString), alloc];
+            let prompt_allocated_string: *mut Object = msg_send![prompt_string, initWithBytes:prompt.as_ptr() length:prompt.len() encoding:4];
+
+            let title_string: *mut Object = msg_send![class!(NSString), alloc];
+            let title_allocated_string: *mut Object =
+                msg_send![title_string, initWithBytes:title.as_ptr() length:title.len() encoding:4];
+
+            let yes_string: *mut Object = msg_send![class!(NSString), alloc];
+            let yes_allocated_string: *mut Object =
+                msg_send![yes_string, initWithBytes:yes.as_ptr() length:yes.len() encoding:4];
+
+            let no_string: *mut Object = msg_send![class!(NSString), alloc];
+            let no_allocated_string: *mut Object =
+                msg_send![no_string, initWithBytes:no.as_ptr() length:no.len() encoding:4];
+
+            let _: () = msg_send![panel, setMessageText: title_allocated_string];
+            let _: () = msg_send![panel, setInformativeText: prompt_allocated_string];
+            let _: () = msg_send![panel, addButtonWithTitle: yes_allocated_string];
+            let _: () = msg_send![panel, addButtonWithTitle: no_allocated_string];
+            let response: c_long = msg_send![panel, runModal];
+            response == 1000
+        }
+    }
+
+    pub fn announce(&self, text: &str) {
+        unsafe {
+            let string: *mut Object = msg_send![class!(NSString), alloc];
+            let allocated_string: *mut Object =
+                msg_send![string, initWithBytes:text.as_ptr() length:text.len() encoding:4];
+            let priority: *mut Object =
+                msg_send![class!(NSNumber), numberWithLong: ACCESSIBILITY_PRIORITY_HIGH];
+
+            let user_info: *mut Object = msg_send![class!(NSMutableDictionary), dictionary];
+            let _: () = msg_send![user_info, setObject:allocated_string forKey:NSAccessibilityAnnouncementKey];
+            let _: () = msg_send![user_info, setObject:priority forKey:NSAccessibilityPriorityKey];
+
+            let application: *mut Object = msg_send![class!(NSApplication), sharedApplication];
+            NSAccessibilityPostNotificationWithUserInfo(
+                application,
+                NSAccessibilityAnnouncementRequestedNotification,
+                user_info,
+            );
+        }
+    }
 }
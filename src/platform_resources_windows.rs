@@ -1,26 +1,60 @@
-use std::{ffi::CStr, ptr::copy_nonoverlapping};
+use std::{
+    cell::RefCell,
+    ffi::CStr,
+    fs, io,
+    os::windows::io::AsRawHandle,
+    path::{Path, PathBuf},
+    ptr::copy_nonoverlapping,
+    slice,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
 
 use windows::{
     core::{HSTRING, PCWSTR},
     w,
     Win32::{
-        Foundation::{HANDLE, HGLOBAL, HWND},
+        Foundation::{CloseHandle, BSTR, HANDLE, HGLOBAL, HWND},
+        Storage::FileSystem::{
+            CreateFileW, ReadDirectoryChangesW, FILE_ACTION, FILE_ACTION_ADDED,
+            FILE_ACTION_REMOVED, FILE_ACTION_RENAMED_NEW_NAME, FILE_ACTION_RENAMED_OLD_NAME,
+            FILE_FLAG_BACKUP_SEMANTICS, FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_DIR_NAME,
+            FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_SHARE_DELETE,
+            FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+        },
         System::{
             Com::{CoCreateInstance, CLSCTX_ALL},
             DataExchange::{
-                CloseClipboard, EmptyClipboard, GetClipboardData, OpenClipboard, SetClipboardData,
+                CloseClipboard, EmptyClipboard, GetClipboardData, OpenClipboard,
+                RegisterClipboardFormatW, SetClipboardData,
             },
             Memory::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_ZEROINIT},
+            IO::CancelSynchronousIo,
         },
         UI::{
+            Accessibility::{
+                NotificationKind_Other, NotificationProcessing_ImportantMostRecent,
+                UiaClientsAreListening, UiaHostProviderFromHwnd, UiaRaiseNotificationEvent,
+            },
             Input::KeyboardAndMouse::SetFocus,
-            Shell::{FileOpenDialog, IFileOpenDialog, FOS_PICKFOLDERS, SIGDN_FILESYSPATH},
-            WindowsAndMessaging::{MessageBoxW, IDNO, IDYES, MB_YESNOCANCEL},
+            Shell::{
+                FileOpenDialog, IFileOpenDialog, SHAddToRecentDocs, SHFileOperationW,
+                ShellExecuteW, FOF_ALLOWUNDO, FOF_NOCONFIRMATION, FOF_NOERRORUI, FOF_SILENT,
+                FOS_PICKFOLDERS, FO_DELETE, SHARD_PATHW, SHFILEOPSTRUCTW, SIGDN_FILESYSPATH,
+            },
+            WindowsAndMessaging::{
+                MessageBoxW, IDNO, IDYES, MB_YESNO, MB_YESNOCANCEL, SW_SHOWNORMAL,
+            },
         },
     },
 };
 use winit::{platform::windows::WindowExtWindows, window::Window};
 
+use crate::file_watcher::FileChange;
+
 pub fn open_folder(window: &Window) -> Option<String> {
     unsafe {
         let file_dialog: IFileOpenDialog =
@@ -42,34 +76,315 @@ pub fn open_folder(window: &Window) -> Option<String> {
     None
 }
 
+pub fn add_recent_workspace(path: &str) {
+    // Registering the folder with the shell makes it show up in the taskbar jump list
+    let path = HSTRING::from(path);
+    unsafe {
+        SHAddToRecentDocs(SHARD_PATHW.0 as u32, Some(path.as_ptr() as *const _));
+    }
+}
+
+pub fn open_url(url: &str) -> bool {
+    let url = HSTRING::from(url);
+    // Values above 32 indicate success
+    unsafe {
+        ShellExecuteW(
+            HWND(0),
+            w!("open"),
+            &url,
+            PCWSTR::null(),
+            PCWSTR::null(),
+            SW_SHOWNORMAL,
+        )
+        .0 > 32
+    }
+}
+
+// Opens an Explorer window with the file selected
+pub fn reveal_file(path: &str) -> bool {
+    let parameters = HSTRING::from(format!("/select,\"{}\"", path));
+    // Values above 32 indicate success
+    unsafe {
+        ShellExecuteW(
+            HWND(0),
+            w!("open"),
+            w!("explorer.exe"),
+            &parameters,
+            PCWSTR::null(),
+            SW_SHOWNORMAL,
+        )
+        .0 > 32
+    }
+}
+
+// A delete that allows undo sends the file to the recycle bin. Where it ended up is looked up
+// afterwards, it is unknown if the drive has no recycle bin and the file was deleted for good
+pub fn move_to_trash(path: &str) -> Result<Option<String>, ()> {
+    // The source is a list of paths terminated by an empty path
+    let from: Vec<u16> = path.encode_utf16().chain([0, 0]).collect();
+    let mut operation = SHFILEOPSTRUCTW {
+        wFunc: FO_DELETE,
+        pFrom: PCWSTR(from.as_ptr()),
+        fFlags: (FOF_ALLOWUNDO | FOF_NOCONFIRMATION | FOF_NOERRORUI | FOF_SILENT) as u16,
+        ..Default::default()
+    };
+    let trashed = unsafe {
+        SHFileOperationW(&mut operation) == 0 && !operation.fAnyOperationsAborted.as_bool()
+    };
+    if !trashed {
+        return Err(());
+    }
+    Ok(trashed_location(path))
+}
+
+// The item goes back to its original path and the recycle bin forgets about it
+pub fn restore_from_trash(trashed: &str, path: &str) -> io::Result<()> {
+    fs::rename(trashed, path)?;
+    let trashed = Path::new(trashed);
+    if let Some(id) = trashed
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("$R"))
+    {
+        fs::remove_file(trashed.with_file_name(format!("$I{}", id)))?;
+    }
+    Ok(())
+}
+
+// The recycle bin of a drive keeps each item as $R<id> in a folder per user, next to a $I<id> file
+// that holds the deletion time and the original path. The latest item deleted from the path wins
+fn trashed_location(path: &str) -> Option<String> {
+    let path = path.replace('/', "\\");
+    let root = Path::new(&path).ancestors().last()?;
+    let mut latest: Option<(i64, PathBuf)> = None;
+    for directory in fs::read_dir(root.join("$Recycle.Bin")).ok()?.flatten() {
+        let Ok(entries) = fs::read_dir(directory.path()) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(id) = name.strip_prefix("$I") else {
+                continue;
+            };
+            let Some((deleted_at, original_path)) = fs::read(entry.path())
+                .ok()
+                .and_then(|info| parse_recycle_bin_info(&info))
+            else {
+                continue;
+            };
+            if original_path.eq_ignore_ascii_case(&path)
+                && latest.as_ref().map_or(true, |(time, _)| deleted_at > *time)
+            {
+                latest = Some((deleted_at, directory.path().join(format!("$R{}", id))));
+            }
+        }
+    }
+    latest.map(|(_, location)| location.to_string_lossy().to_string())
+}
+
+// Version 1 is followed by a fixed 260 character path, version 2 by the length of the path
+fn parse_recycle_bin_info(info: &[u8]) -> Option<(i64, String)> {
+    let field = |offset: usize| {
+        Some(i64::from_le_bytes(
+            info.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    };
+    let path = match field(0)? {
+        1 => info.get(24..)?,
+        2 => info.get(28..)?,
+        _ => return None,
+    };
+    let path: Vec<u16> = path
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    Some((field(16)?, String::from_utf16(&path).ok()?))
+}
+
+pub struct DirectoryWatch {
+    thread: JoinHandle<()>,
+    stopped: Arc<AtomicBool>,
+}
+
+// Changes below the directory are read by a thread that blocks in ReadDirectoryChangesW until the
+// watch is dropped
+pub fn watch_directory(
+    path: &str,
+    on_change: impl Fn(FileChange) + Send + 'static,
+) -> Option<DirectoryWatch> {
+    let directory = unsafe {
+        CreateFileW(
+            &HSTRING::from(path),
+            FILE_LIST_DIRECTORY,
+            FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAG_BACKUP_SEMANTICS,
+            HANDLE(0),
+        )
+    }
+    .ok()?;
+
+    let root = PathBuf::from(path);
+    let stopped = Arc::new(AtomicBool::new(false));
+    let thread_stopped = stopped.clone();
+    let thread = thread::spawn(move || {
+        // The records are DWORD aligned
+        let mut buffer = vec![0u32; 16384];
+        loop {
+            let mut bytes_returned = 0;
+            let read = unsafe {
+                ReadDirectoryChangesW(
+                    directory,
+                    buffer.as_mut_ptr() as *mut _,
+                    (buffer.len() * 4) as u32,
+                    true,
+                    FILE_NOTIFY_CHANGE_FILE_NAME
+                        | FILE_NOTIFY_CHANGE_DIR_NAME
+                        | FILE_NOTIFY_CHANGE_LAST_WRITE,
+                    Some(&mut bytes_returned),
+                    None,
+                    None,
+                )
+            };
+            if !read.as_bool() || thread_stopped.load(Ordering::Relaxed) {
+                break;
+            }
+
+            // Nothing is returned when more changed than fits in the buffer
+            if bytes_returned == 0 {
+                on_change(FileChange::Overflowed);
+                continue;
+            }
+            let records = unsafe {
+                slice::from_raw_parts(buffer.as_ptr() as *const u8, bytes_returned as usize)
+            };
+            for (action, name) in parse_notify_information(records) {
+                let path = root.join(name).to_string_lossy().to_string();
+                on_change(match FILE_ACTION(action) {
+                    FILE_ACTION_ADDED | FILE_ACTION_RENAMED_NEW_NAME => FileChange::Created(path),
+                    FILE_ACTION_REMOVED | FILE_ACTION_RENAMED_OLD_NAME => FileChange::Removed(path),
+                    _ => FileChange::Modified(path),
+                });
+            }
+        }
+        unsafe {
+            CloseHandle(directory);
+        }
+    });
+
+    Some(DirectoryWatch { thread, stopped })
+}
+
+impl Drop for DirectoryWatch {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        unsafe {
+            CancelSynchronousIo(HANDLE(self.thread.as_raw_handle() as isize));
+        }
+    }
+}
+
+// Each FILE_NOTIFY_INFORMATION record holds the offset of the next one, the action and the name
+// of the file relative to the watched directory
+fn parse_notify_information(mut records: &[u8]) -> Vec<(u32, String)> {
+    let mut changes = vec![];
+    while let Some(header) = records.get(..12) {
+        let field = |offset: usize| {
+            u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        let (next_entry_offset, action, name_length) = (field(0), field(4), field(8));
+        let Some(name) = records.get(12..12 + name_length) else {
+            break;
+        };
+        let name: Vec<u16> = name
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        changes.push((action as u32, String::from_utf16_lossy(&name)));
+
+        if next_entry_offset == 0 {
+            break;
+        }
+        records = records.get(next_entry_offset..).unwrap_or_default();
+    }
+    changes
+}
+
+// The "HTML Format" clipboard format requires a header describing the byte offsets of the fragment
+fn cf_html(fragment: &[u8]) -> Vec<u8> {
+    const HEADER_SIZE: usize = 105;
+    let prefix = b"<html><body><!--StartFragment-->";
+    let suffix = b"<!--EndFragment--></body></html>";
+
+    let start_html = HEADER_SIZE;
+    let start_fragment = start_html + prefix.len();
+    let end_fragment = start_fragment + fragment.len();
+    let end_html = end_fragment + suffix.len();
+
+    let mut html = format!(
+        "Version:0.9\r\nStartHTML:{:010}\r\nEndHTML:{:010}\r\nStartFragment:{:010}\r\nEndFragment:{:010}\r\n",
+        start_html, end_html, start_fragment, end_fragment
+    )
+    .into_bytes();
+    debug_assert!(html.len() == HEADER_SIZE);
+
+    html.extend_from_slice(prefix);
+    html.extend_from_slice(fragment);
+    html.extend_from_slice(suffix);
+    html
+}
+
 pub struct PlatformResources {
     hwnd: HWND,
+    // Windows has no primary selection, this register of the editor stands in for it. Other
+    // programs and other running instances of nimble don't see it
+    primary_selection: RefCell<Vec<u8>>,
 }
 
 impl PlatformResources {
     pub fn new(window: &Window) -> Self {
         Self {
             hwnd: HWND(window.hwnd()),
+            primary_selection: RefCell::new(vec![]),
         }
     }
 
     pub fn set_clipboard(&self, text: &[u8]) {
+        // Clipboard format CF_TEXT = 1
+        self.set_clipboard_formats(&[(1, text)]);
+    }
+
+    pub fn set_clipboard_rich(&self, text: &[u8], html: &[u8], rtf: &[u8]) {
+        let (html_format, rtf_format) = unsafe {
+            (
+                RegisterClipboardFormatW(w!("HTML Format")),
+                RegisterClipboardFormatW(w!("Rich Text Format")),
+            )
+        };
+        let html = cf_html(html);
+        self.set_clipboard_formats(&[(1, text), (html_format, &html), (rtf_format, rtf)]);
+    }
+
+    fn set_clipboard_formats(&self, formats: &[(u32, &[u8])]) {
         unsafe {
             if OpenClipboard(self.hwnd).into() {
                 if EmptyClipboard().into() {
-                    if let Ok(data) = GlobalAlloc(GMEM_ZEROINIT, text.len() + 1) {
-                        let memory = GlobalLock(data);
-                        if memory.is_null() {
-                            GlobalFree(data).unwrap();
-                            return;
-                        }
-                        copy_nonoverlapping(text.as_ptr(), data.0 as *mut _, text.len());
+                    for (format, text) in formats {
+                        if let Ok(data) = GlobalAlloc(GMEM_ZEROINIT, text.len() + 1) {
+                            let memory = GlobalLock(data);
+                            if memory.is_null() {
+                                GlobalFree(data).unwrap();
+                                break;
+                            }
+                            copy_nonoverlapping(text.as_ptr(), data.0 as *mut _, text.len());
 
-                        // Clipboard format CF_TEXT = 1
-                        if SetClipboardData(1, HANDLE(data.0)).is_err() {
-                            GlobalFree(data).unwrap();
+                            if SetClipboardData(*format, HANDLE(data.0)).is_err() {
+                                GlobalFree(data).unwrap();
+                            }
+                            GlobalUnlock(data);
                         }
-                        GlobalUnlock(data);
                     }
                 }
                 CloseClipboard();
@@ -96,6 +411,14 @@ impl PlatformResources {
         vec![]
     }
 
+    pub fn set_primary_selection(&self, text: &[u8]) {
+        *self.primary_selection.borrow_mut() = text.to_vec();
+    }
+
+    pub fn get_primary_selection(&self) -> Vec<u8> {
+        self.primary_selection.borrow().clone()
+    }
+
     pub fn confirm_quit(&self, path: &str) -> Option<bool> {
         let prompt = HSTRING::from(format!(
             "Do you want to save changes to {} before quitting?",
@@ -114,4 +437,37 @@ impl PlatformResources {
             }
         }
     }
+
+    pub fn confirm_reload(&self, path: &str) -> bool {
+        let prompt = HSTRING::from(format!(
+            "{} was changed on disk. Do you want to reload it and lose your unsaved changes?",
+            path
+        ));
+        unsafe {
+            MessageBoxW(
+                self.hwnd,
+                PCWSTR::from_raw(prompt.as_wide().as_ptr()),
+                w!("Reload file?"),
+                MB_YESNO,
+            ) == IDYES
+        }
+    }
+
+    pub fn announce(&self, text: &str) {
+        unsafe {
+            // Skip the provider lookup entirely when no screen reader is running
+            if !UiaClientsAreListening().as_bool() {
+                return;
+            }
+            if let Ok(provider) = UiaHostProviderFromHwnd(self.hwnd) {
+                let _ = UiaRaiseNotificationEvent(
+                    &provider,
+                    NotificationKind_Other,
+                    NotificationProcessing_ImportantMostRecent,
+                    &BSTR::from(text),
+                    &BSTR::from("nimble"),
+                );
+            }
+        }
+    }
 }
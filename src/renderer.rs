@@ -9,16 +9,39 @@ use url::Url;
 use winit::window::Window;
 
 use crate::{
-    buffer::{Buffer, BufferMode},
-    editor::{FileFinder, Workspace, MAX_SHOWN_FILE_FINDER_ITEMS},
+    buffer::{Buffer, RenameSession, SubstitutionPreview},
+    command::BufferMode,
+    completion::{completion_documentation, is_deprecated, MAX_SHOWN_DOCUMENTATION_LINES},
+    decoration::{Decoration, DecorationKind, DecorationStyle},
+    editor::{
+        context_menu_width, ContextMenu, MessagesPanel, PassphrasePrompt, RegistersPanel,
+        ThemeSwitcher, TrustPrompt, Workspace, CONTEXT_MENU_ITEMS, MAX_SHOWN_MESSAGES,
+    },
+    file_finder::{FileFinder, MAX_SHOWN_FILE_FINDER_ITEMS},
+    file_history::{self, DiffLine, HistoryPanel, MAX_SHOWN_DIFF_LINES, MAX_SHOWN_SNAPSHOTS},
     graphics_context::GraphicsContext,
-    language_server::LanguageServer,
-    language_server_types::ParameterLabelType,
-    text_utils::search_highlights,
-    theme::{Theme, THEMES},
+    language_server::{LanguageServer, ServerStatus},
+    language_server_types::{Diagnostic, ParameterLabelType},
+    lint::{lint_lines, LintKind},
+    log::{self, LogLevel, LogMessage},
+    picker::Picker,
+    symbol_search::{symbol_kind_name, SymbolPanel},
+    text_utils::{char_type, ignores_case, search_highlights, CharType},
+    theme::{Theme, THEMES, THEME_NAMES},
     view::View,
+    workspace_search::SearchPanel,
 };
 
+const MAX_REGISTER_PREVIEW_LENGTH: usize = 80;
+// Made again before every draw, see update_view_decorations
+const VIEW_DECORATION_NAMESPACES: [&str; 5] = [
+    "search",
+    "word_highlight",
+    "substitution",
+    "rename_session",
+    "lint",
+];
+
 #[derive(Clone, Copy, Debug)]
 pub enum TextEffectKind {
     ForegroundColor(Color),
@@ -41,6 +64,64 @@ pub struct Color {
     pub b_u8: u8,
 }
 
+// Area covered by a popup in window coordinates, used to keep hovers interactive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PopupBounds {
+    pub left: f64,
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+}
+
+impl PopupBounds {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        (self.left..=self.right).contains(&x) && (self.top..=self.bottom).contains(&y)
+    }
+}
+
+// Popups open on the preferred side of the line they belong to. They flip to the other side when
+// only that side has room for them, or when neither has and the other side has more
+pub fn popup_opens_above(
+    space_above: f64,
+    space_below: f64,
+    height: f64,
+    prefer_above: bool,
+) -> bool {
+    let (preferred, other) = match prefer_above {
+        true => (space_above, space_below),
+        false => (space_below, space_above),
+    };
+    let flip = height > preferred && (height <= other || other > preferred);
+    prefer_above != flip
+}
+
+// Places a popup that opens below the line ending at `row`, or above that line, inside the bounds.
+// Popups that would cross the right edge are moved left just far enough to fit. Units are pixels
+// for the graphics contexts and cells for the completion list
+pub fn place_popup(
+    bounds: &PopupBounds,
+    (row, col): (f64, f64),
+    (width, height): (f64, f64),
+    line_height: f64,
+    prefer_above: bool,
+) -> (f64, f64) {
+    let above = row - line_height - height;
+    let row = match popup_opens_above(
+        row - line_height - bounds.top,
+        bounds.bottom - row,
+        height,
+        prefer_above,
+    ) {
+        true => above,
+        false => row,
+    };
+
+    (
+        row.min(bounds.bottom - height).max(bounds.top),
+        col.min(bounds.right - width).max(bounds.left),
+    )
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct RenderLayout {
     pub row_offset: usize,
@@ -52,6 +133,12 @@ pub struct RenderLayout {
 pub struct Renderer {
     context: GraphicsContext,
     pub theme: Theme,
+    theme_index: usize,
+    color_blind: bool,
+    inline_diagnostics: bool,
+    // Both together show the absolute number on the cursor line and relative numbers elsewhere
+    number: bool,
+    relative_number: bool,
 }
 
 impl Renderer {
@@ -61,19 +148,63 @@ impl Renderer {
         Self {
             context,
             theme: THEMES[0],
+            theme_index: 0,
+            color_blind: false,
+            inline_diagnostics: false,
+            number: true,
+            relative_number: false,
         }
     }
 
-    pub fn ensure_size(&mut self, window: &Window) {
-        self.context.ensure_size(window);
+    pub fn resize(&mut self, window: &Window) {
+        self.context.resize(window);
     }
 
-    pub fn cycle_theme(&mut self) {
-        let i = THEMES
-            .iter()
-            .position(|theme| *theme == self.theme)
-            .unwrap();
-        self.theme = THEMES[(i + 1) % THEMES.len()];
+    pub fn theme_index(&self) -> usize {
+        self.theme_index
+    }
+
+    pub fn set_theme(&mut self, theme_index: usize) {
+        self.theme_index = theme_index % THEMES.len();
+        self.update_theme();
+    }
+
+    pub fn set_color_blind(&mut self, color_blind: bool) {
+        self.color_blind = color_blind;
+        self.update_theme();
+    }
+
+    pub fn set_inline_diagnostics(&mut self, inline_diagnostics: bool) {
+        self.inline_diagnostics = inline_diagnostics;
+    }
+
+    pub fn set_line_numbers(&mut self, number: bool, relative_number: bool) {
+        self.number = number;
+        self.relative_number = relative_number;
+    }
+
+    pub fn line_numbers(&self) -> (bool, bool) {
+        (self.number, self.relative_number)
+    }
+
+    // Without line numbers the gutter keeps room for the signs
+    pub fn gutter_width(&self, num_lines: usize) -> usize {
+        if !self.number && !self.relative_number {
+            return 2;
+        }
+        (0..)
+            .take_while(|i| 10usize.pow(*i) <= num_lines)
+            .count()
+            .max(4)
+            + 2
+    }
+
+    fn update_theme(&mut self) {
+        self.theme = if self.color_blind {
+            THEMES[self.theme_index].color_blind()
+        } else {
+            THEMES[self.theme_index]
+        };
     }
 
     pub fn get_font_size(&self) -> (f64, f64) {
@@ -92,12 +223,7 @@ impl Renderer {
         self.context.end_draw();
     }
 
-    pub fn draw_file_finder(
-        &mut self,
-        layout: &mut RenderLayout,
-        workspace_path: &str,
-        file_finder: &FileFinder,
-    ) {
+    pub fn draw_file_finder(&mut self, layout: &mut RenderLayout, file_finder: &FileFinder) {
         if file_finder.files.is_empty() {
             return;
         }
@@ -160,12 +286,582 @@ impl Renderer {
         );
     }
 
+    // The query with a status above the list, the selected item highlighted
+    fn draw_picker<T>(
+        &mut self,
+        layout: &RenderLayout,
+        picker: &Picker<T>,
+        status: &str,
+        placeholder: &str,
+        draw_item: impl Fn(&T, bool, &Theme, &mut String, &mut Vec<TextEffect>),
+    ) {
+        let header = format!("{}  ({})", picker.query, status);
+
+        let mut text = String::default();
+        let mut effects = vec![];
+        if picker.items.is_empty() {
+            text.push_str(placeholder);
+            effects.push(TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.background_color),
+                start: 0,
+                length: text.len(),
+            });
+        }
+        for (selected, item) in picker.shown_items() {
+            draw_item(item, selected, &self.theme, &mut text, &mut effects);
+            text.push('\n');
+        }
+
+        self.context.draw_completion_popup(
+            0,
+            0,
+            layout,
+            &header,
+            picker.selection_index - picker.selection_view_offset,
+            text.strip_suffix('\n').unwrap_or(&text).as_bytes(),
+            self.theme.selection_background_color,
+            self.theme.background_color,
+            Some(&effects),
+            &self.theme,
+        );
+    }
+
+    pub fn draw_search_panel(&mut self, layout: &RenderLayout, search_panel: &SearchPanel) {
+        let picker = &search_panel.picker;
+        let status = match picker.items.len() {
+            _ if !search_panel.finished => "searching...".to_string(),
+            1 => "1 result".to_string(),
+            num_results => format!("{} results", num_results),
+        };
+        let placeholder = match picker.query.is_empty() {
+            true => "Type to search the workspace",
+            false => "No results",
+        };
+        self.draw_picker(
+            layout,
+            picker,
+            &status,
+            placeholder,
+            |result, selected, theme, text, effects| {
+                let location = format!("{}:{}: ", result.name, result.line + 1);
+                effects.push(TextEffect {
+                    kind: TextEffectKind::ForegroundColor(match selected {
+                        true => theme.background_color,
+                        false => theme.palette.blue,
+                    }),
+                    start: text.len(),
+                    length: location.len(),
+                });
+                text.push_str(&location);
+
+                effects.push(TextEffect {
+                    kind: TextEffectKind::ForegroundColor(match selected {
+                        true => theme.background_color,
+                        false => theme.foreground_color,
+                    }),
+                    start: text.len(),
+                    length: result.preview.len(),
+                });
+                if let Some((start, length)) = result.preview_match.filter(|_| !selected) {
+                    effects.push(TextEffect {
+                        kind: TextEffectKind::ForegroundColor(theme.search_background_color),
+                        start: text.len() + start,
+                        length,
+                    });
+                }
+                text.push_str(&result.preview);
+            },
+        );
+    }
+
+    // Symbol locations are shown relative to the workspace
+    pub fn draw_symbol_panel(
+        &mut self,
+        layout: &RenderLayout,
+        symbol_panel: &SymbolPanel,
+        workspace_path: Option<&str>,
+    ) {
+        let picker = &symbol_panel.picker;
+        let status = match picker.items.len() {
+            _ if symbol_panel.searching() => "searching...".to_string(),
+            1 => "1 symbol".to_string(),
+            num_symbols => format!("{} symbols", num_symbols),
+        };
+        let placeholder = match picker.query.is_empty() {
+            true => "Type to search for symbols",
+            false => "No symbols",
+        };
+        self.draw_picker(
+            layout,
+            picker,
+            &status,
+            placeholder,
+            |symbol, selected, theme, text, effects| {
+                let name = format!("{} ", symbol.name);
+                effects.push(TextEffect {
+                    kind: TextEffectKind::ForegroundColor(match selected {
+                        true => theme.background_color,
+                        false => theme.foreground_color,
+                    }),
+                    start: text.len(),
+                    length: name.len(),
+                });
+                text.push_str(&name);
+
+                let kind = match &symbol.container_name {
+                    Some(container_name) if !container_name.is_empty() => {
+                        format!("{} in {} ", symbol_kind_name(symbol.kind), container_name)
+                    }
+                    _ => format!("{} ", symbol_kind_name(symbol.kind)),
+                };
+                effects.push(TextEffect {
+                    kind: TextEffectKind::ForegroundColor(match selected {
+                        true => theme.background_color,
+                        false => theme.numbers_color,
+                    }),
+                    start: text.len(),
+                    length: kind.len(),
+                });
+                text.push_str(&kind);
+
+                let path = Url::parse(&symbol.location.uri)
+                    .ok()
+                    .and_then(|url| url.to_file_path().ok())
+                    .and_then(|path| path.to_str().map(|path| path.replace('\\', "/")))
+                    .unwrap_or_else(|| symbol.location.uri.clone());
+                let path = workspace_path
+                    .map(|workspace_path| workspace_path.replace('\\', "/"))
+                    .and_then(|workspace_path| {
+                        path.strip_prefix(&workspace_path)
+                            .map(|path| path.trim_start_matches('/').to_string())
+                    })
+                    .unwrap_or(path);
+                let location = format!("{}:{}", path, symbol.location.range.start.line + 1);
+                effects.push(TextEffect {
+                    kind: TextEffectKind::ForegroundColor(match selected {
+                        true => theme.background_color,
+                        false => theme.palette.blue,
+                    }),
+                    start: text.len(),
+                    length: location.len(),
+                });
+                text.push_str(&location);
+            },
+        );
+    }
+
+    pub fn draw_history_panel(&mut self, layout: &RenderLayout, history_panel: &HistoryPanel) {
+        let header = format!(
+            "{}  ({} saved versions)",
+            history_panel.path,
+            history_panel.snapshots.len()
+        );
+        let mut text = String::default();
+        let mut effects = vec![];
+        for (i, snapshot) in history_panel
+            .snapshots
+            .iter()
+            .enumerate()
+            .skip(history_panel.selection_view_offset)
+            .take(MAX_SHOWN_SNAPSHOTS)
+        {
+            let age = format!("{:<16}", file_history::age(snapshot.time));
+            effects.push(TextEffect {
+                kind: TextEffectKind::ForegroundColor(match i == history_panel.selection_index {
+                    true => self.theme.background_color,
+                    false => self.theme.foreground_color,
+                }),
+                start: text.len(),
+                length: age.len(),
+            });
+            text.push_str(&age);
+
+            let size = format!("{} bytes", snapshot.size);
+            effects.push(TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.numbers_color),
+                start: text.len(),
+                length: size.len(),
+            });
+            text.push_str(&size);
+            text.push('\n');
+        }
+
+        self.context.draw_completion_popup(
+            0,
+            0,
+            layout,
+            &header,
+            history_panel.selection_index - history_panel.selection_view_offset,
+            text.strip_suffix('\n').unwrap_or(&text).as_bytes(),
+            self.theme.selection_background_color,
+            self.theme.background_color,
+            Some(&effects),
+            &self.theme,
+        );
+
+        let mut diff = String::default();
+        let mut diff_effects = vec![];
+        if history_panel.diff.is_empty() {
+            diff.push_str("Same as the buffer");
+        }
+        for line in history_panel.diff.iter().take(MAX_SHOWN_DIFF_LINES) {
+            let (prefix, line, color) = match line {
+                DiffLine::Unchanged(line) => ("  ", line.as_str(), self.theme.foreground_color),
+                DiffLine::Removed(line) => ("- ", line.as_str(), self.theme.palette.red),
+                DiffLine::Added(line) => ("+ ", line.as_str(), self.theme.palette.green),
+                DiffLine::Skipped => ("", "...", self.theme.numbers_color),
+            };
+            let line = format!("{}{}", prefix, line.replace('\t', "    "));
+            diff_effects.push(TextEffect {
+                kind: TextEffectKind::ForegroundColor(color),
+                start: diff.len(),
+                length: line.len(),
+            });
+            diff.push_str(&line);
+            diff.push('\n');
+        }
+        if history_panel.diff.len() > MAX_SHOWN_DIFF_LINES {
+            diff.push_str("...");
+        }
+
+        let shown_snapshots = min(history_panel.snapshots.len(), MAX_SHOWN_SNAPSHOTS);
+        self.context.draw_popup_below(
+            shown_snapshots + 2,
+            0,
+            layout,
+            diff.trim_end().as_bytes(),
+            self.theme.selection_background_color,
+            self.theme.background_color,
+            Some(&diff_effects),
+            &self.theme,
+            false,
+        );
+    }
+
+    // The passphrase is masked
+    pub fn draw_passphrase_prompt(
+        &mut self,
+        layout: &RenderLayout,
+        passphrase_prompt: &PassphrasePrompt,
+    ) {
+        let header = "*".repeat(passphrase_prompt.passphrase.chars().count());
+        let text = match (passphrase_prompt.encrypted, &passphrase_prompt.confirmation) {
+            (true, _) => format!("Enter the passphrase for {}", passphrase_prompt.path),
+            (false, None) => format!(
+                "Choose a passphrase to encrypt {} with",
+                passphrase_prompt.path
+            ),
+            (false, Some(_)) => "Repeat the passphrase".to_string(),
+        };
+        let effects = [TextEffect {
+            kind: TextEffectKind::ForegroundColor(self.theme.background_color),
+            start: 0,
+            length: text.len(),
+        }];
+
+        self.context.draw_completion_popup(
+            0,
+            0,
+            layout,
+            &header,
+            0,
+            text.as_bytes(),
+            self.theme.selection_background_color,
+            self.theme.background_color,
+            Some(&effects),
+            &self.theme,
+        );
+    }
+
+    pub fn draw_trust_prompt(&mut self, layout: &RenderLayout, trust_prompt: &TrustPrompt) {
+        let header = "Trust this workspace? (y/n)";
+        let text = format!(
+            "{}/.nimble configures language servers and key mappings, which can run commands\n\
+             y uses the configuration, n ignores it, Escape asks again next time",
+            trust_prompt.path
+        );
+        // The first line is drawn on the selection
+        let first_line_length = text.find('\n').unwrap_or(text.len());
+        let effects = [
+            TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.background_color),
+                start: 0,
+                length: first_line_length,
+            },
+            TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.foreground_color),
+                start: first_line_length,
+                length: text.len() - first_line_length,
+            },
+        ];
+
+        self.context.draw_completion_popup(
+            0,
+            0,
+            layout,
+            header,
+            0,
+            text.as_bytes(),
+            self.theme.selection_background_color,
+            self.theme.background_color,
+            Some(&effects),
+            &self.theme,
+        );
+    }
+
+    pub fn draw_context_menu(&mut self, layout: &RenderLayout, context_menu: &ContextMenu) {
+        let width = context_menu_width();
+
+        self.context.fill_cells(
+            context_menu.row,
+            context_menu.col,
+            layout,
+            (width, CONTEXT_MENU_ITEMS.len()),
+            self.theme.selection_background_color,
+        );
+        self.context.fill_cells(
+            context_menu.row + context_menu.selection_index,
+            context_menu.col,
+            layout,
+            (width, 1),
+            self.theme.cursor_color,
+        );
+
+        let mut menu_string = String::default();
+        let mut selected_item_start_position = 0;
+        for (i, item) in CONTEXT_MENU_ITEMS.iter().enumerate() {
+            if i == context_menu.selection_index {
+                selected_item_start_position = menu_string.len();
+            }
+            menu_string.push(' ');
+            menu_string.push_str(item.label());
+            menu_string.push('\n');
+        }
+
+        let effects = [
+            TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.foreground_color),
+                start: 0,
+                length: menu_string.len(),
+            },
+            TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.background_color),
+                start: selected_item_start_position,
+                length: CONTEXT_MENU_ITEMS[context_menu.selection_index]
+                    .label()
+                    .len()
+                    + 1,
+            },
+        ];
+
+        self.context.draw_text(
+            context_menu.row,
+            context_menu.col,
+            layout,
+            menu_string.as_bytes(),
+            &effects,
+            &self.theme,
+            false,
+        );
+    }
+
+    pub fn draw_theme_switcher(&mut self, layout: &RenderLayout, theme_switcher: &ThemeSwitcher) {
+        let width = THEME_NAMES.iter().map(|name| name.len()).max().unwrap_or(0) + 2;
+        let (row, col) = (1, layout.num_cols.saturating_sub(width) / 2);
+
+        self.context.fill_cells(
+            row,
+            col,
+            layout,
+            (width, theme_switcher.themes.len()),
+            self.theme.selection_background_color,
+        );
+        self.context.fill_cells(
+            row + theme_switcher.selection_index,
+            col,
+            layout,
+            (width, 1),
+            self.theme.cursor_color,
+        );
+
+        let mut menu_string = String::default();
+        let mut selected_item_start_position = 0;
+        for (i, theme_index) in theme_switcher.themes.iter().enumerate() {
+            if i == theme_switcher.selection_index {
+                selected_item_start_position = menu_string.len();
+            }
+            menu_string.push(' ');
+            menu_string.push_str(THEME_NAMES[*theme_index]);
+            menu_string.push('\n');
+        }
+
+        let selected_theme = theme_switcher.themes[theme_switcher.selection_index];
+        let effects = [
+            TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.foreground_color),
+                start: 0,
+                length: menu_string.len(),
+            },
+            TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.background_color),
+                start: selected_item_start_position,
+                length: THEME_NAMES[selected_theme].len() + 1,
+            },
+        ];
+
+        self.context.draw_text(
+            row,
+            col,
+            layout,
+            menu_string.as_bytes(),
+            &effects,
+            &self.theme,
+            false,
+        );
+    }
+
+    pub fn draw_messages(&mut self, layout: &RenderLayout, messages_panel: &MessagesPanel) {
+        let messages = log::messages(messages_panel.min_level);
+
+        let mut text = String::default();
+        let mut effects = vec![];
+        if messages.is_empty() {
+            text.push_str("No messages");
+        }
+        for message in messages
+            .iter()
+            .skip(messages_panel.line_offset)
+            .take(MAX_SHOWN_MESSAGES)
+        {
+            let (label, color) = match message.level {
+                LogLevel::Info => ("[info]    ", self.theme.foreground_color),
+                LogLevel::Warning => ("[warning] ", self.theme.palette.yellow),
+                LogLevel::Error => ("[error]   ", self.theme.palette.red),
+            };
+            effects.push(TextEffect {
+                kind: TextEffectKind::ForegroundColor(color),
+                start: text.len(),
+                length: label.len(),
+            });
+            text.push_str(label);
+            effects.push(TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.foreground_color),
+                start: text.len(),
+                length: message.message.len(),
+            });
+            text.push_str(&message.message);
+            text.push('\n');
+        }
+
+        self.context.draw_popup_below(
+            0,
+            0,
+            layout,
+            text.trim_end().as_bytes(),
+            self.theme.selection_background_color,
+            self.theme.background_color,
+            Some(&effects),
+            &self.theme,
+            false,
+        );
+    }
+
+    pub fn draw_registers(&mut self, layout: &RenderLayout, registers_panel: &RegistersPanel) {
+        let mut text = String::default();
+        let mut effects = vec![];
+        for (label, contents) in &registers_panel.registers {
+            let label = format!("{:<12}", label);
+            effects.push(TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.palette.yellow),
+                start: text.len(),
+                length: label.len(),
+            });
+            text.push_str(&label);
+
+            // Show multi-line contents on a single row, truncated to keep the panel compact
+            let mut preview: String = String::from_utf8_lossy(contents)
+                .replace('\r', "")
+                .replace('\n', "\\n")
+                .replace('\t', "\\t");
+            if preview.chars().count() > MAX_REGISTER_PREVIEW_LENGTH {
+                preview = preview.chars().take(MAX_REGISTER_PREVIEW_LENGTH).collect();
+                preview.push_str("...");
+            }
+            if contents.is_empty() {
+                preview.push_str("(empty)");
+            }
+            effects.push(TextEffect {
+                kind: TextEffectKind::ForegroundColor(self.theme.foreground_color),
+                start: text.len(),
+                length: preview.len(),
+            });
+            text.push_str(&preview);
+            text.push('\n');
+        }
+
+        self.context.draw_popup_below(
+            0,
+            0,
+            layout,
+            text.trim_end().as_bytes(),
+            self.theme.selection_background_color,
+            self.theme.background_color,
+            Some(&effects),
+            &self.theme,
+            false,
+        );
+    }
+
+    // Toasts are stacked above the status line in the bottom right corner, the newest at the bottom
+    pub fn draw_toasts(&mut self, layout: &RenderLayout, toasts: &[LogMessage]) {
+        let max_length = layout.num_cols / 2;
+        for (i, toast) in toasts.iter().rev().enumerate() {
+            let Some(row) = layout.num_rows.checked_sub(3 + i) else {
+                break;
+            };
+            let message = toast.message.lines().next().unwrap_or_default();
+            let mut length = min(message.len(), max_length);
+            while !message.is_char_boundary(length) {
+                length -= 1;
+            }
+            let text = format!(" {} ", &message[..length]);
+            let col = layout.num_cols.saturating_sub(text.len() + 1);
+
+            let color = match toast.level {
+                LogLevel::Info => self.theme.foreground_color,
+                LogLevel::Warning => self.theme.palette.yellow,
+                LogLevel::Error => self.theme.palette.red,
+            };
+            self.context.fill_cells(
+                row,
+                col,
+                layout,
+                (text.len(), 1),
+                self.theme.status_line_background_color,
+            );
+            self.context.draw_text(
+                row,
+                col,
+                layout,
+                text.as_bytes(),
+                &[TextEffect {
+                    kind: TextEffectKind::ForegroundColor(color),
+                    start: 0,
+                    length: text.len(),
+                }],
+                &self.theme,
+                false,
+            );
+        }
+    }
+
     pub fn draw_status_line(
         &mut self,
         workspace: &Option<Workspace>,
         opened_file: Option<Url>,
         layout: &RenderLayout,
         active: bool,
+        document: Option<(&Buffer, &View)>,
     ) {
         self.context.fill_cells(
             0,
@@ -193,7 +889,20 @@ impl Renderer {
                     });
                 }
             }
-            (format!(" {}", file_path.to_str().unwrap()), effects)
+            let dirty = document.is_some_and(|(buffer, _)| buffer.piece_table.dirty);
+            let git_status = document
+                .and_then(|(buffer, _)| buffer.git_status.get())
+                .map(|status| format!(" [{}]", status))
+                .unwrap_or_default();
+            (
+                format!(
+                    " {}{}{}",
+                    file_path.to_str().unwrap(),
+                    git_status,
+                    if dirty { " \u{25CF}" } else { "" }
+                ),
+                effects,
+            )
         } else {
             (
                 format!(
@@ -226,6 +935,138 @@ impl Renderer {
             &self.theme,
             false,
         );
+
+        if let Some((buffer, view)) = document {
+            self.draw_buffer_status(buffer, view, layout, color);
+        }
+    }
+
+    // Language server state, language, cursor position and mode on the right of the status line
+    fn draw_buffer_status(
+        &mut self,
+        buffer: &Buffer,
+        view: &View,
+        layout: &RenderLayout,
+        color: Color,
+    ) {
+        let mut segments = vec![];
+        if let Some(server) = &buffer.language_server {
+            segments.push(match server.borrow().status() {
+                ServerStatus::Starting => ("LSP starting".to_string(), color),
+                ServerStatus::Ready => ("LSP".to_string(), color),
+                ServerStatus::Busy(title) => (format!("LSP: {}", title), color),
+                ServerStatus::NotResponding => (
+                    "Language server not responding".to_string(),
+                    self.theme.palette.yellow,
+                ),
+                ServerStatus::Terminated => (
+                    "Language server stopped".to_string(),
+                    self.theme.palette.red,
+                ),
+            });
+        }
+        if let Some(language) = buffer.language {
+            segments.push((language.identifier.to_string(), color));
+        }
+        let (line, col) = buffer.cursor_line_col(view);
+        segments.push((format!("{}:{}", line + 1, col + 1), color));
+        segments.push((
+            match view.mode {
+                BufferMode::Normal => "NORMAL",
+                BufferMode::Insert => "INSERT",
+                BufferMode::Visual => "VISUAL",
+                BufferMode::VisualLine => "V-LINE",
+            }
+            .to_string(),
+            color,
+        ));
+
+        let mut text = String::default();
+        let mut effects = vec![];
+        for (segment, color) in segments {
+            effects.push(TextEffect {
+                kind: TextEffectKind::ForegroundColor(color),
+                start: text.len(),
+                length: segment.len(),
+            });
+            text.push_str(&segment);
+            text.push_str("  ");
+        }
+        text.pop();
+
+        self.context.draw_text(
+            0,
+            layout.num_cols.saturating_sub(text.len()),
+            layout,
+            text.as_bytes(),
+            &effects,
+            &self.theme,
+            false,
+        );
+    }
+
+    // The decorations that follow the search prompt, the cursors and the lines on screen are made
+    // again before every draw of the view. Only the active view previews a substitution
+    pub fn update_view_decorations(
+        &self,
+        buffer: &mut Buffer,
+        layout: &RenderLayout,
+        view: &mut View,
+        active: bool,
+    ) {
+        let text = view.visible_text(buffer, layout);
+        let text_offset = view.visible_text_offset(buffer);
+
+        let mut decorations = vec![];
+        if buffer.input.as_bytes().first() == Some(&b'/') {
+            decorations.extend(self.search_decorations(buffer, view, &text, text_offset));
+        }
+        if let Some(word) = &buffer.highlighted_word {
+            decorations.extend(self.word_highlight_decorations(word, &text, text_offset));
+        }
+        if active {
+            buffer.update_substitution_preview(
+                view,
+                view.line_offset,
+                view.line_offset + layout.num_rows,
+            );
+            if let Some(preview) = &view.substitution_preview {
+                decorations.extend(self.substitution_decorations(preview));
+            }
+        }
+        if let Some(session) = &buffer.rename_session {
+            decorations.extend(self.rename_session_decorations(session));
+        }
+        if buffer.lint {
+            decorations.extend(self.lint_decorations(buffer, view, &text, text_offset));
+        }
+
+        for namespace in VIEW_DECORATION_NAMESPACES {
+            buffer.clear_decorations(namespace);
+        }
+        for decoration in decorations {
+            buffer.add_decoration(decoration);
+        }
+    }
+
+    // Diagnostics become decorations when they arrive and whenever the way they are shown changes,
+    // after that they move with the edits like any other decoration
+    pub fn update_diagnostic_decorations(&self, buffer: &mut Buffer) {
+        buffer.clear_decorations("diagnostics");
+        let Some(server) = buffer.language_server.clone() else {
+            return;
+        };
+        let decorations = match server
+            .borrow()
+            .saved_diagnostics
+            .get(&buffer.uri.to_lowercase())
+        {
+            Some(diagnostics) => self.diagnostic_decorations(buffer, diagnostics),
+            None => return,
+        };
+        for decoration in decorations {
+            buffer.add_decoration(decoration);
+        }
     }
 
     pub fn draw_buffer(
@@ -233,7 +1074,6 @@ impl Renderer {
         buffer: &Buffer,
         layout: &RenderLayout,
         view: &View,
-        language_server: &Option<Rc<RefCell<LanguageServer>>>,
         active: bool,
     ) {
         use TextEffectKind::*;
@@ -247,6 +1087,18 @@ impl Renderer {
             length: text.len(),
         }];
 
+        for ruler in &buffer.rulers {
+            if let Some(col) = ruler
+                .checked_sub(view.col_offset)
+                .filter(|col| *col < layout.num_cols)
+            {
+                for row in 0..layout.num_rows {
+                    self.context
+                        .fill_cell_slim_line(row, col, layout, self.theme.numbers_color);
+                }
+            }
+        }
+
         if let Some(syntect) = &buffer.syntect {
             effects.extend(syntect.highlight_lines(
                 &buffer.piece_table,
@@ -255,42 +1107,57 @@ impl Renderer {
             ))
         }
 
-        if buffer.input.as_bytes().first() == Some(&b'/') {
-            let mut first_result_found = false;
-            for (start, length) in search_highlights(&text, &buffer.input[1..]) {
-                let (row, col) = (
-                    view.absolute_to_view_row(buffer.piece_table.line_index(text_offset + start)),
-                    view.absolute_to_view_col(buffer.piece_table.col_index(text_offset + start)),
-                );
+        // Diagnostics of the lines being edited in insert mode are left alone
+        let searching = buffer.input.as_bytes().first() == Some(&b'/');
+        let cursor_lines: Vec<usize> = match view.mode {
+            BufferMode::Insert => view
+                .cursors
+                .iter()
+                .map(|cursor| buffer.piece_table.line_index(cursor.position))
+                .collect(),
+            _ => vec![],
+        };
+        let decorations = || {
+            buffer.decorations.iter().filter(|decoration| {
+                decoration.namespace != "diagnostics"
+                    || !cursor_lines.iter().any(|line| {
+                        (buffer.piece_table.line_index(decoration.start)
+                            ..=buffer.piece_table.line_index(decoration.end))
+                            .contains(line)
+                    })
+            })
+        };
 
-                let (mut foreground_color, mut background_color) = (
-                    self.theme.search_foreground_color,
-                    self.theme.search_background_color,
+        for decoration in decorations() {
+            let DecorationKind::Style(style) = decoration.kind else {
+                continue;
+            };
+            if let Some(color) = style.background {
+                view.visible_range_iter(
+                    buffer,
+                    layout,
+                    decoration.start,
+                    decoration.end,
+                    |row, col, count| {
+                        self.context.fill_cells(row, col, layout, (count, 1), color);
+                    },
                 );
-
-                if !first_result_found
-                    && buffer
-                        .cursors
-                        .last()
-                        .is_some_and(|cursor| text_offset + start >= cursor.position)
-                {
-                    foreground_color = self.theme.active_search_foreground_color;
-                    background_color = self.theme.active_search_background_color;
-                    first_result_found = true;
+            }
+            if let Some(color) = style.foreground {
+                let start = max(decoration.start, text_offset);
+                let end = min(decoration.end, text_offset + text.len());
+                if start < end {
+                    effects.push(TextEffect {
+                        kind: ForegroundColor(color),
+                        start: start - text_offset,
+                        length: end - start,
+                    });
                 }
-
-                self.context
-                    .fill_cells(row, col, layout, (length, 1), background_color);
-                self.context
-                    .fill_cells(row, col, layout, (1, 1), self.theme.cursor_color);
-                effects.push(TextEffect {
-                    kind: ForegroundColor(foreground_color),
-                    start,
-                    length,
-                });
             }
-        } else if active {
-            if buffer.mode != BufferMode::Insert {
+        }
+
+        if active && !searching {
+            if view.mode != BufferMode::Insert {
                 view.visible_cursors_iter(layout, buffer, |row, col, num| {
                     self.context.fill_cells(
                         row,
@@ -303,7 +1170,7 @@ impl Renderer {
             }
 
             view.visible_cursor_leads_iter(buffer, layout, |row, col, pos| {
-                if buffer.mode == BufferMode::Insert {
+                if view.mode == BufferMode::Insert {
                     self.context
                         .fill_cell_slim_line(row, col, layout, self.theme.cursor_color);
                 } else {
@@ -321,35 +1188,327 @@ impl Renderer {
         self.context
             .draw_text_fit_view(view, layout, &text, &effects, &self.theme);
 
-        if let Some(server) = language_server {
-            if let Some(diagnostics) = server
-                .borrow()
-                .saved_diagnostics
-                .get(&buffer.uri.to_lowercase())
-            {
-                view.visible_diagnostic_lines_iter(
+        if let Some((line, col, length)) = view.definition_link {
+            if (view.line_offset..view.line_offset + layout.num_rows).contains(&line) {
+                let cells = buffer.piece_table.display_cols(line, col, col + length);
+                self.context.underline_cells(
+                    view.absolute_to_view_row(line),
+                    view.absolute_to_view_col(cells.start),
+                    layout,
+                    cells.len(),
+                    self.theme.foreground_color,
+                );
+            }
+        }
+
+        for decoration in decorations() {
+            let DecorationKind::Style(style) = decoration.kind else {
+                continue;
+            };
+            if let Some(color) = style.underline {
+                view.visible_range_iter(
+                    buffer,
+                    layout,
+                    decoration.start,
+                    decoration.end,
+                    |row, col, count| {
+                        self.context.underline_cells(row, col, layout, count, color);
+                    },
+                );
+            }
+            if let Some(color) = style.strikethrough {
+                view.visible_range_iter(
                     buffer,
                     layout,
-                    diagnostics,
+                    decoration.start,
+                    decoration.end,
                     |row, col, count| {
-                        self.context.underline_cells(
-                            row,
-                            col,
-                            layout,
-                            count,
-                            self.theme.diagnostic_color,
-                        );
+                        self.context.strike_cells(row, col, layout, count, color);
                     },
                 );
             }
         }
 
+        view.visible_virtual_text(buffer, layout, decorations(), |row, col, text, color| {
+            self.context.draw_text(
+                row,
+                col,
+                layout,
+                text.as_bytes(),
+                &[TextEffect {
+                    kind: ForegroundColor(color),
+                    start: 0,
+                    length: text.len(),
+                }],
+                &self.theme,
+                false,
+            );
+        });
+    }
+
+    fn search_decorations(
+        &self,
+        buffer: &Buffer,
+        view: &View,
+        text: &[u8],
+        text_offset: usize,
+    ) -> Vec<Decoration> {
+        let mut decorations = vec![];
+        let mut first_result_found = false;
+        for (start, length) in search_highlights(
+            text,
+            &buffer.input[1..],
+            ignores_case(&buffer.input.as_bytes()[1..]),
+        ) {
+            let start = text_offset + start;
+            let (mut foreground_color, mut background_color) = (
+                self.theme.search_foreground_color,
+                self.theme.search_background_color,
+            );
+
+            if !first_result_found
+                && view
+                    .cursors
+                    .last()
+                    .is_some_and(|cursor| start >= cursor.position)
+            {
+                foreground_color = self.theme.active_search_foreground_color;
+                background_color = self.theme.active_search_background_color;
+                first_result_found = true;
+            }
+
+            decorations.push(Decoration {
+                namespace: "search",
+                start,
+                end: start + length,
+                kind: DecorationKind::Style(DecorationStyle {
+                    foreground: Some(foreground_color),
+                    background: Some(background_color),
+                    underline: None,
+                    strikethrough: None,
+                }),
+            });
+            // The start of every result is marked like a cursor
+            decorations.push(Decoration {
+                namespace: "search",
+                start,
+                end: start + 1,
+                kind: DecorationKind::Style(DecorationStyle {
+                    background: Some(self.theme.cursor_color),
+                    ..Default::default()
+                }),
+            });
+        }
+        decorations
+    }
+
+    // Only whole words are highlighted, the occurrence under the cursor included
+    fn word_highlight_decorations(
+        &self,
+        word: &[u8],
+        text: &[u8],
+        text_offset: usize,
+    ) -> Vec<Decoration> {
+        let is_word = |c: Option<&u8>| c.is_some_and(|c| char_type(*c) == CharType::Word);
+        search_highlights(text, &String::from_utf8_lossy(word), false)
+            .into_iter()
+            .filter(|(start, length)| {
+                !is_word(start.checked_sub(1).and_then(|i| text.get(i)))
+                    && !is_word(text.get(start + length))
+            })
+            .map(|(start, length)| Decoration {
+                namespace: "word_highlight",
+                start: text_offset + start,
+                end: text_offset + start + length,
+                kind: DecorationKind::Style(DecorationStyle {
+                    background: Some(self.theme.word_highlight_background_color),
+                    ..Default::default()
+                }),
+            })
+            .collect()
+    }
+
+    // Lint issues are underlined like diagnostics of the lowest severity. Lines over the narrowest
+    // ruler are long, and trailing whitespace is not flagged while it is being typed
+    fn lint_decorations(
+        &self,
+        buffer: &Buffer,
+        view: &View,
+        text: &[u8],
+        text_offset: usize,
+    ) -> Vec<Decoration> {
+        let cursor_lines: Vec<usize> = match view.mode {
+            BufferMode::Insert => view
+                .cursors
+                .iter()
+                .map(|cursor| buffer.piece_table.line_index(cursor.position))
+                .collect(),
+            _ => vec![],
+        };
+        lint_lines(text, buffer.rulers.iter().min().copied())
+            .into_iter()
+            .filter(|issue| {
+                issue.kind != LintKind::TrailingWhitespace
+                    || !cursor_lines
+                        .contains(&buffer.piece_table.line_index(text_offset + issue.start))
+            })
+            .map(|issue| Decoration {
+                namespace: "lint",
+                start: text_offset + issue.start,
+                end: text_offset + issue.end,
+                kind: DecorationKind::Style(DecorationStyle {
+                    underline: Some(self.theme.palette.blue),
+                    ..Default::default()
+                }),
+            })
+            .collect()
+    }
+
+    fn rename_session_decorations(&self, session: &RenameSession) -> Vec<Decoration> {
+        session
+            .regions
+            .iter()
+            .map(|(start, end)| Decoration {
+                namespace: "rename_session",
+                start: *start,
+                end: *end,
+                kind: DecorationKind::Style(DecorationStyle {
+                    background: Some(self.theme.word_highlight_background_color),
+                    underline: Some(self.theme.palette.aqua),
+                    ..Default::default()
+                }),
+            })
+            .collect()
+    }
+
+    // The text a substitution replaces is struck through once the replacement is typed and the
+    // changed lines are shown after the end of the line
+    fn substitution_decorations(&self, preview: &SubstitutionPreview) -> Vec<Decoration> {
+        let mut decorations = vec![];
+        for &(start, length) in &preview.matches {
+            decorations.push(Decoration {
+                namespace: "substitution",
+                start,
+                end: start + length,
+                kind: DecorationKind::Style(DecorationStyle {
+                    foreground: Some(self.theme.search_foreground_color),
+                    background: Some(self.theme.search_background_color),
+                    strikethrough: preview
+                        .replaced
+                        .then_some(self.theme.search_foreground_color),
+                    ..Default::default()
+                }),
+            });
+        }
+        for (start, text) in &preview.lines {
+            decorations.push(Decoration {
+                namespace: "substitution",
+                start: *start,
+                end: *start,
+                kind: DecorationKind::VirtualText(
+                    text.clone(),
+                    self.theme.active_search_background_color,
+                ),
+            });
+        }
+        decorations
+    }
+
+    // Errors and warnings are underlined and marked in the gutter, errors taking precedence, and the
+    // most severe message of each line is shown after it if inline diagnostics are enabled
+    fn diagnostic_decorations(
+        &self,
+        buffer: &Buffer,
+        diagnostics: &[Diagnostic],
+    ) -> Vec<Decoration> {
+        let mut decorations = vec![];
+        let mut line_messages: Vec<(usize, &Diagnostic)> = vec![];
+        for diagnostic in diagnostics {
+            if diagnostic.severity.is_some_and(|s| s > 2) {
+                continue;
+            }
+
+            let (start_line, end_line) = (
+                diagnostic.range.start.line as usize,
+                diagnostic.range.end.line as usize,
+            );
+            let (Some(start), Some(end)) = (
+                buffer.piece_table.char_index_from_line_col(
+                    start_line,
+                    diagnostic.range.start.character as usize,
+                ),
+                buffer
+                    .piece_table
+                    .char_index_from_line_col(end_line, diagnostic.range.end.character as usize),
+            ) else {
+                continue;
+            };
+
+            decorations.push(Decoration {
+                namespace: "diagnostics",
+                start,
+                end: max(start, end) + 1,
+                kind: DecorationKind::Style(DecorationStyle {
+                    underline: Some(self.theme.diagnostic_color),
+                    ..Default::default()
+                }),
+            });
+
+            let severity = diagnostic.severity.unwrap_or(1);
+            match line_messages
+                .iter_mut()
+                .find(|(line, _)| *line == start_line)
+            {
+                Some((_, shown)) if severity < shown.severity.unwrap_or(1) => *shown = diagnostic,
+                Some(_) => (),
+                None => line_messages.push((start_line, diagnostic)),
+            }
+        }
+
+        for (line, diagnostic) in line_messages {
+            let Some(start) = buffer.piece_table.char_index_from_line_col(line, 0) else {
+                continue;
+            };
+            let (sign, color) = match diagnostic.severity.unwrap_or(1) {
+                1 => ('\u{2717}', self.theme.palette.red),
+                _ => ('\u{26A0}', self.theme.palette.yellow),
+            };
+            decorations.push(Decoration {
+                namespace: "diagnostics",
+                start,
+                end: start,
+                kind: DecorationKind::GutterSign(sign, color),
+            });
+            if self.inline_diagnostics {
+                decorations.push(Decoration {
+                    namespace: "diagnostics",
+                    start,
+                    end: start,
+                    kind: DecorationKind::VirtualText(
+                        diagnostic.message.clone(),
+                        self.theme.numbers_color,
+                    ),
+                });
+            }
+        }
+        decorations
+    }
+
+    // Completions, signature help and the command line are drawn after every buffer so they stay on
+    // top of the neighbouring split
+    pub fn draw_buffer_popups(&mut self, buffer: &Buffer, layout: &RenderLayout, view: &View) {
+        use TextEffectKind::*;
+
         view.visible_completions(buffer, layout, |completions, completion_view, request| {
             if completions.is_empty() {
                 return;
             }
 
-            let selected_item = request.selection_index - request.selection_view_offset;
+            // The popup may have shrunk since the selection was last scrolled into view
+            let view_offset = request
+                .selection_view_offset
+                .max((request.selection_index + 1).saturating_sub(completion_view.height));
+            let selected_item = request.selection_index - view_offset;
 
             self.context.fill_cells(
                 completion_view.row,
@@ -368,9 +1527,10 @@ impl Renderer {
 
             let mut selected_item_start_position = 0;
             let mut completion_string = String::default();
+            let mut deprecated_rows = vec![];
             for (i, item) in completions
                 .iter()
-                .skip(request.selection_view_offset)
+                .skip(view_offset)
                 .enumerate()
                 .take(completion_view.height)
             {
@@ -378,7 +1538,11 @@ impl Renderer {
                     selected_item_start_position = completion_string.len();
                 }
 
-                completion_string.push_str(item.insert_text.as_ref().unwrap_or(&item.label));
+                let text = item.insert_text.as_ref().unwrap_or(&item.label);
+                if is_deprecated(item) {
+                    deprecated_rows.push((i, text.trim_end().len()));
+                }
+                completion_string.push_str(text);
                 completion_string.push('\n');
             }
 
@@ -445,6 +1609,25 @@ impl Renderer {
                 }
             }
 
+            // The documentation is scrolled separately from the completions, see
+            // scroll_completion_documentation
+            if let Some(documentation) =
+                completion_documentation(&completions[request.selection_index])
+            {
+                if !label_detail_combined.trim().is_empty() {
+                    label_detail_combined.push('\n');
+                }
+                for line in documentation
+                    .message
+                    .lines()
+                    .skip(request.documentation_offset)
+                    .take(MAX_SHOWN_DOCUMENTATION_LINES)
+                {
+                    label_detail_combined.push_str(line);
+                    label_detail_combined.push('\n');
+                }
+            }
+
             if !label_detail_combined.trim().is_empty() {
                 let mut bytes = vec![];
                 for c in label_detail_combined.as_bytes() {
@@ -455,9 +1638,24 @@ impl Renderer {
                     }
                 }
 
+                // Show the details on the left of the completions when they don't fit on the right
+                let detail_width = bytes
+                    .split(|c| *c == b'\n')
+                    .map(|line| line.len())
+                    .max()
+                    .unwrap_or(0);
+                let detail_col = if completion_view.col + completion_view.width + detail_width + 1
+                    > layout.num_cols
+                    && completion_view.col >= detail_width + 2
+                {
+                    completion_view.col - detail_width - 2
+                } else {
+                    completion_view.col + completion_view.width
+                };
+
                 self.context.draw_popup_below(
                     completion_view.row,
-                    completion_view.col + completion_view.width,
+                    detail_col,
                     layout,
                     bytes.trim_ascii_end(),
                     self.theme.selection_background_color,
@@ -477,6 +1675,20 @@ impl Renderer {
                 &self.theme,
                 false,
             );
+
+            for (i, length) in deprecated_rows {
+                self.context.strike_cells(
+                    completion_view.row + i,
+                    completion_view.col,
+                    layout,
+                    length,
+                    if i == selected_item {
+                        self.theme.background_color
+                    } else {
+                        self.theme.foreground_color
+                    },
+                );
+            }
         });
 
         view.visible_signature_helps(buffer, layout, |signature_help, signature_help_view| {
@@ -541,6 +1753,7 @@ impl Renderer {
             .as_bytes()
             .first()
             .is_some_and(|c| *c == b':' || *c == b'/')
+            || (view.mode == BufferMode::Insert && buffer.input.starts_with('^'))
         {
             self.context.draw_popup_above(
                 layout.num_rows,
@@ -553,6 +1766,19 @@ impl Renderer {
                 &self.theme,
                 false,
             );
+        } else if view.mode != BufferMode::Insert && !buffer.input.is_empty() {
+            // Partially entered commands are shown in the bottom right corner, like vim's showcmd
+            self.context.draw_popup_above(
+                layout.num_rows,
+                layout.num_cols.saturating_sub(buffer.input.len() + 2),
+                layout,
+                buffer.input.as_bytes(),
+                self.theme.selection_background_color,
+                self.theme.background_color,
+                None,
+                &self.theme,
+                false,
+            );
         }
     }
 
@@ -562,122 +1788,130 @@ impl Renderer {
         layout: &RenderLayout,
         view: &View,
         language_server: &Option<Rc<RefCell<LanguageServer>>>,
-    ) {
-        if let Some(server) = language_server {
-            if let Some(diagnostics) = server
+    ) -> Option<PopupBounds> {
+        let (line, col) = view.hover?;
+        let (row, view_col) = (
+            view.absolute_to_view_row(line) + 1,
+            view.absolute_to_view_col(buffer.piece_table.display_col(line, col)) + 1,
+        );
+
+        // Diagnostics under the hover take precedence over the hover information
+        let diagnostic_message = language_server.as_ref().and_then(|server| {
+            server
                 .borrow()
                 .saved_diagnostics
-                .get(&buffer.uri.to_lowercase())
-            {
-                if let Some((line, col)) = view.hover {
-                    if let Some(diagnostic) = diagnostics.iter().find(|diagnostic| {
-                        let (start_line, start_col) = (
-                            diagnostic.range.start.line as usize,
-                            diagnostic.range.start.character as usize,
-                        );
-                        let (end_line, end_col) = (
-                            diagnostic.range.end.line as usize,
-                            diagnostic.range.end.character as usize,
-                        );
-
-                        let diagnostic_on_cursor_line = buffer.mode == BufferMode::Insert
-                            && buffer.cursors.iter().any(|cursor| {
-                                (start_line..=end_line)
-                                    .contains(&buffer.piece_table.line_index(cursor.position))
-                            });
-
-                        !diagnostic_on_cursor_line
-                            && ((start_line == line && (start_col..=end_col).contains(&col))
-                                || (end_line == line && (start_col..=end_col).contains(&col))
-                                || (diagnostic.range.start.line as usize
-                                    ..diagnostic.range.end.line as usize)
-                                    .contains(&line))
-                    }) {
-                        let (row, col) = (
-                            view.absolute_to_view_row(line) + 1,
-                            view.absolute_to_view_col(col) + 1,
-                        );
-
-                        self.context.draw_popup_below(
-                            row,
-                            col,
-                            layout,
-                            diagnostic.message.as_bytes(),
-                            self.theme.selection_background_color,
-                            self.theme.background_color,
-                            None,
-                            &self.theme,
-                            true,
-                        );
-                    } else if let Some(hover_message) = &view.hover_message {
-                        // TODO: Rendering the hover message this way is pretty inefficient.
-                        // However, most hovers are not many thousands characters long..
-                        let (row, col) = (
-                            view.absolute_to_view_row(line) + 1,
-                            view.absolute_to_view_col(col) + 1,
-                        );
-
-                        let mut leading_lines = 0;
-                        let mut line_limit = 0;
-                        let mut offset = 0;
-                        let truncated_message: Vec<u8> = hover_message
-                            .message
-                            .as_bytes()
-                            .iter()
-                            .skip_while(|&x| {
-                                let skip = leading_lines < hover_message.line_offset;
-                                if *x == b'\n' {
-                                    leading_lines += 1;
-                                }
-                                offset += 1;
-                                skip
-                            })
-                            .take_while(|&x| {
-                                let limit_reached = line_limit < (layout.num_rows / 2);
-                                if *x == b'\n' {
-                                    line_limit += 1;
-                                }
-                                limit_reached
-                            })
-                            .copied()
-                            .collect();
-
-                        let mut offset_ranges = vec![];
-                        for range in &hover_message.code_block_ranges {
-                            offset_ranges.push((
-                                range.0.saturating_sub(offset),
-                                range.1.saturating_sub(offset),
-                            ));
-                        }
+                .get(&buffer.uri.to_lowercase())?
+                .iter()
+                .find(|diagnostic| {
+                    let (start_line, start_col) = (
+                        diagnostic.range.start.line as usize,
+                        diagnostic.range.start.character as usize,
+                    );
+                    let (end_line, end_col) = (
+                        diagnostic.range.end.line as usize,
+                        diagnostic.range.end.character as usize,
+                    );
 
-                        let mut effects = vec![];
-                        if let Some(syntect) = &buffer.syntect {
-                            effects =
-                                syntect.highlight_code_blocks(&truncated_message, &offset_ranges);
-                        }
+                    let diagnostic_on_cursor_line = view.mode == BufferMode::Insert
+                        && view.cursors.iter().any(|cursor| {
+                            (start_line..=end_line)
+                                .contains(&buffer.piece_table.line_index(cursor.position))
+                        });
+
+                    !diagnostic_on_cursor_line
+                        && ((start_line == line && (start_col..=end_col).contains(&col))
+                            || (end_line == line && (start_col..=end_col).contains(&col))
+                            || (diagnostic.range.start.line as usize
+                                ..diagnostic.range.end.line as usize)
+                                .contains(&line))
+                })
+                .map(|diagnostic| diagnostic.message.clone())
+        });
 
-                        self.context.draw_popup_below(
-                            row,
-                            col,
-                            layout,
-                            &truncated_message,
-                            self.theme.selection_background_color,
-                            self.theme.background_color,
-                            Some(&effects),
-                            &self.theme,
-                            true,
-                        );
-                    }
+        if let Some(message) = diagnostic_message {
+            return Some(self.context.draw_popup_below(
+                row,
+                view_col,
+                layout,
+                message.as_bytes(),
+                self.theme.selection_background_color,
+                self.theme.background_color,
+                None,
+                &self.theme,
+                true,
+            ));
+        }
+
+        let hover_message = view.hover_message.as_ref()?;
+
+        // TODO: Rendering the hover message this way is pretty inefficient.
+        // However, most hovers are not many thousands characters long..
+        let mut leading_lines = 0;
+        let mut line_limit = 0;
+        let mut offset = 0;
+        let truncated_message: Vec<u8> = hover_message
+            .message
+            .as_bytes()
+            .iter()
+            .skip_while(|&x| {
+                let skip = leading_lines < hover_message.line_offset;
+                if *x == b'\n' {
+                    leading_lines += 1;
                 }
-            }
+                offset += 1;
+                skip
+            })
+            .take_while(|&x| {
+                let limit_reached = line_limit < (layout.num_rows / 2);
+                if *x == b'\n' {
+                    line_limit += 1;
+                }
+                limit_reached
+            })
+            .copied()
+            .collect();
+
+        let mut offset_ranges = vec![];
+        for range in &hover_message.code_block_ranges {
+            offset_ranges.push((
+                range.0.saturating_sub(offset),
+                range.1.saturating_sub(offset),
+            ));
         }
+
+        let mut effects = vec![];
+        if let Some(syntect) = &buffer.syntect {
+            effects = syntect.highlight_code_blocks(&truncated_message, &offset_ranges);
+        }
+
+        Some(self.context.draw_popup_below(
+            row,
+            view_col,
+            layout,
+            &truncated_message,
+            self.theme.selection_background_color,
+            self.theme.background_color,
+            Some(&effects),
+            &self.theme,
+            true,
+        ))
     }
 
     pub fn draw_numbers(&mut self, buffer: &Buffer, layout: &RenderLayout, view: &View) {
         let mut numbers = String::default();
         let num_lines = buffer.piece_table.num_lines();
-        for line in view.line_offset + 1..=min(view.line_offset + 1 + layout.num_rows, num_lines) {
-            numbers.push_str(line.to_string().as_str());
+        let cursor_line = view
+            .cursors
+            .last()
+            .map_or(0, |cursor| buffer.piece_table.line_index(cursor.position));
+        for line in view.line_offset..min(view.line_offset + 1 + layout.num_rows, num_lines) {
+            let number = match (self.number, self.relative_number) {
+                (false, false) => break,
+                (true, false) => line + 1,
+                (true, true) if line == cursor_line => line + 1,
+                (_, true) => line.abs_diff(cursor_line),
+            };
+            numbers.push_str(number.to_string().as_str());
             numbers.push(b'\n' as char);
         }
 
@@ -701,6 +1935,33 @@ impl Renderer {
             &self.theme,
             true,
         );
+
+        for decoration in &buffer.decorations {
+            let DecorationKind::GutterSign(sign, color) = decoration.kind else {
+                continue;
+            };
+            if decoration.start > buffer.piece_table.num_chars() {
+                continue;
+            }
+            let line = buffer.piece_table.line_index(decoration.start);
+            if (view.line_offset..view.line_offset + layout.num_rows).contains(&line) {
+                let mut bytes = [0; 4];
+                let sign = sign.encode_utf8(&mut bytes);
+                self.context.draw_text(
+                    view.absolute_to_view_row(line),
+                    0,
+                    layout,
+                    sign.as_bytes(),
+                    &[TextEffect {
+                        kind: TextEffectKind::ForegroundColor(color),
+                        start: 0,
+                        length: sign.len(),
+                    }],
+                    &self.theme,
+                    true,
+                );
+            }
+        }
     }
 
     pub fn draw_split(&mut self, window: &Window) {
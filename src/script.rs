@@ -0,0 +1,442 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::mpsc::Receiver,
+};
+
+use serde_json::Value;
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+use crate::{
+    buffer::Buffer, command::BufferMode, config, editor::EditorCommand, keymap,
+    language_server::LanguageServer, renderer::RenderLayout, theme::THEMES, view::View,
+};
+
+// Drives buffers without a window from a script, so that the vim emulation can be regression
+// tested headlessly. Run with "nimble --script FILE", the exit code is 1 if anything failed.
+//
+//   # comment
+//   lsp                           Record the notifications for the files opened after this
+//   config PATH                   Apply a config.toml to the files opened after this
+//   open PATH                     Open a file, relative to the script
+//   keymap PATH                   Use the mappings of a keymap.toml, relative to the script
+//   input dwiHello<Esc><C-r>      Type characters, <...> are keys with optional C-/S-/A- modifiers
+//   expect text Hello\nWorld      Compare the whole buffer, \n \t and \\ are unescaped
+//   expect cursors 0:5 1:0        Line:col (zero based) of every cursor
+//   expect mode insert            normal, insert, visual or visualline
+//   expect notification METHOD {"a":1}
+//                                 The oldest unchecked notification, its params have to contain
+//                                 the given JSON if there is one, "none" means that none are left
+
+const LETTERS: [VirtualKeyCode; 26] = [
+    VirtualKeyCode::A,
+    VirtualKeyCode::B,
+    VirtualKeyCode::C,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::F,
+    VirtualKeyCode::G,
+    VirtualKeyCode::H,
+    VirtualKeyCode::I,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+    VirtualKeyCode::N,
+    VirtualKeyCode::O,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::R,
+    VirtualKeyCode::S,
+    VirtualKeyCode::T,
+    VirtualKeyCode::U,
+    VirtualKeyCode::V,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+];
+
+const DIGITS: [VirtualKeyCode; 10] = [
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+];
+
+// A key press as the window would report it, followed by the character it produces
+struct KeyEvent {
+    key_code: Option<VirtualKeyCode>,
+    modifiers: ModifiersState,
+    c: Option<char>,
+}
+
+struct ScriptRunner {
+    buffer: Option<Buffer>,
+    view: View,
+    layout: RenderLayout,
+    failures: usize,
+    record_notifications: bool,
+    config: Option<PathBuf>,
+    messages: Option<Receiver<String>>,
+    notifications: VecDeque<(String, Value)>,
+}
+
+// Returns the number of failures
+pub fn run(path: &str) -> usize {
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(error) => {
+            eprintln!("Failed to read {}: {}", path, error);
+            return 1;
+        }
+    };
+    let directory = Path::new(path).parent().unwrap_or(Path::new("."));
+
+    let mut runner = ScriptRunner {
+        buffer: None,
+        view: View::new(),
+        layout: RenderLayout {
+            row_offset: 0,
+            col_offset: 0,
+            num_rows: 50,
+            num_cols: 120,
+        },
+        failures: 0,
+        record_notifications: false,
+        config: None,
+        messages: None,
+        notifications: VecDeque::new(),
+    };
+
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let result = match command {
+            "lsp" => {
+                runner.record_notifications = true;
+                Ok(())
+            }
+            "config" => {
+                runner.config = Some(directory.join(argument));
+                Ok(())
+            }
+            "open" => runner.open(&directory.join(argument)),
+            "keymap" => {
+                keymap::load_file(&directory.join(argument));
+                Ok(())
+            }
+            "input" => runner.input(argument),
+            "expect" => runner.expect(argument),
+            _ => Err(format!("Unknown command {}", command)),
+        };
+        if let Err(message) = result {
+            eprintln!("{}:{}: {}", path, i + 1, message);
+            runner.failures += 1;
+        }
+    }
+
+    println!("{}: {} failure(s)", path, runner.failures);
+    runner.failures
+}
+
+impl ScriptRunner {
+    fn open(&mut self, path: &Path) -> Result<(), String> {
+        let path = path
+            .canonicalize()
+            .map_err(|error| format!("Failed to open {}: {}", path.display(), error))?;
+        let mut buffer = Buffer::new(None, &path.to_string_lossy(), &THEMES[0], None);
+        if let Some(config) = &self.config {
+            buffer.apply_settings(&config::settings_from_file(
+                config,
+                buffer.language.map(|language| language.identifier),
+            ));
+        }
+        self.messages = None;
+        self.notifications.clear();
+        if let Some(language) = buffer.language.filter(|_| self.record_notifications) {
+            let (server, messages) = LanguageServer::recorder(language);
+            let server = Rc::new(RefCell::new(server));
+            buffer.send_did_open(&mut server.borrow_mut());
+            buffer.language_server = Some(server);
+            self.messages = Some(messages);
+        }
+        self.buffer = Some(buffer);
+        self.view = View::new();
+        self.view = View::new();
+        Ok(())
+    }
+
+    fn input(&mut self, input: &str) -> Result<(), String> {
+        let Some(buffer) = &mut self.buffer else {
+            return Err("No file is open".to_string());
+        };
+
+        for event in parse_input(input)? {
+            let mut editor_commands = vec![];
+            if let Some(key_code) = event.key_code {
+                editor_commands.extend(buffer.handle_key(
+                    key_code,
+                    Some(event.modifiers),
+                    &mut self.view,
+                    &self.layout,
+                ));
+            }
+            // The window drops characters typed with Ctrl unless AltGr (Ctrl + Alt) is involved
+            if let Some(c) = event.c {
+                if !event.modifiers.contains(ModifiersState::CTRL)
+                    || event.modifiers.contains(ModifiersState::ALT)
+                {
+                    editor_commands.extend(buffer.handle_char(&mut self.view, c));
+                }
+            }
+
+            for editor_command in editor_commands {
+                match editor_command {
+                    EditorCommand::CenterView => self.view.center(buffer, &self.layout),
+                    EditorCommand::RevealJump => self.view.reveal_jump(buffer, &self.layout),
+                    EditorCommand::CompleteWord(forward) => {
+                        buffer.complete_word(&mut self.view, None, forward)
+                    }
+                    _ => (),
+                }
+            }
+            // Large pastes are normally finished over the next frames
+            while buffer.paste_progress().is_some() {
+                buffer.update_paste(&mut self.view);
+            }
+            self.view.adjust(buffer, &self.layout);
+            buffer.sync_views(std::slice::from_mut(&mut self.view));
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, expectation: &str) -> Result<(), String> {
+        let Some(buffer) = &self.buffer else {
+            return Err("No file is open".to_string());
+        };
+
+        let (kind, expected) = expectation.split_once(' ').unwrap_or((expectation, ""));
+        let actual = match kind {
+            "notification" => return self.expect_notification(expected),
+            "text" => {
+                let text: Vec<u8> = buffer.piece_table.iter_chars().collect();
+                let expected = unescape(expected);
+                if text == expected.as_bytes() {
+                    return Ok(());
+                }
+                return Err(format!(
+                    "Expected text {:?}, got {:?}",
+                    expected,
+                    String::from_utf8_lossy(&text)
+                ));
+            }
+            "cursors" => self
+                .view
+                .cursors
+                .iter()
+                .map(|cursor| {
+                    let (line, col) = cursor.get_line_col(&buffer.piece_table);
+                    format!("{}:{}", line, col)
+                })
+                .collect::<Vec<String>>()
+                .join(" "),
+            "mode" => match self.view.mode {
+                BufferMode::Normal => "normal",
+                BufferMode::Insert => "insert",
+                BufferMode::Visual => "visual",
+                BufferMode::VisualLine => "visualline",
+            }
+            .to_string(),
+            _ => return Err(format!("Unknown expectation {}", kind)),
+        };
+
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(format!("Expected {} {}, got {}", kind, expected, actual))
+        }
+    }
+
+    fn expect_notification(&mut self, expected: &str) -> Result<(), String> {
+        let Some(messages) = &self.messages else {
+            return Err("No notifications are recorded for this file".to_string());
+        };
+
+        // Requests are sent through the same channel, only messages without an id are kept
+        for message in messages.try_iter() {
+            let Some((_, content)) = message.split_once("\r\n\r\n") else {
+                continue;
+            };
+            let Ok(mut message) = serde_json::from_str::<Value>(content) else {
+                continue;
+            };
+            if let (None, Some(Value::String(method))) = (message.get("id"), message.get("method"))
+            {
+                let method = method.clone();
+                let params = message
+                    .get_mut("params")
+                    .map(Value::take)
+                    .unwrap_or_default();
+                self.notifications.push_back((method, params));
+            }
+        }
+
+        let (method, params) = expected.split_once(' ').unwrap_or((expected, ""));
+        if method == "none" {
+            return match self.notifications.pop_front() {
+                Some((actual, _)) => Err(format!("Expected no notification, got {}", actual)),
+                None => Ok(()),
+            };
+        }
+
+        let Some((actual, actual_params)) = self.notifications.pop_front() else {
+            return Err(format!("Expected notification {}, got none", method));
+        };
+        if actual != method {
+            return Err(format!("Expected notification {}, got {}", method, actual));
+        }
+        if !params.is_empty() {
+            let params: Value = serde_json::from_str(params)
+                .map_err(|error| format!("Invalid JSON {}: {}", params, error))?;
+            if !json_contains(&actual_params, &params) {
+                return Err(format!(
+                    "Expected {} params containing {}, got {}",
+                    method, params, actual_params
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Objects only have to contain the expected keys, everything else has to match exactly
+fn json_contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => expected.iter().all(|(key, value)| {
+            actual
+                .get(key)
+                .is_some_and(|actual| json_contains(actual, value))
+        }),
+        (Value::Array(actual), Value::Array(expected)) => {
+            actual.len() == expected.len()
+                && actual
+                    .iter()
+                    .zip(expected)
+                    .all(|(actual, expected)| json_contains(actual, expected))
+        }
+        _ => actual == expected,
+    }
+}
+
+fn parse_input(input: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut events = vec![];
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        if c != '<' {
+            events.push(char_event(c, ModifiersState::empty()));
+            continue;
+        }
+
+        let name: String = chars.by_ref().take_while(|c| *c != '>').collect();
+        events.push(parse_key(&name).ok_or(format!("Unknown key <{}>", name))?);
+    }
+    Ok(events)
+}
+
+fn parse_key(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = ModifiersState::empty();
+    let mut name = name;
+    while let Some((modifier, rest)) = name.split_once('-').filter(|(_, rest)| !rest.is_empty()) {
+        match modifier {
+            "C" => modifiers |= ModifiersState::CTRL,
+            "S" => modifiers |= ModifiersState::SHIFT,
+            "A" => modifiers |= ModifiersState::ALT,
+            _ => return None,
+        }
+        name = rest;
+    }
+
+    let (key_code, c) = match name {
+        "Esc" => (VirtualKeyCode::Escape, Some('\u{1b}')),
+        "CR" => (VirtualKeyCode::Return, Some('\r')),
+        "BS" => (VirtualKeyCode::Back, Some('\u{8}')),
+        "Tab" => (VirtualKeyCode::Tab, Some('\t')),
+        "Space" => (VirtualKeyCode::Space, Some(' ')),
+        "lt" => return Some(char_event('<', modifiers)),
+        "Del" => (VirtualKeyCode::Delete, None),
+        "Up" => (VirtualKeyCode::Up, None),
+        "Down" => (VirtualKeyCode::Down, None),
+        "Left" => (VirtualKeyCode::Left, None),
+        "Right" => (VirtualKeyCode::Right, None),
+        "Home" => (VirtualKeyCode::Home, None),
+        "End" => (VirtualKeyCode::End, None),
+        "PageUp" => (VirtualKeyCode::PageUp, None),
+        "PageDown" => (VirtualKeyCode::PageDown, None),
+        _ => {
+            let mut chars = name.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(char_event(c, modifiers)),
+                _ => None,
+            };
+        }
+    };
+
+    Some(KeyEvent {
+        key_code: Some(key_code),
+        modifiers,
+        c,
+    })
+}
+
+fn char_event(c: char, mut modifiers: ModifiersState) -> KeyEvent {
+    let key_code = if c.is_ascii_alphabetic() {
+        if c.is_ascii_uppercase() {
+            modifiers |= ModifiersState::SHIFT;
+        }
+        Some(LETTERS[(c.to_ascii_lowercase() as u8 - b'a') as usize])
+    } else if c.is_ascii_digit() {
+        Some(DIGITS[(c as u8 - b'0') as usize])
+    } else if c == ' ' {
+        Some(VirtualKeyCode::Space)
+    } else {
+        None
+    };
+
+    KeyEvent {
+        key_code,
+        modifiers,
+        c: Some(c),
+    }
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,160 @@
+use std::{cell::RefCell, rc::Rc};
+
+use serde_json::Value;
+
+use crate::{
+    language_server::LanguageServer,
+    language_server_types::{SymbolInformation, WorkspaceSymbolParams},
+    picker::Picker,
+    text_utils,
+};
+
+enum SymbolSource {
+    // Asked for the symbols matching the query every time it changes, answers to earlier queries
+    // are dropped
+    LanguageServer {
+        server: Rc<RefCell<LanguageServer>>,
+        request: Option<i32>,
+    },
+    // The symbols found by the workspace index, matched against the query here
+    Index(Vec<SymbolInformation>),
+}
+
+// The symbols of the workspace, from the language server or the workspace index, ranked by how well
+// they match the query
+pub struct SymbolPanel {
+    pub picker: Picker<SymbolInformation>,
+    source: SymbolSource,
+}
+
+impl SymbolPanel {
+    pub fn from_server(server: Rc<RefCell<LanguageServer>>, query: &str) -> Self {
+        let mut symbol_panel = Self {
+            picker: Picker::new(query),
+            source: SymbolSource::LanguageServer {
+                server,
+                request: None,
+            },
+        };
+        symbol_panel.search();
+        symbol_panel
+    }
+
+    pub fn from_index(symbols: Vec<SymbolInformation>, query: &str) -> Self {
+        let mut symbol_panel = Self {
+            picker: Picker::new(query),
+            source: SymbolSource::Index(symbols),
+        };
+        symbol_panel.search();
+        symbol_panel
+    }
+
+    pub fn searching(&self) -> bool {
+        matches!(
+            self.source,
+            SymbolSource::LanguageServer {
+                request: Some(_),
+                ..
+            }
+        )
+    }
+
+    pub fn is_from_server(&self, server: &Rc<RefCell<LanguageServer>>) -> bool {
+        matches!(&self.source, SymbolSource::LanguageServer { server: s, .. } if Rc::ptr_eq(s, server))
+    }
+
+    // Returns whether the response was for the latest query. Symbols without a range (workspace
+    // symbols that need resolving) can't be jumped to
+    pub fn update_symbols(&mut self, id: i32, value: Option<Value>) -> bool {
+        let SymbolSource::LanguageServer { request, .. } = &mut self.source else {
+            return false;
+        };
+        if *request != Some(id) {
+            return false;
+        }
+        *request = None;
+
+        let symbols = value
+            .and_then(|value| serde_json::from_value::<Vec<Value>>(value).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|symbol| serde_json::from_value::<SymbolInformation>(symbol).ok())
+            .collect();
+        self.set_ranked_symbols(symbols);
+        true
+    }
+
+    pub fn search(&mut self) {
+        match &mut self.source {
+            SymbolSource::LanguageServer { server, request } => {
+                let mut server = server.borrow_mut();
+                if let Some(id) = request.take() {
+                    server.cancel_request(id);
+                }
+                *request = server.send_request(
+                    "workspace/symbol",
+                    WorkspaceSymbolParams {
+                        query: self.picker.query.clone(),
+                    },
+                );
+            }
+            SymbolSource::Index(symbols) => {
+                let symbols = symbols.clone();
+                self.set_ranked_symbols(symbols);
+            }
+        }
+    }
+
+    fn set_ranked_symbols(&mut self, symbols: Vec<SymbolInformation>) {
+        let mut symbols: Vec<(isize, SymbolInformation)> = symbols
+            .into_iter()
+            .map(|symbol| {
+                let score =
+                    text_utils::fuzzy_match(self.picker.query.as_bytes(), symbol.name.as_bytes());
+                (score, symbol)
+            })
+            .collect();
+        symbols.sort_by(|(score1, _), (score2, _)| score2.cmp(score1));
+        self.picker
+            .set_items(symbols.into_iter().map(|(_, symbol)| symbol).collect());
+    }
+}
+
+impl Drop for SymbolPanel {
+    fn drop(&mut self) {
+        if let SymbolSource::LanguageServer {
+            server,
+            request: Some(id),
+        } = &self.source
+        {
+            if let Ok(mut server) = server.try_borrow_mut() {
+                server.cancel_request(*id);
+            }
+        }
+    }
+}
+
+pub fn symbol_kind_name(kind: u32) -> &'static str {
+    match kind {
+        1 => "file",
+        2 => "module",
+        3 => "namespace",
+        4 => "package",
+        5 => "class",
+        6 => "method",
+        7 => "property",
+        8 => "field",
+        9 => "constructor",
+        10 => "enum",
+        11 => "interface",
+        12 => "function",
+        13 => "variable",
+        14 => "constant",
+        22 => "enum member",
+        23 => "struct",
+        24 => "event",
+        25 => "operator",
+        26 => "type parameter",
+        _ => "symbol",
+    }
+}
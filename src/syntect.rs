@@ -1,11 +1,12 @@
 use std::{
+    cell::RefCell,
     cmp::min,
     collections::{HashMap, VecDeque},
-    path::Path,
     str::FromStr,
-    sync::{Arc, Mutex, RwLock},
-    thread,
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock, RwLock,
+    },
 };
 
 use syntect::{
@@ -20,6 +21,7 @@ use syntect::{
 use crate::{
     piece_table::PieceTable,
     renderer::{TextEffect, TextEffectKind},
+    thread_pool::{self, CancellationToken, Priority},
 };
 
 impl From<crate::renderer::Color> for Color {
@@ -34,54 +36,150 @@ impl From<crate::renderer::Color> for Color {
 }
 
 pub const SYNTECT_CACHE_FREQUENCY: usize = 100;
+// Parse states live on the highlight workers and can't be measured, this is a rough average
+const PARSE_STATE_SIZE_ESTIMATE: usize = 8 * 1024;
+
+// Syntax definitions and converted themes are shared by all buffers and highlight threads
+static SYNTAX_SET: OnceLock<Arc<SyntaxSet>> = OnceLock::new();
+static CONVERTED_THEMES: Mutex<Vec<(crate::theme::Theme, Arc<Theme>)>> = Mutex::new(Vec::new());
+
+// Loading the syntax definitions takes a while, so it starts on a worker at startup. Until it is
+// done buffers are shown without highlighting instead of blocking the window
+pub fn preload_syntax_set() {
+    thread_pool::spawn(Priority::High, || {
+        shared_syntax_set();
+    });
+}
+
+fn shared_syntax_set() -> Arc<SyntaxSet> {
+    Arc::clone(SYNTAX_SET.get_or_init(|| {
+        Arc::new(
+            from_uncompressed_data(include_bytes!("../resources/syntax_definitions.packdump"))
+                .unwrap(),
+        )
+    }))
+}
+
+fn shared_theme(theme: &crate::theme::Theme) -> Arc<Theme> {
+    let mut converted_themes = CONVERTED_THEMES.lock().unwrap();
+    if let Some((_, converted_theme)) = converted_themes.iter().find(|(t, _)| t == theme) {
+        return Arc::clone(converted_theme);
+    }
+    let converted_theme = Arc::new(convert_theme(theme));
+    converted_themes.push((*theme, Arc::clone(&converted_theme)));
+    converted_theme
+}
 
 pub struct IndexedLine {
     pub index: usize,
     pub text: Vec<u8>,
 }
 
+type ParseStates = HashMap<usize, (ParseState, HighlightState)>;
+
+static NEXT_SYNTECT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Parse states can't be sent between threads, so every buffer is pinned to one pool worker
+    // which keeps the states of its chunks here, keyed by the id of the buffer's Syntect
+    static PARSE_STATES: RefCell<HashMap<usize, ParseStates>> = RefCell::new(HashMap::new());
+}
+
 pub struct Syntect {
-    pub queue: Arc<Mutex<VecDeque<IndexedLine>>>,
     pub cache_updated: Arc<Mutex<bool>>,
+    id: usize,
+    queue: Arc<Mutex<VecDeque<IndexedLine>>>,
     cache: Arc<RwLock<HashMap<usize, Vec<TextEffect>>>>,
-    theme: Theme,
-    syntax_set: SyntaxSet,
+    worker_running: Arc<AtomicBool>,
+    cancellation: CancellationToken,
+    theme: Arc<Theme>,
     extension: String,
 }
 
-impl Syntect {
-    pub fn new(path: &str, theme: &crate::theme::Theme) -> Option<Self> {
-        let queue = Arc::new(Mutex::new(VecDeque::new()));
-        let cache_updated = Arc::new(Mutex::new(false));
-        let cache = Arc::new(RwLock::new(HashMap::new()));
-
-        let theme = convert_theme(theme);
-        let extension = Path::new(path).extension()?.to_str()?.to_string();
-        let syntax_set: SyntaxSet =
-            from_uncompressed_data(include_bytes!("../resources/syntax_definitions.packdump"))
-                .unwrap();
-
-        start_highlight_thread(
-            path,
-            theme.clone(),
-            Arc::clone(&queue),
-            Arc::clone(&cache_updated),
-            Arc::clone(&cache),
-        )?;
+impl Drop for Syntect {
+    fn drop(&mut self) {
+        self.cancellation.cancel();
+        remove_parse_states(self.id, Priority::Low);
+    }
+}
 
+impl Syntect {
+    pub fn new(filetype: &str, theme: &crate::theme::Theme) -> Option<Self> {
         Some(Self {
-            queue,
-            cache_updated,
-            cache,
-            theme,
-            syntax_set,
-            extension,
+            cache_updated: Arc::new(Mutex::new(false)),
+            id: NEXT_SYNTECT_ID.fetch_add(1, Ordering::Relaxed),
+            queue: Arc::new(Mutex::new(VecDeque::new())),
+            cache: Arc::new(RwLock::new(HashMap::new())),
+            worker_running: Arc::new(AtomicBool::new(false)),
+            cancellation: CancellationToken::default(),
+            theme: shared_theme(theme),
+            extension: filetype.to_string(),
         })
     }
 
+    pub fn enqueue(&self, indexed_line: IndexedLine) {
+        self.queue.lock().unwrap().push_back(indexed_line);
+
+        // At most one job per buffer, chunks depend on the parse state of the previous chunk
+        if !self.worker_running.swap(true, Ordering::SeqCst) {
+            let queue = Arc::clone(&self.queue);
+            let cache_updated = Arc::clone(&self.cache_updated);
+            let cache = Arc::clone(&self.cache);
+            let worker_running = Arc::clone(&self.worker_running);
+            let cancellation = self.cancellation.clone();
+            let theme = Arc::clone(&self.theme);
+            let extension = self.extension.clone();
+            let id = self.id;
+            thread_pool::spawn_pinned(id, Priority::High, move || {
+                highlight_queued_lines(
+                    id,
+                    &extension,
+                    &theme,
+                    &shared_syntax_set(),
+                    &queue,
+                    &cache_updated,
+                    &cache,
+                    &worker_running,
+                    &cancellation,
+                );
+            });
+        }
+    }
+
+    pub fn clear_queue(&self) {
+        self.queue.lock().unwrap().clear();
+    }
+
+    pub fn queue_length(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.cache
+            .read()
+            .unwrap()
+            .values()
+            .map(|effects| {
+                effects.capacity() * std::mem::size_of::<TextEffect>() + PARSE_STATE_SIZE_ESTIMATE
+            })
+            .sum()
+    }
+
+    // Drops the highlights and parse states, every chunk has to be highlighted again afterwards.
+    // The chunk being highlighted right now may still be cached, it is overwritten once requeued.
+    pub fn evict(&mut self) {
+        self.clear_queue();
+        self.cache.write().unwrap().clear();
+        // Jobs of the same priority run in order, so chunks requeued later start from scratch
+        remove_parse_states(self.id, Priority::High);
+    }
+
     pub fn highlight_code_blocks(&self, text: &[u8], ranges: &[(usize, usize)]) -> Vec<TextEffect> {
+        let Some(syntax_set) = SYNTAX_SET.get() else {
+            return vec![];
+        };
         let highlighter = Highlighter::new(&self.theme);
-        let syntax_reference = self.syntax_set.find_syntax_by_extension(&self.extension);
+        let syntax_reference = syntax_set.find_syntax_by_extension(&self.extension);
         if syntax_reference.is_none() {
             return vec![];
         }
@@ -110,7 +208,7 @@ impl Syntect {
             let mut offset = 0;
             for line in code_block.split_inclusive(|c| *c == b'\n') {
                 let line = unsafe { std::str::from_utf8_unchecked(line) };
-                let ops = parse_state.parse_line(line, &self.syntax_set).unwrap();
+                let ops = parse_state.parse_line(line, syntax_set).unwrap();
                 for highlight in
                     RangedHighlightIterator::new(&mut highlight_state, &ops, line, &highlighter)
                 {
@@ -136,6 +234,52 @@ impl Syntect {
         effects
     }
 
+    pub fn highlight_text(&self, text: &[u8]) -> Vec<TextEffect> {
+        let Some(syntax_set) = SYNTAX_SET.get() else {
+            return vec![];
+        };
+        let highlighter = Highlighter::new(&self.theme);
+        let syntax_reference = match syntax_set.find_syntax_by_extension(&self.extension) {
+            Some(syntax_reference) => syntax_reference,
+            None => return vec![],
+        };
+
+        let mut parse_state = ParseState::new(syntax_reference);
+        let mut highlight_state = HighlightState::new(&highlighter, ScopeStack::new());
+
+        let mut effects = vec![];
+        let mut offset = 0;
+        for line in text.split_inclusive(|c| *c == b'\n') {
+            let line = unsafe { std::str::from_utf8_unchecked(line) };
+            let ops = parse_state.parse_line(line, syntax_set).unwrap();
+            for highlight in
+                RangedHighlightIterator::new(&mut highlight_state, &ops, line, &highlighter)
+            {
+                effects.push(TextEffect {
+                    kind: TextEffectKind::ForegroundColor(crate::renderer::Color::from_rgb(
+                        highlight.0.foreground.r,
+                        highlight.0.foreground.g,
+                        highlight.0.foreground.b,
+                    )),
+                    start: offset + highlight.2.start,
+                    length: highlight.2.len(),
+                });
+            }
+            offset += line.len();
+        }
+
+        effects
+    }
+
+    pub fn theme_colors(&self) -> (crate::renderer::Color, crate::renderer::Color) {
+        let foreground = self.theme.settings.foreground.unwrap_or(Color::BLACK);
+        let background = self.theme.settings.background.unwrap_or(Color::WHITE);
+        (
+            crate::renderer::Color::from_rgb(foreground.r, foreground.g, foreground.b),
+            crate::renderer::Color::from_rgb(background.r, background.g, background.b),
+        )
+    }
+
     pub fn delete_rebalance(&mut self, piece_table: &PieceTable, position: usize, end: usize) {
         let start_index = piece_table.line_index(position) / SYNTECT_CACHE_FREQUENCY;
         if let Some(start_cache_offset) =
@@ -225,80 +369,93 @@ impl Syntect {
     }
 }
 
-fn start_highlight_thread(
-    path: &str,
-    theme: Theme,
-    queue: Arc<Mutex<VecDeque<IndexedLine>>>,
-    cache_updated: Arc<Mutex<bool>>,
-    cache: Arc<RwLock<HashMap<usize, Vec<TextEffect>>>>,
-) -> Option<()> {
-    let extension = Path::new(path).extension()?.to_str()?.to_string();
-
-    thread::spawn(move || {
-        let mut internal_cache = HashMap::new();
-        let syntax_set: SyntaxSet =
-            from_uncompressed_data(include_bytes!("../resources/syntax_definitions.packdump"))
-                .unwrap();
-        let highlighter = Highlighter::new(&theme);
-        let syntax_reference = syntax_set.find_syntax_by_extension(&extension);
-        if syntax_reference.is_none() {
-            return;
-        }
-
-        loop {
-            thread::sleep(Duration::from_micros(8333));
-            let (start, text) = if let Some(indexed_line) = queue.lock().unwrap().pop_front() {
-                (indexed_line.index, indexed_line.text)
-            } else {
-                continue;
-            };
-
-            let index = start / SYNTECT_CACHE_FREQUENCY;
-
-            let (mut parse_state, mut highlight_state) = if index > 0 {
-                internal_cache.get(&(index - 1)).cloned().unwrap_or((
-                    ParseState::new(syntax_reference.unwrap()),
-                    HighlightState::new(&highlighter, ScopeStack::new()),
-                ))
-            } else {
-                (
-                    ParseState::new(syntax_reference.unwrap()),
-                    HighlightState::new(&highlighter, ScopeStack::new()),
-                )
-            };
+// Runs after the current highlight job of the buffer, which is pinned to the same worker
+fn remove_parse_states(id: usize, priority: Priority) {
+    thread_pool::spawn_pinned(id, priority, move || {
+        PARSE_STATES.with(|parse_states| parse_states.borrow_mut().remove(&id));
+    });
+}
 
-            let mut effects = vec![];
-            let mut offset = 0;
-            for line in text.split_inclusive(|c| *c == b'\n') {
-                let line = unsafe { std::str::from_utf8_unchecked(line) };
-                let ops = parse_state.parse_line(line, &syntax_set).unwrap();
-                for highlight in
-                    RangedHighlightIterator::new(&mut highlight_state, &ops, line, &highlighter)
-                {
-                    effects.push(TextEffect {
-                        kind: TextEffectKind::ForegroundColor(crate::renderer::Color::from_rgb(
-                            highlight.0.foreground.r,
-                            highlight.0.foreground.g,
-                            highlight.0.foreground.b,
-                        )),
-                        start: offset + highlight.2.start,
-                        length: highlight.2.len(),
-                    });
-                }
-                offset += line.len();
+#[allow(clippy::too_many_arguments)]
+fn highlight_queued_lines(
+    id: usize,
+    extension: &str,
+    theme: &Theme,
+    syntax_set: &SyntaxSet,
+    queue: &Mutex<VecDeque<IndexedLine>>,
+    cache_updated: &Mutex<bool>,
+    cache: &RwLock<HashMap<usize, Vec<TextEffect>>>,
+    worker_running: &AtomicBool,
+    cancellation: &CancellationToken,
+) {
+    let highlighter = Highlighter::new(theme);
+    let Some(syntax_reference) = syntax_set.find_syntax_by_extension(extension) else {
+        queue.lock().unwrap().clear();
+        worker_running.store(false, Ordering::SeqCst);
+        return;
+    };
+
+    while !cancellation.is_cancelled() {
+        let Some(indexed_line) = queue.lock().unwrap().pop_front() else {
+            worker_running.store(false, Ordering::SeqCst);
+            // A line may have been queued after the pop but before the flag was cleared
+            if queue.lock().unwrap().is_empty() || worker_running.swap(true, Ordering::SeqCst) {
+                return;
             }
+            continue;
+        };
+        let (start, text) = (indexed_line.index, indexed_line.text);
+
+        let index = start / SYNTECT_CACHE_FREQUENCY;
+
+        let previous_state = index.checked_sub(1).and_then(|previous_index| {
+            PARSE_STATES.with(|parse_states| {
+                parse_states
+                    .borrow()
+                    .get(&id)
+                    .and_then(|states| states.get(&previous_index).cloned())
+            })
+        });
+        let (mut parse_state, mut highlight_state) = previous_state.unwrap_or((
+            ParseState::new(syntax_reference),
+            HighlightState::new(&highlighter, ScopeStack::new()),
+        ));
 
+        let mut effects = vec![];
+        let mut offset = 0;
+        for line in text.split_inclusive(|c| *c == b'\n') {
+            let line = unsafe { std::str::from_utf8_unchecked(line) };
+            let ops = parse_state.parse_line(line, syntax_set).unwrap();
+            for highlight in
+                RangedHighlightIterator::new(&mut highlight_state, &ops, line, &highlighter)
             {
-                let mut cache = cache.write().unwrap();
-                cache.insert(index, effects);
-                *cache_updated.lock().unwrap() = true;
+                effects.push(TextEffect {
+                    kind: TextEffectKind::ForegroundColor(crate::renderer::Color::from_rgb(
+                        highlight.0.foreground.r,
+                        highlight.0.foreground.g,
+                        highlight.0.foreground.b,
+                    )),
+                    start: offset + highlight.2.start,
+                    length: highlight.2.len(),
+                });
             }
+            offset += line.len();
+        }
 
-            internal_cache.insert(index, (parse_state, highlight_state));
+        {
+            let mut cache = cache.write().unwrap();
+            cache.insert(index, effects);
+            *cache_updated.lock().unwrap() = true;
         }
-    });
 
-    Some(())
+        PARSE_STATES.with(|parse_states| {
+            parse_states
+                .borrow_mut()
+                .entry(id)
+                .or_default()
+                .insert(index, (parse_state, highlight_state));
+        });
+    }
 }
 
 fn convert_theme(theme: &crate::theme::Theme) -> Theme {
@@ -47,6 +47,42 @@ const EVERFOREST_LIGHT_PALETTE: Palette = Palette {
     pink: Color::from_rgb(223, 105, 186),
 };
 
+const HIGH_CONTRAST_DARK_PALETTE: Palette = Palette {
+    bg0: Color::from_rgb(0, 0, 0),
+    bg1: Color::from_rgb(0, 70, 140),
+    bg2: Color::from_rgb(200, 200, 200),
+    bg_dim: Color::from_rgb(24, 24, 24),
+    fg0: Color::from_rgb(255, 255, 255),
+    red: Color::from_rgb(255, 110, 110),
+    orange: Color::from_rgb(255, 170, 60),
+    yellow: Color::from_rgb(255, 235, 60),
+    green: Color::from_rgb(120, 255, 120),
+    aqua: Color::from_rgb(80, 255, 220),
+    blue: Color::from_rgb(110, 190, 255),
+    pink: Color::from_rgb(255, 130, 230),
+};
+
+const HIGH_CONTRAST_LIGHT_PALETTE: Palette = Palette {
+    bg0: Color::from_rgb(255, 255, 255),
+    bg1: Color::from_rgb(180, 215, 255),
+    bg2: Color::from_rgb(60, 60, 60),
+    bg_dim: Color::from_rgb(230, 230, 230),
+    fg0: Color::from_rgb(0, 0, 0),
+    red: Color::from_rgb(180, 0, 0),
+    orange: Color::from_rgb(160, 70, 0),
+    yellow: Color::from_rgb(120, 90, 0),
+    green: Color::from_rgb(0, 110, 0),
+    aqua: Color::from_rgb(0, 110, 110),
+    blue: Color::from_rgb(0, 60, 190),
+    pink: Color::from_rgb(160, 0, 130),
+};
+
+// Okabe-Ito colors, distinguishable with the common forms of color vision deficiency
+const COLOR_BLIND_BLUE: Color = Color::from_rgb(0, 114, 178);
+const COLOR_BLIND_SKY_BLUE: Color = Color::from_rgb(86, 180, 233);
+const COLOR_BLIND_ORANGE: Color = Color::from_rgb(230, 159, 0);
+const COLOR_BLIND_VERMILLION: Color = Color::from_rgb(213, 94, 0);
+
 #[derive(Clone, Copy, PartialEq)]
 pub struct Theme {
     pub background_color: Color,
@@ -61,6 +97,7 @@ pub struct Theme {
     pub active_search_background_color: Color,
     pub active_parameter_color: Color,
     pub status_line_background_color: Color,
+    pub word_highlight_background_color: Color,
     pub palette: Palette,
 }
 
@@ -79,7 +116,30 @@ impl Theme {
             active_search_background_color: palette.red,
             active_parameter_color: palette.green,
             status_line_background_color: palette.bg_dim,
+            // Halfway between the background and a selection, so it is never mistaken for one
+            word_highlight_background_color: Color::from_rgb(
+                ((palette.bg0.r_u8 as u16 + palette.bg1.r_u8 as u16) / 2) as u8,
+                ((palette.bg0.g_u8 as u16 + palette.bg1.g_u8 as u16) / 2) as u8,
+                ((palette.bg0.b_u8 as u16 + palette.bg1.b_u8 as u16) / 2) as u8,
+            ),
+            palette,
+        }
+    }
+
+    // Red and green are replaced wherever they carry meaning (diagnostics, search, parameters)
+    pub fn color_blind(self) -> Self {
+        let palette = Palette {
+            red: COLOR_BLIND_VERMILLION,
+            green: COLOR_BLIND_SKY_BLUE,
+            ..self.palette
+        };
+        Self {
+            diagnostic_color: COLOR_BLIND_ORANGE,
+            search_background_color: COLOR_BLIND_SKY_BLUE,
+            active_search_background_color: COLOR_BLIND_ORANGE,
+            active_parameter_color: COLOR_BLIND_BLUE,
             palette,
+            ..self
         }
     }
 }
@@ -87,4 +147,19 @@ impl Theme {
 pub const EVERFOREST_DARK: Theme = Theme::new(EVERFOREST_DARK_PALETTE);
 pub const EVERFOREST_LIGHT: Theme = Theme::new(EVERFOREST_LIGHT_PALETTE);
 
-pub const THEMES: [Theme; 2] = [EVERFOREST_DARK, EVERFOREST_LIGHT];
+pub const HIGH_CONTRAST_DARK: Theme = Theme::new(HIGH_CONTRAST_DARK_PALETTE);
+pub const HIGH_CONTRAST_LIGHT: Theme = Theme::new(HIGH_CONTRAST_LIGHT_PALETTE);
+
+pub const THEMES: [Theme; 4] = [
+    EVERFOREST_DARK,
+    EVERFOREST_LIGHT,
+    HIGH_CONTRAST_DARK,
+    HIGH_CONTRAST_LIGHT,
+];
+
+pub const THEME_NAMES: [&str; 4] = [
+    "Everforest Dark",
+    "Everforest Light",
+    "High Contrast Dark",
+    "High Contrast Light",
+];
@@ -0,0 +1,126 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+    thread,
+};
+
+const MAX_WORKERS: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Priority {
+    High,
+    Low,
+}
+
+// Shared flag that lets the owner of a job stop it early, jobs are expected to poll it
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+struct Queues {
+    high: VecDeque<Job>,
+    low: VecDeque<Job>,
+}
+
+impl Queues {
+    fn push(&mut self, priority: Priority, job: Job) {
+        match priority {
+            Priority::High => self.high.push_back(job),
+            Priority::Low => self.low.push_back(job),
+        }
+    }
+}
+
+struct Jobs {
+    // Any idle worker takes the next one of these
+    shared: Queues,
+    // One queue per worker
+    pinned: Vec<Queues>,
+}
+
+impl Jobs {
+    // High priority jobs go first, whether they are pinned or not
+    fn next(&mut self, worker: usize) -> Option<Job> {
+        let pinned = &mut self.pinned[worker];
+        pinned
+            .high
+            .pop_front()
+            .or_else(|| self.shared.high.pop_front())
+            .or_else(|| pinned.low.pop_front())
+            .or_else(|| self.shared.low.pop_front())
+    }
+}
+
+struct ThreadPool {
+    jobs: Mutex<Jobs>,
+    available: Condvar,
+}
+
+// All background work runs on a fixed number of workers instead of a thread per buffer
+static THREAD_POOL: OnceLock<Arc<ThreadPool>> = OnceLock::new();
+
+pub fn spawn(priority: Priority, job: impl FnOnce() + Send + 'static) {
+    let pool = THREAD_POOL.get_or_init(start_workers);
+    pool.jobs
+        .lock()
+        .unwrap()
+        .shared
+        .push(priority, Box::new(job));
+    pool.available.notify_one();
+}
+
+// Jobs with the same key always run on the same worker, in the order they were spawned for each
+// priority. This allows them to keep state that can't be sent between threads in thread locals.
+pub fn spawn_pinned(key: usize, priority: Priority, job: impl FnOnce() + Send + 'static) {
+    let pool = THREAD_POOL.get_or_init(start_workers);
+    let mut jobs = pool.jobs.lock().unwrap();
+    let num_workers = jobs.pinned.len();
+    jobs.pinned[key % num_workers].push(priority, Box::new(job));
+    // Only the worker the job is pinned to can take it
+    pool.available.notify_all();
+}
+
+fn start_workers() -> Arc<ThreadPool> {
+    let num_workers = thread::available_parallelism()
+        .map_or(1, |n| n.get().saturating_sub(1))
+        .clamp(1, MAX_WORKERS);
+
+    let pool = Arc::new(ThreadPool {
+        jobs: Mutex::new(Jobs {
+            shared: Queues::default(),
+            pinned: (0..num_workers).map(|_| Queues::default()).collect(),
+        }),
+        available: Condvar::new(),
+    });
+    for worker in 0..num_workers {
+        let pool = Arc::clone(&pool);
+        thread::spawn(move || loop {
+            let job = {
+                let mut jobs = pool.jobs.lock().unwrap();
+                loop {
+                    if let Some(job) = jobs.next(worker) {
+                        break job;
+                    }
+                    jobs = pool.available.wait(jobs).unwrap();
+                }
+            };
+            job();
+        });
+    }
+    pool
+}
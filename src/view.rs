@@ -1,18 +1,66 @@
-use std::cmp::{max, min};
+use std::{
+    cmp::{max, min},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use winit::dpi::LogicalPosition;
 
 use crate::{
-    buffer::{Buffer, BufferMode},
-    cursor::{get_filtered_completions, CompletionRequest},
-    language_server_types::{CompletionItem, Diagnostic, SignatureHelp},
+    buffer::{Buffer, SubstitutionPreview},
+    command::BufferMode,
+    completion::get_filtered_completions,
+    cursor::{CompletionRequest, Cursor},
+    decoration::{Decoration, DecorationKind},
+    language_server_types::{CompletionItem, Hover, SignatureHelp},
     piece_table::PieceTable,
-    renderer::RenderLayout,
+    renderer::{popup_opens_above, Color, PopupBounds, RenderLayout},
     text_utils::{self, CharType},
 };
 
 pub const SCROLL_LINES_PER_ROLL: isize = 3;
-const MAX_SHOWN_COMPLETION_ITEMS: usize = 10;
+const SCROLL_COLS_PER_ROLL: isize = 6;
+// Set with ":set pumheight=N"
+static MAX_SHOWN_COMPLETION_ITEMS: AtomicUsize = AtomicUsize::new(10);
+
+pub fn set_max_shown_completion_items(num_items: usize) {
+    MAX_SHOWN_COMPLETION_ITEMS.store(max(num_items, 1), Ordering::Relaxed);
+}
+
+// How the view follows jumps to search matches, definitions and lines, set with
+// ":set jumpcenter=always|offscreen|scrolloff"
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JumpCenter {
+    Always,
+    Offscreen,
+    ScrollOff,
+}
+
+impl JumpCenter {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "always" => Some(Self::Always),
+            "offscreen" => Some(Self::Offscreen),
+            "scrolloff" => Some(Self::ScrollOff),
+            _ => None,
+        }
+    }
+}
+
+// Lines kept between the cursor and the top or bottom of the view with JumpCenter::ScrollOff
+const JUMP_SCROLL_OFF: usize = 5;
+static JUMP_CENTER: AtomicUsize = AtomicUsize::new(JumpCenter::ScrollOff as usize);
+
+pub fn set_jump_center(jump_center: JumpCenter) {
+    JUMP_CENTER.store(jump_center as usize, Ordering::Relaxed);
+}
+
+fn jump_center() -> JumpCenter {
+    match JUMP_CENTER.load(Ordering::Relaxed) {
+        0 => JumpCenter::Always,
+        1 => JumpCenter::Offscreen,
+        _ => JumpCenter::ScrollOff,
+    }
+}
 
 pub struct CompletionView {
     pub row: usize,
@@ -33,20 +81,102 @@ pub struct HoverMessage {
     pub num_lines: usize,
 }
 
+impl HoverMessage {
+    // Markdown code fences are stripped, the ranges of the code blocks are kept for highlighting
+    pub fn from_hover(hover: Hover) -> Option<Self> {
+        let (message, code_block_ranges) = match hover.contents.kind.as_str() {
+            "plaintext" => (hover.contents.value, vec![]),
+            "markdown" => {
+                let mut processed_markdown = String::default();
+                let mut code_block_ranges = vec![];
+                let mut offset = 0;
+                let mut code_block_start = None;
+                for line in hover.contents.value.lines() {
+                    if line.starts_with("```") {
+                        if let Some(start) = code_block_start {
+                            code_block_ranges.push((start, offset));
+                            code_block_start = None;
+                        } else {
+                            code_block_start = Some(offset);
+                        }
+                    } else {
+                        processed_markdown.push_str(line);
+                        processed_markdown.push('\n');
+                        offset = processed_markdown.len();
+                    }
+                }
+                (processed_markdown, code_block_ranges)
+            }
+            _ => return None,
+        };
+
+        if message.trim().is_empty() {
+            return None;
+        }
+
+        let num_lines = message.as_bytes().iter().filter(|&c| *c == b'\n').count();
+        Some(Self {
+            message,
+            code_block_ranges,
+            line_offset: 0,
+            num_lines,
+        })
+    }
+}
+
+// The state a hover was requested in, the hover is dismissed as soon as any of it changes
+#[derive(Clone, Copy, PartialEq)]
+pub struct HoverOrigin {
+    version: i32,
+    mode: BufferMode,
+    cursor: usize,
+    line_offset: usize,
+    col_offset: usize,
+    keyboard: bool,
+}
+
+// What a double or triple click selected, dragging afterwards extends the selection by whole words
+// or lines from there
+#[derive(Clone, Copy, Debug)]
+pub enum DragOrigin {
+    Words(usize, usize),
+    Lines(usize, usize),
+}
+
+// What a split view shows of a buffer, where it's scrolled to and where its cursors are. A buffer
+// shown in both views is edited through either of them independently
 pub struct View {
+    pub cursors: Vec<Cursor>,
+    pub mode: BufferMode,
+    // How many of the edits made through the other view are applied to the cursors, see
+    // Buffer::sync_views
+    pub rebalanced: usize,
     pub line_offset: usize,
     pub col_offset: usize,
     pub hover: Option<(usize, usize)>,
     pub hover_message: Option<HoverMessage>,
+    pub hover_bounds: Option<PopupBounds>,
+    pub hover_origin: Option<HoverOrigin>,
+    pub definition_link: Option<(usize, usize, usize)>,
+    pub drag_origin: Option<DragOrigin>,
+    pub substitution_preview: Option<SubstitutionPreview>,
 }
 
 impl View {
     pub fn new() -> Self {
         Self {
+            cursors: vec![Cursor::default()],
+            mode: BufferMode::Normal,
+            rebalanced: 0,
             line_offset: 0,
             col_offset: 0,
             hover: None,
             hover_message: None,
+            hover_bounds: None,
+            hover_origin: None,
+            definition_link: None,
+            drag_origin: None,
+            substitution_preview: None,
         }
     }
 
@@ -54,51 +184,101 @@ impl View {
         self.scroll_vertical(buffer, -sign * SCROLL_LINES_PER_ROLL)
     }
 
-    pub fn hover(
+    pub fn handle_horizontal_scroll(
         &mut self,
+        buffer: &Buffer,
+        sign: isize,
         layout: &RenderLayout,
-        mouse_position: LogicalPosition<f64>,
-        font_size: (f64, f64),
     ) {
-        let (line, col) = self.get_line_col(layout, mouse_position, font_size);
+        self.scroll_horizontal(buffer, -sign * SCROLL_COLS_PER_ROLL, layout)
+    }
+
+    pub fn hover(&mut self, buffer: &Buffer, line: usize, col: usize, keyboard: bool) {
+        self.exit_hover();
         self.hover = Some((line, col));
+        self.hover_origin = Some(self.hover_origin(buffer, keyboard));
     }
 
     pub fn exit_hover(&mut self) {
         self.hover = None;
         self.hover_message = None;
+        self.hover_bounds = None;
+        self.hover_origin = None;
+    }
+
+    // Edits, mode changes, cursor movement and scrolling all dismiss the hover
+    pub fn hover_is_stale(&self, buffer: &Buffer) -> bool {
+        self.hover_origin
+            .is_some_and(|origin| origin != self.hover_origin(buffer, origin.keyboard))
+    }
+
+    pub fn hover_from_keyboard(&self) -> bool {
+        self.hover_origin.is_some_and(|origin| origin.keyboard)
+    }
+
+    fn hover_origin(&self, buffer: &Buffer, keyboard: bool) -> HoverOrigin {
+        HoverOrigin {
+            version: buffer.version,
+            mode: self.mode,
+            cursor: self.cursors.last().map_or(0, |cursor| cursor.position),
+            line_offset: self.line_offset,
+            col_offset: self.col_offset,
+            keyboard,
+        }
+    }
+
+    pub fn mouse_in_hover(&self, mouse_position: LogicalPosition<f64>) -> bool {
+        self.hover_bounds
+            .is_some_and(|bounds| bounds.contains(mouse_position.x, mouse_position.y))
+    }
+
+    pub fn scroll_hover_message(&mut self, delta: isize, popup_max_height: usize) {
+        if let Some(hover_message) = &mut self.hover_message {
+            hover_message.line_offset = min(
+                hover_message.line_offset.saturating_add_signed(delta),
+                hover_message.num_lines.saturating_sub(
+                    popup_max_height.saturating_sub(SCROLL_LINES_PER_ROLL as usize),
+                ),
+            );
+        }
     }
 
     pub fn visible_cursors_iter<F>(&self, layout: &RenderLayout, buffer: &Buffer, f: F)
     where
         F: Fn(usize, usize, usize),
     {
-        if buffer.mode == BufferMode::VisualLine {
-            for cursor in buffer.cursors.iter() {
+        if self.mode == BufferMode::VisualLine {
+            for cursor in self.cursors.iter() {
                 let line = buffer.piece_table.line_index(cursor.position);
                 let anchor_line = buffer.piece_table.line_index(cursor.anchor);
                 for line in min(line, anchor_line)..=max(line, anchor_line) {
-                    let start = 0;
                     let end = buffer.piece_table.line_at_index(line).unwrap().length;
-                    let num = (start..=end)
+                    let cells = buffer.piece_table.display_cols(line, 0, end + 1);
+                    let num = cells
+                        .clone()
                         .filter(|col| self.pos_in_render_visible_range(line, *col, layout))
                         .count();
                     f(
                         self.absolute_to_view_row(line),
-                        self.absolute_to_view_col(start),
+                        self.absolute_to_view_col(cells.start),
                         num,
                     );
                 }
             }
         } else {
-            for cursor in buffer.cursors.iter() {
+            for cursor in self.cursors.iter() {
                 for range in cursor.get_selection_ranges(&buffer.piece_table) {
-                    let num = (range.start..=range.end)
+                    let cells =
+                        buffer
+                            .piece_table
+                            .display_cols(range.line, range.start, range.end + 1);
+                    let num = cells
+                        .clone()
                         .filter(|col| self.pos_in_render_visible_range(range.line, *col, layout))
                         .count();
                     f(
                         self.absolute_to_view_row(range.line),
-                        self.absolute_to_view_col(range.start),
+                        self.absolute_to_view_col(cells.start),
                         num,
                     );
                 }
@@ -110,8 +290,9 @@ impl View {
     where
         F: FnMut(usize, usize, usize),
     {
-        for cursor in buffer.cursors.iter() {
+        for cursor in self.cursors.iter() {
             let (line, col) = cursor.get_line_col(&buffer.piece_table);
+            let col = buffer.piece_table.display_col(line, col);
             if self.pos_in_render_visible_range(line, col, layout) {
                 f(
                     self.absolute_to_view_row(line),
@@ -127,7 +308,7 @@ impl View {
         F: Fn(&[CompletionItem], &CompletionView, &CompletionRequest),
     {
         if let Some(server) = &buffer.language_server {
-            for cursor in buffer.cursors.iter() {
+            for cursor in self.cursors.iter() {
                 if let Some(request) = cursor.completion_request {
                     if let Some(completion_list) =
                         server.borrow().saved_completions.get(&request.id)
@@ -176,7 +357,7 @@ impl View {
         F: Fn(&SignatureHelp, &SignatureHelpView),
     {
         if let Some(server) = &buffer.language_server {
-            for cursor in buffer.cursors.iter() {
+            for cursor in self.cursors.iter() {
                 if let Some(request) = cursor.signature_help_request {
                     if let Some(signature_help) =
                         server.borrow().saved_signature_helps.get(&request.id)
@@ -208,79 +389,131 @@ impl View {
             .text_between_lines(self.line_offset, self.line_offset + layout.num_rows)
     }
 
-    pub fn visible_diagnostic_lines_iter<F>(
+    // The cells covered by the characters start..end, one call per visible line. Ranges spanning
+    // several lines include the cell of each line break
+    pub fn visible_range_iter<F>(
         &self,
         buffer: &Buffer,
         layout: &RenderLayout,
-        diagnostics: &[Diagnostic],
+        start: usize,
+        end: usize,
         mut f: F,
     ) where
         F: FnMut(usize, usize, usize),
     {
-        if let Some(offset) = buffer
-            .piece_table
-            .char_index_from_line_col(self.line_offset, self.col_offset)
-        {
-            for diagnostic in diagnostics {
-                if diagnostic.severity.is_some_and(|s| s > 2) {
-                    continue;
-                }
-
-                let (start_line, start_col) = (
-                    diagnostic.range.start.line as usize,
-                    diagnostic.range.start.character as usize,
-                );
-                let (end_line, end_col) = (
-                    diagnostic.range.end.line as usize,
-                    diagnostic.range.end.character as usize,
-                );
+        let end = min(end, buffer.piece_table.num_chars());
+        if start >= end || layout.num_rows == 0 || layout.num_cols == 0 {
+            return;
+        }
 
-                if (buffer.mode == BufferMode::Insert
-                    && buffer.cursors.iter().any(|cursor| {
-                        (start_line..=end_line)
-                            .contains(&buffer.piece_table.line_index(cursor.position))
-                    }))
-                    || (!self.pos_in_render_visible_range(start_line, start_col, layout)
-                        && !self.pos_in_render_visible_range(end_line, end_col, layout))
-                {
-                    continue;
-                }
+        let (start_line, start_col) = (
+            buffer.piece_table.line_index(start),
+            buffer.piece_table.col_index(start),
+        );
+        let (end_line, end_col) = (
+            buffer.piece_table.line_index(end - 1),
+            buffer.piece_table.col_index(end - 1),
+        );
 
-                if start_line == end_line {
-                    f(
-                        self.absolute_to_view_row(start_line),
-                        self.absolute_to_view_col(start_col),
-                        end_col.saturating_sub(start_col) + 1,
-                    );
-                } else {
-                    f(
-                        self.absolute_to_view_row(start_line),
-                        self.absolute_to_view_col(start_col),
-                        buffer.piece_table.line_at_index(start_line).unwrap().length - start_col
-                            + 1,
-                    );
+        let last_visible_line = self.line_offset + layout.num_rows - 1;
+        for line in max(start_line, self.line_offset)..=min(end_line, last_visible_line) {
+            let first_col = if line == start_line { start_col } else { 0 };
+            let last_col = if line == end_line {
+                end_col
+            } else {
+                buffer
+                    .piece_table
+                    .line_at_index(line)
+                    .map_or(0, |line| line.length)
+            };
+
+            let cells = buffer
+                .piece_table
+                .display_cols(line, first_col, last_col + 1);
+            let first_visible_col = max(cells.start, self.col_offset);
+            let last_visible_col = min(cells.end - 1, self.col_offset + layout.num_cols - 1);
+            if first_visible_col <= last_visible_col {
+                f(
+                    self.absolute_to_view_row(line),
+                    self.absolute_to_view_col(first_visible_col),
+                    last_visible_col - first_visible_col + 1,
+                );
+            }
+        }
+    }
 
-                    for line in start_line + 1..end_line {
-                        f(
-                            self.absolute_to_view_row(line),
-                            self.absolute_to_view_col(0),
-                            buffer.piece_table.line_at_index(line).unwrap().length + 1,
-                        );
-                    }
+    // Virtual text is placed after the end of its line, following any earlier virtual text on the
+    // same line, and truncated to the width of the view
+    pub fn visible_virtual_text<'a, F>(
+        &self,
+        buffer: &Buffer,
+        layout: &RenderLayout,
+        decorations: impl Iterator<Item = &'a Decoration>,
+        mut f: F,
+    ) where
+        F: FnMut(usize, usize, String, Color),
+    {
+        let mut line_ends: Vec<(usize, usize)> = vec![];
+        for decoration in decorations {
+            let DecorationKind::VirtualText(text, color) = &decoration.kind else {
+                continue;
+            };
+            if decoration.start > buffer.piece_table.num_chars() {
+                continue;
+            }
 
-                    f(
-                        self.absolute_to_view_row(end_line),
-                        self.absolute_to_view_col(0),
-                        end_col + 1,
-                    );
-                }
+            let line = buffer.piece_table.line_index(decoration.start);
+            if !(self.line_offset..self.line_offset + layout.num_rows).contains(&line) {
+                continue;
+            }
+            let Some(line_length) = buffer
+                .piece_table
+                .line_at_index(line)
+                .map(|line| buffer.piece_table.display_col(line.index, line.length))
+            else {
+                continue;
+            };
+
+            let start_col = line_ends
+                .iter()
+                .find(|(l, _)| *l == line)
+                .map_or(line_length, |(_, end)| *end)
+                + 2;
+            let text_length = text.lines().next().unwrap_or_default().chars().count();
+            line_ends.retain(|(l, _)| *l != line);
+            line_ends.push((line, start_col + text_length));
+
+            let col = self.absolute_to_view_col(start_col);
+            let available_cols = layout.num_cols.saturating_sub(col);
+
+            let mut text: Vec<char> = text
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .skip(self.col_offset.saturating_sub(start_col))
+                .collect();
+            if text.is_empty() || available_cols < 2 {
+                continue;
+            }
+            if text.len() > available_cols {
+                text.truncate(available_cols - 1);
+                text.push('\u{2026}');
             }
+
+            f(
+                self.absolute_to_view_row(line),
+                col,
+                text.into_iter().collect(),
+                *color,
+            );
         }
     }
 
     pub fn adjust(&mut self, buffer: &Buffer, layout: &RenderLayout) {
-        if let Some(last_cursor) = buffer.cursors.last() {
+        if let Some(last_cursor) = self.cursors.last() {
             let (line, col) = last_cursor.get_line_col(&buffer.piece_table);
+            let col = buffer.piece_table.display_col(line, col);
             if !self.pos_in_edit_visible_range(line, col, layout) {
                 if line < self.line_offset {
                     self.line_offset = line;
@@ -299,17 +532,36 @@ impl View {
     }
 
     pub fn center(&mut self, buffer: &Buffer, layout: &RenderLayout) {
-        if let Some(last_cursor) = buffer.cursors.last() {
+        if let Some(last_cursor) = self.cursors.last() {
             let (line, _) = last_cursor.get_line_col(&buffer.piece_table);
             self.line_offset = line.saturating_sub(layout.num_rows / 2);
         }
     }
 
-    pub fn center_if_not_visible(&mut self, buffer: &Buffer, layout: &RenderLayout) {
-        if let Some(last_cursor) = buffer.cursors.last() {
-            let (line, col) = last_cursor.get_line_col(&buffer.piece_table);
-            if !self.pos_in_edit_visible_range(line, col, layout) {
-                self.line_offset = line.saturating_sub(layout.num_rows / 2);
+    pub fn reveal_jump(&mut self, buffer: &Buffer, layout: &RenderLayout) {
+        let Some(last_cursor) = self.cursors.last() else {
+            return;
+        };
+        let (line, col) = last_cursor.get_line_col(&buffer.piece_table);
+        let col = buffer.piece_table.display_col(line, col);
+        match jump_center() {
+            JumpCenter::Always => self.line_offset = line.saturating_sub(layout.num_rows / 2),
+            JumpCenter::Offscreen => {
+                if !self.pos_in_edit_visible_range(line, col, layout) {
+                    self.line_offset = line.saturating_sub(layout.num_rows / 2);
+                }
+            }
+            // Far jumps are centered, near ones scroll just enough to keep the margin
+            JumpCenter::ScrollOff => {
+                let num_rows = layout.num_rows.saturating_sub(1);
+                let margin = min(JUMP_SCROLL_OFF, num_rows.saturating_sub(1) / 2);
+                if line + num_rows < self.line_offset || line >= self.line_offset + 2 * num_rows {
+                    self.line_offset = line.saturating_sub(layout.num_rows / 2);
+                } else if line < self.line_offset + margin {
+                    self.line_offset = line.saturating_sub(margin);
+                } else if line + margin >= self.line_offset + num_rows {
+                    self.line_offset = line + margin + 1 - num_rows;
+                }
             }
         }
     }
@@ -322,7 +574,7 @@ impl View {
         layout: &RenderLayout,
     ) -> Option<SignatureHelpView> {
         let line = piece_table.line_index(position);
-        let col = piece_table.col_index(position);
+        let col = piece_table.display_col(line, piece_table.col_index(position));
 
         if signature_help.signatures.is_empty()
             || !self.pos_in_render_visible_range(line, col, layout)
@@ -330,23 +582,11 @@ impl View {
             return None;
         }
 
-        let row = self.absolute_to_view_row(line) + 1;
-        let col = self.absolute_to_view_col(col);
-
-        let content_length = signature_help.signatures
-            [signature_help.active_signature.unwrap_or(0) as usize]
-            .label
-            .len();
-
-        let available_rows_right = layout.num_cols.saturating_sub(col + 1);
-        let move_left = available_rows_right < content_length;
-        let col = if move_left {
-            col.saturating_sub(content_length)
-        } else {
-            col
-        };
-
-        Some(SignatureHelpView { row, col })
+        // The popup is measured and kept inside the view when it is drawn
+        Some(SignatureHelpView {
+            row: self.absolute_to_view_row(line) + 1,
+            col: self.absolute_to_view_col(col),
+        })
     }
 
     pub fn get_completion_view(
@@ -357,7 +597,7 @@ impl View {
         layout: &RenderLayout,
     ) -> Option<CompletionView> {
         let line = piece_table.line_index(position);
-        let col = piece_table.col_index(position);
+        let col = piece_table.display_col(line, piece_table.col_index(position));
 
         if !self.pos_in_render_visible_range(line, col, layout) {
             return None;
@@ -375,7 +615,10 @@ impl View {
             .map(|x| x.insert_text.as_ref().unwrap_or(&x.label).len() + 1)
             .unwrap_or(0);
 
-        let mut num_shown_completion_items = min(MAX_SHOWN_COMPLETION_ITEMS, completions.len());
+        let mut num_shown_completion_items = min(
+            MAX_SHOWN_COMPLETION_ITEMS.load(Ordering::Relaxed),
+            completions.len(),
+        );
 
         let row = self.absolute_to_view_row(line);
         let col = self.absolute_to_view_col(col);
@@ -383,7 +626,12 @@ impl View {
         let available_rows_above = row.saturating_sub(1);
         let available_rows_below = layout.num_rows.saturating_sub(row + 2);
 
-        let grow_up = available_rows_below < 5 && available_rows_above > available_rows_below;
+        let grow_up = popup_opens_above(
+            available_rows_above as f64,
+            available_rows_below as f64,
+            num_shown_completion_items as f64,
+            false,
+        );
         let row = if grow_up {
             num_shown_completion_items = min(num_shown_completion_items, available_rows_above);
             row.saturating_sub(num_shown_completion_items)
@@ -392,33 +640,34 @@ impl View {
             row + 1
         };
 
-        let available_rows_right = layout.num_cols.saturating_sub(col + 1);
-        let move_left = available_rows_right < longest_string;
-        let col = if move_left {
-            col.saturating_sub(longest_string)
-        } else {
-            col
-        };
+        // Moved left just far enough to fit
+        let width = min(longest_string, layout.num_cols.saturating_sub(1));
+        let col = min(col, layout.num_cols.saturating_sub(width));
 
         Some(CompletionView {
             row,
             col,
-            width: longest_string,
+            width,
             height: num_shown_completion_items,
         })
     }
 
     pub fn get_line_col(
         &self,
+        buffer: &Buffer,
         layout: &RenderLayout,
         mouse_position: LogicalPosition<f64>,
         font_size: (f64, f64),
     ) -> (usize, usize) {
         let row = (mouse_position.y / font_size.1).floor() as usize;
         let col = (mouse_position.x / font_size.0).floor() as usize;
+        let line = (row.saturating_sub(layout.row_offset)) + self.line_offset;
         (
-            (row.saturating_sub(layout.row_offset)) + self.line_offset,
-            (col.saturating_sub(layout.col_offset)) + self.col_offset,
+            line,
+            buffer.piece_table.col_from_display(
+                line,
+                (col.saturating_sub(layout.col_offset)) + self.col_offset,
+            ),
         )
     }
 
@@ -437,6 +686,17 @@ impl View {
         );
     }
 
+    // Stops once the end of the longest line is in view
+    fn scroll_horizontal(&mut self, buffer: &Buffer, delta: isize, layout: &RenderLayout) {
+        self.col_offset = min(
+            self.col_offset.saturating_add_signed(delta),
+            buffer
+                .piece_table
+                .longest_line()
+                .saturating_sub(layout.num_cols.saturating_sub(2)),
+        );
+    }
+
     fn pos_in_edit_visible_range(&self, line: usize, col: usize, layout: &RenderLayout) -> bool {
         (self.line_offset..self.line_offset + layout.num_rows.saturating_sub(1)).contains(&line)
             && (self.col_offset..self.col_offset + layout.num_cols.saturating_sub(1)).contains(&col)
@@ -0,0 +1,365 @@
+use std::{
+    collections::BTreeSet,
+    ffi::OsStr,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use url::Url;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{
+    editor::{FileIdentifier, Workspace},
+    language_server_types::{Location, Position, Range, SymbolInformation},
+    text_utils::{self, CharType},
+    thread_pool::{self, CancellationToken, Priority},
+};
+
+const MAX_INDEXED_FILES: usize = 20000;
+const MAX_INDEXED_FILE_SIZE: u64 = 1024 * 1024;
+const MAX_INDEXED_WORDS: usize = 200000;
+const MIN_WORD_LENGTH: usize = 3;
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+// Declarations are recognized ctags-style by a keyword after any modifiers, nothing is parsed
+const SYMBOL_KEYWORDS: [&str; 15] = [
+    "fn",
+    "struct",
+    "enum",
+    "union",
+    "trait",
+    "type",
+    "mod",
+    "macro_rules!",
+    "class",
+    "interface",
+    "def",
+    "function",
+    "func",
+    "namespace",
+    "module",
+];
+
+const SYMBOL_MODIFIERS: [&str; 19] = [
+    "pub",
+    "pub(crate)",
+    "pub(super)",
+    "export",
+    "default",
+    "async",
+    "unsafe",
+    "const",
+    "extern",
+    "static",
+    "public",
+    "private",
+    "protected",
+    "internal",
+    "abstract",
+    "final",
+    "sealed",
+    "inline",
+    "typedef",
+];
+
+pub struct IndexedSymbol {
+    pub name: String,
+    pub path: String,
+    pub line: usize,
+}
+
+#[derive(Default)]
+pub struct IndexData {
+    // Counts the scans of the workspace, so that lists built from an earlier scan can be refreshed
+    pub generation: usize,
+    pub files: Vec<FileIdentifier>,
+    pub symbols: Vec<IndexedSymbol>,
+    pub words: BTreeSet<String>,
+}
+
+// The workspace is scanned on a worker, the previous results stay available while it is rescanned
+pub struct WorkspaceIndex {
+    data: Arc<Mutex<Option<IndexData>>>,
+    cancellation: CancellationToken,
+    started: Instant,
+    generation: usize,
+}
+
+impl WorkspaceIndex {
+    pub fn new(workspace: &Workspace) -> Self {
+        let mut index = Self {
+            data: Arc::new(Mutex::new(None)),
+            cancellation: CancellationToken::default(),
+            started: Instant::now(),
+            generation: 0,
+        };
+        index.scan(workspace);
+        index
+    }
+
+    pub fn refresh_if_stale(&mut self, workspace: &Workspace) {
+        if self.started.elapsed() > REFRESH_INTERVAL {
+            self.scan(workspace);
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.data.lock().unwrap().is_some()
+    }
+
+    pub fn generation(&self) -> Option<usize> {
+        self.data
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|data| data.generation)
+    }
+
+    pub fn files(&self) -> Option<(usize, Vec<FileIdentifier>)> {
+        self.data
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|data| (data.generation, data.files.clone()))
+    }
+
+    // The index doesn't know what kind of symbol it found or the column it starts at
+    pub fn symbols(&self) -> Vec<SymbolInformation> {
+        self.data
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|data| {
+                data.symbols
+                    .iter()
+                    .filter_map(|symbol| {
+                        let position = Position {
+                            line: symbol.line as u32,
+                            character: 0,
+                        };
+                        Some(SymbolInformation {
+                            name: symbol.name.clone(),
+                            kind: 0,
+                            location: Location {
+                                uri: Url::from_file_path(&symbol.path).ok()?.to_string(),
+                                range: Range {
+                                    start: position,
+                                    end: position,
+                                },
+                            },
+                            container_name: None,
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn words_with_prefix(&self, prefix: &str, limit: usize) -> Vec<String> {
+        self.data
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|data| {
+                data.words
+                    .range(prefix.to_string()..)
+                    .take_while(|word| word.starts_with(prefix))
+                    .filter(|word| word.len() > prefix.len())
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Created files are indexed on a worker and removed ones are dropped. The words of removed files
+    // stay until the next scan, nothing tracks which files they came from
+    pub fn update_files(
+        &mut self,
+        workspace: &Workspace,
+        created: Vec<String>,
+        removed: Vec<String>,
+    ) {
+        if created.is_empty() && removed.is_empty() {
+            return;
+        }
+        self.generation += 1;
+
+        let data = self.data.clone();
+        let path = workspace.path.clone();
+        let gitignore_paths = workspace.gitignore_paths.clone();
+        let generation = self.generation;
+        thread_pool::spawn(Priority::Low, move || {
+            // Created directories are walked, their files aren't reported one by one
+            let mut created_data = IndexData::default();
+            for entry in created
+                .iter()
+                .filter(|created| !is_ignored(&path, created, &gitignore_paths))
+                .flat_map(|created| walk_workspace(created, &gitignore_paths))
+            {
+                index_file(&mut created_data, &entry);
+            }
+
+            let mut data = data.lock().unwrap();
+            // A scan that hasn't finished yet finds the files itself
+            let Some(data) = data.as_mut() else {
+                return;
+            };
+            let is_stale = |file_path: &Path| {
+                removed.iter().any(|removed| file_path.starts_with(removed))
+                    || created_data
+                        .files
+                        .iter()
+                        .any(|file| file_path == Path::new(&file.path))
+            };
+            data.files.retain(|file| !is_stale(Path::new(&file.path)));
+            data.symbols
+                .retain(|symbol| !is_stale(Path::new(&symbol.path)));
+
+            let free = MAX_INDEXED_FILES.saturating_sub(data.files.len());
+            data.files.extend(created_data.files.into_iter().take(free));
+            data.symbols.extend(created_data.symbols);
+            for word in created_data.words {
+                if data.words.len() >= MAX_INDEXED_WORDS {
+                    break;
+                }
+                data.words.insert(word);
+            }
+            data.generation = generation;
+        });
+    }
+
+    pub fn scan(&mut self, workspace: &Workspace) {
+        self.cancellation.cancel();
+        self.cancellation = CancellationToken::default();
+        self.started = Instant::now();
+        self.generation += 1;
+
+        let data = self.data.clone();
+        let cancellation = self.cancellation.clone();
+        let path = workspace.path.clone();
+        let gitignore_paths = workspace.gitignore_paths.clone();
+        let generation = self.generation;
+        thread_pool::spawn(Priority::Low, move || {
+            let mut index_data = IndexData {
+                generation,
+                ..Default::default()
+            };
+            for entry in walk_workspace(&path, &gitignore_paths).take(MAX_INDEXED_FILES) {
+                if cancellation.is_cancelled() {
+                    return;
+                }
+
+                index_file(&mut index_data, &entry);
+            }
+
+            if !cancellation.is_cancelled() {
+                *data.lock().unwrap() = Some(index_data);
+            }
+        });
+    }
+}
+
+impl Drop for WorkspaceIndex {
+    fn drop(&mut self) {
+        self.cancellation.cancel();
+    }
+}
+
+pub fn walk_workspace<'a>(
+    path: &str,
+    gitignore_paths: &'a [String],
+) -> impl Iterator<Item = DirEntry> + 'a {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_entry(|e| {
+            e.file_name() != OsStr::new(".git")
+                && !gitignore_paths
+                    .iter()
+                    .any(|entry| Some(entry.as_str()) == e.file_name().to_str())
+        })
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+}
+
+fn index_file(index_data: &mut IndexData, entry: &DirEntry) {
+    let Some(file_path) = entry.path().to_str() else {
+        return;
+    };
+    index_data.files.push(FileIdentifier {
+        name: entry.file_name().to_os_string(),
+        path: entry.path().as_os_str().to_os_string(),
+        position: None,
+    });
+
+    if entry
+        .metadata()
+        .is_ok_and(|metadata| metadata.len() <= MAX_INDEXED_FILE_SIZE)
+    {
+        if let Ok(text) = fs::read(entry.path()) {
+            index_text(index_data, file_path, &text);
+        }
+    }
+}
+
+// Changes inside .git or an ignored directory are left out like the scan leaves them out
+fn is_ignored(workspace_path: &str, path: &str, gitignore_paths: &[String]) -> bool {
+    let Ok(relative_path) = Path::new(path).strip_prefix(workspace_path) else {
+        return true;
+    };
+    relative_path.iter().any(|component| {
+        component == OsStr::new(".git")
+            || gitignore_paths
+                .iter()
+                .any(|entry| Some(entry.as_str()) == component.to_str())
+    })
+}
+
+fn index_text(index_data: &mut IndexData, path: &str, text: &[u8]) {
+    // Binary files have neither words nor symbols
+    if text.contains(&0) {
+        return;
+    }
+
+    for (line_index, line) in text.split(|c| *c == b'\n').enumerate() {
+        if let Some(name) = std::str::from_utf8(line).ok().and_then(parse_symbol) {
+            index_data.symbols.push(IndexedSymbol {
+                name: name.to_string(),
+                path: path.to_string(),
+                line: line_index,
+            });
+        }
+
+        if index_data.words.len() >= MAX_INDEXED_WORDS {
+            continue;
+        }
+        for word in line.split(|c| text_utils::char_type(*c) != CharType::Word) {
+            if word.len() >= MIN_WORD_LENGTH && !word[0].is_ascii_digit() {
+                // Words only contain ascii characters
+                index_data
+                    .words
+                    .insert(unsafe { std::str::from_utf8_unchecked(word) }.to_string());
+            }
+        }
+    }
+}
+
+fn parse_symbol(line: &str) -> Option<&str> {
+    let mut words = line.split_ascii_whitespace();
+    let mut word = words.next()?;
+    while SYMBOL_MODIFIERS.contains(&word) {
+        word = words.next()?;
+    }
+    if !SYMBOL_KEYWORDS.contains(&word) {
+        return None;
+    }
+
+    let name = words.next()?;
+    let end = name
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(name.len());
+    let name = &name[..end];
+    (!name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit())).then_some(name)
+}
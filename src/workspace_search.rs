@@ -0,0 +1,189 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    editor::Workspace,
+    picker::Picker,
+    thread_pool::{self, CancellationToken, Priority},
+    workspace_index::walk_workspace,
+};
+
+const MAX_SEARCH_RESULTS: usize = 1000;
+const MAX_SEARCHED_FILE_SIZE: u64 = 1024 * 1024;
+const MAX_PREVIEW_LENGTH: usize = 80;
+
+pub struct SearchResult {
+    pub path: String,
+    // Relative to the workspace
+    pub name: String,
+    pub line: usize,
+    pub col: usize,
+    pub preview: String,
+    // Range of the match in the preview, if it wasn't cut off
+    pub preview_match: Option<(usize, usize)>,
+}
+
+struct SearchState {
+    results: Vec<SearchResult>,
+    finished: bool,
+}
+
+// Files are searched on a worker while the query is typed, every change of the query starts a new
+// search and the results of the previous one are dropped
+pub struct SearchPanel {
+    pub picker: Picker<SearchResult>,
+    pub finished: bool,
+    workspace_path: String,
+    gitignore_paths: Vec<String>,
+    state: Arc<Mutex<SearchState>>,
+    cancellation: CancellationToken,
+}
+
+impl SearchPanel {
+    pub fn new(workspace: &Workspace, query: &str) -> Self {
+        let mut search_panel = Self {
+            picker: Picker::new(query),
+            finished: true,
+            workspace_path: workspace.path.clone(),
+            gitignore_paths: workspace.gitignore_paths.clone(),
+            state: Arc::new(Mutex::new(SearchState {
+                results: vec![],
+                finished: true,
+            })),
+            cancellation: CancellationToken::default(),
+        };
+        search_panel.search();
+        search_panel
+    }
+
+    // Picks up the results found since the last update, returns whether anything changed
+    pub fn update(&mut self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.results.is_empty() && state.finished == self.finished {
+            return false;
+        }
+        self.picker.items.append(&mut state.results);
+        self.finished = state.finished;
+        true
+    }
+
+    pub fn search(&mut self) {
+        self.cancellation.cancel();
+        self.cancellation = CancellationToken::default();
+        self.finished = self.picker.query.is_empty();
+        self.state = Arc::new(Mutex::new(SearchState {
+            results: vec![],
+            finished: self.finished,
+        }));
+        self.picker.set_items(vec![]);
+        if self.picker.query.is_empty() {
+            return;
+        }
+
+        let state = self.state.clone();
+        let cancellation = self.cancellation.clone();
+        let query = self.picker.query.clone();
+        let workspace_path = self.workspace_path.clone();
+        let gitignore_paths = self.gitignore_paths.clone();
+        thread_pool::spawn(Priority::Low, move || {
+            // Like the search in a buffer, the query is case sensitive if it contains uppercase
+            let ignore_case = !query.bytes().any(|c| c.is_ascii_uppercase());
+            let mut num_results = 0;
+            for entry in walk_workspace(&workspace_path, &gitignore_paths) {
+                if cancellation.is_cancelled() || num_results >= MAX_SEARCH_RESULTS {
+                    break;
+                }
+                if !entry
+                    .metadata()
+                    .is_ok_and(|metadata| metadata.len() <= MAX_SEARCHED_FILE_SIZE)
+                {
+                    continue;
+                }
+                let (Some(path), Ok(text)) = (entry.path().to_str(), fs::read(entry.path())) else {
+                    continue;
+                };
+
+                let name = Path::new(path)
+                    .strip_prefix(&workspace_path)
+                    .map_or(path, |name| name.to_str().unwrap_or(path));
+                let results = search_text(
+                    &text,
+                    &query,
+                    ignore_case,
+                    path,
+                    name,
+                    MAX_SEARCH_RESULTS - num_results,
+                );
+                if !results.is_empty() {
+                    num_results += results.len();
+                    state.lock().unwrap().results.extend(results);
+                }
+            }
+
+            if !cancellation.is_cancelled() {
+                state.lock().unwrap().finished = true;
+            }
+        });
+    }
+}
+
+impl Drop for SearchPanel {
+    fn drop(&mut self) {
+        self.cancellation.cancel();
+    }
+}
+
+fn search_text(
+    text: &[u8],
+    query: &str,
+    ignore_case: bool,
+    path: &str,
+    name: &str,
+    limit: usize,
+) -> Vec<SearchResult> {
+    // Binary files never match
+    if text.contains(&0) {
+        return vec![];
+    }
+
+    let query = query.as_bytes();
+    let mut results = vec![];
+    for (line_index, line) in text.split(|c| *c == b'\n').enumerate() {
+        if results.len() >= limit {
+            break;
+        }
+        let Some(col) = line.windows(query.len()).position(|window| {
+            if ignore_case {
+                window.eq_ignore_ascii_case(query)
+            } else {
+                window == query
+            }
+        }) else {
+            continue;
+        };
+
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+        let indentation = line.len() - line.trim_start().len();
+        let mut preview = line[indentation..].replace('\t', " ");
+        if let Some((end, _)) = preview.char_indices().nth(MAX_PREVIEW_LENGTH) {
+            preview.truncate(end);
+        }
+        let match_start = col.saturating_sub(indentation);
+        let preview_match = (col >= indentation && match_start + query.len() <= preview.len())
+            .then_some((match_start, query.len()));
+
+        results.push(SearchResult {
+            path: path.to_string(),
+            name: name.to_string(),
+            line: line_index,
+            col,
+            preview,
+            preview_match,
+        });
+    }
+    results
+}
@@ -0,0 +1,69 @@
+use std::{fs, path::PathBuf};
+
+use crate::{config, log};
+
+// The .nimble directory of a workspace can configure language servers, whose settings may name
+// commands to run (rust-analyzer's overrideCommand for example). It is only used once the user
+// trusts the workspace, the decision is remembered per workspace
+pub fn has_workspace_config(workspace_path: &str) -> bool {
+    let directory = PathBuf::from(workspace_path).join(".nimble");
+    directory.join("config.toml").is_file() || directory.join("keymap.toml").is_file()
+}
+
+// None if the user hasn't decided yet
+pub fn is_trusted(workspace_path: &str) -> Option<bool> {
+    read_decisions()
+        .into_iter()
+        .find(|(path, _)| path == workspace_path)
+        .map(|(_, trusted)| trusted)
+}
+
+pub fn remember(workspace_path: &str, trusted: bool) {
+    let Some(decisions_path) = decisions_path() else {
+        return;
+    };
+    let mut decisions = read_decisions();
+    decisions.retain(|(path, _)| path != workspace_path);
+    decisions.push((workspace_path.to_string(), trusted));
+
+    let mut text = String::default();
+    for (path, trusted) in decisions {
+        let decision = if trusted { "trusted" } else { "untrusted" };
+        text.push_str(&format!("{} {}\n", decision, path));
+    }
+
+    let written = decisions_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&decisions_path, text));
+    if let Err(error) = written {
+        log::warning(format!(
+            "Failed to remember the trust decision for {}: {}",
+            workspace_path, error
+        ));
+    }
+}
+
+// Each line is "trusted PATH" or "untrusted PATH"
+fn read_decisions() -> Vec<(String, bool)> {
+    let Some(text) = decisions_path().and_then(|path| fs::read_to_string(path).ok()) else {
+        return vec![];
+    };
+    text.lines()
+        .filter_map(|line| {
+            let (decision, path) = line.split_once(' ')?;
+            match decision {
+                "trusted" => Some((path.to_string(), true)),
+                "untrusted" => Some((path.to_string(), false)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn decisions_path() -> Option<PathBuf> {
+    config::config_directories(None)
+        .into_iter()
+        .next()
+        .map(|directory| directory.join("trusted_workspaces"))
+}
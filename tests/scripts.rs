@@ -0,0 +1,41 @@
+use std::{fs, path::Path, process::Command};
+
+// Runs every script in tests/scripts with "nimble --script" (see src/script.rs). The scripts run
+// on a copy of the directory, since some of them save the files they open
+#[test]
+fn scripts() {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("scripts");
+    let directory = std::env::temp_dir().join(format!("nimble-scripts-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    let mut scripts = vec![];
+    for entry in fs::read_dir(source).unwrap() {
+        let path = entry.unwrap().path();
+        let copy = directory.join(path.file_name().unwrap());
+        fs::copy(&path, &copy).unwrap();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "script")
+        {
+            scripts.push(copy);
+        }
+    }
+    scripts.sort();
+
+    let failed: Vec<String> = scripts
+        .iter()
+        .filter(|script| {
+            !Command::new(env!("CARGO_BIN_EXE_nimble"))
+                .arg("--script")
+                .arg(script)
+                .status()
+                .is_ok_and(|status| status.success())
+        })
+        .map(|script| script.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    fs::remove_dir_all(&directory).ok();
+
+    assert!(failed.is_empty(), "Failed scripts: {}", failed.join(", "));
+}
@@ -0,0 +1,6 @@
+fn a() {
+    x;
+    y;
+}
+
+fn b() {}